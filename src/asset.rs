@@ -1,9 +1,13 @@
+pub mod acm;
+pub mod ai;
 pub mod font;
 pub mod frame;
 pub mod map;
 pub mod message;
+pub mod mve;
 pub mod palette;
 pub mod proto;
+pub mod save;
 pub mod script;
 
 use enumflags2_derive::EnumFlags;
@@ -799,6 +803,23 @@ pub fn read_ini(rd: &mut impl BufRead) -> io::Result<HashMap<String, HashMap<Str
     Ok(parser.sections)
 }
 
+/// Reads PIDs of the party members from `data/party.txt`. The first one is the dude.
+// partyMember_init()
+pub fn read_party_member_pids(rd: &mut impl BufRead) -> io::Result<Vec<proto::ProtoId>> {
+    let ini = read_ini(rd)?;
+    let mut r = Vec::new();
+    while let Some(v) = ini.get(&format!("Party Member {}", r.len()))
+        .and_then(|s| s.get("party_member_pid"))
+    {
+        let pid = v.parse().ok()
+            .and_then(proto::ProtoId::from_packed)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                format!("invalid party member PID: {}", v)))?;
+        r.push(pid);
+    }
+    Ok(r)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -880,4 +901,20 @@ key2
         let act = read_ini(&mut BufReader::new(Cursor::new(inp))).unwrap();
         assert_eq!(act, exp_map);
     }
+
+    #[test]
+    fn read_party_member_pids_() {
+        let inp = "
+[Party Member 0]
+party_member_pid=16777216
+
+[Party Member 1]
+party_member_pid=16777377
+area_attack_mode=no_pref
+
+[Party Member 3]
+party_member_pid=16777378";
+        assert_eq!(read_party_member_pids(&mut BufReader::new(Cursor::new(inp))).unwrap(),
+            [proto::ProtoId::DUDE, proto::ProtoId::from_packed(16777377).unwrap()]);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,486 @@
+use byteorder::ReadBytesExt;
+use std::cmp;
+use std::io::{self, Error, ErrorKind, prelude::*};
+
+const SIGNATURE: u32 = 0x032897;
+const VERSION: u32 = 1;
+
+/// Offset of the zero value in `AcmReader::amp`.
+const AMP_MIDDLE: i32 = 0x8000;
+
+/// Streaming decoder of Interplay ACM audio files. Produces signed 16-bit samples with channels
+/// interleaved.
+pub struct AcmReader<R> {
+    rd: R,
+    bits: u32,
+    bit_count: u32,
+    channels: u16,
+    sample_rate: u32,
+    sample_count: usize,
+    pos: usize,
+    level: u32,
+    rows: usize,
+    cols: usize,
+    block: Vec<i32>,
+    block_pos: usize,
+    wrap: Vec<i32>,
+    amp: Vec<i32>,
+}
+
+impl<R: Read> AcmReader<R> {
+    pub fn new(rd: R) -> io::Result<Self> {
+        let mut r = Self {
+            rd,
+            bits: 0,
+            bit_count: 0,
+            channels: 0,
+            sample_rate: 0,
+            sample_count: 0,
+            pos: 0,
+            level: 0,
+            rows: 0,
+            cols: 0,
+            block: Vec::new(),
+            block_pos: 0,
+            wrap: Vec::new(),
+            amp: vec![0; 0x10000],
+        };
+
+        if r.read_bits(24)? != SIGNATURE || r.read_bits(8)? != VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "not an ACM file"));
+        }
+        let lo = r.read_bits(16)?;
+        let hi = r.read_bits(16)?;
+        r.sample_count = (lo | hi << 16) as usize;
+        r.channels = r.read_bits(16)? as u16;
+        if r.channels == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "ACM file has no channels"));
+        }
+        r.sample_rate = r.read_bits(16)?;
+        r.level = r.read_bits(4)?;
+        r.rows = r.read_bits(12)? as usize;
+        if r.rows == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "ACM file has zero block rows"));
+        }
+        r.cols = 1 << r.level;
+        r.block = vec![0; r.rows * r.cols];
+        r.block_pos = r.block.len();
+        r.wrap = vec![0; 2 * r.cols - 2];
+
+        Ok(r)
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Total number of samples in all channels.
+    pub fn sample_count(&self) -> usize {
+        self.sample_count
+    }
+
+    /// Decodes samples into `buf`. Returns number of samples written which is less than
+    /// `buf.len()` only at the end of stream.
+    pub fn read_samples(&mut self, buf: &mut [i16]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() && self.pos < self.sample_count {
+            if self.block_pos == self.block.len() {
+                self.decode_block()?;
+            }
+            let count = cmp::min(cmp::min(self.block.len() - self.block_pos, buf.len() - n),
+                self.sample_count - self.pos);
+            for (dst, &src) in buf[n..n + count].iter_mut()
+                .zip(&self.block[self.block_pos..self.block_pos + count])
+            {
+                *dst = (src >> self.level) as i16;
+            }
+            n += count;
+            self.block_pos += count;
+            self.pos += count;
+        }
+        Ok(n)
+    }
+
+    /// Decodes all remaining samples.
+    pub fn read_to_end(&mut self) -> io::Result<Vec<i16>> {
+        let mut r = vec![0; self.sample_count - self.pos];
+        let n = self.read_samples(&mut r)?;
+        r.truncate(n);
+        Ok(r)
+    }
+
+    fn read_bits(&mut self, count: u32) -> io::Result<u32> {
+        while self.bit_count < count {
+            let b = match self.rd.read_u8() {
+                Ok(b) => b,
+                // Some files are truncated, pad them with silence.
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => 0,
+                Err(e) => return Err(e),
+            };
+            self.bits |= (b as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let r = self.bits & ((1 << count) - 1);
+        self.bits >>= count;
+        self.bit_count -= count;
+        Ok(r)
+    }
+
+    fn amp(&self, i: i32) -> i32 {
+        self.amp[(AMP_MIDDLE + i) as usize]
+    }
+
+    fn set(&mut self, row: usize, col: usize, v: i32) {
+        self.block[(row << self.level) + col] = v;
+    }
+
+    fn set_amp(&mut self, row: usize, col: usize, i: i32) {
+        let v = self.amp(i);
+        self.set(row, col, v);
+    }
+
+    // decode_block()
+    fn decode_block(&mut self) -> io::Result<()> {
+        let pwr = self.read_bits(4)?;
+        let step = self.read_bits(16)? as i32;
+
+        let count = 1 << pwr;
+        let mut x = 0i32;
+        for i in 0..count {
+            self.amp[(AMP_MIDDLE + i) as usize] = x;
+            x = x.wrapping_add(step);
+        }
+        let mut x = -step;
+        for i in 1..=count {
+            self.amp[(AMP_MIDDLE - i) as usize] = x;
+            x = x.wrapping_sub(step);
+        }
+
+        for col in 0..self.cols {
+            let kind = self.read_bits(5)?;
+            self.fill_column(kind, col)?;
+        }
+
+        self.juggle_block();
+        self.block_pos = 0;
+
+        Ok(())
+    }
+
+    // fill_block()
+    fn fill_column(&mut self, kind: u32, col: usize) -> io::Result<()> {
+        const MAP_1BIT: [i32; 2] = [-1, 1];
+        const MAP_2BIT_NEAR: [i32; 4] = [-2, -1, 1, 2];
+        const MAP_2BIT_FAR: [i32; 4] = [-3, -2, 2, 3];
+        const MAP_3BIT: [i32; 8] = [-4, -3, -2, -1, 1, 2, 3, 4];
+
+        let rows = self.rows;
+        let mut row = 0;
+        // Sets the value and the next one (if any) to zero.
+        macro_rules! zero2 {
+            () => {{
+                self.set(row, col, 0);
+                if row + 1 < rows {
+                    self.set(row + 1, col, 0);
+                }
+                row += 2;
+                continue;
+            }};
+        }
+        macro_rules! zero1 {
+            () => {{
+                self.set(row, col, 0);
+                row += 1;
+                continue;
+            }};
+        }
+        match kind {
+            0 => for row in 0..rows {
+                self.set(row, col, 0);
+            }
+            3..=16 => {
+                let middle = 1 << (kind - 1);
+                for row in 0..rows {
+                    let b = self.read_bits(kind)? as i32;
+                    self.set_amp(row, col, b - middle);
+                }
+            }
+            // k13
+            17 => while row < rows {
+                if self.read_bits(1)? == 0 { zero2!() }
+                if self.read_bits(1)? == 0 { zero1!() }
+                let b = self.read_bits(1)?;
+                self.set_amp(row, col, MAP_1BIT[b as usize]);
+                row += 1;
+            }
+            // k12
+            18 => while row < rows {
+                if self.read_bits(1)? == 0 { zero1!() }
+                let b = self.read_bits(1)?;
+                self.set_amp(row, col, MAP_1BIT[b as usize]);
+                row += 1;
+            }
+            // t15
+            19 => while row < rows {
+                let b = self.read_bits(5)? as i32;
+                self.fill_packed(&mut row, col, &[b % 3 - 1, b / 3 % 3 - 1, b / 9 - 1]);
+            }
+            // k24
+            20 => while row < rows {
+                if self.read_bits(1)? == 0 { zero2!() }
+                if self.read_bits(1)? == 0 { zero1!() }
+                let b = self.read_bits(2)?;
+                self.set_amp(row, col, MAP_2BIT_NEAR[b as usize]);
+                row += 1;
+            }
+            // k23
+            21 => while row < rows {
+                if self.read_bits(1)? == 0 { zero1!() }
+                let b = self.read_bits(2)?;
+                self.set_amp(row, col, MAP_2BIT_NEAR[b as usize]);
+                row += 1;
+            }
+            // t27
+            22 => while row < rows {
+                let b = self.read_bits(7)? as i32;
+                self.fill_packed(&mut row, col, &[b % 5 - 2, b / 5 % 5 - 2, b / 25 - 2]);
+            }
+            // k35
+            23 => while row < rows {
+                if self.read_bits(1)? == 0 { zero2!() }
+                if self.read_bits(1)? == 0 { zero1!() }
+                let v = if self.read_bits(1)? == 0 {
+                    MAP_1BIT[self.read_bits(1)? as usize]
+                } else {
+                    MAP_2BIT_FAR[self.read_bits(2)? as usize]
+                };
+                self.set_amp(row, col, v);
+                row += 1;
+            }
+            // k34
+            24 => while row < rows {
+                if self.read_bits(1)? == 0 { zero1!() }
+                let v = if self.read_bits(1)? == 0 {
+                    MAP_1BIT[self.read_bits(1)? as usize]
+                } else {
+                    MAP_2BIT_FAR[self.read_bits(2)? as usize]
+                };
+                self.set_amp(row, col, v);
+                row += 1;
+            }
+            // k45
+            26 => while row < rows {
+                if self.read_bits(1)? == 0 { zero2!() }
+                if self.read_bits(1)? == 0 { zero1!() }
+                let b = self.read_bits(3)?;
+                self.set_amp(row, col, MAP_3BIT[b as usize]);
+                row += 1;
+            }
+            // k44
+            27 => while row < rows {
+                if self.read_bits(1)? == 0 { zero1!() }
+                let b = self.read_bits(3)?;
+                self.set_amp(row, col, MAP_3BIT[b as usize]);
+                row += 1;
+            }
+            // t37
+            29 => while row < rows {
+                let b = self.read_bits(7)? as i32;
+                self.fill_packed(&mut row, col, &[b % 11 - 5, b / 11 - 5]);
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidData,
+                format!("bad ACM column filler: {}", kind))),
+        }
+        Ok(())
+    }
+
+    fn fill_packed(&mut self, row: &mut usize, col: usize, values: &[i32]) {
+        for &v in values {
+            if *row >= self.rows {
+                break;
+            }
+            self.set_amp(*row, col, v);
+            *row += 1;
+        }
+    }
+
+    // juggle_block()
+    fn juggle_block(&mut self) {
+        if self.level == 0 {
+            return;
+        }
+
+        let step_subcount = if self.level > 9 {
+            1
+        } else {
+            (2048 >> self.level) - 2
+        };
+
+        let mut todo_rows = self.rows;
+        let mut block_pos = 0;
+        loop {
+            let mut wrap_pos = 0;
+            let mut sub_count = cmp::min(step_subcount, todo_rows) * 2;
+            let mut sub_len = self.cols / 2;
+
+            self.juggle(wrap_pos, block_pos, sub_len, sub_count);
+            wrap_pos += sub_len * 2;
+
+            for i in 0..sub_count {
+                let v = &mut self.block[block_pos + i * sub_len];
+                *v = v.wrapping_add(1);
+            }
+
+            while sub_len > 1 {
+                sub_len >>= 1;
+                sub_count <<= 1;
+                self.juggle(wrap_pos, block_pos, sub_len, sub_count);
+                wrap_pos += sub_len * 2;
+            }
+
+            if todo_rows <= step_subcount {
+                break;
+            }
+            todo_rows -= step_subcount;
+            block_pos += step_subcount << self.level;
+        }
+    }
+
+    // juggle()
+    fn juggle(&mut self, wrap_pos: usize, block_pos: usize, sub_len: usize, sub_count: usize) {
+        for i in 0..sub_len {
+            let wrap = wrap_pos + i * 2;
+            let mut p = block_pos + i;
+            let mut r0 = self.wrap[wrap];
+            let mut r1 = self.wrap[wrap + 1];
+            for _ in 0..sub_count / 2 {
+                let r2 = self.block[p];
+                self.block[p] = r1.wrapping_mul(2).wrapping_add(r0.wrapping_add(r2));
+                p += sub_len;
+                let r3 = self.block[p];
+                self.block[p] = r2.wrapping_mul(2).wrapping_sub(r1.wrapping_add(r3));
+                p += sub_len;
+                r0 = r2;
+                r1 = r3;
+            }
+            self.wrap[wrap] = r0;
+            self.wrap[wrap + 1] = r1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct BitWriter {
+        buf: Vec<u8>,
+        bits: u32,
+        bit_count: u32,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, v: u32, count: u32) -> &mut Self {
+            for i in 0..count {
+                self.bits |= (v >> i & 1) << self.bit_count;
+                self.bit_count += 1;
+                if self.bit_count == 8 {
+                    self.buf.push(self.bits as u8);
+                    self.bits = 0;
+                    self.bit_count = 0;
+                }
+            }
+            self
+        }
+
+        fn header(&mut self, sample_count: u32, channels: u32, level: u32, rows: u32) -> &mut Self {
+            self.write(SIGNATURE, 24)
+                .write(VERSION, 8)
+                .write(sample_count, 32)
+                .write(channels, 16)
+                .write(22050, 16)
+                .write(level, 4)
+                .write(rows, 12)
+        }
+
+        fn finish(&mut self) -> Vec<u8> {
+            if self.bit_count > 0 {
+                self.buf.push(self.bits as u8);
+            }
+            self.buf.clone()
+        }
+    }
+
+    #[test]
+    fn header() {
+        let data = BitWriter::default().header(1234, 2, 3, 16).finish();
+        let acm = AcmReader::new(&data[..]).unwrap();
+        assert_eq!(acm.channels(), 2);
+        assert_eq!(acm.sample_rate(), 22050);
+        assert_eq!(acm.sample_count(), 1234);
+        assert_eq!(acm.cols, 8);
+        assert_eq!(acm.rows, 16);
+
+        assert!(AcmReader::new(&[0x97, 0x28, 0x03, 0x02, 0, 0, 0, 0][..]).is_err());
+    }
+
+    #[test]
+    fn linear() {
+        // Single column, two blocks of 4 rows.
+        let data = BitWriter::default()
+            .header(6, 1, 0, 4)
+            .write(2, 4).write(100, 16)
+            .write(3, 5).write(4, 3).write(5, 3).write(3, 3).write(0, 3)
+            .write(2, 4).write(10, 16)
+            .write(18, 5).write(1, 1).write(1, 1).write(0, 1).write(1, 1).write(0, 1)
+            .finish();
+        let mut acm = AcmReader::new(&data[..]).unwrap();
+        assert_eq!(acm.read_to_end().unwrap(), vec![0, 100, -100, -400, 10, 0]);
+        assert_eq!(acm.read_to_end().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn packed() {
+        let data = BitWriter::default()
+            .header(7, 1, 0, 7)
+            .write(3, 4).write(1, 16)
+            // t15: 2 + 0 * 3 + 1 * 9 => 1, -1, 0
+            .write(19, 5).write(11, 5)
+            // 2 * 3 + 2 * 9 => -1, 1, 1
+            .write(24, 5)
+            // 1 => 0
+            .write(1, 5)
+            .finish();
+        let mut acm = AcmReader::new(&data[..]).unwrap();
+        assert_eq!(acm.read_to_end().unwrap(), vec![1, -1, 0, -1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn juggle() {
+        let data = BitWriter::default()
+            .header(4, 1, 1, 2)
+            .write(2, 4).write(1, 16)
+            .write(3, 5).write(5, 3).write(6, 3)
+            .write(3, 5).write(4, 3).write(2, 3)
+            .finish();
+        let mut acm = AcmReader::new(&data[..]).unwrap();
+        // [1, 0, 2, -2] is juggled into [1, 2, 3, 6], then incremented and shifted by level.
+        assert_eq!(acm.read_to_end().unwrap(), vec![1, 1, 2, 3]);
+    }
+
+    #[test]
+    fn truncated() {
+        let data = BitWriter::default()
+            .header(4, 1, 0, 4)
+            .write(2, 4).write(100, 16)
+            .write(3, 5).write(5, 3)
+            .finish();
+        let mut acm = AcmReader::new(&data[..]).unwrap();
+        assert_eq!(acm.read_to_end().unwrap(), vec![100, -400, -400, -400]);
+    }
+}
@@ -0,0 +1,295 @@
+use log::*;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Error, ErrorKind};
+
+use crate::fs::FileSystem;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AttackWho {
+    WhomeverAttackingMe,
+    Strongest,
+    Weakest,
+    Whomever,
+    Closest,
+}
+
+impl AttackWho {
+    fn from_name(s: &str) -> Option<Self> {
+        use AttackWho::*;
+        Some(match s {
+            "whomever_attacking_me" => WhomeverAttackingMe,
+            "strongest" => Strongest,
+            "weakest" => Weakest,
+            "whomever" => Whomever,
+            "closest" => Closest,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BestWeapon {
+    NoPref,
+    Melee,
+    MeleeOverRanged,
+    RangedOverMelee,
+    Ranged,
+    Unarmed,
+    UnarmedOverThrown,
+    Random,
+}
+
+impl BestWeapon {
+    fn from_name(s: &str) -> Option<Self> {
+        use BestWeapon::*;
+        Some(match s {
+            "no_pref" => NoPref,
+            "melee" => Melee,
+            "melee_over_ranged" => MeleeOverRanged,
+            "ranged_over_melee" => RangedOverMelee,
+            "ranged" => Ranged,
+            "unarmed" => Unarmed,
+            "unarmed_over_thrown" => UnarmedOverThrown,
+            "random" => Random,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChemUse {
+    Clean,
+    StimsWhenHurtLittle,
+    StimsWhenHurtLots,
+    Sometimes,
+    Anytime,
+    Always,
+}
+
+impl ChemUse {
+    fn from_name(s: &str) -> Option<Self> {
+        use ChemUse::*;
+        Some(match s {
+            "clean" => Clean,
+            "stims_when_hurt_little" => StimsWhenHurtLittle,
+            "stims_when_hurt_lots" => StimsWhenHurtLots,
+            "sometimes" => Sometimes,
+            "anytime" => Anytime,
+            "always" => Always,
+            _ => return None,
+        })
+    }
+}
+
+/// Preferred distance to the enemy.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Distance {
+    StayClose,
+    Charge,
+    Snipe,
+    OnYourOwn,
+    Stay,
+}
+
+impl Distance {
+    fn from_name(s: &str) -> Option<Self> {
+        use Distance::*;
+        Some(match s {
+            "stay_close" => StayClose,
+            "charge" => Charge,
+            "snipe" => Snipe,
+            "on_your_own" => OnYourOwn,
+            "stay" => Stay,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RunAwayMode {
+    None,
+    Coward,
+    FingerHurts,
+    Bleeding,
+    NotFeelingGood,
+    Tourniquet,
+    Never,
+}
+
+impl RunAwayMode {
+    fn from_name(s: &str) -> Option<Self> {
+        use RunAwayMode::*;
+        Some(match s {
+            "none" => None,
+            "coward" => Coward,
+            "finger_hurts" => FingerHurts,
+            "bleeding" => Bleeding,
+            "not_feeling_good" => NotFeelingGood,
+            "tourniquet" => Tourniquet,
+            "never" => Never,
+            _ => return Option::None,
+        })
+    }
+}
+
+/// AI packet from `data/ai.txt`.
+/// `area_attack_mode` isn't read since burst and area attacks aren't implemented.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Packet {
+    pub name: String,
+    pub id: i32,
+    /// Percent chance to attack when there's an opportunity.
+    pub aggression: i32,
+    pub attack_who: Option<AttackWho>,
+    pub best_weapon: Option<BestWeapon>,
+    pub chem_use: Option<ChemUse>,
+    pub distance: Option<Distance>,
+    pub run_away_mode: Option<RunAwayMode>,
+    /// Max distance from the dude for party members.
+    pub max_dist: i32,
+    /// Critter runs away when its hit points go below this value.
+    pub min_hp: i32,
+    /// Minimum to-hit chance to attempt an attack.
+    pub min_to_hit: i32,
+}
+
+impl Default for Packet {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            id: -1,
+            aggression: 100,
+            attack_who: None,
+            best_weapon: None,
+            chem_use: None,
+            distance: None,
+            run_away_mode: None,
+            max_dist: 0,
+            min_hp: 0,
+            min_to_hit: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct AiDb {
+    packets: HashMap<i32, Packet>,
+}
+
+impl AiDb {
+    pub fn new(fs: &FileSystem) -> io::Result<Self> {
+        Self::read(&mut fs.reader("data/ai.txt")?)
+    }
+
+    fn read(rd: &mut impl BufRead) -> io::Result<Self> {
+        let ini = crate::asset::read_ini(rd)?;
+        let mut packets = HashMap::new();
+        for (name, section) in ini {
+            fn err(name: &str, key: &str, v: &str) -> Error {
+                Error::new(ErrorKind::InvalidData,
+                    format!("invalid value of `{}` in AI packet `{}`: {}", key, name, v))
+            }
+            let int = |key: &str| -> io::Result<Option<i32>> {
+                section.get(key)
+                    .map(|v| v.parse().map_err(|_| err(&name, key, v)))
+                    .transpose()
+            };
+            // Unknown values are treated as if the key was missing.
+            fn enum_val<T>(section: &HashMap<String, String>, name: &str, key: &str,
+                f: impl Fn(&str) -> Option<T>) -> Option<T>
+            {
+                section.get(key).and_then(|v| {
+                    let r = f(&v.to_ascii_lowercase());
+                    if r.is_none() {
+                        warn!("{}", err(name, key, v));
+                    }
+                    r
+                })
+            }
+
+            let id = int("packet_num")?.ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                format!("missing packet_num in AI packet `{}`", name)))?;
+            let default = Packet::default();
+            let packet = Packet {
+                id,
+                aggression: int("aggression")?.unwrap_or(default.aggression),
+                attack_who: enum_val(&section, &name, "attack_who", AttackWho::from_name),
+                best_weapon: enum_val(&section, &name, "best_weapon", BestWeapon::from_name),
+                chem_use: enum_val(&section, &name, "chem_use", ChemUse::from_name),
+                distance: enum_val(&section, &name, "distance", Distance::from_name),
+                run_away_mode: enum_val(&section, &name, "run_away_mode",
+                    RunAwayMode::from_name),
+                max_dist: int("max_dist")?.unwrap_or(default.max_dist),
+                min_hp: int("min_hp")?.unwrap_or(default.min_hp),
+                min_to_hit: int("min_to_hit")?.unwrap_or(default.min_to_hit),
+                name,
+            };
+            packets.insert(id, packet);
+        }
+        Ok(Self {
+            packets,
+        })
+    }
+
+    pub fn get(&self, id: i32) -> Option<&Packet> {
+        self.packets.get(&id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::*;
+    use super::*;
+
+    #[test]
+    fn read() {
+        let inp = "
+[ARROYO MANTIS]
+aggression=20
+area_attack_mode=no_pref
+packet_num=47
+run_away_mode=Coward ; comment
+best_weapon=no_pref
+chem_use=clean
+distance=charge
+attack_who=whomever_attacking_me
+min_hp=10
+min_to_hit=5
+max_dist=8
+
+[Minimal]
+packet_num=1
+";
+        let db = AiDb::read(&mut BufReader::new(Cursor::new(inp))).unwrap();
+        assert_eq!(db.get(47).unwrap().distance, Some(Distance::Charge));
+
+        let bad_enum = inp.replace("chem_use=clean", "chem_use=x");
+        let db = AiDb::read(&mut BufReader::new(Cursor::new(bad_enum))).unwrap();
+        assert_eq!(db.get(47).unwrap().chem_use, None);
+
+        let bad_int = inp.replace("packet_num=1", "packet_num=x");
+        let db = AiDb::read(&mut BufReader::new(Cursor::new(bad_int)));
+        assert_eq!(db.err().unwrap().kind(), ErrorKind::InvalidData);
+
+        let db = AiDb::read(&mut BufReader::new(Cursor::new(inp))).unwrap();
+        assert_eq!(db.get(47).unwrap(), &Packet {
+            name: "ARROYO MANTIS".into(),
+            id: 47,
+            aggression: 20,
+            attack_who: Some(AttackWho::WhomeverAttackingMe),
+            best_weapon: Some(BestWeapon::NoPref),
+            chem_use: Some(ChemUse::Clean),
+            distance: Some(Distance::Charge),
+            run_away_mode: Some(RunAwayMode::Coward),
+            max_dist: 8,
+            min_hp: 10,
+            min_to_hit: 5,
+        });
+        assert_eq!(db.get(1).unwrap(), &Packet {
+            name: "Minimal".into(),
+            id: 1,
+            ..Default::default()
+        });
+        assert!(db.get(2).is_none());
+    }
+}
@@ -1,12 +1,13 @@
 mod db;
 mod id;
+pub mod sheet;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use enum_map::EnumMap;
-use std::io::{self, prelude::*};
+use std::io::{self, Error, ErrorKind, prelude::*};
 
 pub use id::{FrameId, Idx};
-pub use db::FrameDb;
+pub use db::{critter_anim_codes, FrameDb};
 
 use crate::graphics::Point;
 use crate::graphics::geometry::hex::Direction;
@@ -14,85 +15,206 @@ use crate::graphics::render::TextureFactory;
 use crate::graphics::sprite::*;
 use crate::util::EnumExt;
 
-pub fn read_frm(rd: &mut impl Read, texture_factory: &TextureFactory) -> io::Result<FrameSet> {
-    let _version = rd.read_u32::<BigEndian>()?;
-
-    let fps = rd.read_u16::<BigEndian>()?;
-    let fps = if fps == 0 {
-        10
-    } else {
-        fps
-    };
-
-    let action_frame = rd.read_u16::<BigEndian>()?;
-    let frames_per_direction = rd.read_u16::<BigEndian>()? as usize;
-    assert!(frames_per_direction > 0);
-
-    let mut centers_x = EnumMap::new();
-    for dir in Direction::iter() {
-        centers_x[dir] = rd.read_i16::<BigEndian>()? as i32;
-    }
-    let mut centers_y = EnumMap::new();
-    for dir in Direction::iter() {
-        centers_y[dir] = rd.read_i16::<BigEndian>()? as i32;
+/// Raw contents of FRM file with the palette-indexed pixels.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Frm {
+    pub fps: u16,
+    pub action_frame: u16,
+    /// Distinct frame lists. All lists must have the same number of frames.
+    pub frame_lists: Vec<FrmList>,
+    /// Index into `frame_lists` for each direction. Directions sharing the same data in the file
+    /// refer to the same list.
+    pub directions: EnumMap<Direction, usize>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FrmList {
+    pub center: Point,
+    pub frames: Vec<FrmFrame>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FrmFrame {
+    pub shift: Point,
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Box<[u8]>,
+}
+
+impl Frm {
+    pub fn read(rd: &mut impl Read) -> io::Result<Self> {
+        let _version = rd.read_u32::<BigEndian>()?;
+
+        let fps = rd.read_u16::<BigEndian>()?;
+        let action_frame = rd.read_u16::<BigEndian>()?;
+        let frames_per_direction = rd.read_u16::<BigEndian>()? as usize;
+        if frames_per_direction == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "FRM has no frames"));
+        }
+
+        let mut centers_x = EnumMap::new();
+        for dir in Direction::iter() {
+            centers_x[dir] = rd.read_i16::<BigEndian>()? as i32;
+        }
+        let mut centers_y = EnumMap::new();
+        for dir in Direction::iter() {
+            centers_y[dir] = rd.read_i16::<BigEndian>()? as i32;
+        }
+
+        let mut frame_offsets = EnumMap::new();
+        for dir in Direction::iter() {
+            frame_offsets[dir] = rd.read_u32::<BigEndian>()?;
+        }
+
+        let _data_len = rd.read_u32::<BigEndian>()?;
+
+        let mut loaded_offsets = Vec::new();
+        let mut frame_lists = Vec::new();
+        let mut directions = EnumMap::new();
+        for dir in Direction::iter() {
+            let offset = frame_offsets[dir];
+            if let Some(i) = loaded_offsets.iter().position(|&o| o == offset) {
+                directions[dir] = i;
+                continue;
+            }
+
+            loaded_offsets.push(offset);
+            directions[dir] = frame_lists.len();
+
+            let mut frames = Vec::with_capacity(frames_per_direction);
+            for _ in 0..frames_per_direction {
+                let width = rd.read_i16::<BigEndian>()? as i32;
+                let height = rd.read_i16::<BigEndian>()? as i32;
+                let _len = rd.read_u32::<BigEndian>()?;
+                let shift = Point::new(
+                    rd.read_i16::<BigEndian>()? as i32,
+                    rd.read_i16::<BigEndian>()? as i32,
+                );
+                if width < 0 || height < 0 {
+                    return Err(Error::new(ErrorKind::InvalidData,
+                        format!("invalid FRM frame size: {}x{}", width, height)));
+                }
+
+                let len = (width * height) as usize;
+                let mut pixels = vec![0; len].into_boxed_slice();
+                rd.read_exact(&mut pixels)?;
+
+                frames.push(FrmFrame {
+                    shift,
+                    width,
+                    height,
+                    pixels,
+                });
+            }
+            frame_lists.push(FrmList {
+                center: Point::new(centers_x[dir], centers_y[dir]),
+                frames,
+            });
+        }
+
+        Ok(Self {
+            fps,
+            action_frame,
+            frame_lists,
+            directions,
+        })
     }
 
-    let mut frame_offsets = EnumMap::new();
-    for dir in Direction::iter() {
-        frame_offsets[dir] = rd.read_u32::<BigEndian>()?;
+    pub fn write(&self, wr: &mut impl Write) -> io::Result<()> {
+        let frames_per_direction = self.frame_lists.first().map(|l| l.frames.len()).unwrap_or(0);
+        if frames_per_direction == 0 || frames_per_direction > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                format!("invalid number of frames: {}", frames_per_direction)));
+        }
+        if self.frame_lists.iter().any(|l| l.frames.len() != frames_per_direction) {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                "frame lists have different number of frames"));
+        }
+        if self.directions.values().any(|&i| i >= self.frame_lists.len()) {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid frame list index"));
+        }
+        for frame in self.frame_lists.iter().flat_map(|l| &l.frames) {
+            if frame.width < 0 || frame.width > i16::MAX as i32
+                || frame.height < 0 || frame.height > i16::MAX as i32
+                || frame.pixels.len() != (frame.width * frame.height) as usize
+            {
+                return Err(Error::new(ErrorKind::InvalidInput,
+                    format!("invalid frame size: {}x{}", frame.width, frame.height)));
+            }
+        }
+
+        let mut offsets = Vec::with_capacity(self.frame_lists.len());
+        let mut data_len = 0;
+        for list in &self.frame_lists {
+            offsets.push(data_len as u32);
+            data_len += list.frames.iter().map(|f| 12 + f.pixels.len()).sum::<usize>();
+        }
+        if data_len > u32::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "FRM is too big"));
+        }
+
+        wr.write_u32::<BigEndian>(4)?;
+        wr.write_u16::<BigEndian>(self.fps)?;
+        wr.write_u16::<BigEndian>(self.action_frame)?;
+        wr.write_u16::<BigEndian>(frames_per_direction as u16)?;
+        for &i in self.directions.values() {
+            wr.write_i16::<BigEndian>(self.frame_lists[i].center.x as i16)?;
+        }
+        for &i in self.directions.values() {
+            wr.write_i16::<BigEndian>(self.frame_lists[i].center.y as i16)?;
+        }
+        for &i in self.directions.values() {
+            wr.write_u32::<BigEndian>(offsets[i])?;
+        }
+        wr.write_u32::<BigEndian>(data_len as u32)?;
+
+        for frame in self.frame_lists.iter().flat_map(|l| &l.frames) {
+            wr.write_i16::<BigEndian>(frame.width as i16)?;
+            wr.write_i16::<BigEndian>(frame.height as i16)?;
+            wr.write_u32::<BigEndian>(frame.pixels.len() as u32)?;
+            wr.write_i16::<BigEndian>(frame.shift.x as i16)?;
+            wr.write_i16::<BigEndian>(frame.shift.y as i16)?;
+            wr.write_all(&frame.pixels)?;
+        }
+        Ok(())
     }
 
-    let _data_len = rd.read_u32::<BigEndian>()?;
-
-    let mut loaded_offsets: EnumMap<Direction, Option<u32>> = EnumMap::new();
-    let mut frame_lists: EnumMap<Direction, Option<FrameList>> = EnumMap::new();
-    for dir in Direction::iter() {
-        let offset = frame_offsets[dir];
-        let already_loaded_dir = loaded_offsets
-            .iter()
-            .filter_map(|(d, o)| o.filter(|&o| o == offset).map(|_| d))
-            .next();
-        if let Some(already_loaded_dir) = already_loaded_dir {
-            frame_lists[dir] = frame_lists[already_loaded_dir].clone();
-            continue;
-        }
-
-        loaded_offsets[dir] = Some(offset);
-
-        let mut frames = Vec::with_capacity(frames_per_direction);
-        for _ in 0..frames_per_direction {
-            let width = rd.read_i16::<BigEndian>()? as i32;
-            let height = rd.read_i16::<BigEndian>()? as i32;
-            let _len = rd.read_u32::<BigEndian>()?;
-            let shift = Point::new(
-                rd.read_i16::<BigEndian>()? as i32,
-                rd.read_i16::<BigEndian>()? as i32,
-            );
-
-            let len = (width * height) as usize;
-            let mut pixels = vec![0; len].into_boxed_slice();
-            rd.read_exact(&mut pixels)?;
-
-            let mask = Mask::new(width, &pixels);
-            let texture = texture_factory.new_texture(width, height, pixels);
-
-            frames.push(Frame {
-                shift,
-                width,
-                height,
-                texture,
-                mask,
-            });
+    /// Uploads the frames as textures. Directions sharing the same frame list share the textures.
+    pub fn into_frame_set(self, texture_factory: &TextureFactory) -> FrameSet {
+        let fps = if self.fps == 0 {
+            10
+        } else {
+            self.fps
+        };
+
+        let directions = self.directions;
+        let frame_lists: Vec<_> = self.frame_lists.into_iter()
+            .map(|list| FrameList {
+                center: list.center,
+                frames: list.frames.into_iter()
+                    .map(|f| {
+                        let mask = Mask::new(f.width, &f.pixels);
+                        let texture = texture_factory.new_texture(f.width, f.height, f.pixels);
+                        Frame {
+                            shift: f.shift,
+                            width: f.width,
+                            height: f.height,
+                            texture,
+                            mask,
+                        }
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        FrameSet {
+            fps,
+            action_frame: self.action_frame,
+            frame_lists: EnumMap::from(|dir| frame_lists[directions[dir]].clone()),
         }
-        frame_lists[dir] = Some(FrameList {
-            center: Point::new(centers_x[dir], centers_y[dir]),
-            frames,
-        });
     }
+}
 
-    Ok(FrameSet {
-        fps,
-        action_frame,
-        frame_lists: EnumMap::from(|k| frame_lists[k].take().unwrap()),
-    })
-}
\ No newline at end of file
+pub fn read_frm(rd: &mut impl Read, texture_factory: &TextureFactory) -> io::Result<FrameSet> {
+    Ok(Frm::read(rd)?.into_frame_set(texture_factory))
+}
@@ -1,4 +1,5 @@
 use enum_map::EnumMap;
+use log::*;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::io::{self, Error, ErrorKind, prelude::*};
@@ -35,6 +36,10 @@ impl FrameDb {
         })
     }
 
+    pub fn texture_factory(&self) -> &TextureFactory {
+        &self.texture_factory
+    }
+
     // art_get_name()
     /// Returns .frm or .frN file name without path.
     pub fn name(&self, fid: FrameId) -> Option<String> {
@@ -42,6 +47,12 @@ impl FrameDb {
         self.name_no_normalize(fid)
     }
 
+    /// Returns base name of the `fid` as listed in the `.lst` file. For critters it's the name
+    /// without animation codes like `hmjmps`.
+    pub fn base_name(&self, fid: FrameId) -> Option<&str> {
+        self.lst[fid.kind()].get(fid.idx() as usize).map(|e| e.fields[0].as_str())
+    }
+
     //  art_exists()
     pub fn exists(&self, fid: FrameId) -> bool {
         let fid = self.normalize_fid(fid);
@@ -60,6 +71,17 @@ impl FrameDb {
         })
     }
 
+    /// Reads the frame set bypassing the cache.
+    pub fn load(&self, fid: FrameId) -> io::Result<FrameSet> {
+        let fid = self.normalize_fid(fid);
+        read_frm(&mut self.read(fid)?, &self.texture_factory)
+    }
+
+    /// Returns number of entries in the `.lst` file of the `kind`.
+    pub fn lst_len(&self, kind: EntityKind) -> usize {
+        self.lst[kind].len()
+    }
+
     /// Looks for `base_name` and returns its ID if found.
     /// Note the `base_name` format depends on the `kind`. For example for `Critter` it's
     /// just a part of the `.fr_` filename like `hapowr`, and for `Interface` it's a full
@@ -91,12 +113,17 @@ impl FrameDb {
                 | FireDance
                 | CalledShotPic
                 => {
-                    // TODO replace unwraps with logging
-                    let alias = self.lst[EntityKind::Critter].get(critter_fid.idx() as usize).unwrap()
-                        .fields.get(1).unwrap();
                     // TODO parse this once during Self::new().
-                    let alias = alias.parse().unwrap();
-                    critter_fid.with_id(alias).unwrap().into()
+                    let alias = self.lst[EntityKind::Critter].get(critter_fid.idx() as usize)
+                        .and_then(|e| e.fields.get(1))
+                        .and_then(|s| s.parse().ok())
+                        .and_then(|alias| critter_fid.with_id(alias));
+                    if let Some(alias) = alias {
+                        alias.into()
+                    } else {
+                        warn!("no valid critter alias for {:?}", fid);
+                        fid
+                    }
                 }
                 _ => fid,
             };
@@ -193,7 +220,7 @@ impl FrameDb {
     }
 }
 
-fn critter_anim_codes(weapon_kind: WeaponKind, anim: CritterAnim) -> Option<(char, char)> {
+pub fn critter_anim_codes(weapon_kind: WeaponKind, anim: CritterAnim) -> Option<(char, char)> {
     use self::WeaponKind::*;
     use self::CritterAnim::*;
     Some(match anim {
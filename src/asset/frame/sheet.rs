@@ -0,0 +1,370 @@
+//! Conversion between FRM files and PNG sprite sheets.
+//!
+//! Each distinct frame list becomes a row of the sheet and each frame is placed in the top-left
+//! corner of its cell. Frame sizes, shifts, centers and the rest of the FRM header are stored in
+//! the `tEXt` chunk in INI format so the sheet can be imported back.
+//!
+//! Directions stored in separate `.fr0`-`.fr5` files are combined into one FRM with
+//! `combine_directions()` before exporting and split back with `split_directions()`.
+
+use enum_map::EnumMap;
+use std::collections::HashMap;
+use std::io::{self, Error, ErrorKind, prelude::*};
+use std::str::FromStr;
+
+use super::{Frm, FrmFrame, FrmList};
+use crate::asset::read_ini;
+use crate::graphics::Point;
+use crate::graphics::color::{Color8, Rgb24};
+use crate::graphics::color::palette::Palette;
+use crate::graphics::geometry::hex::Direction;
+use crate::graphics::image::{self, PngImage};
+use crate::util::EnumExt;
+
+const TEXT_KEY: &str = "vault13-frm";
+
+/// Writes `frm` as palette-indexed PNG sprite sheet. Color index 0 is transparent.
+pub fn export_png(frm: &Frm, palette: &Palette, wr: &mut impl Write) -> io::Result<()> {
+    let frames = frm.frame_lists.iter().flat_map(|l| &l.frames);
+    let cell_width = frames.clone().map(|f| f.width).max().unwrap_or(0).max(1);
+    let cell_height = frames.map(|f| f.height).max().unwrap_or(0).max(1);
+    let frame_count = frm.frame_lists.iter().map(|l| l.frames.len()).max().unwrap_or(0).max(1);
+    let width = cell_width * frame_count as i32;
+    let height = cell_height * frm.frame_lists.len().max(1) as i32;
+    if width > i16::MAX as i32 || height > i16::MAX as i32 {
+        return Err(Error::new(ErrorKind::InvalidInput,
+            format!("sprite sheet is too big: {}x{}", width, height)));
+    }
+
+    let mut pixels = vec![0; (width * height) as usize];
+    for (row, list) in frm.frame_lists.iter().enumerate() {
+        for (col, frame) in list.frames.iter().enumerate() {
+            let x = col * cell_width as usize;
+            let y = row * cell_height as usize;
+            for (i, src) in frame.pixels.chunks(frame.width.max(1) as usize).enumerate() {
+                let dst = (y + i) * width as usize + x;
+                pixels[dst..dst + src.len()].copy_from_slice(src);
+            }
+        }
+    }
+
+    let mut rgb = Vec::with_capacity(256 * 3);
+    for i in 0..=255 {
+        let c: Rgb24 = palette.rgb(i);
+        rgb.extend_from_slice(&[c.r(), c.g(), c.b()]);
+    }
+
+    let text = metadata(frm, cell_width, cell_height);
+    image::write_indexed_png(wr, width, height, &pixels, &rgb, &[0], &[(TEXT_KEY, &text)])
+}
+
+/// Reads PNG sprite sheet written by `export_png()`. Pixels are quantized to the `palette` and
+/// the ones with alpha below 50% become transparent. Palette-indexed pixels that match the
+/// `palette` color exactly keep their index. PNG without the FRM metadata is imported as a single
+/// frame.
+pub fn import_png(rd: &mut impl Read, palette: &Palette) -> io::Result<Frm> {
+    let image = image::read_png(rd)?;
+    let text = image.text.iter()
+        .find(|(k, _)| k == TEXT_KEY)
+        .map(|(_, v)| v.as_str());
+    let (mut frm, cell_width, cell_height) = if let Some(text) = text {
+        parse_metadata(text)?
+    } else {
+        let frm = Frm {
+            fps: 10,
+            action_frame: 0,
+            frame_lists: vec![FrmList {
+                center: Point::new(0, 0),
+                frames: vec![FrmFrame {
+                    shift: Point::new(0, 0),
+                    width: image.width,
+                    height: image.height,
+                    pixels: Box::new([]),
+                }],
+            }],
+            directions: EnumMap::new(),
+        };
+        (frm, image.width, image.height)
+    };
+
+    for (row, list) in frm.frame_lists.iter_mut().enumerate() {
+        for (col, frame) in list.frames.iter_mut().enumerate() {
+            let x = col as i32 * cell_width;
+            let y = row as i32 * cell_height;
+            if x + frame.width > image.width || y + frame.height > image.height {
+                return Err(Error::new(ErrorKind::InvalidData,
+                    format!("frame {} of row {} is out of the image bounds", col, row)));
+            }
+            frame.pixels = quantize(&image, palette, x, y, frame.width, frame.height);
+        }
+    }
+
+    Ok(frm)
+}
+
+/// Combines single-direction FRMs read from `.fr0`-`.fr5` files (in that order) into one FRM with
+/// a frame list per direction. Each file contributes the frame list of its own direction. FPS and
+/// action frame are taken from the first file.
+pub fn combine_directions(frms: &[Frm]) -> io::Result<Frm> {
+    if frms.len() != Direction::len() {
+        return Err(Error::new(ErrorKind::InvalidInput,
+            format!("expected {} FRMs, got {}", Direction::len(), frms.len())));
+    }
+    let first = &frms[0];
+    let frame_lists = Direction::iter().zip(frms)
+        .map(|(dir, frm)| frm.frame_lists.get(frm.directions[dir]).cloned()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                format!("missing frame list for direction {:?}", dir))))
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(Frm {
+        fps: first.fps,
+        action_frame: first.action_frame,
+        frame_lists,
+        directions: EnumMap::from(|dir: Direction| dir.ordinal()),
+    })
+}
+
+/// Splits `frm` into single-direction FRMs to be written as `.fr0`-`.fr5` files.
+pub fn split_directions(frm: &Frm) -> EnumMap<Direction, Frm> {
+    EnumMap::from(|dir: Direction| Frm {
+        fps: frm.fps,
+        action_frame: frm.action_frame,
+        frame_lists: vec![frm.frame_lists[frm.directions[dir]].clone()],
+        directions: EnumMap::new(),
+    })
+}
+
+fn quantize(image: &PngImage, palette: &Palette, x: i32, y: i32, width: i32, height: i32)
+    -> Box<[u8]>
+{
+    let mut r = Vec::with_capacity((width * height) as usize);
+    for y in y..y + height {
+        for x in x..x + width {
+            let i = (y * image.width + x) as usize;
+            let c = &image.rgba[i * 4..i * 4 + 4];
+            let color_idx = if c[3] < 0x80 {
+                0
+            } else {
+                let rgb = Rgb24::new(c[0], c[1], c[2]);
+                match image.indices.as_ref().map(|v| v[i]) {
+                    Some(idx) if idx != 0 && palette.rgb::<Color8>(idx) == rgb => idx,
+                    _ => palette.color_idx(rgb),
+                }
+            };
+            r.push(color_idx);
+        }
+    }
+    r.into()
+}
+
+fn metadata(frm: &Frm, cell_width: i32, cell_height: i32) -> String {
+    let directions: Vec<_> = frm.directions.values().map(|i| i.to_string()).collect();
+    let mut r = format!("[frm]\n\
+        fps={}\n\
+        action_frame={}\n\
+        directions={}\n\
+        cell_width={}\n\
+        cell_height={}\n",
+        frm.fps, frm.action_frame, directions.join(","), cell_width, cell_height);
+    for (i, list) in frm.frame_lists.iter().enumerate() {
+        r += &format!("[list{}]\ncenter={},{}\n", i, list.center.x, list.center.y);
+        for (j, f) in list.frames.iter().enumerate() {
+            r += &format!("frame{}={},{},{},{}\n", j, f.width, f.height, f.shift.x, f.shift.y);
+        }
+    }
+    r
+}
+
+fn parse_metadata(text: &str) -> io::Result<(Frm, i32, i32)> {
+    fn bad(msg: String) -> Error {
+        Error::new(ErrorKind::InvalidData, format!("invalid FRM metadata: {}", msg))
+    }
+
+    fn get<'a>(section: &'a HashMap<String, String>, key: &str) -> io::Result<&'a str> {
+        section.get(key)
+            .map(|s| s.as_str())
+            .ok_or_else(|| bad(format!("missing {}", key)))
+    }
+
+    fn parse<T: FromStr>(section: &HashMap<String, String>, key: &str) -> io::Result<T> {
+        let s = get(section, key)?;
+        s.parse().map_err(|_| bad(format!("{}={}", key, s)))
+    }
+
+    fn parse_list<T: FromStr>(section: &HashMap<String, String>, key: &str, len: usize)
+        -> io::Result<Vec<T>>
+    {
+        let s = get(section, key)?;
+        let r = s.split(',')
+            .map(|v| v.trim().parse().ok())
+            .collect::<Option<Vec<_>>>()
+            .filter(|v| v.len() == len)
+            .ok_or_else(|| bad(format!("{}={}", key, s)))?;
+        Ok(r)
+    }
+
+    let ini = read_ini(&mut text.as_bytes())?;
+    let frm = ini.get("frm").ok_or_else(|| bad("missing [frm] section".into()))?;
+    let directions: Vec<usize> = parse_list(frm, "directions", Direction::len())?;
+    let cell_width = parse(frm, "cell_width")?;
+    let cell_height = parse(frm, "cell_height")?;
+
+    let mut frame_lists = Vec::new();
+    while let Some(list) = ini.get(&format!("list{}", frame_lists.len())) {
+        let center = parse_list(list, "center", 2)?;
+        let mut frames = Vec::new();
+        while list.contains_key(&format!("frame{}", frames.len())) {
+            let v = parse_list(list, &format!("frame{}", frames.len()), 4)?;
+            if v[0] < 0 || v[1] < 0 || v[0] > cell_width || v[1] > cell_height {
+                return Err(bad(format!("invalid frame size: {}x{}", v[0], v[1])));
+            }
+            frames.push(FrmFrame {
+                shift: Point::new(v[2], v[3]),
+                width: v[0],
+                height: v[1],
+                pixels: Box::new([]),
+            });
+        }
+        frame_lists.push(FrmList {
+            center: Point::new(center[0], center[1]),
+            frames,
+        });
+    }
+    if let Some(&i) = directions.iter().find(|&&i| i >= frame_lists.len()) {
+        return Err(bad(format!("missing [list{}] section", i)));
+    }
+
+    let frm = Frm {
+        fps: parse(frm, "fps")?,
+        action_frame: parse(frm, "action_frame")?,
+        frame_lists,
+        directions: EnumMap::from(|dir: Direction| directions[dir.ordinal()]),
+    };
+    Ok((frm, cell_width, cell_height))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::test::ungz;
+
+    fn palette() -> Palette {
+        let data = ungz(include_bytes!("../../graphics/color/color.pal.gz"));
+        crate::asset::palette::read_palette(&mut std::io::Cursor::new(&data[..])).unwrap()
+    }
+
+    fn frame(width: i32, height: i32, shift: (i32, i32), seed: u8) -> FrmFrame {
+        FrmFrame {
+            shift: Point::new(shift.0, shift.1),
+            width,
+            height,
+            pixels: (0..width * height).map(|i| (i as u8).wrapping_mul(seed)).collect(),
+        }
+    }
+
+    #[test]
+    fn export_import() {
+        let frm = Frm {
+            fps: 8,
+            action_frame: 1,
+            frame_lists: vec![
+                FrmList {
+                    center: Point::new(1, -2),
+                    frames: vec![frame(3, 4, (0, 0), 7), frame(5, 2, (-1, 3), 13)],
+                },
+                FrmList {
+                    center: Point::new(0, 5),
+                    frames: vec![frame(1, 1, (2, 2), 1), frame(4, 4, (0, -7), 31)],
+                },
+            ],
+            directions: EnumMap::from(|dir: Direction| dir.ordinal() % 2),
+        };
+
+        let mut frm_data = Vec::new();
+        frm.write(&mut frm_data).unwrap();
+        assert_eq!(Frm::read(&mut &frm_data[..]).unwrap(), frm);
+
+        let pal = palette();
+        let mut png = Vec::new();
+        export_png(&frm, &pal, &mut png).unwrap();
+        let image = image::read_png(&mut &png[..]).unwrap();
+        assert_eq!((image.width, image.height), (10, 8));
+
+        let actual = import_png(&mut &png[..], &pal).unwrap();
+        assert_eq!(actual.fps, frm.fps);
+        assert_eq!(actual.action_frame, frm.action_frame);
+        assert_eq!(actual.directions, frm.directions);
+        for (actual, expected) in actual.frame_lists.iter().zip(&frm.frame_lists) {
+            assert_eq!(actual.center, expected.center);
+            for (actual, expected) in actual.frames.iter().zip(&expected.frames) {
+                assert_eq!((actual.width, actual.height, actual.shift),
+                    (expected.width, expected.height, expected.shift));
+                for (&a, &e) in actual.pixels.iter().zip(expected.pixels.iter()) {
+                    // Colors duplicated in the palette may map to another index.
+                    assert_eq!(pal.rgb18(a), pal.rgb18(e));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn combine_split_directions() {
+        let single = |center: i32| Frm {
+            fps: 12,
+            action_frame: 2,
+            frame_lists: vec![FrmList {
+                center: Point::new(center, 0),
+                frames: vec![frame(2, 3, (0, 0), center as u8)],
+            }],
+            directions: EnumMap::new(),
+        };
+        let frms: Vec<_> = Direction::iter().map(|dir| single(dir.ordinal() as i32)).collect();
+
+        let frm = combine_directions(&frms).unwrap();
+        assert_eq!((frm.fps, frm.action_frame), (12, 2));
+        assert_eq!(frm.frame_lists.len(), Direction::len());
+        for dir in Direction::iter() {
+            assert_eq!(frm.frame_lists[frm.directions[dir]], frms[dir.ordinal()].frame_lists[0]);
+        }
+
+        let pal = palette();
+        let mut png = Vec::new();
+        export_png(&frm, &pal, &mut png).unwrap();
+        let image = image::read_png(&mut &png[..]).unwrap();
+        assert_eq!((image.width, image.height), (2, 3 * Direction::len() as i32));
+        let imported = import_png(&mut &png[..], &pal).unwrap();
+
+        let split = split_directions(&imported);
+        for dir in Direction::iter() {
+            let mut data = Vec::new();
+            split[dir].write(&mut data).unwrap();
+            let actual = Frm::read(&mut &data[..]).unwrap();
+            assert_eq!((actual.fps, actual.action_frame), (12, 2));
+            assert_eq!(actual.frame_lists.len(), 1);
+            let expected = &frms[dir.ordinal()].frame_lists[0];
+            assert_eq!(actual.frame_lists[0].center, expected.center);
+            let (a, e) = (&actual.frame_lists[0].frames[0], &expected.frames[0]);
+            assert_eq!((a.width, a.height, a.shift), (e.width, e.height, e.shift));
+        }
+    }
+
+    #[test]
+    fn import_plain_png() {
+        let pal = palette();
+        let rgb = [
+            255, 0, 0,      0, 0, 255,
+            0, 255, 0,      255, 255, 255,
+        ];
+        let mut png = Vec::new();
+        image::ImageFormat::Png.write(&mut png, 2, 2, &rgb).unwrap();
+
+        let frm = import_png(&mut &png[..], &pal).unwrap();
+        assert_eq!(frm.frame_lists.len(), 1);
+        let frame = &frm.frame_lists[0].frames[0];
+        assert_eq!((frame.width, frame.height), (2, 2));
+        let expected: Vec<_> = rgb.chunks(3)
+            .map(|c| pal.color_idx(Rgb24::new(c[0], c[1], c[2])))
+            .collect();
+        assert_eq!(&frame.pixels[..], &expected[..]);
+    }
+}
@@ -1,11 +1,13 @@
 pub mod db;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use bstring::BString;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use enumflags2::BitFlags;
 use enumflags2_derive::EnumFlags;
 use log::*;
 use measure_time::*;
 use num_traits::FromPrimitive;
+use slotmap::SecondaryMap;
 use std::cmp;
 use std::convert::{TryFrom, TryInto};
 use std::io::{self, Error, ErrorKind, prelude::*};
@@ -29,6 +31,7 @@ struct ScriptInfo {
     program_id: ProgramId,
     local_var_count: usize,
     local_var_offset: usize,
+    spatial: Option<Spatial>,
 }
 
 #[derive(Clone, Copy, Debug, Enum, EnumFlags, Eq, PartialEq)]
@@ -49,9 +52,15 @@ pub type SqrTiles = Vec<Option<Array2d<(u16, u16)>>>;
 /// Unique map ID as defined in `maps.txt`.
 pub type MapId = u32;
 
+#[derive(Clone)]
 pub struct Map {
+    pub version: u32,
+    pub name: BString,
     pub id: MapId,
     pub savegame: bool,
+    pub program_id: Option<ProgramId>,
+    /// Game time when the map was last saved.
+    pub time: u32,
     pub entrance: EPoint,
     pub entrance_direction: Direction,
     pub sqr_tiles: SqrTiles,
@@ -74,13 +83,17 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         let version = self.reader.read_u32::<BigEndian>()?;
 
         let mut name = [0; 16];
-        self.reader.read_exact(&mut name[..]).unwrap();
+        self.reader.read_exact(&mut name[..])?;
+        let name = BString::from(name.iter().take_while(|&&c| c != 0).cloned().collect::<Vec<_>>());
 
         let entrance_pos_lin = self.reader.read_i32::<BigEndian>()?;
         let entrance_pos = TileGrid::default().from_linear_inv(entrance_pos_lin as u32);
         debug!("entrance_pos={} ({:?})", entrance_pos_lin, entrance_pos);
         let entrance_elevation = self.reader.read_u32::<BigEndian>()?;
-        assert!(entrance_elevation <= ELEVATION_COUNT);
+        if entrance_elevation > ELEVATION_COUNT {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("invalid entrance elevation: {}", entrance_elevation)));
+        }
         let entrance_direction = Direction::from_u32(self.reader.read_u32::<BigEndian>()?)
             .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid entrance direction"))?;
         let local_var_count = cmp::max(self.reader.read_i32::<BigEndian>()?, 0) as usize;
@@ -94,8 +107,10 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
 
         let _ = self.reader.read_i32::<BigEndian>()?;
         let map_var_count = cmp::max(self.reader.read_i32::<BigEndian>()?, 0) as usize;
-        let id = self.reader.read_i32::<BigEndian>()?.try_into().unwrap();
-        let _time = self.reader.read_u32::<BigEndian>()?;
+        let id = self.reader.read_i32::<BigEndian>()?;
+        let id = id.try_into()
+            .map_err(|_| Error::new(ErrorKind::InvalidData, format!("invalid map id: {}", id)))?;
+        let time = self.reader.read_u32::<BigEndian>()?;
 
         self.reader.read_exact(&mut [0; 44 * 4][..])?;
 
@@ -123,8 +138,12 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         self.read_objects(version)?;
 
         Ok(Map {
+            version,
+            name,
             id,
             savegame,
+            program_id,
+            time,
             entrance: EPoint {
                 elevation: entrance_elevation,
                 point: entrance_pos,
@@ -168,6 +187,7 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
                             None
                         };
                         self.scripts.instantiate(script.sid, script.program_id, local_vars)?;
+                        self.scripts.get_mut(script.sid).unwrap().spatial = script.spatial;
                     }
                 }
             }
@@ -192,16 +212,20 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
 
         let _ = self.reader.read_i32::<BigEndian>()?;
 
-        match sid.kind() {
+        let spatial = match sid.kind() {
             ScriptKind::Spatial => {
-                let _elevation_and_tile = self.reader.read_i32::<BigEndian>()?;
-                let _spatial_radius = self.reader.read_i32::<BigEndian>()?;
+                let elevation_and_tile = self.reader.read_u32::<BigEndian>()?;
+                let radius = cmp::max(self.reader.read_i32::<BigEndian>()?, 0) as u32;
+                let pos = TileGrid::default().from_linear_inv(elevation_and_tile & 0x3ffffff)
+                    .elevated(elevation_and_tile >> 29);
+                Some(Spatial { pos, radius })
             }
             ScriptKind::Time => {
                 let _elevation_and_tile = self.reader.read_i32::<BigEndian>()?;
+                None
             }
-            _ => {}
-        }
+            _ => None,
+        };
 
         let _flags = self.reader.read_i32::<BigEndian>()?;
 
@@ -228,6 +252,7 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
                 program_id,
                 local_var_count,
                 local_var_offset,
+                spatial,
             }))
         } else {
             Ok(None)
@@ -253,7 +278,7 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         Ok(())
     }
 
-    fn read_object(&mut self, f2: bool) -> io::Result<Object> {
+    pub fn read_object(&mut self, f2: bool) -> io::Result<Object> {
         let id = self.reader.read_u32::<BigEndian>()?;
 
         trace!("object ID {}", id);
@@ -350,11 +375,13 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
                     let proto = proto.borrow();
                     match proto.sub.as_item().unwrap().sub {
                         SubItem::Weapon(ref proto) => {
-                            let _ammo_count = self.reader.read_i32::<BigEndian>()?;
+                            let ammo_count = self.reader.read_i32::<BigEndian>()?;
                             let ammo_proto_id = ProtoId::from_packed(self.reader.read_u32::<BigEndian>()?);
 
                             // object_fix_weapon_ammo()
-                            let ammo_count = proto.max_ammo_count;
+                            let ammo_count = ammo_count.try_into()
+                                .map(|v| cmp::min(v, proto.max_ammo_count))
+                                .unwrap_or(proto.max_ammo_count);
                             let ammo_proto_id = ammo_proto_id.or(proto.ammo_proto_id);
                             let ammo_proto = if let Some(pid) = ammo_proto_id {
                                 Some(self.proto_db.proto(pid)?)
@@ -364,7 +391,10 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
                             SubObject::Item(object::Item { ammo_count, ammo_proto })
                         }
                         SubItem::Ammo(_) => {
-                            let ammo_count = self.reader.read_i32::<BigEndian>()?.try_into().unwrap();
+                            let ammo_count = self.reader.read_i32::<BigEndian>()?;
+                            let ammo_count = ammo_count.try_into()
+                                .map_err(|_| Error::new(ErrorKind::InvalidData,
+                                    format!("invalid ammo count: {}", ammo_count)))?;
                             SubObject::Item(object::Item { ammo_count, ammo_proto: None })
                         }
                         SubItem::Misc(ref proto) => {
@@ -372,12 +402,13 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
 
                             // object_fix_weapon_ammo()
                             let ammo_count = ammo_count.try_into()
+                                .map(|v| cmp::min(v, proto.max_ammo_count))
                                 .unwrap_or(proto.max_ammo_count);
                             SubObject::Item(object::Item { ammo_count, ammo_proto: None })
                         }
                         SubItem::Key(_) => {
-                            let _key_code = self.reader.read_i32::<BigEndian>()?;
-                            SubObject::None
+                            let id = self.reader.read_i32::<BigEndian>()?;
+                            SubObject::Key(object::Key { id })
                         }
                         _ => SubObject::None
                     }
@@ -427,7 +458,10 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
                         // Exit area.
                         let map = self.reader.read_i32::<BigEndian>()?;
                         trace!("map={}", map);
-                        assert!(map >= 0 || fid.idx() >= 33);
+                        if map < 0 && fid.idx() < 33 {
+                            return Err(Error::new(ErrorKind::InvalidData,
+                                format!("invalid exit area map: {}", map)));
+                        }
                         let map = TargetMap::decode(map)
                             .ok_or_else(|| Error::new(ErrorKind::InvalidData,
                                 format!("invalid exit target map: {}", map)))?;
@@ -465,7 +499,10 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         };
         for i in 0..inventory_len {
             trace!("loading inventory item {}/{}", i, inventory_len);
-            let count = self.reader.read_i32::<BigEndian>()?.try_into().unwrap();
+            let count = self.reader.read_i32::<BigEndian>()?;
+            let count = count.try_into()
+                .map_err(|_| Error::new(ErrorKind::InvalidData,
+                    format!("invalid inventory item count: {}", count)))?;
             trace!("item count: {}", count);
             let object = self.read_object(f2)?;
             let object = self.objects.insert(object);
@@ -511,42 +548,7 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
     }
 
     fn read_outline(&mut self) -> io::Result<Option<Outline>> {
-        let flags_u32 = self.reader.read_u32::<BigEndian>()?;
-        let flags = &mut BitFlags::from_bits(flags_u32)
-            .ok_or_else(|| Error::new(ErrorKind::InvalidData,
-                format!("unknown object outline flags: {:x}", flags_u32)))?;
-
-        fn take_bit(flags: &mut BitFlags<OutlineFlag>, flag: OutlineFlag) -> bool {
-            let r = flags.contains(flag);
-            flags.remove(flag);
-            r
-        }
-
-        let translucent = take_bit(flags, OutlineFlag::Translucent);
-        let disabled = take_bit(flags, OutlineFlag::Translucent);
-
-        let style =
-            if take_bit(flags, OutlineFlag::GlowingRed) { OutlineStyle::GlowingRed }
-            else if take_bit(flags, OutlineFlag::Red) { OutlineStyle::Red }
-            else if take_bit(flags, OutlineFlag::Gray) { OutlineStyle::Gray }
-            else if take_bit(flags, OutlineFlag::GlowingGreen) { OutlineStyle::GlowingGreen }
-            else if take_bit(flags, OutlineFlag::Yellow) { OutlineStyle::Yellow }
-            else if take_bit(flags, OutlineFlag::Brown) { OutlineStyle::Brown }
-            else { return Ok(None) };
-        if !flags.is_empty() {
-            warn!("mutually exclusive outline flags present: 0x{:x}", flags_u32);
-            return Ok(Some(Outline {
-                style: OutlineStyle::Purple,
-                translucent: false,
-                disabled: false,
-            }));
-        }
-
-        Ok(Some(Outline {
-            style,
-            translucent,
-            disabled,
-        }))
+        decode_outline(self.reader.read_u32::<BigEndian>()?)
     }
 
     fn make_map_script(&mut self, program_id: ProgramId) -> io::Result<()> {
@@ -568,23 +570,848 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
     }
 
     fn read_sqr_tiles(&mut self, flags: u32) -> io::Result<SqrTiles> {
-        let mut sqr_tiles: Vec<Option<_>> = Vec::with_capacity(ELEVATION_COUNT as usize);
-        for i in 0..ELEVATION_COUNT {
-            if flags & (1 << (i as u32 + 1)) != 0 {
-                debug!("no {} elevation", i);
-                sqr_tiles.push(None);
+        read_sqr_tiles(self.reader, flags)
+    }
+}
+
+/// Writes map in the format understood by `MapReader`. Used to persist the state of visited maps
+/// in savegame `.SAV` files.
+pub struct MapWriter<'a, W: 'a> {
+    pub writer: &'a mut W,
+    pub objects: &'a Objects,
+    pub scripts: &'a Scripts,
+}
+
+impl<'a, W: 'a + Write> MapWriter<'a, W> {
+    pub fn write(&mut self, map: &Map) -> io::Result<()> {
+        debug_time!("MapWriter::write()");
+
+        let roots = self.root_objects();
+        let mut obj_ids = SecondaryMap::new();
+        for objs in &roots {
+            for &obj in objs {
+                self.assign_obj_ids(obj, &mut obj_ids);
+            }
+        }
+
+        let (scripts, local_vars) = self.collect_scripts();
+
+        // header
+
+        self.writer.write_u32::<BigEndian>(map.version)?;
+
+        let mut name = [0; 16];
+        let len = cmp::min(map.name.len(), name.len() - 1);
+        name[..len].copy_from_slice(&map.name.as_bytes()[..len]);
+        self.writer.write_all(&name[..])?;
+
+        let entrance_pos_lin = TileGrid::default().to_linear_inv(map.entrance.point)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "invalid entrance position"))?;
+        self.writer.write_i32::<BigEndian>(entrance_pos_lin as i32)?;
+        self.writer.write_u32::<BigEndian>(map.entrance.elevation)?;
+        self.writer.write_u32::<BigEndian>(map.entrance_direction as u32)?;
+        self.writer.write_i32::<BigEndian>(local_vars.len() as i32)?;
+        self.write_program_id(map.program_id, 0)?;
+
+        let mut flags = 0;
+        if map.savegame {
+            flags |= 0x1;
+        }
+        for (i, tiles) in map.sqr_tiles.iter().enumerate() {
+            if tiles.is_none() {
+                flags |= 1 << (i as u32 + 1);
+            }
+        }
+        self.writer.write_u32::<BigEndian>(flags)?;
+
+        self.writer.write_i32::<BigEndian>(0)?;
+        self.writer.write_i32::<BigEndian>(map.map_vars.len() as i32)?;
+        self.writer.write_i32::<BigEndian>(map.id as i32)?;
+        self.writer.write_u32::<BigEndian>(map.time)?;
+
+        self.writer.write_all(&[0; 44 * 4][..])?;
+
+        // map global vars
+
+        for &v in map.map_vars.iter() {
+            self.writer.write_i32::<BigEndian>(v)?;
+        }
+
+        // map local vars
+
+        for &v in &local_vars {
+            self.writer.write_i32::<BigEndian>(v)?;
+        }
+
+        self.write_sqr_tiles(&map.sqr_tiles)?;
+        self.write_scripts(&scripts, &obj_ids)?;
+        self.write_objects(&roots, &obj_ids, map.version != 19)?;
+
+        Ok(())
+    }
+
+    /// Objects attached to tiles grouped by elevation. Excludes objects that are never saved
+    /// with the map: the dude, the map script object and temporary objects.
+    fn root_objects(&self) -> Vec<Vec<Handle>> {
+        let map_script_obj = self.scripts.map_sid()
+            .and_then(|sid| self.scripts.get(sid))
+            .and_then(|s| s.object);
+        let mut r = vec![Vec::new(); ELEVATION_COUNT as usize];
+        for h in self.objects.iter() {
+            let obj = self.objects.get(h);
+            if obj.is_dude() || Some(h) == map_script_obj || obj.flags.contains(Flag::Temp) {
                 continue;
             }
-            let mut tiles = Array2d::with_default(100, 100);
-            for y in 0..tiles.height() {
-                for x in (0..tiles.width()).rev() {
-                    let roof_id = self.reader.read_u16::<BigEndian>()?;
-                    let floor_id = self.reader.read_u16::<BigEndian>()?;
-                    *tiles.get_mut(x, y).unwrap() = (floor_id, roof_id);
+            if let Some(pos) = obj.try_pos() {
+                r[pos.elevation as usize].push(h);
+            }
+        }
+        r
+    }
+
+    fn assign_obj_ids(&self, obj: Handle, obj_ids: &mut SecondaryMap<Handle, u32>) {
+        let id = obj_ids.len() as u32 + 1;
+        obj_ids.insert(obj, id);
+        for item in &self.objects.get(obj).inventory.items {
+            self.assign_obj_ids(item.object, obj_ids);
+        }
+    }
+
+    fn collect_scripts(&self) -> (Vec<ScriptInfo>, Vec<i32>) {
+        let map_sid = self.scripts.map_sid();
+        let mut sids: Vec<_> = self.scripts.iter()
+            .map(|(sid, _)| sid)
+            .filter(|&sid| Some(sid) != map_sid)
+            .collect();
+        sids.sort_by_key(|sid| (sid.kind(), sid.id()));
+
+        let mut scripts = Vec::with_capacity(sids.len());
+        let mut local_vars = Vec::new();
+        for sid in sids {
+            let script = self.scripts.get(sid).unwrap();
+            scripts.push(ScriptInfo {
+                sid,
+                program_id: script.program_id,
+                local_var_count: script.local_vars.len(),
+                local_var_offset: local_vars.len(),
+                spatial: script.spatial,
+            });
+            local_vars.extend_from_slice(&script.local_vars);
+        }
+        (scripts, local_vars)
+    }
+
+    fn write_scripts(&mut self, scripts: &[ScriptInfo],
+        obj_ids: &SecondaryMap<Handle, u32>) -> io::Result<()>
+    {
+        const NODE_LEN: usize = 16;
+
+        for script_kind in ScriptKind::iter() {
+            let scripts: Vec<_> = scripts.iter()
+                .filter(|s| s.sid.kind() == script_kind)
+                .collect();
+            self.writer.write_i32::<BigEndian>(scripts.len() as i32)?;
+            for node in scripts.chunks(NODE_LEN) {
+                for &script in node {
+                    let obj_id = self.scripts.get(script.sid).unwrap().object
+                        .and_then(|h| obj_ids.get(h))
+                        .map(|&id| id as i32)
+                        .unwrap_or(-1);
+                    self.write_script(script, obj_id)?;
+                }
+                for _ in node.len()..NODE_LEN {
+                    // Unused slot. Must not parse as a valid SID.
+                    self.writer.write_u32::<BigEndian>(0xcccccccc)?;
+                    self.writer.write_all(&[0; 15 * 4][..])?;
+                }
+                self.writer.write_i32::<BigEndian>(node.len() as i32)?;
+                self.writer.write_i32::<BigEndian>(0)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_script(&mut self, script: &ScriptInfo, self_obj_id: i32) -> io::Result<()> {
+        self.writer.write_u32::<BigEndian>(script.sid.pack())?;
+        self.writer.write_i32::<BigEndian>(-1)?;
+
+        match script.sid.kind() {
+            ScriptKind::Spatial => {
+                let (elevation_and_tile, radius) = if let Some(spatial) = script.spatial {
+                    let tile = TileGrid::default().to_linear_inv(spatial.pos.point)
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                            format!("invalid spatial script position: {:?}", spatial.pos)))?;
+                    ((spatial.pos.elevation << 29 | tile) as i32, spatial.radius as i32)
+                } else {
+                    (-1, 0)
+                };
+                self.writer.write_i32::<BigEndian>(elevation_and_tile)?;
+                self.writer.write_i32::<BigEndian>(radius)?;
+            }
+            ScriptKind::Time => {
+                self.writer.write_i32::<BigEndian>(-1)?;
+            }
+            _ => {}
+        }
+
+        self.writer.write_i32::<BigEndian>(0)?;
+        self.write_program_id(Some(script.program_id), 1)?;
+        self.writer.write_i32::<BigEndian>(-1)?;
+        self.writer.write_i32::<BigEndian>(self_obj_id)?;
+        self.writer.write_i32::<BigEndian>(script.local_var_offset as i32)?;
+        self.writer.write_i32::<BigEndian>(script.local_var_count as i32)?;
+        for _ in 0..8 {
+            self.writer.write_i32::<BigEndian>(0)?;
+        }
+        Ok(())
+    }
+
+    fn write_objects(&mut self, roots: &[Vec<Handle>], obj_ids: &SecondaryMap<Handle, u32>, f2: bool)
+        -> io::Result<()>
+    {
+        let total_obj_count = roots.iter().map(|v| v.len()).sum::<usize>();
+        self.writer.write_i32::<BigEndian>(total_obj_count as i32)?;
+        for objs in roots {
+            self.writer.write_u32::<BigEndian>(objs.len() as u32)?;
+            for &obj in objs {
+                self.write_object(obj, obj_ids.get(obj).cloned().unwrap_or(0), f2)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes object and its inventory recursively. Inventory items get consecutive IDs
+    /// following the `id`.
+    pub fn write_object(&mut self, objh: Handle, id: u32, f2: bool) -> io::Result<()> {
+        let obj = self.objects.get(objh);
+
+        self.writer.write_u32::<BigEndian>(id)?;
+
+        let pos = obj.try_pos();
+        let pos_lin = pos
+            .and_then(|p| TileGrid::default().to_linear_inv(p.point))
+            .map(|v| v as i32)
+            .unwrap_or(-1);
+        self.writer.write_i32::<BigEndian>(pos_lin)?;
+
+        self.writer.write_i32::<BigEndian>(obj.screen_shift.x)?;
+        self.writer.write_i32::<BigEndian>(obj.screen_shift.y)?;
+        self.writer.write_i32::<BigEndian>(obj.screen_pos.x)?;
+        self.writer.write_i32::<BigEndian>(obj.screen_pos.y)?;
+        self.writer.write_i32::<BigEndian>(obj.frame_idx as i32)?;
+        self.writer.write_u32::<BigEndian>(obj.direction as u32)?;
+        self.writer.write_u32::<BigEndian>(obj.fid.packed())?;
+        self.writer.write_u32::<BigEndian>(obj.flags.bits())?;
+        self.writer.write_u32::<BigEndian>(pos.map(|p| p.elevation).unwrap_or(0))?;
+        let pid = obj.proto_id()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                format!("can't write object without proto: {:?}", obj.fid)))?;
+        self.writer.write_u32::<BigEndian>(pid.pack())?;
+        self.writer.write_i32::<BigEndian>(-1)?;
+        let light_emitter = obj.light_emitter();
+        self.writer.write_i32::<BigEndian>(light_emitter.radius as i32)?;
+        self.writer.write_i32::<BigEndian>(light_emitter.intensity as i32)?;
+        self.write_outline(obj.outline)?;
+
+        if let Some((sid, program_id)) = obj.script {
+            self.writer.write_u32::<BigEndian>(sid.pack())?;
+            self.write_program_id(Some(program_id), 1)?;
+        } else {
+            self.writer.write_i32::<BigEndian>(-1)?;
+            self.writer.write_i32::<BigEndian>(-1)?;
+        }
+
+        // proto update data
+
+        let inventory_len = obj.inventory.items.len();
+        self.writer.write_u32::<BigEndian>(inventory_len as u32)?;
+        self.writer.write_i32::<BigEndian>(inventory_len as i32)?;
+        self.writer.write_u32::<BigEndian>(0)?;
+
+        self.writer.write_u32::<BigEndian>(obj.updated_flags.bits())?;
+
+        fn bad_sub(obj: &Object) -> Error {
+            Error::new(ErrorKind::InvalidInput,
+                format!("object data doesn't match its proto: {:?} {:?}", obj.proto_id(), obj.sub))
+        }
+
+        match pid.kind() {
+            EntityKind::Critter => {
+                let critter = obj.sub.as_critter().ok_or_else(|| bad_sub(&obj))?;
+
+                // combat data
+                self.writer.write_u32::<BigEndian>(0)?;
+                self.writer.write_u32::<BigEndian>(0)?;
+                self.writer.write_u32::<BigEndian>(0)?;
+
+                self.writer.write_u32::<BigEndian>(critter.combat.damage_flags.bits())?;
+                self.writer.write_i32::<BigEndian>(critter.combat.ai_packet)?;
+                self.writer.write_i32::<BigEndian>(critter.combat.team_id)?;
+                self.writer.write_i32::<BigEndian>(critter.combat.who_hit_me)?;
+
+                self.writer.write_i32::<BigEndian>(critter.hit_points)?;
+                self.writer.write_i32::<BigEndian>(critter.radiation)?;
+                self.writer.write_i32::<BigEndian>(critter.poison)?;
+            }
+            EntityKind::Item => {
+                let proto = obj.proto().unwrap();
+                match proto.sub.as_item().unwrap().sub {
+                    SubItem::Weapon(_) => {
+                        let item = obj.sub.as_item().ok_or_else(|| bad_sub(&obj))?;
+                        self.writer.write_i32::<BigEndian>(item.ammo_count as i32)?;
+                        let ammo_pid = item.ammo_proto.as_ref()
+                            .map(|p| p.borrow().id().pack() as i32)
+                            .unwrap_or(-1);
+                        self.writer.write_i32::<BigEndian>(ammo_pid)?;
+                    }
+                    SubItem::Ammo(_) | SubItem::Misc(_) => {
+                        let item = obj.sub.as_item().ok_or_else(|| bad_sub(&obj))?;
+                        self.writer.write_i32::<BigEndian>(item.ammo_count as i32)?;
+                    }
+                    SubItem::Key(ref proto) => {
+                        let key_code = obj.sub.as_key().map(|k| k.id).unwrap_or(proto.id);
+                        self.writer.write_i32::<BigEndian>(key_code)?;
+                    }
+                    _ => {}
+                }
+            }
+            EntityKind::Scenery => {
+                let kind = obj.proto().unwrap().sub.kind().scenery().unwrap();
+                let scenery = || obj.sub.as_scenery().ok_or_else(|| bad_sub(&obj));
+                match kind {
+                    SceneryKind::Door => {
+                        let door = scenery()?.as_door().ok_or_else(|| bad_sub(&obj))?;
+                        self.writer.write_u32::<BigEndian>(door.flags.bits())?;
+                    }
+                    SceneryKind::Stairs => {
+                        let exit = scenery()?.as_stairs().ok_or_else(|| bad_sub(&obj))?;
+                        let (map, location) = exit.encode();
+                        self.writer.write_u32::<BigEndian>(location)?;
+                        self.writer.write_i32::<BigEndian>(map)?;
+                    }
+                    SceneryKind::Elevator => {
+                        let elevator = scenery()?.as_elevator().ok_or_else(|| bad_sub(&obj))?;
+                        self.writer.write_u32::<BigEndian>(elevator.kind)?;
+                        self.writer.write_u32::<BigEndian>(elevator.level)?;
+                    }
+                    SceneryKind::LadderDown | SceneryKind::LadderUp => {
+                        let exit = scenery()?.as_ladder().ok_or_else(|| bad_sub(&obj))?;
+                        let (map, location) = exit.encode();
+                        if f2 {
+                            self.writer.write_i32::<BigEndian>(map)?;
+                        }
+                        self.writer.write_u32::<BigEndian>(location)?;
+                    }
+                    SceneryKind::Misc => {}
+                }
+            }
+            EntityKind::Misc => {
+                if pid.is_exit_area() {
+                    let exit = obj.sub.as_map_exit().ok_or_else(|| bad_sub(&obj))?;
+                    let map = exit.map.encode()
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                            format!("invalid exit target map: {:?}", exit.map)))?;
+                    self.writer.write_i32::<BigEndian>(map)?;
+                    let pos = TileGrid::default().to_linear_inv(exit.pos.point)
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                            format!("invalid exit position: {:?}", exit.pos)))?;
+                    self.writer.write_u32::<BigEndian>(pos)?;
+                    self.writer.write_u32::<BigEndian>(exit.pos.elevation)?;
+                    self.writer.write_u32::<BigEndian>(exit.direction as u32)?;
                 }
             }
-            sqr_tiles.push(Some(tiles));
+            _ => {}
+        }
+
+        // inventory
+
+        let mut next_id = id + 1;
+        for item in &obj.inventory.items {
+            self.writer.write_i32::<BigEndian>(item.count as i32)?;
+            self.write_object(item.object, next_id, f2)?;
+            next_id += self.object_count(item.object);
+        }
+
+        Ok(())
+    }
+
+    fn object_count(&self, obj: Handle) -> u32 {
+        1 + self.objects.get(obj).inventory.items.iter()
+            .map(|item| self.object_count(item.object))
+            .sum::<u32>()
+    }
+
+    fn write_outline(&mut self, outline: Option<Outline>) -> io::Result<()> {
+        self.writer.write_u32::<BigEndian>(encode_outline(outline))
+    }
+
+    fn write_program_id(&mut self, program_id: Option<ProgramId>, offset: i32) -> io::Result<()> {
+        let v = program_id.map(|v| v.val() as i32 - offset).unwrap_or(-1);
+        self.writer.write_i32::<BigEndian>(v)
+    }
+
+    fn write_sqr_tiles(&mut self, sqr_tiles: &SqrTiles) -> io::Result<()> {
+        write_sqr_tiles(self.writer, sqr_tiles)
+    }
+}
+
+fn decode_outline(flags_u32: u32) -> io::Result<Option<Outline>> {
+    let flags = &mut BitFlags::from_bits(flags_u32)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+            format!("unknown object outline flags: {:x}", flags_u32)))?;
+
+    fn take_bit(flags: &mut BitFlags<OutlineFlag>, flag: OutlineFlag) -> bool {
+        let r = flags.contains(flag);
+        flags.remove(flag);
+        r
+    }
+
+    let translucent = take_bit(flags, OutlineFlag::Translucent);
+    let disabled = take_bit(flags, OutlineFlag::Disabled);
+
+    let style =
+        if take_bit(flags, OutlineFlag::GlowingRed) { OutlineStyle::GlowingRed }
+        else if take_bit(flags, OutlineFlag::Red) { OutlineStyle::Red }
+        else if take_bit(flags, OutlineFlag::Gray) { OutlineStyle::Gray }
+        else if take_bit(flags, OutlineFlag::GlowingGreen) { OutlineStyle::GlowingGreen }
+        else if take_bit(flags, OutlineFlag::Yellow) { OutlineStyle::Yellow }
+        else if take_bit(flags, OutlineFlag::Brown) { OutlineStyle::Brown }
+        else { return Ok(None) };
+    if !flags.is_empty() {
+        warn!("mutually exclusive outline flags present: 0x{:x}", flags_u32);
+        return Ok(Some(Outline {
+            style: OutlineStyle::Purple,
+            translucent: false,
+            disabled: false,
+        }));
+    }
+
+    Ok(Some(Outline {
+        style,
+        translucent,
+        disabled,
+    }))
+}
+
+fn encode_outline(outline: Option<Outline>) -> u32 {
+    let mut flags = BitFlags::empty();
+    if let Some(outline) = outline {
+        flags = match outline.style {
+            OutlineStyle::GlowingRed => OutlineFlag::GlowingRed.into(),
+            OutlineStyle::Red => OutlineFlag::Red.into(),
+            OutlineStyle::Gray => OutlineFlag::Gray.into(),
+            OutlineStyle::GlowingGreen => OutlineFlag::GlowingGreen.into(),
+            OutlineStyle::Yellow => OutlineFlag::Yellow.into(),
+            OutlineStyle::Brown => OutlineFlag::Brown.into(),
+            // Purple is what mutually exclusive flags are decoded into.
+            OutlineStyle::Purple => OutlineFlag::GlowingRed | OutlineFlag::Red,
+        };
+        if outline.translucent {
+            flags |= OutlineFlag::Translucent;
+        }
+        if outline.disabled {
+            flags |= OutlineFlag::Disabled;
+        }
+    }
+    flags.bits()
+}
+
+fn read_sqr_tiles(rd: &mut impl Read, flags: u32) -> io::Result<SqrTiles> {
+    let mut sqr_tiles: Vec<Option<_>> = Vec::with_capacity(ELEVATION_COUNT as usize);
+    for i in 0..ELEVATION_COUNT {
+        if flags & (1 << (i as u32 + 1)) != 0 {
+            debug!("no {} elevation", i);
+            sqr_tiles.push(None);
+            continue;
+        }
+        let mut tiles = Array2d::with_default(100, 100);
+        for y in 0..tiles.height() {
+            for x in (0..tiles.width()).rev() {
+                let roof_id = rd.read_u16::<BigEndian>()?;
+                let floor_id = rd.read_u16::<BigEndian>()?;
+                *tiles.get_mut(x, y).unwrap() = (floor_id, roof_id);
+            }
+        }
+        sqr_tiles.push(Some(tiles));
+    }
+    Ok(sqr_tiles)
+}
+
+fn write_sqr_tiles(w: &mut impl Write, sqr_tiles: &SqrTiles) -> io::Result<()> {
+    for tiles in sqr_tiles.iter().filter_map(|t| t.as_ref()) {
+        for y in 0..tiles.height() {
+            for x in (0..tiles.width()).rev() {
+                let &(floor_id, roof_id) = tiles.get(x, y).unwrap();
+                w.write_u16::<BigEndian>(roof_id)?;
+                w.write_u16::<BigEndian>(floor_id)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use std::rc::Rc;
+    use enum_map::EnumMap;
+    use crate::asset::frame::{Frm, FrmFrame, FrmList};
+    use crate::asset::proto::WorldMapKind;
+    use crate::asset::script::db::ScriptDb;
+    use crate::fs::FileSystem;
+    use crate::graphics::color::palette::overlay::PaletteOverlay;
+    use crate::graphics::render::software::Backend;
+    use crate::util::test::ungz;
+    use crate::vm::Vm;
+
+    struct Dbs {
+        proto_db: Rc<ProtoDb>,
+        frm_db: Rc<FrameDb>,
+        fs: Rc<FileSystem>,
+    }
+
+    impl Dbs {
+        fn new_objects(&self) -> Objects {
+            Objects::new(TileGrid::default(), ELEVATION_COUNT,
+                self.frm_db.clone(), self.proto_db.clone())
+        }
+
+        fn new_scripts(&self) -> Scripts {
+            Scripts::new(self.proto_db.clone(),
+                ScriptDb::new(self.fs.clone(), "english").unwrap(), Vm::default())
+        }
+
+        fn create(&self, objects: &mut Objects, pid: u32, pos: Option<EPoint>) -> Handle {
+            let proto = self.proto_db.proto(ProtoId::from_packed(pid).unwrap()).unwrap();
+            objects.create(None, Some(proto), pos, None).handle()
+        }
+
+        fn read(&self, data: &[u8], objects: &mut Objects, scripts: &mut Scripts)
+            -> io::Result<Map>
+        {
+            MapReader {
+                reader: &mut &data[..],
+                objects,
+                proto_db: &self.proto_db,
+                frm_db: &self.frm_db,
+                scripts,
+            }.read()
+        }
+    }
+
+    fn write_map(map: &Map, objects: &Objects, scripts: &Scripts) -> Vec<u8> {
+        let mut r = Vec::new();
+        MapWriter { writer: &mut r, objects, scripts }.write(map).unwrap();
+        r
+    }
+
+    fn new_map() -> Map {
+        Map {
+            version: 20,
+            name: "TEST.MAP".into(),
+            id: 3,
+            savegame: true,
+            program_id: None,
+            time: 1234,
+            entrance: EPoint::new(1, Point::new(10, 20)),
+            entrance_direction: Direction::SE,
+            sqr_tiles: vec![None, None, None],
+            map_vars: vec![1, 2, 3].into(),
+        }
+    }
+
+    /// Writes minimal game data with misc item (PID 1), key (PID 2), ammo (PID 3) and exit area
+    /// (PID 0x5000010) protos, FRMs for them and a script `test.int` with 2 local vars.
+    fn write_data(dir: &Path) -> Dbs {
+        fn write(dir: &Path, path: &str, data: &[u8]) {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, data).unwrap();
+        }
+
+        fn item_proto(pid: u32, kind: ItemKind, data: &[i32]) -> Vec<u8> {
+            let mut r = Vec::new();
+            for &v in &[pid as i32, 100, 0, 0, 0, 0, 0, -1, kind as i32, 0, 1, 1, 1, -1] {
+                r.write_i32::<BigEndian>(v).unwrap();
+            }
+            r.push(0);
+            for &v in data {
+                r.write_i32::<BigEndian>(v).unwrap();
+            }
+            r
+        }
+
+        for kind in EntityKind::iter() {
+            let lst = match kind {
+                EntityKind::Item | EntityKind::Misc => "test.frm\n",
+                _ => "",
+            };
+            write(dir, &format!("art/{0}/{0}.lst", kind.dir()), lst.as_bytes());
+        }
+        let mut frm = Vec::new();
+        Frm {
+            fps: 10,
+            action_frame: 0,
+            frame_lists: vec![FrmList {
+                center: Point::new(0, 0),
+                frames: vec![FrmFrame {
+                    shift: Point::new(0, 0),
+                    width: 1,
+                    height: 1,
+                    pixels: vec![1].into(),
+                }],
+            }],
+            directions: EnumMap::from(|_| 0),
+        }.write(&mut frm).unwrap();
+        write(dir, "art/items/test.frm", &frm);
+        write(dir, "art/misc/test.frm", &frm);
+
+        for kind in proto::proto_entity_kinds() {
+            let lst = match kind {
+                EntityKind::Item => "misc.pro\nkey.pro\nammo.pro\n".into(),
+                EntityKind::Misc => "exit.pro\n".repeat(0x10),
+                _ => String::new(),
+            };
+            write(dir, &format!("proto/{0}/{0}.lst", kind.dir()), lst.as_bytes());
+            write(dir, &format!("text/english/game/pro_{}.msg", &kind.dir()[..4]), b"");
+        }
+        write(dir, "text/english/game/proto.msg", b"");
+        write(dir, "proto/items/misc.pro", &item_proto(1, ItemKind::Misc, &[-1, 0, 10]));
+        write(dir, "proto/items/key.pro", &item_proto(2, ItemKind::Key, &[7]));
+        write(dir, "proto/items/ammo.pro", &item_proto(3, ItemKind::Ammo, &[0, 10, 0, 0, 0, 0]));
+        let mut exit = Vec::new();
+        for &v in &[0x500_0010, 100, 0x500_0000, 0, 0, 0, 0] {
+            exit.write_i32::<BigEndian>(v).unwrap();
+        }
+        write(dir, "proto/misc/exit.pro", &exit);
+
+        write(dir, "scripts/scripts.lst", b"test.int ; Test # local_vars=2\n");
+        // Empty procedure, name and string tables.
+        let mut int = vec![0; 42 + 4];
+        for _ in 0..2 {
+            int.extend_from_slice(&[0, 0, 0, 0, 0xff, 0xff, 0, 0]);
+        }
+        write(dir, "scripts/test.int", &int);
+
+        let mut fs = FileSystem::new();
+        fs.register_provider(crate::fs::std::new_provider(dir).unwrap());
+        let fs = Rc::new(fs);
+
+        let palette = crate::asset::palette::read_palette(&mut &ungz(
+            include_bytes!("../graphics/color/color.pal.gz"))[..]).unwrap();
+        let backend = Backend::new_headless(1, 1, Box::new(palette), PaletteOverlay::standard());
+
+        Dbs {
+            proto_db: Rc::new(ProtoDb::new(fs.clone(), "english").unwrap()),
+            frm_db: Rc::new(FrameDb::new(fs.clone(), "english", backend.new_texture_factory())
+                .unwrap()),
+            fs,
+        }
+    }
+
+    #[test]
+    fn outline_roundtrip() {
+        assert_eq!(encode_outline(None), 0);
+        assert!(decode_outline(0).unwrap().is_none());
+
+        for &style in &[
+            OutlineStyle::GlowingRed,
+            OutlineStyle::Red,
+            OutlineStyle::Gray,
+            OutlineStyle::GlowingGreen,
+            OutlineStyle::Yellow,
+            OutlineStyle::Brown,
+        ] {
+            for &(translucent, disabled) in &[(false, false), (true, false), (false, true), (true, true)] {
+                let outline = Outline { style, translucent, disabled };
+                let actual = decode_outline(encode_outline(Some(outline))).unwrap().unwrap();
+                assert_eq!(actual.style, style);
+                assert_eq!(actual.translucent, translucent);
+                assert_eq!(actual.disabled, disabled);
+            }
+        }
+
+        let purple = decode_outline(encode_outline(Some(Outline {
+            style: OutlineStyle::Purple,
+            translucent: false,
+            disabled: false,
+        }))).unwrap().unwrap();
+        assert_eq!(purple.style, OutlineStyle::Purple);
+    }
+
+    #[test]
+    fn sqr_tiles_roundtrip() {
+        let mut tiles = Array2d::with_default(100, 100);
+        for (i, t) in tiles.as_slice_mut().iter_mut().enumerate() {
+            *t = (i as u16, (i * 7) as u16);
+        }
+        let sqr_tiles = vec![None, Some(tiles), None];
+        let flags = 0b1010;
+
+        let mut buf = Vec::new();
+        write_sqr_tiles(&mut buf, &sqr_tiles).unwrap();
+        assert_eq!(buf.len(), 100 * 100 * 4);
+        // Roof comes first and x axis is inverted.
+        assert_eq!(&buf[..4], &[0x02, 0xb5, 0, 99]);
+
+        let actual = read_sqr_tiles(&mut &buf[..], flags).unwrap();
+        assert_eq!(actual.len(), 3);
+        assert!(actual[0].is_none());
+        assert!(actual[2].is_none());
+        assert_eq!(actual[1].as_ref().unwrap().as_slice(), sqr_tiles[1].as_ref().unwrap().as_slice());
+
+        let mut buf2 = Vec::new();
+        write_sqr_tiles(&mut buf2, &actual).unwrap();
+        assert_eq!(buf2, buf);
+    }
+
+    #[test]
+    fn map_exit_roundtrip() {
+        for &(map, location) in &[
+            (0, 0x20007f4a_u32),
+            (12, 1 << 29 | 5 << 26 | 20100),
+            (-1, 0x0c00_0000 | 1),
+            (-2, 0),
+        ] {
+            let exit = MapExit::decode(map, location).unwrap();
+            assert_eq!(exit.encode(), (map, location));
         }
-        Ok(sqr_tiles)
+
+        let exit = MapExit::decode(5, 2 << 29 | 3 << 26 | 12345).unwrap();
+        assert_eq!(exit.map, TargetMap::Map { map_id: 5 });
+        assert_eq!(exit.pos.elevation, 2);
+        assert_eq!(exit.pos.point, TileGrid::default().from_linear_inv(12345));
+        assert_eq!(exit.direction, Direction::from_u32(3).unwrap());
+    }
+
+    #[test]
+    fn map_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("vault13-test-{}-map", std::process::id()));
+        let dbs = write_data(&dir);
+        let program_id = ProgramId::new(1).unwrap();
+
+        let mut objects = dbs.new_objects();
+        let mut scripts = dbs.new_scripts();
+
+        let item_pos = EPoint::new(1, Point::new(30, 40));
+        let item = dbs.create(&mut objects, 1, Some(item_pos));
+        let key = dbs.create(&mut objects, 2, None);
+        let item_sid = ScriptIid::new(ScriptKind::Item, 0);
+        {
+            let mut item = objects.get_mut(item);
+            item.sub.as_item_mut().unwrap().ammo_count = 5;
+            item.inventory.items.push(InventoryItem { object: key, count: 3 });
+            item.script = Some((item_sid, program_id));
+        }
+        scripts.instantiate(item_sid, program_id, Some(vec![11, 12].into())).unwrap();
+        scripts.attach_to_object(item_sid, item);
+
+        let spatial_sid = ScriptIid::new(ScriptKind::Spatial, 0);
+        let spatial = Spatial { pos: EPoint::new(2, Point::new(50, 60)), radius: 3 };
+        scripts.instantiate(spatial_sid, program_id, Some(vec![21, 22].into())).unwrap();
+        scripts.get_mut(spatial_sid).unwrap().spatial = Some(spatial);
+
+        let mut tiles = Array2d::with_default(100, 100);
+        *tiles.get_mut(5, 6).unwrap() = (7, 8);
+        let mut map = new_map();
+        map.sqr_tiles[1] = Some(tiles);
+
+        let data = write_map(&map, &objects, &scripts);
+
+        let mut objects2 = dbs.new_objects();
+        let mut scripts2 = dbs.new_scripts();
+        let map2 = dbs.read(&data, &mut objects2, &mut scripts2).unwrap();
+
+        assert_eq!(map2.version, map.version);
+        assert_eq!(map2.name, map.name);
+        assert_eq!(map2.id, map.id);
+        assert!(map2.savegame);
+        assert_eq!(map2.program_id, None);
+        assert_eq!(map2.time, map.time);
+        assert_eq!(map2.entrance, map.entrance);
+        assert_eq!(map2.entrance_direction, map.entrance_direction);
+        assert_eq!(map2.map_vars, map.map_vars);
+        assert!(map2.sqr_tiles[0].is_none());
+        assert_eq!(map2.sqr_tiles[1].as_ref().unwrap().get(5, 6), Some(&(7, 8)));
+
+        let item2 = objects2.at(item_pos)[0];
+        {
+            let item2 = objects2.get(item2);
+            assert_eq!(item2.proto_id(), ProtoId::from_packed(1));
+            assert_eq!(item2.sub.as_item().unwrap().ammo_count, 5);
+            assert_eq!(item2.script, Some((item_sid, program_id)));
+            assert_eq!(item2.inventory.items.len(), 1);
+            assert_eq!(item2.inventory.items[0].count, 3);
+            let key2 = objects2.get(item2.inventory.items[0].object);
+            assert_eq!(key2.sub.as_key().unwrap().id, 7);
+        }
+        let script = scripts2.get(item_sid).unwrap();
+        assert_eq!(&script.local_vars[..], &[11, 12]);
+        assert_eq!(script.object, Some(item2));
+        let script = scripts2.get(spatial_sid).unwrap();
+        assert_eq!(&script.local_vars[..], &[21, 22]);
+        let spatial2 = script.spatial.unwrap();
+        assert_eq!((spatial2.pos, spatial2.radius), (spatial.pos, spatial.radius));
+
+        assert_eq!(write_map(&map2, &objects2, &scripts2), data);
+
+        // Ammo count is clamped to the proto's max.
+        objects2.get_mut(item2).sub.as_item_mut().unwrap().ammo_count = 15;
+        let data = write_map(&map2, &objects2, &scripts2);
+        let mut objects3 = dbs.new_objects();
+        dbs.read(&data, &mut objects3, &mut dbs.new_scripts()).unwrap();
+        let item3 = objects3.at(item_pos)[0];
+        assert_eq!(objects3.get(item3).sub.as_item().unwrap().ammo_count, 10);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn map_read_errors() {
+        let dir = std::env::temp_dir().join(format!("vault13-test-{}-map-errors",
+            std::process::id()));
+        let dbs = write_data(&dir);
+        let pos = EPoint::new(0, Point::new(30, 40));
+        let read_err = |data: &[u8]| dbs.read(data, &mut dbs.new_objects(), &mut dbs.new_scripts())
+            .err().unwrap().to_string();
+
+        let objects = dbs.new_objects();
+        let scripts = dbs.new_scripts();
+        let data = write_map(&new_map(), &objects, &scripts);
+        let patched = |offset: usize, v: i32| {
+            let mut r = data.clone();
+            r[offset..offset + 4].copy_from_slice(&v.to_be_bytes());
+            r
+        };
+        assert_eq!(read_err(&patched(24, 4)), "invalid entrance elevation: 4");
+        assert_eq!(read_err(&patched(52, -1)), "invalid map id: -1");
+
+        let mut objects = dbs.new_objects();
+        let ammo = dbs.create(&mut objects, 3, Some(pos));
+        objects.get_mut(ammo).sub.as_item_mut().unwrap().ammo_count = 0x8000_0000;
+        let data = write_map(&new_map(), &objects, &scripts);
+        assert_eq!(read_err(&data), "invalid ammo count: -2147483648");
+
+        let mut objects = dbs.new_objects();
+        let item = dbs.create(&mut objects, 1, Some(pos));
+        let key = dbs.create(&mut objects, 2, None);
+        objects.get_mut(item).inventory.items.push(InventoryItem { object: key, count: 0x8000_0000 });
+        let data = write_map(&new_map(), &objects, &scripts);
+        assert_eq!(read_err(&data), "invalid inventory item count: -2147483648");
+
+        // Exit area with FID index < 33 must lead to a map.
+        let mut objects = dbs.new_objects();
+        let exit = dbs.create(&mut objects, 0x500_0010, Some(pos));
+        objects.get_mut(exit).sub = SubObject::MapExit(MapExit {
+            map: TargetMap::WorldMap(WorldMapKind::Town),
+            pos: EPoint::new(0, Point::new(1, 2)),
+            direction: Direction::NE,
+        });
+        let data = write_map(&new_map(), &objects, &scripts);
+        assert_eq!(read_err(&data), "invalid exit area map: -1");
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
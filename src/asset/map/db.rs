@@ -138,6 +138,11 @@ impl MapDb {
     pub fn get(&self, id: u32) -> Option<&MapDef> {
         self.maps.get(id as usize)
     }
+
+    /// Finds map by its `MapDef::lookup_name` ignoring case.
+    pub fn find(&self, lookup_name: &str) -> Option<&MapDef> {
+        self.maps.iter().find(|m| m.lookup_name.eq_ignore_ascii_case(lookup_name))
+    }
 }
 
 #[cfg(test)]
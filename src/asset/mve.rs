@@ -0,0 +1,955 @@
+use bstring::{bstr, BString};
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::cmp;
+use std::io::{self, Error, ErrorKind, prelude::*};
+use std::mem;
+use std::time::Duration;
+
+use crate::graphics::color::{Rgb, Rgb18};
+
+const SIGNATURE: &[u8] = b"Interplay MVE File\x1a\0";
+const MAGIC: [u16; 3] = [0x001a, 0x0100, 0x1133];
+
+/// Size of the square block video frames are encoded in.
+const BLOCK_SIZE: usize = 8;
+
+/// Size of the header in the video data opcode.
+const VIDEO_DATA_HEADER_LEN: usize = 14;
+
+const DPCM_DELTAS: [i16; 128] = [
+         0,      1,      2,      3,      4,      5,      6,      7,
+         8,      9,     10,     11,     12,     13,     14,     15,
+        16,     17,     18,     19,     20,     21,     22,     23,
+        24,     25,     26,     27,     28,     29,     30,     31,
+        32,     33,     34,     35,     36,     37,     38,     39,
+        40,     41,     42,     43,     47,     51,     56,     61,
+        66,     72,     79,     86,     94,    102,    112,    122,
+       133,    145,    158,    173,    189,    206,    225,    245,
+       267,    292,    318,    348,    379,    414,    452,    493,
+       538,    587,    640,    699,    763,    832,    908,    991,
+      1081,   1180,   1288,   1405,   1534,   1673,   1826,   1993,
+      2175,   2373,   2590,   2826,   3084,   3365,   3672,   4008,
+      4373,   4772,   5208,   5683,   6202,   6767,   7385,   8059,
+      8794,   9597,  10472,  11428,  12471,  13609,  14851,  16206,
+     17685,  19298,  21060,  22981,  25078,  27367,  29864,  32589,
+    -29973, -26728, -23186, -19322, -15105, -10503,  -5481,     -1,
+];
+
+fn dpcm_delta(i: u8) -> i32 {
+    match i {
+        0..=127 => DPCM_DELTAS[i as usize] as i32,
+        128 => 1,
+        _ => -(DPCM_DELTAS[256 - i as usize] as i32),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AudioFormat {
+    pub channels: u16,
+    pub sample_rate: u32,
+}
+
+/// Decoded video frame. Pixels are indices into the frame's `palette`.
+#[derive(Clone)]
+pub struct Frame {
+    pub index: usize,
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Box<[u8]>,
+    pub palette: Box<[Rgb18]>,
+}
+
+impl Frame {
+    /// Returns FNV-1a hash of the pixels and the palette.
+    pub fn hash(&self) -> u64 {
+        let mut h = 0xcbf2_9ce4_8422_2325u64;
+        let palette = self.palette.iter().flat_map(|c| vec![c.r(), c.g(), c.b()]);
+        for b in self.pixels.iter().cloned().chain(palette) {
+            h ^= b as u64;
+            h = h.wrapping_mul(0x100_0000_01b3);
+        }
+        h
+    }
+}
+
+/// Streaming decoder of Interplay MVE movies. Supports 8-bit video and PCM/DPCM audio.
+/// Decoded audio is accumulated between the frames and can be retrieved with `take_audio()`.
+pub struct MveReader<R> {
+    rd: R,
+    in_chunk: bool,
+    done: bool,
+    buf: Vec<u8>,
+    frame_duration: Duration,
+    frame_index: usize,
+    width: usize,
+    height: usize,
+    cur: Vec<u8>,
+    prev: Vec<u8>,
+    decoding_map: Vec<u8>,
+    palette: [Rgb18; 256],
+    audio: Option<AudioFormat>,
+    audio_16bit: bool,
+    audio_compressed: bool,
+    audio_buf: Vec<i16>,
+}
+
+impl<R: Read> MveReader<R> {
+    pub fn new(mut rd: R) -> io::Result<Self> {
+        let mut sig = [0; 20];
+        rd.read_exact(&mut sig)?;
+        if sig != SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "not a MVE file"));
+        }
+        for &m in &MAGIC {
+            if rd.read_u16::<LittleEndian>()? != m {
+                return Err(Error::new(ErrorKind::InvalidData, "bad MVE header"));
+            }
+        }
+        Ok(Self {
+            rd,
+            in_chunk: false,
+            done: false,
+            buf: Vec::new(),
+            frame_duration: Duration::from_millis(1000 / 15),
+            frame_index: 0,
+            width: 0,
+            height: 0,
+            cur: Vec::new(),
+            prev: Vec::new(),
+            decoding_map: Vec::new(),
+            palette: [Rgb::black(); 256],
+            audio: None,
+            audio_16bit: false,
+            audio_compressed: false,
+            audio_buf: Vec::new(),
+        })
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        self.frame_duration
+    }
+
+    /// Returns format of the audio stream. Becomes known after the first frame is read.
+    pub fn audio_format(&self) -> Option<AudioFormat> {
+        self.audio
+    }
+
+    /// Returns the audio decoded so far with channels interleaved.
+    pub fn take_audio(&mut self) -> Vec<i16> {
+        mem::take(&mut self.audio_buf)
+    }
+
+    /// Decodes the next video frame. Returns `None` at the end of the movie.
+    pub fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        while !self.done {
+            if !self.in_chunk {
+                match self.rd.read_u16::<LittleEndian>() {
+                    Ok(_) => {}
+                    Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => {
+                        self.done = true;
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                }
+                let _chunk_kind = self.rd.read_u16::<LittleEndian>()?;
+                self.in_chunk = true;
+            }
+
+            let len = self.rd.read_u16::<LittleEndian>()? as usize;
+            let kind = self.rd.read_u8()?;
+            let version = self.rd.read_u8()?;
+            let mut buf = mem::take(&mut self.buf);
+            buf.clear();
+            buf.resize(len, 0);
+            self.rd.read_exact(&mut buf)?;
+            let frame = self.handle_opcode(kind, version, &buf);
+            self.buf = buf;
+            if let Some(frame) = frame? {
+                return Ok(Some(frame));
+            }
+        }
+        Ok(None)
+    }
+
+    fn handle_opcode(&mut self, kind: u8, version: u8, mut data: &[u8])
+        -> io::Result<Option<Frame>>
+    {
+        let data = &mut data;
+        match kind {
+            // End of stream.
+            0x00 => self.done = true,
+            // End of chunk.
+            0x01 => self.in_chunk = false,
+            // Create timer.
+            0x02 => {
+                let rate = data.read_u32::<LittleEndian>()? as u64;
+                let subdivision = data.read_u16::<LittleEndian>()? as u64;
+                self.frame_duration = Duration::from_micros(rate * subdivision);
+            }
+            // Init audio buffers.
+            0x03 => {
+                data.read_u16::<LittleEndian>()?;
+                let flags = data.read_u16::<LittleEndian>()?;
+                let sample_rate = data.read_u16::<LittleEndian>()? as u32;
+                self.audio = Some(AudioFormat {
+                    channels: (flags & 1) + 1,
+                    sample_rate,
+                });
+                self.audio_16bit = flags & 2 != 0;
+                self.audio_compressed = version >= 1 && flags & 4 != 0;
+            }
+            // Init video buffers.
+            0x05 => {
+                let width = data.read_u16::<LittleEndian>()? as usize * BLOCK_SIZE;
+                let height = data.read_u16::<LittleEndian>()? as usize * BLOCK_SIZE;
+                if version >= 2 {
+                    data.read_u16::<LittleEndian>()?;
+                    if data.read_u16::<LittleEndian>()? != 0 {
+                        return Err(Error::new(ErrorKind::InvalidData,
+                            "true color MVE video is not supported"));
+                    }
+                }
+                self.width = width;
+                self.height = height;
+                self.cur = vec![0; width * height];
+                self.prev = vec![0; width * height];
+            }
+            // Send buffer.
+            0x07 => {
+                if self.cur.is_empty() {
+                    return Err(Error::new(ErrorKind::InvalidData,
+                        "MVE video buffers are not initialized"));
+                }
+                let frame = Frame {
+                    index: self.frame_index,
+                    width: self.width as i32,
+                    height: self.height as i32,
+                    pixels: self.cur.clone().into(),
+                    palette: self.palette.to_vec().into(),
+                };
+                self.frame_index += 1;
+                return Ok(Some(frame));
+            }
+            // Audio frame.
+            0x08 => self.read_audio(data, false)?,
+            // Silence frame.
+            0x09 => self.read_audio(data, true)?,
+            // Set palette.
+            0x0c => {
+                let start = data.read_u16::<LittleEndian>()? as usize;
+                let count = data.read_u16::<LittleEndian>()? as usize;
+                if start + count > self.palette.len() {
+                    return Err(Error::new(ErrorKind::InvalidData,
+                        format!("bad MVE palette range: {}..{}", start, start + count)));
+                }
+                for c in &mut self.palette[start..start + count] {
+                    let r = data.read_u8()? & 0x3f;
+                    let g = data.read_u8()? & 0x3f;
+                    let b = data.read_u8()? & 0x3f;
+                    *c = Rgb::new(r, g, b);
+                }
+            }
+            // Set decoding map.
+            0x0f => self.decoding_map = data.to_vec(),
+            // Video data.
+            0x11 => self.decode_video(data)?,
+            // Start/stop audio, init video mode, compressed palette and other opcodes
+            // that don't affect decoding.
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn read_audio(&mut self, data: &mut &[u8], silence: bool) -> io::Result<()> {
+        let format = if let Some(v) = self.audio {
+            v
+        } else {
+            return Ok(());
+        };
+        let _seq = data.read_u16::<LittleEndian>()?;
+        let stream_mask = data.read_u16::<LittleEndian>()?;
+        let len = data.read_u16::<LittleEndian>()? as usize;
+        if stream_mask & 1 == 0 {
+            return Ok(());
+        }
+        let sample_count = if self.audio_16bit || self.audio_compressed {
+            len / 2
+        } else {
+            len
+        };
+        if silence {
+            self.audio_buf.resize(self.audio_buf.len() + sample_count, 0);
+        } else if self.audio_compressed {
+            let channels = format.channels as usize;
+            let mut predictors = [0i32; 2];
+            for p in &mut predictors[..channels] {
+                *p = data.read_i16::<LittleEndian>()? as i32;
+                self.audio_buf.push(*p as i16);
+            }
+            let mut ch = 0;
+            for _ in 0..sample_count.saturating_sub(channels) {
+                let p = &mut predictors[ch];
+                *p = (*p + dpcm_delta(data.read_u8()?))
+                    .clamp(i16::MIN as i32, i16::MAX as i32);
+                self.audio_buf.push(*p as i16);
+                ch = (ch + 1) % channels;
+            }
+        } else if self.audio_16bit {
+            for _ in 0..sample_count {
+                self.audio_buf.push(data.read_i16::<LittleEndian>()?);
+            }
+        } else {
+            for _ in 0..sample_count {
+                self.audio_buf.push((data.read_u8()? as i16 - 128) << 8);
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_video(&mut self, data: &mut &[u8]) -> io::Result<()> {
+        if data.len() < VIDEO_DATA_HEADER_LEN {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated MVE video data"));
+        }
+        let flags = (&data[12..]).read_u16::<LittleEndian>()?;
+        *data = &data[VIDEO_DATA_HEADER_LEN..];
+        if flags & 1 != 0 {
+            mem::swap(&mut self.cur, &mut self.prev);
+        }
+
+        let blocks_x = self.width / BLOCK_SIZE;
+        let blocks_y = self.height / BLOCK_SIZE;
+        if self.decoding_map.len() * 2 < blocks_x * blocks_y {
+            return Err(Error::new(ErrorKind::InvalidData, "MVE decoding map is too short"));
+        }
+        let mut bufs = Buffers {
+            cur: &mut self.cur,
+            prev: &self.prev,
+            width: self.width,
+            height: self.height,
+        };
+        for i in 0..blocks_x * blocks_y {
+            let op = self.decoding_map[i / 2] >> (i % 2 * 4) & 0xf;
+            let x = i % blocks_x * BLOCK_SIZE;
+            let y = i / blocks_x * BLOCK_SIZE;
+            bufs.decode_block(op, data, x, y)?;
+        }
+        Ok(())
+    }
+}
+
+type Block = [u8; BLOCK_SIZE * BLOCK_SIZE];
+
+struct Buffers<'a> {
+    cur: &'a mut [u8],
+    prev: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+impl Buffers<'_> {
+    fn decode_block(&mut self, op: u8, data: &mut &[u8], x: usize, y: usize) -> io::Result<()> {
+        let mut b: Block = [0; BLOCK_SIZE * BLOCK_SIZE];
+        match op {
+            0x0 => b = self.copy(false, x as isize, y as isize)?,
+            0x1 | 0x6 => return Ok(()),
+            0x2 | 0x3 => {
+                let sign = if op == 0x2 { 1 } else { -1 };
+                let v = data.read_u8()? as isize;
+                let (dx, dy) = if v < 56 {
+                    (8 + v % 7, v / 7)
+                } else {
+                    (-14 + (v - 56) % 29, 8 + (v - 56) / 29)
+                };
+                b = self.copy(true, x as isize + sign * dx, y as isize + sign * dy)?;
+            }
+            0x4 => {
+                let v = data.read_u8()? as isize;
+                b = self.copy(false, x as isize + (v & 0xf) - 8, y as isize + (v >> 4) - 8)?;
+            }
+            0x5 => {
+                let dx = data.read_i8()? as isize;
+                let dy = data.read_i8()? as isize;
+                b = self.copy(false, x as isize + dx, y as isize + dy)?;
+            }
+            0x7 => {
+                let p = [data.read_u8()?, data.read_u8()?];
+                if p[0] <= p[1] {
+                    for row in b.chunks_exact_mut(BLOCK_SIZE) {
+                        let flags = data.read_u8()?;
+                        for (i, px) in row.iter_mut().enumerate() {
+                            *px = p[(flags >> i) as usize & 1];
+                        }
+                    }
+                } else {
+                    let flags = data.read_u16::<LittleEndian>()? as u64;
+                    fill_2x2(&mut b, |i| p[(flags >> i) as usize & 1]);
+                }
+            }
+            0x8 => {
+                let mut p = [data.read_u8()?, data.read_u8()?, 0, 0];
+                if p[0] <= p[1] {
+                    for q in 0..4 {
+                        if q > 0 {
+                            p[0] = data.read_u8()?;
+                            p[1] = data.read_u8()?;
+                        }
+                        let flags = data.read_u16::<LittleEndian>()? as u64;
+                        fill_quadrant(&mut b, q, |i| p[(flags >> i) as usize & 1]);
+                    }
+                } else {
+                    let mut flags = data.read_u32::<LittleEndian>()? as u64;
+                    p[2] = data.read_u8()?;
+                    p[3] = data.read_u8()?;
+                    let vertical = p[2] <= p[3];
+                    for half in 0..2 {
+                        if half == 1 {
+                            p[0] = p[2];
+                            p[1] = p[3];
+                            flags = data.read_u32::<LittleEndian>()? as u64;
+                        }
+                        fill_half(&mut b, half, vertical, |i| p[(flags >> i) as usize & 1]);
+                    }
+                }
+            }
+            0x9 => {
+                let mut p = [0; 4];
+                data.read_exact(&mut p)?;
+                if p[0] <= p[1] {
+                    if p[2] <= p[3] {
+                        for row in b.chunks_exact_mut(BLOCK_SIZE) {
+                            let flags = data.read_u16::<LittleEndian>()?;
+                            for (i, px) in row.iter_mut().enumerate() {
+                                *px = p[(flags >> (i * 2)) as usize & 3];
+                            }
+                        }
+                    } else {
+                        let flags = data.read_u32::<LittleEndian>()? as u64;
+                        fill_2x2(&mut b, |i| p[(flags >> (i * 2)) as usize & 3]);
+                    }
+                } else {
+                    let flags = data.read_u64::<LittleEndian>()?;
+                    let (w, h) = if p[2] <= p[3] { (2, 1) } else { (1, 2) };
+                    for (i, px) in b.iter_mut().enumerate() {
+                        let (x, y) = (i % BLOCK_SIZE / w, i / BLOCK_SIZE / h);
+                        let j = y * (BLOCK_SIZE / w) + x;
+                        *px = p[(flags >> (j * 2)) as usize & 3];
+                    }
+                }
+            }
+            0xa => {
+                let mut p = [0; 8];
+                data.read_exact(&mut p[..4])?;
+                if p[0] <= p[1] {
+                    for q in 0..4 {
+                        if q > 0 {
+                            data.read_exact(&mut p[..4])?;
+                        }
+                        let flags = data.read_u32::<LittleEndian>()? as u64;
+                        fill_quadrant(&mut b, q, |i| p[(flags >> (i * 2)) as usize & 3]);
+                    }
+                } else {
+                    let mut flags = data.read_u64::<LittleEndian>()?;
+                    data.read_exact(&mut p[4..])?;
+                    let vertical = p[4] <= p[5];
+                    for half in 0..2 {
+                        if half == 1 {
+                            p.copy_within(4..8, 0);
+                            flags = data.read_u64::<LittleEndian>()?;
+                        }
+                        fill_half(&mut b, half, vertical, |i| p[(flags >> (i * 2)) as usize & 3]);
+                    }
+                }
+            }
+            0xb => data.read_exact(&mut b)?,
+            0xc => {
+                let mut p = [0; 16];
+                data.read_exact(&mut p)?;
+                fill_2x2(&mut b, |i| p[i]);
+            }
+            0xd => {
+                let mut p = [0; 4];
+                data.read_exact(&mut p)?;
+                for (i, px) in b.iter_mut().enumerate() {
+                    *px = p[i / BLOCK_SIZE / 4 * 2 + i % BLOCK_SIZE / 4];
+                }
+            }
+            0xe => b = [data.read_u8()?; BLOCK_SIZE * BLOCK_SIZE],
+            0xf => {
+                let p = [data.read_u8()?, data.read_u8()?];
+                for (i, px) in b.iter_mut().enumerate() {
+                    *px = p[(i % BLOCK_SIZE + i / BLOCK_SIZE) % 2];
+                }
+            }
+            _ => unreachable!(),
+        }
+        for (row, src) in b.chunks_exact(BLOCK_SIZE).enumerate() {
+            let i = (y + row) * self.width + x;
+            self.cur[i..i + BLOCK_SIZE].copy_from_slice(src);
+        }
+        Ok(())
+    }
+
+    /// Copies block at `x`, `y` from the current or the previous frame.
+    fn copy(&self, cur: bool, x: isize, y: isize) -> io::Result<Block> {
+        if x < 0 || y < 0
+            || x as usize + BLOCK_SIZE > self.width
+            || y as usize + BLOCK_SIZE > self.height
+        {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("MVE block copy source is out of bounds: {}, {}", x, y)));
+        }
+        let src = if cur { &*self.cur } else { self.prev };
+        let mut b = [0; BLOCK_SIZE * BLOCK_SIZE];
+        for (row, dst) in b.chunks_exact_mut(BLOCK_SIZE).enumerate() {
+            let i = (y as usize + row) * self.width + x as usize;
+            dst.copy_from_slice(&src[i..i + BLOCK_SIZE]);
+        }
+        Ok(b)
+    }
+}
+
+/// Fills the 16 2x2 subblocks of the block in row-major order.
+fn fill_2x2(b: &mut Block, f: impl Fn(usize) -> u8) {
+    for (i, px) in b.iter_mut().enumerate() {
+        *px = f(i / BLOCK_SIZE / 2 * 4 + i % BLOCK_SIZE / 2);
+    }
+}
+
+/// Fills the 4x4 quadrant `q` of the block. Quadrants are ordered top-left, bottom-left,
+/// top-right, bottom-right.
+fn fill_quadrant(b: &mut Block, q: usize, f: impl Fn(usize) -> u8) {
+    let (qx, qy) = (q / 2 * 4, q % 2 * 4);
+    for i in 0..16 {
+        b[(qy + i / 4) * BLOCK_SIZE + qx + i % 4] = f(i);
+    }
+}
+
+/// Fills left/right (if `vertical`) or top/bottom half of the block.
+fn fill_half(b: &mut Block, half: usize, vertical: bool, f: impl Fn(usize) -> u8) {
+    for i in 0..32 {
+        let (x, y) = if vertical {
+            (half * 4 + i % 4, i / 4)
+        } else {
+            (i % BLOCK_SIZE, half * 4 + i / BLOCK_SIZE)
+        };
+        b[y * BLOCK_SIZE + x] = f(i);
+    }
+}
+
+/// Movie subtitles from `.sve` file. Each line of the file is `<frame>:<text>`, the text is
+/// displayed starting from the frame until the next subtitle.
+pub struct Subtitles {
+    list: Vec<(usize, BString)>,
+}
+
+impl Subtitles {
+    pub fn read(rd: &mut impl BufRead) -> io::Result<Self> {
+        let mut list = Vec::new();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if rd.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            while line.last().map(|&c| c == b'\n' || c == b'\r') == Some(true) {
+                line.pop();
+            }
+            let sep = if let Some(i) = line.iter().position(|&c| c == b':') {
+                i
+            } else {
+                continue;
+            };
+            let frame = std::str::from_utf8(&line[..sep]).ok()
+                .and_then(|s| s.trim().parse().ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                    format!("bad subtitle frame number: {}",
+                        String::from_utf8_lossy(&line[..sep]))))?;
+            list.push((frame, BString::from(line[sep + 1..].to_vec())));
+        }
+        list.sort_by_key(|&(frame, _)| frame);
+        Ok(Self { list })
+    }
+
+    /// Returns subtitle to display at the `frame`.
+    pub fn get(&self, frame: usize) -> Option<&bstr> {
+        let i = match self.list.binary_search_by_key(&frame, |&(f, _)| f) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        Some(&self.list[cmp::min(i, self.list.len() - 1)].1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use byteorder::WriteBytesExt;
+
+    struct MveWriter {
+        buf: Vec<u8>,
+        chunk: Vec<u8>,
+    }
+
+    impl MveWriter {
+        fn new() -> Self {
+            let mut buf = SIGNATURE.to_vec();
+            for &m in &MAGIC {
+                buf.write_u16::<LittleEndian>(m).unwrap();
+            }
+            Self {
+                buf,
+                chunk: Vec::new(),
+            }
+        }
+
+        fn op(&mut self, kind: u8, version: u8, data: &[u8]) -> &mut Self {
+            self.chunk.write_u16::<LittleEndian>(data.len() as u16).unwrap();
+            self.chunk.push(kind);
+            self.chunk.push(version);
+            self.chunk.extend_from_slice(data);
+            self
+        }
+
+        fn end_chunk(&mut self) -> &mut Self {
+            self.op(0x01, 0, &[]);
+            let chunk = mem::take(&mut self.chunk);
+            self.buf.write_u16::<LittleEndian>(chunk.len() as u16).unwrap();
+            self.buf.write_u16::<LittleEndian>(0).unwrap();
+            self.buf.extend(chunk);
+            self
+        }
+
+        fn video(&mut self, swap: bool, map: &[u8], data: &[u8]) -> &mut Self {
+            self.op(0x0f, 0, map);
+            let mut d = vec![0; VIDEO_DATA_HEADER_LEN];
+            d[12] = swap as u8;
+            d.extend_from_slice(data);
+            self.op(0x11, 2, &d)
+        }
+
+        fn reader(&self) -> MveReader<&[u8]> {
+            MveReader::new(&self.buf[..]).unwrap()
+        }
+    }
+
+    fn decode(op: u8, data: &[u8]) -> Block {
+        let mut cur = [0; 64];
+        let mut bufs = Buffers {
+            cur: &mut cur,
+            prev: &[0; 64],
+            width: 8,
+            height: 8,
+        };
+        let data = &mut &data[..];
+        bufs.decode_block(op, data, 0, 0).unwrap();
+        assert!(data.is_empty());
+        cur
+    }
+
+    fn block(rows: [&str; 8]) -> Block {
+        let mut b = [0; 64];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.bytes().enumerate() {
+                b[y * 8 + x] = c - b'0';
+            }
+        }
+        b
+    }
+
+    #[test]
+    fn header() {
+        assert_eq!(MveReader::new(&b"Interplay MVE File\x1a\0\x1a\0\x00\x01\x33\x12"[..])
+            .err().unwrap().kind(), ErrorKind::InvalidData);
+
+        let mut w = MveWriter::new();
+        w.op(0x02, 0, &[0x10, 0x27, 0, 0, 5, 0]).end_chunk().op(0x00, 0, &[]).end_chunk();
+        let mut r = w.reader();
+        assert!(r.next_frame().unwrap().is_none());
+        assert_eq!(r.frame_duration(), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn pattern_2_colors() {
+        assert_eq!(&decode(0x7, &[1, 2, 0x01, 0x80, 0, 0, 0, 0, 0xff, 0xf0])[..], &block([
+            "21111111",
+            "11111112",
+            "11111111",
+            "11111111",
+            "11111111",
+            "11111111",
+            "22222222",
+            "11112222",
+        ])[..]);
+        assert_eq!(&decode(0x7, &[2, 1, 0x01, 0x80])[..], &block([
+            "11222222",
+            "11222222",
+            "22222222",
+            "22222222",
+            "22222222",
+            "22222222",
+            "22222211",
+            "22222211",
+        ])[..]);
+        assert_eq!(&decode(0x8, &[1, 2, 0x01, 0, 3, 4, 0, 0x80, 5, 6, 0, 0, 7, 8, 0xff, 0xff])[..],
+            &block([
+                "21115555",
+                "11115555",
+                "11115555",
+                "11115555",
+                "33338888",
+                "33338888",
+                "33338888",
+                "33348888",
+            ])[..]);
+        assert_eq!(&decode(0x8, &[2, 1, 0x01, 0, 0, 0, 3, 4, 0, 0, 0, 0x80])[..], &block([
+            "12223333",
+            "22223333",
+            "22223333",
+            "22223333",
+            "22223333",
+            "22223333",
+            "22223333",
+            "22223334",
+        ])[..]);
+        assert_eq!(&decode(0x8, &[2, 1, 0x01, 0, 0, 0, 4, 3, 0, 0, 0, 0x80])[..], &block([
+            "12222222",
+            "22222222",
+            "22222222",
+            "22222222",
+            "44444444",
+            "44444444",
+            "44444444",
+            "44444443",
+        ])[..]);
+    }
+
+    #[test]
+    fn pattern_4_colors() {
+        assert_eq!(&decode(0x9, &[1, 2, 3, 4,
+                0b11_10_01_00, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0b11_00_00_00])[..],
+            &block([
+                "12341111",
+                "11111111",
+                "11111111",
+                "11111111",
+                "11111111",
+                "11111111",
+                "11111111",
+                "11111114",
+            ])[..]);
+        assert_eq!(&decode(0x9, &[1, 2, 4, 3, 0b11_10_01_00, 0, 0, 0])[..], &block([
+            "11224433",
+            "11224433",
+            "11111111",
+            "11111111",
+            "11111111",
+            "11111111",
+            "11111111",
+            "11111111",
+        ])[..]);
+        assert_eq!(&decode(0x9, &[2, 1, 3, 4, 0b11_10_01_00, 0, 0, 0, 0, 0, 0, 0b11_00_00_00])[..],
+            &block([
+                "22113344",
+                "22222222",
+                "22222222",
+                "22222222",
+                "22222222",
+                "22222222",
+                "22222222",
+                "22222244",
+            ])[..]);
+        assert_eq!(&decode(0x9, &[2, 1, 4, 3, 0b11_10_01_00, 0, 0, 0, 0, 0, 0, 0b11_00_00_00])[..],
+            &block([
+                "21432222",
+                "21432222",
+                "22222222",
+                "22222222",
+                "22222222",
+                "22222222",
+                "22222223",
+                "22222223",
+            ])[..]);
+        let mut data = vec![1, 2, 3, 4, 0b11_10_01_00, 0, 0, 0];
+        data.extend_from_slice(&[5, 6, 7, 8, 0, 0, 0, 0]);
+        data.extend_from_slice(&[1, 2, 3, 4, 0, 0, 0, 0]);
+        data.extend_from_slice(&[9, 9, 9, 9, 0, 0, 0, 0]);
+        assert_eq!(&decode(0xa, &data)[..], &block([
+            "12341111",
+            "11111111",
+            "11111111",
+            "11111111",
+            "55559999",
+            "55559999",
+            "55559999",
+            "55559999",
+        ])[..]);
+        let mut data = vec![2, 1, 3, 4, 0b11_10_01_00, 0, 0, 0, 0, 0, 0, 0, 6, 5, 7, 8];
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0b11_00_00_00]);
+        assert_eq!(&decode(0xa, &data)[..], &block([
+            "21342222",
+            "22222222",
+            "22222222",
+            "22222222",
+            "66666666",
+            "66666666",
+            "66666666",
+            "66666668",
+        ])[..]);
+    }
+
+    #[test]
+    fn other_blocks() {
+        let raw: Vec<u8> = (0..64).collect();
+        assert_eq!(&decode(0xb, &raw)[..], &raw[..]);
+        assert_eq!(&decode(0xc, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 1, 2, 3, 4, 5, 6, 7])[..], &block([
+            "11223344",
+            "11223344",
+            "55667788",
+            "55667788",
+            "99112233",
+            "99112233",
+            "44556677",
+            "44556677",
+        ])[..]);
+        assert_eq!(&decode(0xd, &[1, 2, 3, 4])[..], &block([
+            "11112222",
+            "11112222",
+            "11112222",
+            "11112222",
+            "33334444",
+            "33334444",
+            "33334444",
+            "33334444",
+        ])[..]);
+        assert_eq!(&decode(0xe, &[7])[..], &[7; 64][..]);
+        assert_eq!(&decode(0xf, &[1, 2])[..], &block([
+            "12121212",
+            "21212121",
+            "12121212",
+            "21212121",
+            "12121212",
+            "21212121",
+            "12121212",
+            "21212121",
+        ])[..]);
+    }
+
+    #[test]
+    fn frames() {
+        let mut w = MveWriter::new();
+        w.op(0x02, 0, &[0x35, 0x82, 0, 0, 1, 0]) // 33333 us
+            .op(0x05, 0, &[3, 0, 1, 0])
+            .op(0x0c, 0, &[1, 0, 2, 0, 10, 20, 30, 63, 63, 63])
+            .video(false, &[0xbe, 0x0e], &{
+                let mut v = vec![1];
+                v.extend(0..64);
+                v.push(2);
+                v
+            })
+            .op(0x07, 0, &[0, 0, 0, 0])
+            .end_chunk()
+            // Swap buffers: keep the first block as in the frame before previous, copy the raw
+            // block from the previous frame and copy it again from the current frame with 0x3.
+            .video(true, &[0x01, 0x03], &[0])
+            .op(0x07, 0, &[0, 0, 0, 0])
+            .end_chunk()
+            // Swap buffers: copy the middle block of the previous frame to the left with 0x5,
+            // copy the previous frame at 1, 0 with 0x4 and keep the last block.
+            .video(true, &[0x45, 0x01], &[8, 0, 0x81])
+            .op(0x07, 0, &[0, 0, 0, 0])
+            .end_chunk()
+            .op(0x00, 0, &[])
+            .end_chunk();
+        let mut r = w.reader();
+
+        let f = r.next_frame().unwrap().unwrap();
+        assert_eq!(r.frame_duration(), Duration::from_micros(33333));
+        assert_eq!((f.index, f.width, f.height), (0, 24, 8));
+        assert_eq!(f.palette[1], Rgb::new(10, 20, 30));
+        assert_eq!(f.palette[2], Rgb::new(63, 63, 63));
+        assert_eq!(f.palette[3], Rgb::black());
+        for y in 0..8 {
+            let row = &f.pixels[y * 24..(y + 1) * 24];
+            assert_eq!(&row[..8], &[1; 8]);
+            assert_eq!(row[8..16].to_vec(), (y as u8 * 8..y as u8 * 8 + 8).collect::<Vec<_>>());
+            assert_eq!(&row[16..], &[2; 8]);
+        }
+        let f0 = f;
+
+        let f = r.next_frame().unwrap().unwrap();
+        assert_eq!(f.index, 1);
+        for y in 0..8 {
+            let row = &f.pixels[y * 24..(y + 1) * 24];
+            assert_eq!(&row[..8], &[0; 8]);
+            assert_eq!(&row[8..16], &f0.pixels[y * 24 + 8..y * 24 + 16]);
+            assert_eq!(&row[16..], &f0.pixels[y * 24 + 8..y * 24 + 16]);
+        }
+        let f1 = f;
+
+        let f = r.next_frame().unwrap().unwrap();
+        for y in 0..8 {
+            let row = &f.pixels[y * 24..(y + 1) * 24];
+            assert_eq!(&row[..8], &f1.pixels[y * 24 + 8..y * 24 + 16]);
+            assert_eq!(&row[8..16], &f1.pixels[y * 24 + 1..y * 24 + 9]);
+            assert_eq!(&row[16..], &[2; 8]);
+        }
+
+        assert!(r.next_frame().unwrap().is_none());
+        assert!(r.next_frame().unwrap().is_none());
+
+        let hashes: Vec<_> = [&f0, &f1, &f].iter().map(|f| f.hash()).collect();
+        assert_eq!(hashes,
+            &[0x6a54_1107_f67d_8b8c, 0xf96c_a1d7_ce73_9c0c, 0xee61_3f70_41f3_710c]);
+    }
+
+    #[test]
+    fn copy_out_of_bounds() {
+        let mut w = MveWriter::new();
+        w.op(0x05, 0, &[1, 0, 1, 0])
+            .video(false, &[0x02], &[0])
+            .end_chunk();
+        assert_eq!(w.reader().next_frame().err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn audio() {
+        let mut w = MveWriter::new();
+        // Stereo DPCM.
+        w.op(0x03, 1, &[0, 0, 0b101, 0, 0x22, 0x56, 0, 0, 0, 0])
+            .op(0x08, 0, &[0, 0, 1, 0, 12, 0, 0xe8, 0x03, 0x18, 0xfc, 1, 0xff, 128, 120])
+            .op(0x09, 0, &[1, 0, 1, 0, 4, 0])
+            .op(0x08, 0, &[1, 0, 2, 0, 6, 0, 1, 0, 2, 0, 3, 4])
+            .end_chunk();
+        let mut r = w.reader();
+        assert!(r.next_frame().unwrap().is_none());
+        assert_eq!(r.audio_format(), Some(AudioFormat { channels: 2, sample_rate: 22050 }));
+        assert_eq!(r.take_audio(), &[1000, -1000, 1001, -1001, 1002, -30974, 0, 0]);
+        assert!(r.take_audio().is_empty());
+
+        // Mono 8-bit PCM.
+        let mut w = MveWriter::new();
+        w.op(0x03, 0, &[0, 0, 0, 0, 0x11, 0x2b, 0, 0])
+            .op(0x08, 0, &[0, 0, 1, 0, 3, 0, 0, 128, 255])
+            .end_chunk();
+        let mut r = w.reader();
+        assert!(r.next_frame().unwrap().is_none());
+        assert_eq!(r.audio_format(), Some(AudioFormat { channels: 1, sample_rate: 11025 }));
+        assert_eq!(r.take_audio(), &[-32768, 0, 32512]);
+    }
+
+    #[test]
+    fn subtitles() {
+        let s = Subtitles::read(&mut &b"10:Second\r\n\n0:First\n25 :Third: end\n"[..]).unwrap();
+        assert_eq!(s.get(0).unwrap(), &b"First"[..]);
+        assert_eq!(s.get(9).unwrap(), &b"First"[..]);
+        assert_eq!(s.get(10).unwrap(), &b"Second"[..]);
+        assert_eq!(s.get(100).unwrap(), &b"Third: end"[..]);
+
+        let s = Subtitles::read(&mut &b"5:Late\n"[..]).unwrap();
+        assert!(s.get(4).is_none());
+        assert_eq!(s.get(5).unwrap(), &b"Late"[..]);
+
+        assert!(Subtitles::read(&mut &b"x:Bad\n"[..]).is_err());
+    }
+}
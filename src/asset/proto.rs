@@ -306,7 +306,7 @@ pub enum BodyKind {
     Robotic = 2,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Critter {
     pub flags: BitFlags<CritterFlag>,
     pub base_stats: EnumMap<Stat, i32>,
@@ -452,8 +452,8 @@ impl MapExit {
         } else {
             TargetMap::CurrentMap
         };
-        let elevation = location & 0x3ffffff;
-        let pos = TileGrid::default().from_linear_inv((location & 0xE0000000) >> 29)
+        let elevation = (location & 0xE0000000) >> 29;
+        let pos = TileGrid::default().from_linear_inv(location & 0x3ffffff)
             .elevated(elevation);
         let direction = Direction::from_u32((location & 0x1C000000) >> 26)?;
         Some(MapExit {
@@ -462,6 +462,14 @@ impl MapExit {
             direction,
         })
     }
+
+    /// Inverse of `decode()`. Returns `(map, location)` pair.
+    pub fn encode(&self) -> (i32, u32) {
+        let map = self.map.encode().unwrap_or(0);
+        let tile = TileGrid::default().to_linear_inv(self.pos.point).unwrap();
+        let location = self.pos.elevation << 29 | (self.direction as u32) << 26 | tile;
+        (map, location)
+    }
 }
 
 #[derive(Clone, Copy, Eq, Debug, PartialEq)]
@@ -484,6 +492,16 @@ impl TargetMap {
             _ => return None,
         })
     }
+
+    /// Returns `None` for `CurrentMap` which has no standalone encoding.
+    pub fn encode(self) -> Option<i32> {
+        Some(match self {
+            TargetMap::WorldMap(WorldMapKind::Town) => -1,
+            TargetMap::WorldMap(WorldMapKind::World) => -2,
+            TargetMap::Map { map_id } => map_id as i32,
+            TargetMap::CurrentMap => return None,
+        })
+    }
 }
 
 // Subset that has prototypes.
@@ -78,10 +78,9 @@ impl ProtoDb {
         match protos.entry(pid) {
             hash_map::Entry::Occupied(e) => Ok(e.get().clone()),
             hash_map::Entry::Vacant(e) => {
-                let file_name = self.lst.get(pid)
+                let path = self.path(pid)
                     .ok_or_else(|| Error::new(ErrorKind::InvalidData,
                         format!("can't find proto file name for {:?}", pid)))?;
-                let path = format!("proto/{}/{}", pid.kind().dir(), file_name);
 
                 let proto = Rc::new(RefCell::new(self.read_proto_file(&path)?));
                 e.insert(proto.clone());
@@ -90,6 +89,11 @@ impl ProtoDb {
         }
     }
 
+    /// Returns path of the `.pro` file as listed in the `.lst` file.
+    pub fn path(&self, pid: ProtoId) -> Option<String> {
+        self.lst.get(pid).map(|file_name| format!("proto/{}/{}", pid.kind().dir(), file_name))
+    }
+
     pub fn dude(&self) -> ProtoRef {
         self.protos.borrow().get(&ProtoId::DUDE).unwrap().clone()
     }
@@ -0,0 +1,1114 @@
+//! Savegame format as used by the original game. Each save slot is a `SLOTxx` directory
+//! containing `SAVE.DAT` and one `.SAV` file for each visited map. `.SAV` files have the same
+//! format as the `.MAP` files (see `MapReader` and `MapWriter`).
+//!
+//! `SAVE.DAT` consists of a fixed-size header followed by sections written by various
+//! subsystems in a fixed order. State of the subsystems vault13 doesn't implement is kept in
+//! `Retained` and written back as loaded. Saves made during combat and the queued timed events
+//! aren't supported: the former can't be loaded, the latter are dropped on load.
+
+use bstring::{bstr, BString};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use enum_map::EnumMap;
+use enumflags2::BitFlags;
+use log::*;
+use num_traits::FromPrimitive;
+use std::cmp;
+use std::io::{self, Error, ErrorKind, prelude::*};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::asset::{EntityKind, Perk, PCStat, Skill, Stat, Trait};
+use crate::asset::frame::FrameDb;
+use crate::asset::map::{MapId, MapReader, MapWriter};
+use crate::asset::proto::{self, ProtoDb, ProtoId};
+use crate::game::GameTime;
+use crate::game::object::{self, Hand, Objects};
+use crate::game::script::Scripts;
+use crate::graphics::{Point, Rect};
+use crate::util::EnumExt;
+
+pub const SLOT_COUNT: u32 = 10;
+pub const THUMBNAIL_WIDTH: usize = 224;
+pub const THUMBNAIL_HEIGHT: usize = 133;
+
+const SIGNATURE: &[u8] = b"FALLOUT SAVE FILE";
+const SIGNATURE_LEN: usize = 24;
+const VERSION_MINOR: u16 = 1;
+const VERSION_MAJOR: u16 = 2;
+const VERSION_RELEASE: u8 = b'R';
+const CHARACTER_NAME_LEN: usize = 32;
+const DESCRIPTION_LEN: usize = 30;
+const MAP_FILE_NAME_LEN: usize = 16;
+const HEADER_PADDING_LEN: usize = 128;
+
+const DUDE_OBJ_ID: u32 = 18000;
+const SAVEABLE_STAT_COUNT: usize = 35;
+pub const KILL_KIND_COUNT: usize = 19;
+const TAGGED_SKILL_COUNT: usize = 4;
+const SELECTED_TRAIT_COUNT: usize = 2;
+
+pub const SKILL_USES_PER_DAY: usize = 3;
+/// Number of `GAME_MOVIES`.
+pub const MOVIE_COUNT: usize = 17;
+/// Number of subtiles in a world map tile.
+pub const TILE_SUBTILE_COUNT: usize = 7 * 7;
+
+const COMBAT_STATE_IN_COMBAT: u32 = 0x01;
+const COMBAT_STATE_IDLE: u32 = 0x02;
+
+/// Number of `i32` values following each queue event of the type equal to the index.
+const QUEUE_EVENT_DATA_LEN: &[usize] = &[6, 0, 3, 2, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0];
+
+/// Name of the slot directory relative to `savegame/`. Slots are numbered starting from 1.
+pub fn slot_dir_name(slot: u32) -> String {
+    assert!((1..=SLOT_COUNT).contains(&slot));
+    format!("slot{:02}", slot)
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Date {
+    pub year: u16,
+    pub month: u16,
+    pub day: u16,
+}
+
+impl Date {
+    /// Returns current UTC date and time encoded as `hour * 100 + minute`.
+    pub fn now() -> (Self, u32) {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let days = (secs / 86400) as i64;
+        let time_of_day = (secs % 86400) as u32;
+
+        // Converts days since 1970-01-01 to civil date.
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as i64;
+
+        let date = Self {
+            year: year as u16,
+            month: month as u16,
+            day: day as u16,
+        };
+        (date, time_of_day / 3600 * 100 + time_of_day % 3600 / 60)
+    }
+}
+
+pub struct Header {
+    pub character_name: BString,
+    pub description: BString,
+    /// Real world date the save was made.
+    pub file_date: Date,
+    /// Real world time the save was made encoded as `hour * 100 + minute`.
+    pub file_time: u32,
+    pub game_time: GameTime,
+    pub elevation: u32,
+    pub map_id: MapId,
+    /// Name of the map `.SAV` file the dude is on.
+    pub map_file_name: BString,
+    /// Indexed color image of `THUMBNAIL_WIDTH` x `THUMBNAIL_HEIGHT`.
+    pub thumbnail: Box<[u8]>,
+}
+
+impl Header {
+    /// Name of the map the dude is on without the extension.
+    pub fn map_name(&self) -> String {
+        let s = String::from_utf8_lossy(self.map_file_name.as_bytes()).to_lowercase();
+        match s.rfind('.') {
+            Some(i) => s[..i].into(),
+            None => s,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PartyMemberAi {
+    pub packet: i32,
+    pub team: i32,
+}
+
+/// Game preferences as stored in `SAVE.DAT`. vault13 doesn't use them, they're kept to be
+/// written back as loaded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preferences {
+    pub game_difficulty: i32,
+    pub combat_difficulty: i32,
+    pub violence_level: i32,
+    pub target_highlight: i32,
+    pub combat_looks: i32,
+    pub combat_messages: i32,
+    pub combat_taunts: i32,
+    pub language_filter: i32,
+    pub running: i32,
+    pub subtitles: i32,
+    pub item_highlight: i32,
+    pub combat_speed: i32,
+    pub player_speedup: i32,
+    pub text_base_delay: f32,
+    pub master_volume: i32,
+    pub music_volume: i32,
+    pub sndfx_volume: i32,
+    pub speech_volume: i32,
+    pub brightness: f32,
+    pub mouse_sensitivity: f32,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            game_difficulty: 1,
+            combat_difficulty: 1,
+            violence_level: 3,
+            target_highlight: 2,
+            combat_looks: 0,
+            combat_messages: 1,
+            combat_taunts: 1,
+            language_filter: 0,
+            running: 0,
+            subtitles: 0,
+            item_highlight: 1,
+            combat_speed: 0,
+            player_speedup: 0,
+            text_base_delay: 3.5,
+            master_volume: 22281,
+            music_volume: 22281,
+            sndfx_volume: 22281,
+            speech_volume: 22281,
+            brightness: 1.0,
+            mouse_sensitivity: 1.0,
+        }
+    }
+}
+
+/// Party state of the original game. Party members other than the dude aren't implemented.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Party {
+    /// Object ids of the party members except the dude.
+    pub member_obj_ids: Vec<i32>,
+    /// Next object id the original game assigns to the party member items.
+    pub next_item_obj_id: i32,
+    /// Level, number of level ups and the early flag of each party member in `party.txt` order
+    /// except the dude.
+    pub levels: Vec<[i32; 3]>,
+}
+
+impl Default for Party {
+    fn default() -> Self {
+        Self {
+            member_obj_ids: Vec::new(),
+            next_item_obj_id: 20000,
+            levels: Vec::new(),
+        }
+    }
+}
+
+/// State of the subsystems vault13 doesn't implement. It's kept as loaded so that saving
+/// doesn't lose it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Retained {
+    pub automap_flags: i32,
+    pub preferences: Preferences,
+    pub last_level: i32,
+    pub free_perk: bool,
+    pub pipboy: i32,
+    pub skill_uses: EnumMap<Skill, [i32; SKILL_USES_PER_DAY]>,
+    pub party: Party,
+}
+
+impl Default for Retained {
+    fn default() -> Self {
+        Self {
+            automap_flags: 0,
+            preferences: Default::default(),
+            last_level: 1,
+            free_perk: false,
+            pipboy: 0,
+            skill_uses: Default::default(),
+            party: Default::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EntranceState {
+    pub known: bool,
+    pub pos: Point,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AreaState {
+    pub pos: Point,
+    /// 0 - unknown, 1 - known, 2 - visited.
+    pub state: i32,
+    pub visited: i32,
+    pub entrances: Vec<EntranceState>,
+}
+
+/// World map state as stored in `SAVE.DAT`. See `WorldMap::save_state()` for the parts that are
+/// implemented.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorldMapState {
+    pub met_frank: bool,
+    /// Index of the area the party is in or -1.
+    pub area: i32,
+    pub pos: Point,
+    pub encounter_icon_visible: bool,
+    pub encounter_map: i32,
+    pub encounter_table: i32,
+    pub encounter_entry: i32,
+    pub in_car: bool,
+    pub car_area: i32,
+    pub car_fuel: i32,
+    pub areas: Vec<AreaState>,
+    pub tile_cols: i32,
+    /// Visibility of subtiles of each tile in row-major order:
+    /// 0 - unknown, 1 - known, 2 - visited.
+    pub tiles: Vec<[i32; TILE_SUBTILE_COUNT]>,
+    /// Table index, entry index and the counter of the encounter table entries that have limited
+    /// number of occurrences.
+    pub encounter_counters: Vec<(i32, i32, i32)>,
+}
+
+impl Default for WorldMapState {
+    fn default() -> Self {
+        Self {
+            met_frank: false,
+            area: -1,
+            pos: Point::new(0, 0),
+            encounter_icon_visible: false,
+            encounter_map: -1,
+            encounter_table: -1,
+            encounter_entry: -1,
+            in_car: false,
+            car_area: -1,
+            car_fuel: 80000,
+            areas: Vec::new(),
+            tile_cols: 0,
+            tiles: Vec::new(),
+            encounter_counters: Vec::new(),
+        }
+    }
+}
+
+pub struct SaveGame {
+    pub header: Header,
+    pub global_vars: Box<[i32]>,
+    /// Names of the map `.SAV` files stored in the slot directory.
+    pub map_files: Vec<BString>,
+    pub automap_size: u32,
+    pub dude: object::Handle,
+    pub center_tile: i32,
+    pub sneak_working: bool,
+    /// Critter data of the dude proto.
+    pub dude_critter: proto::Critter,
+    pub kill_counts: [i32; KILL_KIND_COUNT],
+    pub tagged_skills: Vec<Skill>,
+    /// Perks of each party member in `party.txt` order. The first one is the dude.
+    pub perks: Vec<EnumMap<Perk, u32>>,
+    /// AI packet and team of each critter party member in `party.txt` order.
+    pub party_ai: Vec<PartyMemberAi>,
+    pub pc_stats: EnumMap<PCStat, i32>,
+    pub traits: Vec<Trait>,
+    pub worldmap: WorldMapState,
+    /// Whether each of the `GAME_MOVIES` has been seen.
+    pub movies_seen: [bool; MOVIE_COUNT],
+    pub active_hand: Hand,
+    pub retained: Retained,
+}
+
+pub struct SaveReader<'a, R: 'a> {
+    pub reader: &'a mut R,
+    pub objects: &'a mut Objects,
+    pub proto_db: &'a ProtoDb,
+    pub frm_db: &'a FrameDb,
+    pub scripts: &'a mut Scripts,
+    /// Party member PIDs from `party.txt`.
+    pub party: &'a [ProtoId],
+}
+
+impl<'a, R: 'a + Read> SaveReader<'a, R> {
+    pub fn read_header(&mut self) -> io::Result<Header> {
+        read_header(self.reader)
+    }
+
+    /// Reads the whole `SAVE.DAT`. The dude object is inserted into the `objects`, nothing else
+    /// is modified.
+    pub fn read(&mut self) -> io::Result<SaveGame> {
+        let header = self.read_header()?;
+
+        let _dude_obj_id = self.reader.read_u32::<BigEndian>()?;
+
+        let global_vars = self.read_global_vars()?;
+
+        let map_file_count = self.reader.read_i32::<BigEndian>()?;
+        let mut map_files = Vec::with_capacity(cmp::max(map_file_count, 0) as usize);
+        for _ in 0..map_file_count {
+            map_files.push(read_c_str(self.reader)?);
+        }
+        let automap_size = self.reader.read_u32::<BigEndian>()?;
+
+        // The second copy is the same.
+        let global_vars2 = self.read_global_vars()?;
+        if global_vars2 != global_vars {
+            warn!("global vars copies differ in SAVE.DAT");
+        }
+
+        let dude = MapReader {
+            reader: self.reader,
+            objects: self.objects,
+            proto_db: self.proto_db,
+            frm_db: self.frm_db,
+            scripts: self.scripts,
+        }.read_object(true)?;
+        if !dude.is_dude() {
+            return Err(Error::new(ErrorKind::InvalidData, "first object in SAVE.DAT is not dude"));
+        }
+        let dude = self.objects.insert(dude);
+        let center_tile = self.reader.read_i32::<BigEndian>()?;
+
+        let sneak_working = self.reader.read_i32::<BigEndian>()? != 0;
+        let dude_critter = self.read_dude_critter_data()?;
+
+        let mut kill_counts = [0; KILL_KIND_COUNT];
+        for v in &mut kill_counts {
+            *v = self.reader.read_i32::<BigEndian>()?;
+        }
+
+        let mut tagged_skills = Vec::with_capacity(TAGGED_SKILL_COUNT);
+        for _ in 0..TAGGED_SKILL_COUNT {
+            let v = self.reader.read_i32::<BigEndian>()?;
+            if v >= 0 {
+                tagged_skills.push(read_enum_value(v, "invalid tagged skill")?);
+            }
+        }
+
+        // Random generator state isn't saved.
+
+        let mut perks = Vec::with_capacity(self.party.len());
+        for _ in self.party {
+            let mut p = EnumMap::new();
+            for perk in Perk::iter() {
+                p[perk] = cmp::max(self.reader.read_i32::<BigEndian>()?, 0) as u32;
+            }
+            perks.push(p);
+        }
+
+        // combat_load()
+        let combat_state = self.reader.read_u32::<BigEndian>()?;
+        if combat_state & COMBAT_STATE_IN_COMBAT != 0 {
+            return Err(Error::new(ErrorKind::InvalidData,
+                "loading saves made during combat is not supported"));
+        }
+
+        let mut party_ai = Vec::new();
+        for &pid in self.party {
+            if pid.kind() == EntityKind::Critter {
+                party_ai.push(PartyMemberAi {
+                    packet: self.reader.read_i32::<BigEndian>()?,
+                    team: self.reader.read_i32::<BigEndian>()?,
+                });
+            }
+        }
+
+        let mut pc_stats = EnumMap::new();
+        for pc_stat in PCStat::iter() {
+            pc_stats[pc_stat] = self.reader.read_i32::<BigEndian>()?;
+        }
+
+        // Items have nothing to save.
+
+        let mut traits = Vec::with_capacity(SELECTED_TRAIT_COUNT);
+        for _ in 0..SELECTED_TRAIT_COUNT {
+            let v = self.reader.read_i32::<BigEndian>()?;
+            if v >= 0 {
+                traits.push(read_enum_value(v, "invalid trait")?);
+            }
+        }
+
+        let automap_flags = self.reader.read_i32::<BigEndian>()?;
+        let preferences = self.read_preferences()?;
+
+        let last_level = self.reader.read_i32::<BigEndian>()?;
+        let free_perk = self.reader.read_u8()? != 0;
+
+        let worldmap = self.read_worldmap()?;
+
+        let pipboy = self.reader.read_i32::<BigEndian>()?;
+
+        let mut movies_seen = [false; MOVIE_COUNT];
+        for v in &mut movies_seen {
+            *v = self.reader.read_u8()? != 0;
+        }
+
+        let mut skill_uses: EnumMap<Skill, [i32; SKILL_USES_PER_DAY]> = EnumMap::new();
+        for skill in Skill::iter() {
+            for v in &mut skill_uses[skill] {
+                *v = self.reader.read_i32::<BigEndian>()?;
+            }
+        }
+
+        let party = self.read_party()?;
+
+        self.skip_queue()?;
+
+        // intface_load()
+        let _enabled = self.reader.read_i32::<BigEndian>()?;
+        let _hidden = self.reader.read_i32::<BigEndian>()?;
+        let active_hand = match self.reader.read_i32::<BigEndian>()? {
+            0 => Hand::Left,
+            1 => Hand::Right,
+            v => return Err(Error::new(ErrorKind::InvalidData,
+                format!("invalid active hand: {}", v))),
+        };
+        let _end_buttons_visible = self.reader.read_i32::<BigEndian>()?;
+
+        Ok(SaveGame {
+            header,
+            global_vars,
+            map_files,
+            automap_size,
+            dude,
+            center_tile,
+            sneak_working,
+            dude_critter,
+            kill_counts,
+            tagged_skills,
+            perks,
+            party_ai,
+            pc_stats,
+            traits,
+            worldmap,
+            movies_seen,
+            active_hand,
+            retained: Retained {
+                automap_flags,
+                preferences,
+                last_level,
+                free_perk,
+                pipboy,
+                skill_uses,
+                party,
+            },
+        })
+    }
+
+    fn read_global_vars(&mut self) -> io::Result<Box<[i32]>> {
+        let count = self.scripts.vars.global_vars.len();
+        let mut r = Vec::with_capacity(count);
+        for _ in 0..count {
+            r.push(self.reader.read_i32::<BigEndian>()?);
+        }
+        Ok(r.into())
+    }
+
+    // critter_load
+    fn read_dude_critter_data(&mut self) -> io::Result<proto::Critter> {
+        fn read_enum<T: FromPrimitive>(rd: &mut impl Read, err: &str) -> io::Result<T> {
+            let v = rd.read_u32::<BigEndian>()?;
+            T::from_u32(v)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("{}: {}", err, v)))
+        }
+
+        // Fields that aren't saved are taken from the current dude proto.
+        let mut critter = self.proto_db.dude().borrow().sub.as_critter().unwrap().clone();
+
+        let flags = self.reader.read_u32::<BigEndian>()?;
+        critter.flags = BitFlags::from_bits(flags)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                format!("invalid dude critter flags: {:x}", flags)))?;
+        for i in 0..SAVEABLE_STAT_COUNT {
+            critter.base_stats[Stat::from_usize(i).unwrap()] = self.reader.read_i32::<BigEndian>()?;
+        }
+        for i in 0..SAVEABLE_STAT_COUNT {
+            critter.bonus_stats[Stat::from_usize(i).unwrap()] = self.reader.read_i32::<BigEndian>()?;
+        }
+        for skill in Skill::iter() {
+            critter.skills[skill] = self.reader.read_i32::<BigEndian>()?;
+        }
+        critter.body_kind = read_enum(self.reader, "invalid dude body kind")?;
+        critter.experience = self.reader.read_i32::<BigEndian>()?;
+        critter.kill_kind = read_enum(self.reader, "invalid dude kill kind")?;
+        critter.damage_kind = read_enum(self.reader, "invalid dude damage kind")?;
+
+        Ok(critter)
+    }
+
+    // preferences_load()
+    fn read_preferences(&mut self) -> io::Result<Preferences> {
+        let rd = &mut *self.reader;
+        Ok(Preferences {
+            game_difficulty: rd.read_i32::<BigEndian>()?,
+            combat_difficulty: rd.read_i32::<BigEndian>()?,
+            violence_level: rd.read_i32::<BigEndian>()?,
+            target_highlight: rd.read_i32::<BigEndian>()?,
+            combat_looks: rd.read_i32::<BigEndian>()?,
+            combat_messages: rd.read_i32::<BigEndian>()?,
+            combat_taunts: rd.read_i32::<BigEndian>()?,
+            language_filter: rd.read_i32::<BigEndian>()?,
+            running: rd.read_i32::<BigEndian>()?,
+            subtitles: rd.read_i32::<BigEndian>()?,
+            item_highlight: rd.read_i32::<BigEndian>()?,
+            combat_speed: rd.read_i32::<BigEndian>()?,
+            player_speedup: rd.read_i32::<BigEndian>()?,
+            text_base_delay: rd.read_f32::<BigEndian>()?,
+            master_volume: rd.read_i32::<BigEndian>()?,
+            music_volume: rd.read_i32::<BigEndian>()?,
+            sndfx_volume: rd.read_i32::<BigEndian>()?,
+            speech_volume: rd.read_i32::<BigEndian>()?,
+            brightness: rd.read_f32::<BigEndian>()?,
+            mouse_sensitivity: rd.read_f32::<BigEndian>()?,
+        })
+    }
+
+    // wmWorldMap_load()
+    fn read_worldmap(&mut self) -> io::Result<WorldMapState> {
+        let rd = &mut *self.reader;
+        let met_frank = rd.read_i32::<BigEndian>()? != 0;
+        let area = rd.read_i32::<BigEndian>()?;
+        let pos = read_point(rd)?;
+        let encounter_icon_visible = rd.read_i32::<BigEndian>()? != 0;
+        let encounter_map = rd.read_i32::<BigEndian>()?;
+        let encounter_table = rd.read_i32::<BigEndian>()?;
+        let encounter_entry = rd.read_i32::<BigEndian>()?;
+        let in_car = rd.read_i32::<BigEndian>()? != 0;
+        let car_area = rd.read_i32::<BigEndian>()?;
+        let car_fuel = rd.read_i32::<BigEndian>()?;
+
+        let area_count = read_count(rd, "area")?;
+        let mut areas = Vec::with_capacity(area_count);
+        for _ in 0..area_count {
+            let pos = read_point(rd)?;
+            let state = rd.read_i32::<BigEndian>()?;
+            let visited = rd.read_i32::<BigEndian>()?;
+            let entrance_count = read_count(rd, "area entrance")?;
+            let mut entrances = Vec::with_capacity(entrance_count);
+            for _ in 0..entrance_count {
+                let known = rd.read_i32::<BigEndian>()? != 0;
+                let pos = read_point(rd)?;
+                entrances.push(EntranceState { known, pos });
+            }
+            areas.push(AreaState { pos, state, visited, entrances });
+        }
+
+        let tile_count = read_count(rd, "world map tile")?;
+        let tile_cols = rd.read_i32::<BigEndian>()?;
+        let mut tiles = Vec::with_capacity(tile_count);
+        for _ in 0..tile_count {
+            let mut tile = [0; TILE_SUBTILE_COUNT];
+            for v in &mut tile[..] {
+                *v = rd.read_i32::<BigEndian>()?;
+            }
+            tiles.push(tile);
+        }
+
+        let counter_count = read_count(rd, "encounter counter")?;
+        let mut encounter_counters = Vec::with_capacity(counter_count);
+        for _ in 0..counter_count {
+            let table = rd.read_i32::<BigEndian>()?;
+            let entry = rd.read_i32::<BigEndian>()?;
+            let counter = rd.read_i32::<BigEndian>()?;
+            encounter_counters.push((table, entry, counter));
+        }
+
+        Ok(WorldMapState {
+            met_frank,
+            area,
+            pos,
+            encounter_icon_visible,
+            encounter_map,
+            encounter_table,
+            encounter_entry,
+            in_car,
+            car_area,
+            car_fuel,
+            areas,
+            tile_cols,
+            tiles,
+            encounter_counters,
+        })
+    }
+
+    // partyMemberLoad()
+    fn read_party(&mut self) -> io::Result<Party> {
+        let member_count = read_count(self.reader, "party member")?;
+        let next_item_obj_id = self.reader.read_i32::<BigEndian>()?;
+        let mut member_obj_ids = Vec::new();
+        for _ in 1..member_count {
+            member_obj_ids.push(self.reader.read_i32::<BigEndian>()?);
+        }
+        let mut levels = Vec::new();
+        for _ in 1..self.party.len() {
+            let mut v = [0; 3];
+            for v in &mut v {
+                *v = self.reader.read_i32::<BigEndian>()?;
+            }
+            levels.push(v);
+        }
+        Ok(Party {
+            member_obj_ids,
+            next_item_obj_id,
+            levels,
+        })
+    }
+
+    /// Skips the queued events. Timed events aren't implemented.
+    // queue_load()
+    fn skip_queue(&mut self) -> io::Result<()> {
+        let count = read_count(self.reader, "queue event")?;
+        for _ in 0..count {
+            let _time = self.reader.read_u32::<BigEndian>()?;
+            let kind = self.reader.read_i32::<BigEndian>()?;
+            let _obj_id = self.reader.read_i32::<BigEndian>()?;
+            let data_len = *QUEUE_EVENT_DATA_LEN.get(kind as usize)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                    format!("invalid queue event type: {}", kind)))?;
+            for _ in 0..data_len {
+                self.reader.read_i32::<BigEndian>()?;
+            }
+        }
+        if count > 0 {
+            info!("ignored {} queued events in SAVE.DAT", count);
+        }
+        Ok(())
+    }
+}
+
+pub fn read_header(rd: &mut impl Read) -> io::Result<Header> {
+    let mut signature = [0; SIGNATURE_LEN];
+    rd.read_exact(&mut signature)?;
+    if !signature.starts_with(SIGNATURE) {
+        return Err(Error::new(ErrorKind::InvalidData, "bad SAVE.DAT signature"));
+    }
+    let version_minor = rd.read_u16::<BigEndian>()?;
+    let version_major = rd.read_u16::<BigEndian>()?;
+    let version_release = rd.read_u8()?;
+    if (version_major, version_minor, version_release)
+        != (VERSION_MAJOR, VERSION_MINOR, VERSION_RELEASE)
+    {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("unsupported SAVE.DAT version: {}.{}{}",
+                version_major, version_minor, version_release as char)));
+    }
+
+    let character_name = read_fixed_str(rd, CHARACTER_NAME_LEN)?;
+    let description = read_fixed_str(rd, DESCRIPTION_LEN)?;
+
+    let day = rd.read_u16::<BigEndian>()?;
+    let month = rd.read_u16::<BigEndian>()?;
+    let year = rd.read_u16::<BigEndian>()?;
+    let file_date = Date { year, month, day };
+    let file_time = rd.read_u32::<BigEndian>()?;
+
+    // Game date is derivable from the game time.
+    let _game_month = rd.read_u16::<BigEndian>()?;
+    let _game_day = rd.read_u16::<BigEndian>()?;
+    let _game_year = rd.read_u16::<BigEndian>()?;
+    let game_time = GameTime::from_decis(rd.read_u32::<BigEndian>()?);
+
+    let elevation = rd.read_u16::<BigEndian>()? as u32;
+    let map_id = rd.read_u16::<BigEndian>()? as MapId;
+    let map_file_name = read_fixed_str(rd, MAP_FILE_NAME_LEN)?;
+
+    let mut thumbnail = vec![0; THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT];
+    rd.read_exact(&mut thumbnail)?;
+
+    rd.read_exact(&mut [0; HEADER_PADDING_LEN][..])?;
+
+    Ok(Header {
+        character_name,
+        description,
+        file_date,
+        file_time,
+        game_time,
+        elevation,
+        map_id,
+        map_file_name,
+        thumbnail: thumbnail.into(),
+    })
+}
+
+pub fn write_header(w: &mut impl Write, header: &Header) -> io::Result<()> {
+    let mut signature = [0; SIGNATURE_LEN];
+    signature[..SIGNATURE.len()].copy_from_slice(SIGNATURE);
+    w.write_all(&signature)?;
+    w.write_u16::<BigEndian>(VERSION_MINOR)?;
+    w.write_u16::<BigEndian>(VERSION_MAJOR)?;
+    w.write_u8(VERSION_RELEASE)?;
+
+    write_fixed_str(w, &header.character_name, CHARACTER_NAME_LEN)?;
+    write_fixed_str(w, &header.description, DESCRIPTION_LEN)?;
+
+    w.write_u16::<BigEndian>(header.file_date.day)?;
+    w.write_u16::<BigEndian>(header.file_date.month)?;
+    w.write_u16::<BigEndian>(header.file_date.year)?;
+    w.write_u32::<BigEndian>(header.file_time)?;
+
+    w.write_u16::<BigEndian>(header.game_time.month() as u16)?;
+    w.write_u16::<BigEndian>(header.game_time.day() as u16)?;
+    w.write_u16::<BigEndian>(header.game_time.year())?;
+    w.write_u32::<BigEndian>(header.game_time.as_decis())?;
+
+    w.write_u16::<BigEndian>(header.elevation as u16)?;
+    w.write_u16::<BigEndian>(header.map_id as u16)?;
+    write_fixed_str(w, &header.map_file_name, MAP_FILE_NAME_LEN)?;
+
+    assert_eq!(header.thumbnail.len(), THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT);
+    w.write_all(&header.thumbnail)?;
+
+    w.write_all(&[0; HEADER_PADDING_LEN][..])?;
+
+    Ok(())
+}
+
+pub struct SaveWriter<'a, W: 'a> {
+    pub writer: &'a mut W,
+    pub objects: &'a Objects,
+    pub scripts: &'a Scripts,
+    /// Party member PIDs from `party.txt`.
+    pub party: &'a [ProtoId],
+}
+
+impl<'a, W: 'a + Write> SaveWriter<'a, W> {
+    pub fn write(&mut self, save: &SaveGame) -> io::Result<()> {
+        write_header(self.writer, &save.header)?;
+
+        self.writer.write_u32::<BigEndian>(DUDE_OBJ_ID)?;
+
+        self.write_global_vars(&save.global_vars)?;
+
+        self.writer.write_i32::<BigEndian>(save.map_files.len() as i32)?;
+        for name in &save.map_files {
+            self.writer.write_all(name.as_bytes())?;
+            self.writer.write_u8(0)?;
+        }
+        self.writer.write_u32::<BigEndian>(save.automap_size)?;
+
+        self.write_global_vars(&save.global_vars)?;
+
+        MapWriter {
+            writer: self.writer,
+            objects: self.objects,
+            scripts: self.scripts,
+        }.write_object(save.dude, DUDE_OBJ_ID, true)?;
+        self.writer.write_i32::<BigEndian>(save.center_tile)?;
+
+        self.writer.write_i32::<BigEndian>(save.sneak_working as i32)?;
+        self.write_dude_critter_data(&save.dude_critter)?;
+
+        for &v in &save.kill_counts {
+            self.writer.write_i32::<BigEndian>(v)?;
+        }
+
+        for i in 0..TAGGED_SKILL_COUNT {
+            let v = save.tagged_skills.get(i).map(|&v| v as i32).unwrap_or(-1);
+            self.writer.write_i32::<BigEndian>(v)?;
+        }
+
+        assert_eq!(save.perks.len(), self.party.len());
+        for perks in &save.perks {
+            for perk in Perk::iter() {
+                self.writer.write_i32::<BigEndian>(perks[perk] as i32)?;
+            }
+        }
+
+        // Saving during combat is not supported.
+        self.writer.write_u32::<BigEndian>(COMBAT_STATE_IDLE)?;
+
+        assert_eq!(save.party_ai.len(),
+            self.party.iter().filter(|pid| pid.kind() == EntityKind::Critter).count());
+        for ai in &save.party_ai {
+            self.writer.write_i32::<BigEndian>(ai.packet)?;
+            self.writer.write_i32::<BigEndian>(ai.team)?;
+        }
+
+        for pc_stat in PCStat::iter() {
+            self.writer.write_i32::<BigEndian>(save.pc_stats[pc_stat])?;
+        }
+
+        assert!(save.traits.len() <= SELECTED_TRAIT_COUNT);
+        for i in 0..SELECTED_TRAIT_COUNT {
+            let v = save.traits.get(i).map(|&v| v as i32).unwrap_or(-1);
+            self.writer.write_i32::<BigEndian>(v)?;
+        }
+
+        let retained = &save.retained;
+        self.writer.write_i32::<BigEndian>(retained.automap_flags)?;
+        self.write_preferences(&retained.preferences)?;
+
+        self.writer.write_i32::<BigEndian>(retained.last_level)?;
+        self.writer.write_u8(retained.free_perk as u8)?;
+
+        self.write_worldmap(&save.worldmap)?;
+
+        self.writer.write_i32::<BigEndian>(retained.pipboy)?;
+
+        for &v in &save.movies_seen {
+            self.writer.write_u8(v as u8)?;
+        }
+
+        for skill in Skill::iter() {
+            for &v in &retained.skill_uses[skill] {
+                self.writer.write_i32::<BigEndian>(v)?;
+            }
+        }
+
+        self.write_party(&retained.party)?;
+
+        // Timed events aren't implemented so the queue is always empty.
+        self.writer.write_i32::<BigEndian>(0)?;
+
+        // intface_save()
+        self.writer.write_i32::<BigEndian>(1)?;
+        self.writer.write_i32::<BigEndian>(0)?;
+        self.writer.write_i32::<BigEndian>(match save.active_hand {
+            Hand::Left => 0,
+            Hand::Right => 1,
+        })?;
+        self.writer.write_i32::<BigEndian>(0)?;
+
+        Ok(())
+    }
+
+    fn write_global_vars(&mut self, global_vars: &[i32]) -> io::Result<()> {
+        for &v in global_vars {
+            self.writer.write_i32::<BigEndian>(v)?;
+        }
+        Ok(())
+    }
+
+    // critter_save
+    fn write_dude_critter_data(&mut self, critter: &proto::Critter) -> io::Result<()> {
+        self.writer.write_u32::<BigEndian>(critter.flags.bits())?;
+        for i in 0..SAVEABLE_STAT_COUNT {
+            self.writer.write_i32::<BigEndian>(critter.base_stats[Stat::from_usize(i).unwrap()])?;
+        }
+        for i in 0..SAVEABLE_STAT_COUNT {
+            self.writer.write_i32::<BigEndian>(critter.bonus_stats[Stat::from_usize(i).unwrap()])?;
+        }
+        for skill in Skill::iter() {
+            self.writer.write_i32::<BigEndian>(critter.skills[skill])?;
+        }
+        self.writer.write_u32::<BigEndian>(critter.body_kind as u32)?;
+        self.writer.write_i32::<BigEndian>(critter.experience)?;
+        self.writer.write_u32::<BigEndian>(critter.kill_kind as u32)?;
+        self.writer.write_u32::<BigEndian>(critter.damage_kind as u32)?;
+
+        Ok(())
+    }
+
+    // preferences_save()
+    fn write_preferences(&mut self, p: &Preferences) -> io::Result<()> {
+        let w = &mut *self.writer;
+        for &v in &[
+            p.game_difficulty,
+            p.combat_difficulty,
+            p.violence_level,
+            p.target_highlight,
+            p.combat_looks,
+            p.combat_messages,
+            p.combat_taunts,
+            p.language_filter,
+            p.running,
+            p.subtitles,
+            p.item_highlight,
+            p.combat_speed,
+            p.player_speedup,
+        ] {
+            w.write_i32::<BigEndian>(v)?;
+        }
+        w.write_f32::<BigEndian>(p.text_base_delay)?;
+        for &v in &[p.master_volume, p.music_volume, p.sndfx_volume, p.speech_volume] {
+            w.write_i32::<BigEndian>(v)?;
+        }
+        w.write_f32::<BigEndian>(p.brightness)?;
+        w.write_f32::<BigEndian>(p.mouse_sensitivity)?;
+        Ok(())
+    }
+
+    // wmWorldMap_save()
+    fn write_worldmap(&mut self, wm: &WorldMapState) -> io::Result<()> {
+        let w = &mut *self.writer;
+        w.write_i32::<BigEndian>(wm.met_frank as i32)?;
+        w.write_i32::<BigEndian>(wm.area)?;
+        write_point(w, wm.pos)?;
+        w.write_i32::<BigEndian>(wm.encounter_icon_visible as i32)?;
+        w.write_i32::<BigEndian>(wm.encounter_map)?;
+        w.write_i32::<BigEndian>(wm.encounter_table)?;
+        w.write_i32::<BigEndian>(wm.encounter_entry)?;
+        w.write_i32::<BigEndian>(wm.in_car as i32)?;
+        w.write_i32::<BigEndian>(wm.car_area)?;
+        w.write_i32::<BigEndian>(wm.car_fuel)?;
+
+        w.write_i32::<BigEndian>(wm.areas.len() as i32)?;
+        for area in &wm.areas {
+            write_point(w, area.pos)?;
+            w.write_i32::<BigEndian>(area.state)?;
+            w.write_i32::<BigEndian>(area.visited)?;
+            w.write_i32::<BigEndian>(area.entrances.len() as i32)?;
+            for entrance in &area.entrances {
+                w.write_i32::<BigEndian>(entrance.known as i32)?;
+                write_point(w, entrance.pos)?;
+            }
+        }
+
+        w.write_i32::<BigEndian>(wm.tiles.len() as i32)?;
+        w.write_i32::<BigEndian>(wm.tile_cols)?;
+        for tile in &wm.tiles {
+            for &v in &tile[..] {
+                w.write_i32::<BigEndian>(v)?;
+            }
+        }
+
+        w.write_i32::<BigEndian>(wm.encounter_counters.len() as i32)?;
+        for &(table, entry, counter) in &wm.encounter_counters {
+            w.write_i32::<BigEndian>(table)?;
+            w.write_i32::<BigEndian>(entry)?;
+            w.write_i32::<BigEndian>(counter)?;
+        }
+
+        Ok(())
+    }
+
+    // partyMemberSave()
+    fn write_party(&mut self, party: &Party) -> io::Result<()> {
+        self.writer.write_i32::<BigEndian>(party.member_obj_ids.len() as i32 + 1)?;
+        self.writer.write_i32::<BigEndian>(party.next_item_obj_id)?;
+        for &id in &party.member_obj_ids {
+            self.writer.write_i32::<BigEndian>(id)?;
+        }
+        for i in 1..self.party.len() {
+            for &v in &party.levels.get(i - 1).copied().unwrap_or_default() {
+                self.writer.write_i32::<BigEndian>(v)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Scales down the `rect` of the indexed color `image` that is `image_width` pixels wide to
+/// `THUMBNAIL_WIDTH` x `THUMBNAIL_HEIGHT`.
+pub fn make_thumbnail(image: &[u8], image_width: i32, rect: Rect) -> Box<[u8]> {
+    let mut r = vec![0; THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT];
+    if rect.is_empty() {
+        return r.into();
+    }
+    for y in 0..THUMBNAIL_HEIGHT {
+        let sy = rect.top + y as i32 * rect.height() / THUMBNAIL_HEIGHT as i32;
+        for x in 0..THUMBNAIL_WIDTH {
+            let sx = rect.left + x as i32 * rect.width() / THUMBNAIL_WIDTH as i32;
+            r[y * THUMBNAIL_WIDTH + x] = image[(sy * image_width + sx) as usize];
+        }
+    }
+    r.into()
+}
+
+fn read_enum_value<T: FromPrimitive>(v: i32, err: &str) -> io::Result<T> {
+    T::from_i32(v)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("{}: {}", err, v)))
+}
+
+fn read_count(rd: &mut impl Read, what: &str) -> io::Result<usize> {
+    let v = rd.read_i32::<BigEndian>()?;
+    if v < 0 {
+        return Err(Error::new(ErrorKind::InvalidData, format!("invalid {} count: {}", what, v)));
+    }
+    Ok(v as usize)
+}
+
+fn read_point(rd: &mut impl Read) -> io::Result<Point> {
+    let x = rd.read_i32::<BigEndian>()?;
+    let y = rd.read_i32::<BigEndian>()?;
+    Ok(Point::new(x, y))
+}
+
+fn write_point(w: &mut impl Write, p: Point) -> io::Result<()> {
+    w.write_i32::<BigEndian>(p.x)?;
+    w.write_i32::<BigEndian>(p.y)
+}
+
+fn read_fixed_str(rd: &mut impl Read, len: usize) -> io::Result<BString> {
+    let mut buf = vec![0; len];
+    rd.read_exact(&mut buf)?;
+    let end = buf.iter().position(|&c| c == 0).unwrap_or(len);
+    buf.truncate(end);
+    Ok(buf.into())
+}
+
+fn write_fixed_str(w: &mut impl Write, s: &bstr, len: usize) -> io::Result<()> {
+    let mut buf = vec![0; len];
+    // Always keep the terminating zero.
+    let n = cmp::min(s.len(), len - 1);
+    buf[..n].copy_from_slice(&s.as_bytes()[..n]);
+    w.write_all(&buf)
+}
+
+fn read_c_str(rd: &mut impl Read) -> io::Result<BString> {
+    let mut r = Vec::new();
+    loop {
+        let c = rd.read_u8()?;
+        if c == 0 {
+            break;
+        }
+        r.push(c);
+    }
+    Ok(r.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn header_roundtrip() {
+        let header = Header {
+            character_name: "Narg".into(),
+            description: "Before the temple".into(),
+            file_date: Date { year: 2020, month: 3, day: 14 },
+            file_time: 1502,
+            game_time: GameTime::from_decis(302400),
+            elevation: 1,
+            map_id: 42,
+            map_file_name: "ARTEMPLE.SAV".into(),
+            thumbnail: vec![7; THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT].into(),
+        };
+
+        let mut buf = Vec::new();
+        write_header(&mut buf, &header).unwrap();
+        assert_eq!(buf.len(), 24 + 2 + 2 + 1 + 32 + 30 + 3 * 2 + 4 + 3 * 2 + 4 + 2 + 2 + 16
+            + THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT + 128);
+
+        let h = read_header(&mut &buf[..]).unwrap();
+        assert_eq!(h.character_name, header.character_name);
+        assert_eq!(h.description, header.description);
+        assert_eq!(h.file_date, header.file_date);
+        assert_eq!(h.file_time, header.file_time);
+        assert_eq!(h.game_time.as_decis(), header.game_time.as_decis());
+        assert_eq!(h.elevation, header.elevation);
+        assert_eq!(h.map_id, header.map_id);
+        assert_eq!(h.map_file_name, header.map_file_name);
+        assert_eq!(h.map_name(), "artemple");
+        assert_eq!(h.thumbnail, header.thumbnail);
+    }
+
+    #[test]
+    fn make_thumbnail_() {
+        // Left half is 1, right half is 2, the bottom row is outside of the rect.
+        let width = THUMBNAIL_WIDTH as i32 * 2;
+        let height = THUMBNAIL_HEIGHT as i32 * 2 + 1;
+        let image: Vec<u8> = (0..width * height)
+            .map(|i| if i / width == height - 1 { 3 } else if i % width < width / 2 { 1 } else { 2 })
+            .collect();
+        let t = make_thumbnail(&image, width, Rect::with_size(0, 0, width, height - 1));
+        assert_eq!(t.len(), THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT);
+        for (i, &v) in t.iter().enumerate() {
+            assert_eq!(v, if i % THUMBNAIL_WIDTH < THUMBNAIL_WIDTH / 2 { 1 } else { 2 });
+        }
+    }
+}
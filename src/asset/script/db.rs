@@ -35,6 +35,10 @@ impl ScriptDb {
         self.infos.get(program_id.index())
     }
 
+    pub fn program_ids(&self) -> impl Iterator<Item=ProgramId> {
+        (1..=self.infos.len() as u32).map(|v| ProgramId::new(v).unwrap())
+    }
+
     pub fn load(&self, program_id: ProgramId) -> io::Result<(Box<[u8]>, &ScriptInfo)> {
         let info = self.info_ok(program_id)?;
         let path = format!("scripts/{}.int", info.name);
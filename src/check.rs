@@ -0,0 +1,288 @@
+//! Validation of the game assets. Every asset is loaded the same way the game does and the
+//! errors (including panics) are collected into a report instead of aborting.
+
+use enum_map::EnumMap;
+use enum_map_derive::Enum;
+use log::*;
+use std::any::Any;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, Error, ErrorKind};
+use std::panic::{self, AssertUnwindSafe, PanicHookInfo};
+use std::rc::Rc;
+
+use crate::asset::{CritterAnim, EntityKind, WeaponKind};
+use crate::asset::frame::{FrameDb, FrameId, Idx};
+use crate::asset::map::{ELEVATION_COUNT, MapReader};
+use crate::asset::map::db::MapDb;
+use crate::asset::message::Messages;
+use crate::asset::palette::read_palette;
+use crate::asset::proto::{self, ProtoDb, ProtoId};
+use crate::asset::script::db::ScriptDb;
+use crate::fs::FileSystem;
+use crate::game::object::Objects;
+use crate::game::script::Scripts;
+use crate::graphics::color::palette::overlay::PaletteOverlay;
+use crate::graphics::geometry::hex::{Direction, TileGrid};
+use crate::graphics::render::software::Backend;
+use crate::util::EnumExt;
+use crate::vm::Vm;
+
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum AssetKind {
+    Proto,
+    Frame,
+    Map,
+    Script,
+    Message,
+}
+
+impl fmt::Display for AssetKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use AssetKind::*;
+        f.write_str(match self {
+            Proto => "proto",
+            Frame => "frame",
+            Map => "map",
+            Script => "script",
+            Message => "message",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Issue {
+    pub kind: AssetKind,
+    pub path: String,
+    pub error: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}: {}", self.kind, self.path, self.error)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Number of checked assets of each kind.
+    pub checked: EnumMap<AssetKind, usize>,
+    pub issues: Vec<Issue>,
+}
+
+impl Report {
+    fn check(&mut self, kind: AssetKind, path: &str, f: impl FnOnce() -> io::Result<()>) {
+        self.checked[kind] += 1;
+        let r = panic::catch_unwind(AssertUnwindSafe(f))
+            .unwrap_or_else(|e| Err(panic_error(e)));
+        if let Err(e) = r {
+            debug!("{} {}: {}", kind, path, e);
+            self.issues.push(Issue {
+                kind,
+                path: path.into(),
+                error: e.to_string(),
+            });
+        }
+    }
+}
+
+/// Loads all protos, frame sets listed in the `.lst` files, maps from `maps.txt`, scripts from
+/// `scripts.lst` and all `.msg` files. For critters and heads every animation that exists is
+/// loaded. Fails only if the databases themselves can't be loaded.
+pub fn check(fs: Rc<FileSystem>, language: &str) -> io::Result<Report> {
+    let mut r = Report::default();
+
+    // Silence the default hook that would print every caught panic.
+    let _hook = SilentPanicHook::install();
+    panic::catch_unwind(AssertUnwindSafe(|| check_all(fs, language, &mut r)))
+        .unwrap_or_else(|e| Err(panic_error(e)))?;
+
+    Ok(r)
+}
+
+type PanicHook = Box<dyn Fn(&PanicHookInfo) + Sync + Send + 'static>;
+
+/// Replaces the panic hook with a no-op one and restores the previous hook on drop.
+struct SilentPanicHook(Option<PanicHook>);
+
+impl SilentPanicHook {
+    fn install() -> Self {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        Self(Some(hook))
+    }
+}
+
+impl Drop for SilentPanicHook {
+    fn drop(&mut self) {
+        panic::set_hook(self.0.take().unwrap());
+    }
+}
+
+fn panic_error(e: Box<dyn Any + Send>) -> Error {
+    let msg = e.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| e.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown error".into());
+    Error::new(ErrorKind::InvalidData, format!("panic: {}", msg))
+}
+
+fn check_all(fs: Rc<FileSystem>, language: &str, r: &mut Report) -> io::Result<()> {
+    let proto_db = Rc::new(ProtoDb::new(fs.clone(), language)?);
+    check_protos(&proto_db, r);
+
+    let pal = read_palette(&mut fs.reader("color.pal")?)?;
+    let backend = Backend::new_headless(1, 1, Box::new(pal), PaletteOverlay::standard());
+    let frm_db = Rc::new(FrameDb::new(fs.clone(), language, backend.new_texture_factory())?);
+    check_frames(&frm_db, r);
+
+    let script_db = ScriptDb::new(fs.clone(), language)?;
+    check_scripts(&script_db, r);
+
+    let scripts = Scripts::new(proto_db.clone(), script_db, Vm::default());
+    check_maps(&fs, proto_db, frm_db, scripts, r)?;
+
+    check_messages(&fs, r)
+}
+
+fn check_protos(proto_db: &ProtoDb, r: &mut Report) {
+    for kind in proto::proto_entity_kinds() {
+        for id in 1..=proto_db.len(kind) as u32 {
+            let pid = ProtoId::new(kind, id).unwrap();
+            let path = proto_db.path(pid).unwrap_or_else(|| format!("{:?}", pid));
+            r.check(AssetKind::Proto, &path, || proto_db.proto(pid).map(|_| ()));
+        }
+    }
+}
+
+fn check_frames(frm_db: &FrameDb, r: &mut Report) {
+    let mut checked = HashSet::new();
+    for kind in EntityKind::iter() {
+        for idx in 0..frm_db.lst_len(kind) {
+            let (fid, optional_fids) = match idx.try_into() {
+                Ok(idx) => frame_ids(kind, idx),
+                Err(_) => (None, Vec::new()),
+            };
+            let path = fid.and_then(|fid| frm_db.name(fid))
+                .map(|name| format!("art/{}/{}", kind.dir(), name))
+                .unwrap_or_else(|| format!("art/{}/{}.lst:{}", kind.dir(), kind.dir(), idx + 1));
+            checked.insert(path.clone());
+            r.check(AssetKind::Frame, &path, || {
+                let fid = fid.ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                    format!("can't make FID for {:?} #{}", kind, idx)))?;
+                frm_db.load(fid).map(|_| ())
+            });
+
+            for fid in optional_fids {
+                let path = if let Some(name) = frm_db.name(fid) {
+                    format!("art/{}/{}", kind.dir(), name)
+                } else {
+                    continue;
+                };
+                if checked.contains(&path) || !frm_db.exists(fid) {
+                    continue;
+                }
+                checked.insert(path.clone());
+                r.check(AssetKind::Frame, &path, || frm_db.load(fid).map(|_| ()));
+            }
+        }
+    }
+}
+
+/// Returns FID that must exist for the `.lst` entry `idx` of the `kind` and FIDs of other
+/// animations that are checked only if they exist.
+fn frame_ids(kind: EntityKind, idx: Idx) -> (Option<FrameId>, Vec<FrameId>) {
+    match kind {
+        EntityKind::Critter => {
+            let mut optional = Vec::new();
+            for anim in CritterAnim::iter() {
+                for weapon in WeaponKind::iter() {
+                    optional.extend(FrameId::new_critter(None, anim, weapon, idx));
+                }
+                if anim >= CritterAnim::FallBack && anim <= CritterAnim::FallFrontBlood {
+                    for direction in Direction::iter() {
+                        optional.extend(FrameId::new_critter(Some(direction), anim,
+                            WeaponKind::Unarmed, idx));
+                    }
+                }
+            }
+            (FrameId::new_critter(None, CritterAnim::Stand, WeaponKind::Unarmed, idx), optional)
+        }
+        EntityKind::Head => {
+            let optional = (0..12)
+                .flat_map(|anim| (0..10).map(move |sub_anim| (anim, sub_anim)))
+                .filter_map(|(anim, sub_anim)| FrameId::new_head(anim, sub_anim, idx))
+                .collect();
+            (FrameId::new_head(0, 0, idx), optional)
+        }
+        _ => (FrameId::new_generic(kind, idx), Vec::new()),
+    }
+}
+
+fn check_scripts(script_db: &ScriptDb, r: &mut Report) {
+    let vm = Vm::default();
+    for program_id in script_db.program_ids() {
+        let name = &script_db.info(program_id).unwrap().name;
+        r.check(AssetKind::Script, &format!("scripts/{}.int", name), || {
+            let (code, _) = script_db.load(program_id)?;
+            vm.load(name.clone(), code)
+                .map(|_| ())
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{:?}", e)))
+        });
+    }
+}
+
+fn check_maps(fs: &FileSystem, proto_db: Rc<ProtoDb>, frm_db: Rc<FrameDb>, mut scripts: Scripts,
+    r: &mut Report) -> io::Result<()>
+{
+    let map_db = MapDb::new(fs)?;
+    let mut objects = Objects::new(TileGrid::default(), ELEVATION_COUNT,
+        frm_db.clone(), proto_db.clone());
+    for id in 0.. {
+        let map_def = if let Some(v) = map_db.get(id) {
+            v
+        } else {
+            break;
+        };
+        let path = format!("maps/{}.map", map_def.lookup_name.to_ascii_lowercase());
+        objects.clear();
+        scripts.reset();
+        r.check(AssetKind::Map, &path, || {
+            MapReader {
+                reader: &mut fs.reader(&path)?,
+                objects: &mut objects,
+                proto_db: &proto_db,
+                frm_db: &frm_db,
+                scripts: &mut scripts,
+            }.read().map(|_| ())
+        });
+    }
+    Ok(())
+}
+
+fn check_messages(fs: &FileSystem, r: &mut Report) -> io::Result<()> {
+    for path in fs.walk("text/**/*.msg")? {
+        r.check(AssetKind::Message, &path, || Messages::read(&mut fs.reader(&path)?).map(|_| ()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn report_catches_panics() {
+        let mut r = Report::default();
+        r.check(AssetKind::Map, "maps/a.map", || Ok(()));
+        r.check(AssetKind::Map, "maps/b.map", || Err(Error::new(ErrorKind::InvalidData, "bad")));
+        r.check(AssetKind::Frame, "art/c.frm", || panic!("boom {}", 42));
+
+        assert_eq!(r.checked[AssetKind::Map], 2);
+        assert_eq!(r.checked[AssetKind::Frame], 1);
+        assert_eq!(r.issues.iter().map(|i| i.to_string()).collect::<Vec<_>>(), vec![
+            "map maps/b.map: bad",
+            "frame art/c.frm: panic: boom 42",
+        ]);
+    }
+}
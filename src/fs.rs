@@ -1,8 +1,14 @@
 pub mod dat;
+pub mod glob;
+pub mod mount;
 pub mod std;
 
+use log::*;
+use ::std::collections::{BTreeMap, BTreeSet};
 use ::std::io::prelude::*;
-use ::std::io::{Error, ErrorKind, Result};
+use ::std::io::{Cursor, Error, ErrorKind, Result};
+
+pub use glob::Pattern;
 
 #[derive(Clone, Debug)]
 pub struct Metadata {
@@ -15,13 +21,51 @@ impl Metadata {
     }
 }
 
+/// Reader with random access.
+pub trait ReadSeek: BufRead + Seek + Send {}
+
+impl<T: BufRead + Seek + Send> ReadSeek for T {}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct DirEntry {
+    name: String,
+    is_dir: bool,
+}
+
+impl DirEntry {
+    pub fn new(name: impl Into<String>, is_dir: bool) -> Self {
+        Self {
+            name: name.into(),
+            is_dir,
+        }
+    }
+
+    /// Lower case name of the entry.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+}
+
 pub struct FileSystem {
     providers: Vec<Box<dyn Provider>>,
+    log_access: bool,
 }
 
 impl FileSystem {
     pub fn new() -> Self {
-        FileSystem { providers: Vec::new() }
+        FileSystem {
+            providers: Vec::new(),
+            log_access: false,
+        }
+    }
+
+    /// If enabled every path is logged along with the provider that served it.
+    pub fn set_log_access(&mut self, enabled: bool) {
+        self.log_access = enabled;
     }
 
     pub fn register_provider(&mut self, provider: Box<dyn Provider>) {
@@ -32,15 +76,61 @@ impl FileSystem {
         self.find_provider(path, |p| p.reader(path))
     }
 
+    pub fn seekable_reader(&self, path: &str) -> Result<Box<dyn ReadSeek>> {
+        self.find_provider(path, |p| p.seekable_reader(path))
+    }
+
     pub fn metadata(&self, path: &str) -> Result<Metadata> {
         self.find_provider(path, |p| p.metadata(path))
     }
 
+    /// Lists entries in the `dir` merged across all providers. If the same name is listed
+    /// by multiple providers the entry of the provider registered first wins.
+    /// Returns entries sorted by name.
+    pub fn list(&self, dir: &str) -> Result<Vec<DirEntry>> {
+        let mut found = false;
+        let mut r = BTreeMap::new();
+        for provider in &self.providers {
+            match provider.list(dir) {
+                Ok(entries) => {
+                    found = true;
+                    for entry in entries {
+                        r.entry(entry.name.clone()).or_insert(entry);
+                    }
+                }
+                Err(e) => if e.kind() != ErrorKind::NotFound {
+                    return Err(e);
+                }
+            }
+        }
+        if found {
+            Ok(r.into_values().collect())
+        } else {
+            Err(Error::new(ErrorKind::NotFound, format!("directory not found: {}", dir)))
+        }
+    }
+
+    /// Returns sorted paths of all files matching the glob `pattern` across all providers.
+    /// Paths are in lower case with `/` separator. See `Pattern` for the syntax.
+    pub fn walk(&self, pattern: &str) -> Result<Vec<String>> {
+        let pattern = Pattern::new(pattern);
+        let mut r = BTreeSet::new();
+        for provider in &self.providers {
+            r.extend(provider.walk(&pattern)?);
+        }
+        Ok(r.into_iter().collect())
+    }
+
     fn find_provider<T>(&self, path: &str, f: impl Fn(&dyn Provider) -> Result<T>) -> Result<T> {
         let mut error: Option<Error> = None;
         for provider in &self.providers {
             match f(provider.as_ref()) {
-                Ok(r) => return Ok(r),
+                Ok(r) => {
+                    if self.log_access {
+                        info!("{} served by {}", path, provider.name());
+                    }
+                    return Ok(r);
+                }
                 Err(e) => {
                     if e.kind() == ErrorKind::NotFound {
                         continue;
@@ -62,6 +152,70 @@ impl FileSystem {
 }
 
 pub trait Provider {
+    /// Name of the provider for logging.
+    fn name(&self) -> String;
+
     fn reader(&self, path: &str) -> Result<Box<dyn BufRead + Send>>;
     fn metadata(&self, path: &str) -> Result<Metadata>;
+
+    /// Returns reader that supports seeking. The default implementation reads the whole file
+    /// into memory.
+    fn seekable_reader(&self, path: &str) -> Result<Box<dyn ReadSeek>> {
+        let mut buf = Vec::new();
+        self.reader(path)?.read_to_end(&mut buf)?;
+        Ok(Box::new(Cursor::new(buf)))
+    }
+
+    /// Lists entries directly in the `dir`. Fails with `NotFound` if there's no such directory.
+    fn list(&self, dir: &str) -> Result<Vec<DirEntry>>;
+
+    /// Returns paths of all files matching the `pattern`. Paths are in lower case with `/`
+    /// separator.
+    fn walk(&self, pattern: &Pattern) -> Result<Vec<String>>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::std::fs::{self, File};
+
+    #[test]
+    fn list_and_walk() {
+        let dir = ::std::env::temp_dir().join(format!("vault13-test-{}-fs", ::std::process::id()));
+        let data_dir = dir.join("data");
+        fs::create_dir_all(data_dir.join("Maps")).unwrap();
+        fs::write(data_dir.join("Maps").join("A.map"), b"a").unwrap();
+        fs::write(data_dir.join("color.pal"), b"").unwrap();
+        fs::create_dir_all(data_dir.join("art")).unwrap();
+
+        let dat_path = dir.join("master.dat");
+        let mut w = dat::v2::Writer::new(File::create(&dat_path).unwrap());
+        w.add("maps\\a.map", b"aa").unwrap();
+        w.add("maps\\b.map", b"b").unwrap();
+        w.add("art\\critters\\x.frm", b"x").unwrap();
+        w.add("art", b"file").unwrap();
+        w.finish().unwrap();
+
+        let mut fs = FileSystem::new();
+        fs.register_provider(self::std::new_provider(&data_dir).unwrap());
+        fs.register_provider(dat::new_provider(&dat_path).unwrap());
+
+        assert_eq!(fs.list("").unwrap(), vec![
+            DirEntry::new("art", true),
+            DirEntry::new("color.pal", false),
+            DirEntry::new("maps", true),
+        ]);
+        assert_eq!(fs.list("maps").unwrap(), vec![
+            DirEntry::new("a.map", false),
+            DirEntry::new("b.map", false),
+        ]);
+        assert_eq!(fs.list("art/critters").unwrap(), vec![DirEntry::new("x.frm", false)]);
+        assert_eq!(fs.list("scripts").unwrap_err().kind(), ErrorKind::NotFound);
+
+        assert_eq!(fs.walk("maps/*.map").unwrap(), vec!["maps/a.map", "maps/b.map"]);
+        assert_eq!(fs.walk("**/*.frm").unwrap(), vec!["art/critters/x.frm"]);
+        assert_eq!(fs.walk("*").unwrap(), vec!["art", "color.pal"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
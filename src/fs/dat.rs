@@ -2,3 +2,84 @@ mod lzss;
 mod util;
 pub mod v1;
 pub mod v2;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::fs::File;
+use std::io::{Result, SeekFrom};
+use std::io::prelude::*;
+use std::path::Path;
+
+use super::Provider;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Version {
+    /// Fallout 1 archive.
+    V1,
+    /// Fallout 2 archive.
+    V2,
+}
+
+/// Detects version of the DAT file. DAT2 file ends with the size of the whole file.
+pub fn detect_version<P: AsRef<Path>>(path: P) -> Result<Version> {
+    let mut f = File::open(path)?;
+    let len = f.metadata()?.len();
+    if len >= 8 {
+        f.seek(SeekFrom::End(-4))?;
+        if f.read_u32::<LittleEndian>()? as u64 == len {
+            return Ok(Version::V2);
+        }
+    }
+    Ok(Version::V1)
+}
+
+/// Creates provider for the DAT file of either version.
+pub fn new_provider<P: AsRef<Path>>(path: P) -> Result<Box<dyn Provider>> {
+    match detect_version(path.as_ref())? {
+        Version::V1 => v1::new_provider(path),
+        Version::V2 => v2::new_provider(path),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detect_version_() {
+        let dir = std::env::temp_dir().join(format!("vault13-test-{}-dat", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let files: &[(&str, &[u8])] = &[
+            ("dir/a.txt", b"text text text text text text text text"),
+            ("b.txt", b"b"),
+        ];
+
+        let v1_path = dir.join("v1.dat");
+        let mut w = v1::Writer::new(File::create(&v1_path).unwrap());
+        for (path, data) in files {
+            w.add(path, data).unwrap();
+        }
+        w.finish().unwrap();
+
+        let v2_path = dir.join("v2.dat");
+        let mut w = v2::Writer::new(File::create(&v2_path).unwrap());
+        for (path, data) in files {
+            w.add(path, data).unwrap();
+        }
+        w.finish().unwrap();
+
+        assert_eq!(detect_version(&v1_path).unwrap(), Version::V1);
+        assert_eq!(detect_version(&v2_path).unwrap(), Version::V2);
+
+        for path in &[v1_path, v2_path] {
+            let provider = new_provider(path).unwrap();
+            for (path, data) in files {
+                let mut actual = Vec::new();
+                provider.reader(path).unwrap().read_to_end(&mut actual).unwrap();
+                assert_eq!(&actual[..], *data);
+            }
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
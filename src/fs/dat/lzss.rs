@@ -1,5 +1,6 @@
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::cmp;
+use std::convert::TryFrom;
 use std::io::prelude::*;
 use std::io::{self, Cursor, Error, ErrorKind, Result};
 
@@ -77,7 +78,7 @@ pub fn lzss_decode_block(inp: &mut impl Read, out: &mut impl Write) -> Result<u6
     let block_size = block_descr.abs() as u64;
     let block_written;
     if block_descr < 0 {
-        block_written = io::copy(inp, out)?;
+        block_written = io::copy(&mut inp.take(block_size), out)?;
         if block_written != block_size {
             return Err(Error::new(ErrorKind::InvalidData, "Malformed LZSS stream"));
         }
@@ -88,6 +89,115 @@ pub fn lzss_decode_block(inp: &mut impl Read, out: &mut impl Write) -> Result<u6
     Ok(block_written)
 }
 
+/// Max number of input bytes encoded in a single block. Keeps the encoded block size within
+/// the `i16` block descriptor.
+const ENCODE_BLOCK_LEN: usize = 16 * 1024;
+
+/// Encodes `inp` as a sequence of LZSS blocks. Blocks that don't compress are stored raw.
+/// Returns number of bytes written.
+pub fn lzss_encode(inp: &[u8], out: &mut impl Write) -> Result<u64> {
+    let mut written = 0;
+    for block in inp.chunks(ENCODE_BLOCK_LEN) {
+        let content = lzss_encode_block_content(block);
+        let (descr, data) = if content.len() < block.len() {
+            (content.len() as i16, &content[..])
+        } else {
+            (-i16::try_from(block.len()).unwrap(), block)
+        };
+        out.write_i16::<BigEndian>(descr)?;
+        out.write_all(data)?;
+        written += 2 + data.len() as u64;
+    }
+    Ok(written)
+}
+
+fn lzss_encode_block_content(inp: &[u8]) -> Vec<u8> {
+    const N: usize = 4096;
+    const F: usize = 18;
+    const THRESHOLD: usize = 2;
+    const MIN_MATCH: usize = THRESHOLD + 1;
+    const MAX_CHAIN: usize = 256;
+    const HASH_BITS: u32 = 13;
+    const NONE: usize = usize::MAX;
+
+    fn hash(b: &[u8]) -> usize {
+        ((b[0] as usize) << 10 ^ (b[1] as usize) << 5 ^ b[2] as usize) & ((1 << HASH_BITS) - 1)
+    }
+
+    /// Hash chains of positions: `head` maps hash to the last position and `prev` links
+    /// position to the previous one with the same hash.
+    struct Chains {
+        head: Vec<usize>,
+        prev: Vec<usize>,
+    }
+
+    impl Chains {
+        fn insert(&mut self, inp: &[u8], p: usize) {
+            if p + MIN_MATCH <= inp.len() {
+                let h = hash(&inp[p..]);
+                self.prev[p] = self.head[h];
+                self.head[h] = p;
+            }
+        }
+    }
+
+    let mut chains = Chains {
+        head: vec![NONE; 1 << HASH_BITS],
+        prev: vec![NONE; inp.len()],
+    };
+
+    let mut out = Vec::with_capacity(inp.len() + inp.len() / 8 + 1);
+    let mut flags_pos = 0;
+    let mut flag_bit = 8;
+    let mut p = 0;
+    while p < inp.len() {
+        if flag_bit == 8 {
+            flags_pos = out.len();
+            out.push(0);
+            flag_bit = 0;
+        }
+
+        // Find the longest match within the ring buffer. Matches may overlap the current
+        // position since the decoder copies byte by byte.
+        let mut best_len = 0;
+        let mut best_pos = 0;
+        if p + MIN_MATCH <= inp.len() {
+            let max_len = cmp::min(F, inp.len() - p);
+            let mut c = chains.head[hash(&inp[p..])];
+            let mut chain = 0;
+            while c != NONE && p - c <= N - F && chain < MAX_CHAIN {
+                let len = (0..max_len).find(|&i| inp[c + i] != inp[p + i]).unwrap_or(max_len);
+                if len > best_len {
+                    best_len = len;
+                    best_pos = c;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                c = chains.prev[c];
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            let i = (N - F + best_pos) & (N - 1);
+            out.push(i as u8);
+            out.push(((i >> 4) & 0xf0) as u8 | (best_len - MIN_MATCH) as u8);
+            for k in p..p + best_len {
+                chains.insert(inp, k);
+            }
+            p += best_len;
+        } else {
+            out[flags_pos] |= 1 << flag_bit;
+            out.push(inp[p]);
+            chains.insert(inp, p);
+            p += 1;
+        }
+        flag_bit += 1;
+    }
+    out
+}
+
 fn lzss_decode_block_content(
     inp: &mut impl Read,
     block_size: u64,
@@ -165,3 +275,48 @@ fn lzss_decode_block_content(
 
     Ok(block_written)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn roundtrip(data: &[u8]) -> u64 {
+        let mut enc = Vec::new();
+        let len = lzss_encode(data, &mut enc).unwrap();
+        assert_eq!(len, enc.len() as u64);
+        let mut dec = Vec::new();
+        LzssDecoder::new(&enc[..], data.len() as u64).read_to_end(&mut dec).unwrap();
+        assert_eq!(dec, data);
+        len
+    }
+
+    #[test]
+    fn decode_raw_block() {
+        let enc = [0xff, 0xfd, 1, 2, 3, 0x00, 0x02, 0xff, 4];
+        let mut dec = Vec::new();
+        LzssDecoder::new(&enc[..], 4).read_to_end(&mut dec).unwrap();
+        assert_eq!(dec, &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(lzss_encode(b"", &mut Vec::new()).unwrap(), 0);
+        assert_eq!(roundtrip(b"a"), 3);
+        roundtrip(b"abcabcabcabcabcabcabcabcabcabcabcabcabcabc");
+
+        let text = b"Welcome to Vault 13. Welcome to Vault 13! Welcome to Vault 13?\n".repeat(1000);
+        assert!(roundtrip(&text) < text.len() as u64 / 5);
+
+        let mut x = 1u32;
+        let noise: Vec<_> = (0..40000).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        }).collect();
+        assert_eq!(roundtrip(&noise), noise.len() as u64 + 3 * 2);
+
+        let mixed: Vec<_> = noise.iter().zip(text.iter()).flat_map(|(&a, &b)| vec![a, b, b]).collect();
+        roundtrip(&mixed);
+    }
+}
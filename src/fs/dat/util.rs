@@ -1,3 +1,115 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result, SeekFrom};
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::fs::{DirEntry, Pattern};
+
+/// Section of a file that can be read and seeked independently of the rest of the file.
+pub struct FileSection {
+    file: File,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl FileSection {
+    pub fn open(path: &Path, start: u64, len: u64) -> Result<Self> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(start))?;
+        Ok(Self {
+            file,
+            start,
+            len,
+            pos: 0,
+        })
+    }
+}
+
+impl Read for FileSection {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let avail = self.len.saturating_sub(self.pos);
+        let len = (buf.len() as u64).min(avail) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        let r = self.file.read(&mut buf[..len])?;
+        self.pos += r as u64;
+        Ok(r)
+    }
+}
+
+impl Seek for FileSection {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(v) => Some(v),
+            SeekFrom::End(v) => self.len.checked_add_signed(v),
+            SeekFrom::Current(v) => self.pos.checked_add_signed(v),
+        }.ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position"))?;
+        self.file.seek(SeekFrom::Start(self.start + pos))?;
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+/// Default capacity of the `Cache` in bytes.
+pub const CACHE_CAPACITY: usize = 16 * 1024 * 1024;
+
+/// LRU cache of decompressed file contents limited by the total size.
+pub struct Cache {
+    capacity: usize,
+    size: usize,
+    entries: VecDeque<(String, Arc<[u8]>)>,
+}
+
+impl Cache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            size: 0,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Returns cached contents of the file at `path` or caches the contents returned by `f`.
+    /// Contents larger than the cache capacity are not cached.
+    pub fn get_or_insert_with(&mut self, path: &str, f: impl FnOnce() -> Result<Vec<u8>>)
+        -> Result<Arc<[u8]>>
+    {
+        if let Some(i) = self.entries.iter().position(|(p, _)| p == path) {
+            let entry = self.entries.remove(i).unwrap();
+            let r = entry.1.clone();
+            self.entries.push_back(entry);
+            return Ok(r);
+        }
+
+        let data: Arc<[u8]> = f()?.into();
+        if data.len() <= self.capacity {
+            while self.size + data.len() > self.capacity {
+                let (_, evicted) = self.entries.pop_front().unwrap();
+                self.size -= evicted.len();
+            }
+            self.size += data.len();
+            self.entries.push_back((path.into(), data.clone()));
+        }
+        Ok(data)
+    }
+}
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cache")
+            .field("capacity", &self.capacity)
+            .field("size", &self.size)
+            .field("len", &self.entries.len())
+            .finish()
+    }
+}
+
 pub fn normalize_path(path: &str) -> String {
     let mut r = String::with_capacity(path.len());
 
@@ -29,9 +141,133 @@ pub fn build_normalized_path(path: &mut String, c: Option<char>) {
     }
 }
 
+/// Lists entries in the `dir` given normalized paths of all files in the archive.
+pub fn list_dir<'a>(paths: impl Iterator<Item=&'a String>, dir: &str) -> Result<Vec<DirEntry>> {
+    let mut dir = normalize_path(dir);
+    while dir.ends_with('\\') {
+        dir.pop();
+    }
+
+    let mut found = dir.is_empty();
+    let mut r = BTreeSet::new();
+    for path in paths {
+        let rest = if dir.is_empty() {
+            &path[..]
+        } else if path.starts_with(&dir) && path[dir.len()..].starts_with('\\') {
+            &path[dir.len() + 1..]
+        } else {
+            continue;
+        };
+        found = true;
+        r.insert(if let Some(i) = rest.find('\\') {
+            DirEntry::new(&rest[..i], true)
+        } else {
+            DirEntry::new(rest, false)
+        });
+    }
+
+    if found {
+        Ok(r.into_iter().collect())
+    } else {
+        Err(Error::new(ErrorKind::NotFound, "directory not found"))
+    }
+}
+
+/// Returns paths matching the `pattern` given normalized paths of all files in the archive.
+pub fn walk<'a>(paths: impl Iterator<Item=&'a String>, pattern: &Pattern) -> Vec<String> {
+    paths
+        .map(|p| p.replace('\\', "/"))
+        .filter(|p| pattern.matches(p))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::normalize_path;
+    use super::*;
+
+    #[test]
+    fn list_dir_() {
+        let paths: Vec<String> = vec![
+            "color.pal".into(),
+            "art\\critters\\a.frm".into(),
+            "art\\critters\\b.frm".into(),
+            "art\\critters.lst".into(),
+            "art\\intrface\\c.frm".into(),
+            "artemple.txt".into(),
+        ];
+        let list = |dir| list_dir(paths.iter(), dir).map_err(|e| e.kind());
+        assert_eq!(list(""), Ok(vec![
+            DirEntry::new("art", true),
+            DirEntry::new("artemple.txt", false),
+            DirEntry::new("color.pal", false),
+        ]));
+        assert_eq!(list("ART/"), Ok(vec![
+            DirEntry::new("critters", true),
+            DirEntry::new("critters.lst", false),
+            DirEntry::new("intrface", true),
+        ]));
+        assert_eq!(list("art\\critters"), list("./art/critters"));
+        assert_eq!(list("art\\critters").unwrap().len(), 2);
+        assert_eq!(list("maps"), Err(ErrorKind::NotFound));
+        assert_eq!(list("art/critters.lst"), Err(ErrorKind::NotFound));
+
+        let pattern = Pattern::new("art/**/*.frm");
+        let mut files = walk(paths.iter(), &pattern);
+        files.sort();
+        assert_eq!(files, vec!["art/critters/a.frm", "art/critters/b.frm", "art/intrface/c.frm"]);
+    }
+
+    #[test]
+    fn file_section() {
+        let path = std::env::temp_dir()
+            .join(format!("vault13-test-{}-file-section", std::process::id()));
+        std::fs::write(&path, b"0123456789").unwrap();
+
+        let mut f = FileSection::open(&path, 2, 5).unwrap();
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"23456");
+
+        assert_eq!(f.seek(SeekFrom::End(-2)).unwrap(), 3);
+        let mut buf = [0; 4];
+        assert_eq!(f.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"56");
+
+        assert_eq!(f.seek(SeekFrom::Start(1)).unwrap(), 1);
+        assert_eq!(f.seek(SeekFrom::Current(1)).unwrap(), 2);
+        f.read_exact(&mut buf[..1]).unwrap();
+        assert_eq!(buf[0], b'4');
+
+        assert_eq!(f.seek(SeekFrom::Current(-4)).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(f.seek(SeekFrom::Start(100)).unwrap(), 100);
+        assert_eq!(f.read(&mut buf).unwrap(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cache() {
+        let mut c = Cache::new(10);
+        let mut calls = 0;
+        let mut get = |c: &mut Cache, path: &str, len: usize| {
+            c.get_or_insert_with(path, || {
+                calls += 1;
+                Ok(vec![len as u8; len])
+            }).unwrap();
+            calls
+        };
+        assert_eq!(get(&mut c, "a", 4), 1);
+        assert_eq!(get(&mut c, "b", 4), 2);
+        assert_eq!(get(&mut c, "a", 4), 2);
+        // Evicts "b" as the least recently used.
+        assert_eq!(get(&mut c, "c", 4), 3);
+        assert_eq!(get(&mut c, "a", 4), 3);
+        assert_eq!(get(&mut c, "b", 4), 4);
+        // Too big to cache.
+        assert_eq!(get(&mut c, "d", 11), 5);
+        assert_eq!(get(&mut c, "d", 11), 6);
+        assert_eq!(c.size, 8);
+    }
 
     #[test]
     fn normalizes_path_backslash() {
@@ -1,13 +1,14 @@
-use byteorder::{ReadBytesExt, BigEndian};
-use std::collections::HashMap;
+use byteorder::{ReadBytesExt, BigEndian, WriteBytesExt};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::{BufReader, Error, ErrorKind, Result, SeekFrom};
+use std::io::{BufReader, Cursor, Error, ErrorKind, Result, SeekFrom};
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 
 use super::lzss;
-use super::super::{Metadata, Provider};
-use super::util::{build_normalized_path, normalize_path};
+use super::super::{DirEntry, Metadata, Pattern, Provider, ReadSeek};
+use super::util::{self, build_normalized_path, normalize_path};
 
 pub fn new_provider<P: AsRef<Path>>(path: P) -> Result<Box<dyn Provider>> {
     Ok(Box::new(Dat::new(path)?))
@@ -17,6 +18,8 @@ pub fn new_provider<P: AsRef<Path>>(path: P) -> Result<Box<dyn Provider>> {
 struct Dat {
     path: PathBuf,
     files: HashMap<String, DatFile>,
+    /// Decompressed contents of the compressed files opened with `seekable_reader()`.
+    cache: RefCell<util::Cache>,
 }
 
 #[derive(Debug)]
@@ -73,6 +76,7 @@ impl Dat {
         Ok(Dat {
             path: path.as_ref().to_path_buf(),
             files,
+            cache: RefCell::new(util::Cache::new(util::CACHE_CAPACITY)),
         })
     }
 
@@ -89,6 +93,10 @@ impl DatFile {
 }
 
 impl Provider for Dat {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
     fn reader(&self, path: &str) -> Result<Box<dyn BufRead + Send>> {
         let dat_file = self.file(path)?;
         let read_size = if dat_file.is_compressed() {
@@ -109,9 +117,135 @@ impl Provider for Dat {
         })
     }
 
+    fn seekable_reader(&self, path: &str) -> Result<Box<dyn ReadSeek>> {
+        let dat_file = self.file(path)?;
+        if dat_file.is_compressed() {
+            let data = self.cache.borrow_mut().get_or_insert_with(&normalize_path(path), || {
+                let mut buf = Vec::with_capacity(dat_file.size as usize);
+                self.reader(path)?.read_to_end(&mut buf)?;
+                Ok(buf)
+            })?;
+            Ok(Box::new(Cursor::new(data)))
+        } else {
+            Ok(Box::new(BufReader::new(util::FileSection::open(&self.path,
+                dat_file.offset as u64, dat_file.size as u64)?)))
+        }
+    }
+
     fn metadata(&self, path: &str) -> Result<Metadata> {
         self.file(path).map(|f| Metadata { len: f.size as u64 })
     }
+
+    fn list(&self, dir: &str) -> Result<Vec<DirEntry>> {
+        util::list_dir(self.files.keys(), dir)
+    }
+
+    fn walk(&self, pattern: &Pattern) -> Result<Vec<String>> {
+        Ok(util::walk(self.files.keys(), pattern))
+    }
+}
+
+const FLAG_STORED: u32 = 0x20;
+const FLAG_COMPRESSED: u32 = 0x40;
+
+struct WriterFile {
+    name: String,
+    size: u32,
+    data: Vec<u8>,
+    compressed: bool,
+}
+
+/// Writes DAT1 archive. Files are kept in memory until `finish()` since the directory tree
+/// precedes the file data.
+pub struct Writer<W: Write> {
+    inner: W,
+    dirs: BTreeMap<String, Vec<WriterFile>>,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            dirs: BTreeMap::new(),
+        }
+    }
+
+    /// Adds file at `path` with the `data` contents. The data is stored LZSS-compressed unless
+    /// compression doesn't make it smaller.
+    pub fn add(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        let path = path.replace('/', "\\").to_ascii_uppercase();
+        let (dir, name) = match path.rfind('\\') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => (".", &path[..]),
+        };
+        if dir.is_empty() || name.is_empty() || dir.len() > 255 || name.len() > 255
+            || !path.is_ascii()
+        {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("bad file path: {:?}", path)));
+        }
+        if data.len() > u32::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "file is too big"));
+        }
+
+        let mut compressed = Vec::new();
+        lzss::lzss_encode(data, &mut compressed)?;
+        let file = if !data.is_empty() && compressed.len() < data.len() {
+            WriterFile {
+                name: name.into(),
+                size: data.len() as u32,
+                data: compressed,
+                compressed: true,
+            }
+        } else {
+            WriterFile {
+                name: name.into(),
+                size: data.len() as u32,
+                data: data.into(),
+                compressed: false,
+            }
+        };
+        self.dirs.entry(dir.into()).or_default().push(file);
+        Ok(())
+    }
+
+    /// Writes the directory tree followed by the file data. Returns the inner writer.
+    pub fn finish(mut self) -> Result<W> {
+        let mut offset = 16 + self.dirs.iter()
+            .map(|(dir, files)| 1 + dir.len() + 16 +
+                files.iter().map(|f| 1 + f.name.len() + 16).sum::<usize>())
+            .sum::<usize>();
+
+        let w = &mut self.inner;
+        w.write_u32::<BigEndian>(self.dirs.len() as u32)?;
+        w.write_all(&[0; 4 * 3])?;
+        for dir in self.dirs.keys() {
+            write_path(w, dir)?;
+        }
+        for files in self.dirs.values() {
+            w.write_u32::<BigEndian>(files.len() as u32)?;
+            w.write_all(&[0; 4 * 3])?;
+            for file in files {
+                if offset + file.data.len() > u32::MAX as usize {
+                    return Err(Error::new(ErrorKind::InvalidInput, "DAT file is too big"));
+                }
+                write_path(w, &file.name)?;
+                w.write_u32::<BigEndian>(if file.compressed { FLAG_COMPRESSED } else { FLAG_STORED })?;
+                w.write_u32::<BigEndian>(offset as u32)?;
+                w.write_u32::<BigEndian>(file.size)?;
+                w.write_u32::<BigEndian>(if file.compressed { file.data.len() as u32 } else { 0 })?;
+                offset += file.data.len();
+            }
+        }
+        for file in self.dirs.values().flatten() {
+            w.write_all(&file.data)?;
+        }
+        Ok(self.inner)
+    }
+}
+
+fn write_path(w: &mut impl Write, path: &str) -> Result<()> {
+    w.write_u8(path.len() as u8)?;
+    w.write_all(path.as_bytes())
 }
 
 fn read_path<R: Read>(reader: &mut R) -> Result<String> {
@@ -138,3 +272,47 @@ fn read_path_into<R: Read>(reader: &mut R, result: &mut String) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn writer() {
+        let dir = std::env::temp_dir().join(format!("vault13-test-{}-dat1", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("master.dat");
+
+        let compressible = b"0123456789".repeat(100);
+        let files: &[(&str, &[u8])] = &[
+            ("art/critters/hmjmpsaa.frm", &compressible),
+            ("art\\critters\\b.txt", b"short"),
+            ("color.pal", b""),
+            ("maps/v13ent.map", &compressible[..500]),
+        ];
+        let mut w = Writer::new(File::create(&path).unwrap());
+        for (path, data) in files {
+            w.add(path, data).unwrap();
+        }
+        w.finish().unwrap();
+
+        let dat = Dat::new(&path).unwrap();
+        assert_eq!(dat.files.len(), files.len());
+        assert!(dat.file("art/critters/hmjmpsaa.frm").unwrap().is_compressed());
+        assert!(!dat.file("art/critters/b.txt").unwrap().is_compressed());
+        for (path, data) in files {
+            assert_eq!(dat.metadata(path).unwrap().len(), data.len() as u64);
+            let mut actual = Vec::new();
+            dat.reader(&path.to_uppercase()).unwrap().read_to_end(&mut actual).unwrap();
+            assert_eq!(&actual[..], *data);
+
+            let mut actual = Vec::new();
+            let mut rd = dat.seekable_reader(path).unwrap();
+            rd.seek(SeekFrom::Start(data.len() as u64 / 2)).unwrap();
+            rd.read_to_end(&mut actual).unwrap();
+            assert_eq!(&actual[..], &data[data.len() / 2..]);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
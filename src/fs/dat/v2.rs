@@ -1,13 +1,16 @@
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufReader, Error, ErrorKind, Result, SeekFrom};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Cursor, Error, ErrorKind, Result, SeekFrom};
 use std::io::prelude::*;
 
 use std::path::{Path, PathBuf};
 
-use super::super::{Metadata, Provider};
-use super::util::{build_normalized_path, normalize_path};
+use super::super::{DirEntry, Metadata, Pattern, Provider, ReadSeek};
+use super::util::{self, build_normalized_path, normalize_path};
 
 pub fn new_provider<P: AsRef<Path>>(path: P) -> Result<Box<dyn Provider>> {
     Ok(Box::new(Dat::new(path)?))
@@ -17,6 +20,8 @@ pub fn new_provider<P: AsRef<Path>>(path: P) -> Result<Box<dyn Provider>> {
 struct Dat {
     path: PathBuf,
     files: HashMap<String, DatFile>,
+    /// Decompressed contents of the compressed files opened with `seekable_reader()`.
+    cache: RefCell<util::Cache>,
 }
 
 #[derive(Debug)]
@@ -26,6 +31,172 @@ struct DatFile {
     compressed_size: u32,
 }
 
+/// Archive entry as returned by `list()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    /// Normalized path of the file.
+    pub path: String,
+    pub size: u32,
+    /// Size of the compressed data or `None` if the file is stored uncompressed.
+    pub compressed_size: Option<u32>,
+}
+
+/// Lists entries of the DAT2 archive sorted by path.
+pub fn list<P: AsRef<Path>>(path: P) -> Result<Vec<Entry>> {
+    let dat = Dat::new(path)?;
+    let mut r: Vec<_> = dat.files.iter()
+        .map(|(path, f)| Entry {
+            path: path.clone(),
+            size: f.size,
+            compressed_size: if f.is_compressed() { Some(f.compressed_size) } else { None },
+        })
+        .collect();
+    r.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(r)
+}
+
+/// Extracts all files of the DAT2 archive at `path` into `dst_dir`.
+/// Returns number of extracted files.
+pub fn unpack<P: AsRef<Path>, Q: AsRef<Path>>(path: P, dst_dir: Q) -> Result<usize> {
+    let dat = Dat::new(path)?;
+    let mut paths: Vec<_> = dat.files.keys().collect();
+    paths.sort();
+    for path in &paths {
+        let mut dst = dst_dir.as_ref().to_path_buf();
+        for s in path.split('\\') {
+            if s.is_empty() || s == "." || s == ".." {
+                return Err(Error::new(ErrorKind::InvalidData,
+                    format!("bad file path in DAT file: {}", path)));
+            }
+            dst.push(s);
+        }
+        fs::create_dir_all(dst.parent().unwrap())?;
+        io::copy(&mut dat.reader(path)?, &mut BufWriter::new(File::create(dst)?))?;
+    }
+    Ok(paths.len())
+}
+
+/// Packs all files found recursively in `src_dir` into a new DAT2 archive at `path`.
+/// Returns number of packed files.
+pub fn pack<P: AsRef<Path>, Q: AsRef<Path>>(src_dir: P, path: Q) -> Result<usize> {
+    fn collect(dir: &Path, prefix: &str, out: &mut Vec<(String, PathBuf)>) -> Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let name = entry.file_name().into_string()
+                .ok()
+                .filter(|s| s.is_ascii())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                    format!("non-ASCII file name: {}", entry.path().display())))?;
+            let path = format!("{}{}", prefix, name);
+            if entry.file_type()?.is_dir() {
+                collect(&entry.path(), &format!("{}\\", path), out)?;
+            } else {
+                out.push((path, entry.path()));
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    collect(src_dir.as_ref(), "", &mut files)?;
+
+    let mut writer = Writer::new(BufWriter::new(File::create(path)?));
+    for (path, fs_path) in &files {
+        writer.add(path, &fs::read(fs_path)?)?;
+    }
+    writer.finish()?.flush()?;
+    Ok(files.len())
+}
+
+/// Writes DAT2 archive. File data is written as it's added, the file list is written by
+/// `finish()`.
+pub struct Writer<W: Write> {
+    inner: W,
+    files: Vec<(String, DatFile)>,
+    offset: u32,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            files: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    /// Adds file at `path` with the `data` contents. The data is stored compressed unless
+    /// compression doesn't make it smaller.
+    pub fn add(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        let path = path.replace('/', "\\");
+        if path.is_empty() || !path.is_ascii() {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                format!("bad file path: {:?}", path)));
+        }
+
+        let mut enc = ZlibEncoder::new(Vec::with_capacity(data.len() / 2), Compression::best());
+        enc.write_all(data)?;
+        let compressed = enc.finish()?;
+        let (stored, compressed_size) = if compressed.len() < data.len() {
+            (&compressed[..], compressed.len() as u32)
+        } else {
+            (data, 0)
+        };
+
+        let size = to_u32(data.len())?;
+        let file = DatFile {
+            offset: self.offset,
+            size,
+            compressed_size,
+        };
+        self.inner.write_all(stored)?;
+        self.offset = self.offset.checked_add(to_u32(stored.len())?)
+            .ok_or_else(too_big)?;
+        self.files.push((path, file));
+        Ok(())
+    }
+
+    /// Writes the file list and the trailing size fields. Returns the inner writer.
+    pub fn finish(mut self) -> Result<W> {
+        let mut list = Vec::new();
+        list.write_u32::<LittleEndian>(to_u32(self.files.len())?)?;
+        for (path, file) in &self.files {
+            list.write_u32::<LittleEndian>(path.len() as u32)?;
+            list.write_all(path.as_bytes())?;
+            list.write_u8(file.is_compressed() as u8)?;
+            list.write_u32::<LittleEndian>(file.size)?;
+            list.write_u32::<LittleEndian>(if file.is_compressed() {
+                file.compressed_size
+            } else {
+                file.size
+            })?;
+            list.write_u32::<LittleEndian>(file.offset)?;
+        }
+        let list_size = to_u32(list.len())?;
+        let size = self.offset.checked_add(list_size)
+            .and_then(|v| v.checked_add(8))
+            .ok_or_else(too_big)?;
+
+        self.inner.write_all(&list)?;
+        self.inner.write_u32::<LittleEndian>(list_size)?;
+        self.inner.write_u32::<LittleEndian>(size)?;
+        Ok(self.inner)
+    }
+}
+
+fn too_big() -> Error {
+    Error::new(ErrorKind::InvalidInput, "DAT file is too big")
+}
+
+fn to_u32(v: usize) -> Result<u32> {
+    if v > u32::MAX as usize {
+        Err(too_big())
+    } else {
+        Ok(v as u32)
+    }
+}
+
 impl Dat {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
         let mut reader = BufReader::new(File::open(path.as_ref())?);
@@ -72,6 +243,7 @@ impl Dat {
         Ok(Dat {
             path: path.as_ref().to_path_buf(),
             files,
+            cache: RefCell::new(util::Cache::new(util::CACHE_CAPACITY)),
         })
     }
 
@@ -88,6 +260,10 @@ impl DatFile {
 }
 
 impl Provider for Dat {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
     fn reader(&self, path: &str) -> Result<Box<dyn BufRead + Send>> {
         let dat_file = self.file(path)?;
         let read_size = if dat_file.is_compressed() {
@@ -108,9 +284,32 @@ impl Provider for Dat {
         })
     }
 
+    fn seekable_reader(&self, path: &str) -> Result<Box<dyn ReadSeek>> {
+        let dat_file = self.file(path)?;
+        if dat_file.is_compressed() {
+            let data = self.cache.borrow_mut().get_or_insert_with(&normalize_path(path), || {
+                let mut buf = Vec::with_capacity(dat_file.size as usize);
+                self.reader(path)?.read_to_end(&mut buf)?;
+                Ok(buf)
+            })?;
+            Ok(Box::new(Cursor::new(data)))
+        } else {
+            Ok(Box::new(BufReader::new(util::FileSection::open(&self.path,
+                dat_file.offset as u64, dat_file.size as u64)?)))
+        }
+    }
+
     fn metadata(&self, path: &str) -> Result<Metadata> {
         self.file(path).map(|f| Metadata { len: f.size as u64 })
     }
+
+    fn list(&self, dir: &str) -> Result<Vec<DirEntry>> {
+        util::list_dir(self.files.keys(), dir)
+    }
+
+    fn walk(&self, pattern: &Pattern) -> Result<Vec<String>> {
+        Ok(util::walk(self.files.keys(), pattern))
+    }
 }
 
 fn read_path<R: Read>(r: &mut R) -> Result<String> {
@@ -126,3 +325,101 @@ fn read_path<R: Read>(r: &mut R) -> Result<String> {
 
     Ok(s)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let r = std::env::temp_dir().join(format!("vault13-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&r);
+        fs::create_dir_all(&r).unwrap();
+        r
+    }
+
+    fn read(dat: &Dat, path: &str) -> Vec<u8> {
+        let mut r = Vec::new();
+        dat.reader(path).unwrap().read_to_end(&mut r).unwrap();
+        r
+    }
+
+    #[test]
+    fn writer() {
+        let dir = temp_dir("dat2-writer");
+        let path = dir.join("test.dat");
+
+        let compressible = vec![42; 1000];
+        let incompressible: Vec<_> = (0..200u32).map(|i| (i * 7919 % 251) as u8).collect();
+
+        let mut w = Writer::new(File::create(&path).unwrap());
+        w.add("art/Intrface/A.FRM", &compressible).unwrap();
+        w.add("text\\english\\b.msg", &incompressible).unwrap();
+        w.add("empty.txt", &[]).unwrap();
+        w.finish().unwrap();
+
+        let dat = Dat::new(&path).unwrap();
+        assert_eq!(dat.files.len(), 3);
+        assert!(dat.file("art/intrface/a.frm").unwrap().is_compressed());
+        assert!(!dat.file("text/english/b.msg").unwrap().is_compressed());
+        assert_eq!(dat.metadata("art\\intrface\\a.frm").unwrap().len(), 1000);
+        assert_eq!(read(&dat, "ART/INTRFACE/A.FRM"), compressible);
+        assert_eq!(read(&dat, "text/english/b.msg"), incompressible);
+        assert_eq!(read(&dat, "empty.txt"), Vec::<u8>::new());
+
+        for (path, data) in &[("art/intrface/a.frm", &compressible), ("text/english/b.msg", &incompressible)] {
+            let mut rd = dat.seekable_reader(path).unwrap();
+            let mut buf = [0; 10];
+            rd.seek(SeekFrom::Start(100)).unwrap();
+            rd.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf[..], &data[100..110]);
+            rd.seek(SeekFrom::End(-5)).unwrap();
+            let mut buf = Vec::new();
+            rd.read_to_end(&mut buf).unwrap();
+            assert_eq!(&buf[..], &data[data.len() - 5..]);
+        }
+
+        assert_eq!(list(&path).unwrap(), vec![
+            Entry { path: "art\\intrface\\a.frm".into(), size: 1000,
+                compressed_size: Some(dat.file("art/intrface/a.frm").unwrap().compressed_size) },
+            Entry { path: "empty.txt".into(), size: 0, compressed_size: None },
+            Entry { path: "text\\english\\b.msg".into(), size: 200, compressed_size: None },
+        ]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pack_unpack() {
+        let dir = temp_dir("dat2-pack");
+        let src = dir.join("src");
+        let files: &[(&str, &[u8])] = &[
+            ("maps/test.map", b"map data map data map data map data"),
+            ("scripts/scripts.lst", b"test.int ; Test\r\n"),
+            ("scripts/sub/x.int", b"x"),
+            ("vault13.gam", b""),
+        ];
+        for (path, data) in files {
+            let path = src.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, data).unwrap();
+        }
+
+        let dat_path = dir.join("patch000.dat");
+        assert_eq!(pack(&src, &dat_path).unwrap(), files.len());
+
+        let dat = new_provider(&dat_path).unwrap();
+        for (path, data) in files {
+            let mut actual = Vec::new();
+            dat.reader(path).unwrap().read_to_end(&mut actual).unwrap();
+            assert_eq!(&actual[..], *data);
+        }
+
+        let dst = dir.join("dst");
+        assert_eq!(unpack(&dat_path, &dst).unwrap(), files.len());
+        for (path, data) in files {
+            assert_eq!(&fs::read(dst.join(path)).unwrap()[..], *data);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+/// Glob pattern for matching file paths. Matching is case-insensitive and both `/` and `\`
+/// are accepted as separators.
+///
+/// Supported wildcards:
+/// * `?` - any single character except separator;
+/// * `*` - any number of characters except separator;
+/// * `**` as the whole path component - any number of path components.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    components: Vec<String>,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Self {
+        let components = split(pattern)
+            .map(|s| s.to_ascii_lowercase())
+            .collect();
+        Self {
+            components,
+        }
+    }
+
+    /// Returns `true` if the file `path` matches the pattern.
+    pub fn matches(&self, path: &str) -> bool {
+        let path: Vec<_> = split(path).collect();
+        match_components(&self.components, &path, false)
+    }
+
+    /// Returns `true` if there can be matching files inside the `dir`.
+    pub fn matches_dir(&self, dir: &str) -> bool {
+        let dir: Vec<_> = split(dir).collect();
+        match_components(&self.components, &dir, true)
+    }
+}
+
+fn split(path: &str) -> impl Iterator<Item=&str> {
+    path.split(['/', '\\'])
+        .filter(|s| !s.is_empty() && *s != ".")
+}
+
+fn match_components(pattern: &[String], path: &[&str], dir: bool) -> bool {
+    match (pattern.split_first(), path.split_first()) {
+        (Some((p, pattern_rest)), _) if p == "**" =>
+            match_components(pattern_rest, path, dir)
+                || !path.is_empty() && match_components(pattern, &path[1..], dir),
+        (Some((p, pattern_rest)), Some((s, path_rest))) =>
+            match_component(p.as_bytes(), s.to_ascii_lowercase().as_bytes())
+                && match_components(pattern_rest, path_rest, dir),
+        (Some(_), None) => dir,
+        (None, Some(_)) => false,
+        (None, None) => !dir,
+    }
+}
+
+fn match_component(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        Some((b'*', rest)) => (0..=s.len()).any(|i| match_component(rest, &s[i..])),
+        Some((b'?', rest)) => !s.is_empty() && match_component(rest, &s[1..]),
+        Some((&c, rest)) => s.first() == Some(&c) && match_component(rest, &s[1..]),
+        None => s.is_empty(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matches() {
+        let data = &[
+            ("art/critters/*.frm", "art/critters/hmjmpsaa.frm", true),
+            ("art/critters/*.frm", "ART\\Critters\\HMJMPSAA.FRM", true),
+            ("art/critters/*.frm", "art/critters/hmjmpsaa.fr0", false),
+            ("art/critters/*.frm", "art/critters/sub/hmjmpsaa.frm", false),
+            ("art/critters/*.fr?", "art/critters/hmjmpsaa.fr0", true),
+            ("art/critters/*.fr?", "art/critters/hmjmpsaa.fr", false),
+            ("*", "color.pal", true),
+            ("*", "art/critters.lst", false),
+            ("**", "art/critters/x.frm", true),
+            ("**/*.lst", "scripts/scripts.lst", true),
+            ("**/*.lst", "scripts.lst", true),
+            ("art/**/*.lst", "art/critters/critters.lst", true),
+            ("art/**/*.lst", "art/a/b/c.lst", true),
+            ("art/**/*.lst", "data/a.lst", false),
+            ("maps/a*b*c.map", "maps/abc.map", true),
+            ("maps/a*b*c.map", "maps/a1b2c3.map", false),
+            ("./maps/*.map", "maps/x.map", true),
+            ("", "x", false),
+        ];
+        for &(pattern, path, expected) in data {
+            assert_eq!(Pattern::new(pattern).matches(path), expected, "{} {}", pattern, path);
+        }
+    }
+
+    #[test]
+    fn matches_dir() {
+        let data = &[
+            ("art/critters/*.frm", "", true),
+            ("art/critters/*.frm", "art", true),
+            ("art/critters/*.frm", "Art/Critters", true),
+            ("art/critters/*.frm", "art/critters/sub", false),
+            ("art/critters/*.frm", "maps", false),
+            ("**/*.lst", "a/b/c", true),
+            ("*/*.lst", "a", true),
+            ("*/*.lst", "a/b", false),
+        ];
+        for &(pattern, dir, expected) in data {
+            assert_eq!(Pattern::new(pattern).matches_dir(dir), expected, "{} {}", pattern, dir);
+        }
+    }
+}
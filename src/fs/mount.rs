@@ -0,0 +1,240 @@
+//! Discovery of the resource directories and DAT files to mount based on `fallout2.cfg` and
+//! the mods list.
+
+use log::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Result};
+use std::path::{Path, PathBuf};
+
+use crate::asset::read_ini;
+use super::FileSystem;
+use super::std::StdFileSystem;
+
+/// Resource locations from the `[system]` section of `fallout2.cfg`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Config {
+    pub master_dat: String,
+    pub master_patches: String,
+    pub critter_dat: String,
+    pub critter_patches: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            master_dat: "master.dat".into(),
+            master_patches: "data".into(),
+            critter_dat: "critter.dat".into(),
+            critter_patches: "data".into(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads `fallout2.cfg` from the `res_dir`. Returns default config if there's no such file.
+    pub fn read(res_dir: &Path) -> Result<Self> {
+        let mut r = Self::default();
+        if let Some(path) = find_path(&StdFileSystem::new(res_dir), "fallout2.cfg") {
+            info!("Found {}", path.display());
+            let ini = read_ini(&mut BufReader::new(File::open(path)?))?;
+            if let Some(sys) = ini.get("system") {
+                let get = |key: &str, v: &mut String| {
+                    if let Some(s) = sys.get(key).filter(|s| !s.is_empty()) {
+                        *v = s.clone();
+                    }
+                };
+                get("master_dat", &mut r.master_dat);
+                get("master_patches", &mut r.master_patches);
+                get("critter_dat", &mut r.critter_dat);
+                get("critter_patches", &mut r.critter_patches);
+            }
+        }
+        Ok(r)
+    }
+}
+
+/// Reads mods list in the `ddraw.ini` format. The mods are listed in the `[ExtraPatches]`
+/// section as `PatchFileN=path` entries where `path` is a directory or DAT file.
+/// `PatchFile0` has the highest priority. Returns paths ordered by priority.
+pub fn read_mods(path: &Path) -> Result<Vec<String>> {
+    let ini = read_ini(&mut BufReader::new(File::open(path)?))?;
+    let empty = HashMap::new();
+    let section = ini.get("ExtraPatches").unwrap_or(&empty);
+    let mut r: Vec<_> = section.iter()
+        .filter_map(|(k, v)| {
+            let prio = k.strip_prefix("PatchFile")?.parse::<u32>().ok()?;
+            Some((prio, v))
+        })
+        .filter(|(_, v)| !v.is_empty())
+        .collect();
+    r.sort();
+    Ok(r.into_iter().map(|(_, v)| v.clone()).collect())
+}
+
+/// Returns paths of directories and DAT files to mount ordered by priority:
+///
+/// 1. Mods from the `mods` list.
+/// 2. `critter_patches` and `master_patches` directories.
+/// 3. `critter_dat` and `master_dat` files.
+/// 4. `patchXXX.dat` files.
+pub fn mounts(res_dir: &Path, config: &Config, mods: &[String]) -> Vec<PathBuf> {
+    let res_fs = &StdFileSystem::new(res_dir);
+    let mut r = Vec::new();
+    let mut add = |path: Option<PathBuf>, desc: &str| {
+        if let Some(path) = path {
+            if !r.contains(&path) {
+                info!("Found {}: {}", desc, path.display());
+                r.push(path);
+            }
+        }
+    };
+
+    for m in mods {
+        let path = find_path(res_fs, m);
+        if path.is_none() {
+            warn!("Mod not found: {}", m);
+        }
+        add(path, "mod");
+    }
+
+    add(find_path(res_fs, &config.critter_patches).filter(|p| p.is_dir()), "critter_patches");
+    add(find_path(res_fs, &config.master_patches).filter(|p| p.is_dir()), "master_patches");
+    add(find_path(res_fs, &config.critter_dat).filter(|p| p.is_file()), "critter_dat");
+    add(find_path(res_fs, &config.master_dat).filter(|p| p.is_file()), "master_dat");
+
+    for i in 0..999 {
+        if let Some(path) = find_path(res_fs, &format!("patch{:03}.dat", i))
+            .filter(|p| p.is_file())
+        {
+            add(Some(path), "patch");
+        } else {
+            break;
+        }
+    }
+
+    r
+}
+
+/// Registers providers for the resource directories and DAT files in `res_dir`. The `mods` is
+/// the optional mods list file.
+pub fn mount(fs: &mut FileSystem, res_dir: &Path, mods: Option<&Path>) -> Result<()> {
+    let config = Config::read(res_dir)?;
+    let mods = if let Some(path) = mods {
+        read_mods(path)?
+    } else if let Some(path) = find_path(&StdFileSystem::new(res_dir), "ddraw.ini") {
+        info!("Found {}", path.display());
+        read_mods(&path)?
+    } else {
+        Vec::new()
+    };
+
+    for path in mounts(res_dir, &config, &mods) {
+        let provider = if path.is_dir() {
+            super::std::new_provider(&path)?
+        } else {
+            let version = super::dat::detect_version(&path)?;
+            debug!("{} is {:?} archive", path.display(), version);
+            super::dat::new_provider(&path)?
+        };
+        fs.register_provider(provider);
+    }
+    Ok(())
+}
+
+/// Resolves `path` relative to the `res_fs` root. Absolute `path` is used as is.
+/// Returns `None` if the path doesn't exist.
+fn find_path(res_fs: &StdFileSystem, path: &str) -> Option<PathBuf> {
+    let path = path.replace('\\', "/");
+    let r = if Path::new(&path).is_absolute() {
+        PathBuf::from(path)
+    } else {
+        res_fs.resolve(&path)
+    };
+    if r.exists() {
+        Some(r)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::io::prelude::*;
+    use crate::fs::dat::v2;
+
+    fn write_dat(path: &Path, files: &[(&str, &[u8])]) {
+        let mut w = v2::Writer::new(File::create(path).unwrap());
+        for (path, data) in files {
+            w.add(path, data).unwrap();
+        }
+        w.finish().unwrap();
+    }
+
+    #[test]
+    fn mount_() {
+        let dir = std::env::temp_dir().join(format!("vault13-test-{}-mount", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("Mods/Foo")).unwrap();
+        fs::create_dir_all(dir.join("patches")).unwrap();
+
+        fs::write(dir.join("fallout2.cfg"), "\
+            [sound]\n\
+            music_path1=sound\\music\\\n\
+            [system]\n\
+            master_dat=Master.dat\n\
+            master_patches=patches\n\
+            critter_dat=critter.dat\n\
+            critter_patches=\n").unwrap();
+        fs::write(dir.join("ddraw.ini"), "\
+            [Main]\n\
+            Foo=1\n\
+            [ExtraPatches]\n\
+            PatchFile10=mods\\bar.dat\n\
+            PatchFile2=mods\\foo\n\
+            PatchFile3=mods\\missing.dat\n").unwrap();
+
+        write_dat(&dir.join("MASTER.DAT"), &[("a.txt", b"master"), ("b.txt", b"master")]);
+        write_dat(&dir.join("critter.dat"), &[("c.txt", b"critter")]);
+        write_dat(&dir.join("patch000.dat"), &[("a.txt", b"patch000"), ("d.txt", b"patch000")]);
+        write_dat(&dir.join("Mods/bar.dat"), &[("a.txt", b"bar"), ("b.txt", b"bar")]);
+        fs::write(dir.join("Mods/Foo/a.txt"), b"foo").unwrap();
+        fs::write(dir.join("patches/b.txt"), b"patches").unwrap();
+
+        let config = Config::read(&dir).unwrap();
+        assert_eq!(config, Config {
+            master_dat: "Master.dat".into(),
+            master_patches: "patches".into(),
+            critter_dat: "critter.dat".into(),
+            critter_patches: "data".into(),
+        });
+
+        let mods = read_mods(&dir.join("ddraw.ini")).unwrap();
+        assert_eq!(mods, vec!["mods\\foo", "mods\\missing.dat", "mods\\bar.dat"]);
+
+        assert_eq!(mounts(&dir, &config, &mods), vec![
+            dir.join("Mods/Foo"),
+            dir.join("Mods/bar.dat"),
+            dir.join("patches"),
+            dir.join("critter.dat"),
+            dir.join("MASTER.DAT"),
+            dir.join("patch000.dat"),
+        ]);
+
+        let mut fs = FileSystem::new();
+        mount(&mut fs, &dir, None).unwrap();
+        let read = |path| {
+            let mut s = String::new();
+            fs.reader(path).unwrap().read_to_string(&mut s).unwrap();
+            s
+        };
+        assert_eq!(read("a.txt"), "foo");
+        assert_eq!(read("b.txt"), "bar");
+        assert_eq!(read("c.txt"), "critter");
+        assert_eq!(read("d.txt"), "patch000");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
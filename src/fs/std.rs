@@ -1,39 +1,280 @@
-use std::fs::File;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
-use std::io::{BufRead, BufReader, Result};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
+use std::time::SystemTime;
 
-use super::{Metadata, Provider};
+use super::{DirEntry, Metadata, Pattern, Provider, ReadSeek};
 
 pub fn new_provider<P: AsRef<Path>>(path: P) -> Result<Box<dyn Provider>> {
     Ok(Box::new(StdFileSystem::new(path)))
 }
 
-struct StdFileSystem {
+/// File system backed by a directory of the host file system. Paths are resolved
+/// case-insensitively as on Windows. Directory contents are cached and rescanned on
+/// modification.
+pub struct StdFileSystem {
     root: PathBuf,
+    writable: bool,
+    index: RefCell<HashMap<PathBuf, DirIndex>>,
+}
+
+/// Actual names of the directory entries by their lower case names.
+struct DirIndex {
+    modified: Option<SystemTime>,
+    names: HashMap<String, OsString>,
+}
+
+impl DirIndex {
+    fn scan(dir: &Path) -> Self {
+        let modified = dir.metadata().and_then(|m| m.modified()).ok();
+        let mut names = HashMap::new();
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                let name = entry.file_name();
+                if let Some(s) = name.to_str() {
+                    let key = s.to_ascii_lowercase();
+                    // Prefer exact lower case match if there are multiple candidates.
+                    if key != s && names.contains_key(&key) {
+                        continue;
+                    }
+                    names.insert(key, name);
+                }
+            }
+        }
+        Self {
+            modified,
+            names,
+        }
+    }
 }
 
 impl StdFileSystem {
     pub fn new<P: AsRef<Path>>(root: P) -> Self {
-        StdFileSystem { root: root.as_ref().to_path_buf() }
+        StdFileSystem {
+            root: root.as_ref().to_path_buf(),
+            writable: false,
+            index: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Creates file system that also allows creating files and directories.
+    pub fn new_writable<P: AsRef<Path>>(root: P) -> Self {
+        StdFileSystem {
+            writable: true,
+            .. Self::new(root)
+        }
     }
 
-    fn to_fs_path(&self, path: &str) -> PathBuf {
-        let mut r = PathBuf::new();
-        r.push(&self.root);
-        for s in path.split(|c| c == '/' || c == '\\') {
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns host path of the `path`. Existing path components are matched
+    /// case-insensitively, the rest are appended as is.
+    pub fn resolve(&self, path: &str) -> PathBuf {
+        let mut r = self.root.clone();
+        let mut found = true;
+        for s in path.split(['/', '\\']).filter(|s| !s.is_empty() && *s != ".") {
+            if found {
+                if let Some(name) = self.lookup(&r, s) {
+                    r.push(name);
+                    continue;
+                }
+                found = false;
+            }
             r.push(s);
         }
         r
     }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.resolve(path).exists()
+    }
+
+    /// Creates directory at `path` along with all missing parents. Returns host path
+    /// of the directory.
+    pub fn create_dir_all(&self, path: &str) -> Result<PathBuf> {
+        self.check_writable()?;
+        let r = self.resolve(path);
+        fs::create_dir_all(&r)?;
+        Ok(r)
+    }
+
+    /// Creates or truncates file at `path`. Missing parent directories are created.
+    pub fn create(&self, path: &str) -> Result<File> {
+        self.check_writable()?;
+        let r = self.resolve(path);
+        if let Some(parent) = r.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        File::create(r)
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.writable {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::PermissionDenied,
+                format!("file system is read-only: {}", self.root.display())))
+        }
+    }
+
+    /// Returns actual name of the `name` entry in the `dir`.
+    fn lookup(&self, dir: &Path, name: &str) -> Option<OsString> {
+        let key = name.to_ascii_lowercase();
+        let mut index = self.index.borrow_mut();
+        if let Some(dir_index) = index.get(dir) {
+            if let Some(r) = dir_index.names.get(&key) {
+                return Some(r.clone());
+            }
+            let modified = dir.metadata().and_then(|m| m.modified()).ok();
+            if modified == dir_index.modified {
+                return None;
+            }
+        }
+        let dir_index = DirIndex::scan(dir);
+        let r = dir_index.names.get(&key).cloned();
+        index.insert(dir.to_path_buf(), dir_index);
+        r
+    }
+
+    /// `ancestors` holds canonical paths of the directories being walked and is used to break
+    /// cycles formed by symlinks.
+    fn walk_dir(fs_dir: &Path, dir: &str, pattern: &Pattern, ancestors: &mut HashSet<PathBuf>,
+        out: &mut Vec<String>) -> Result<()>
+    {
+        let canonical = fs_dir.canonicalize()?;
+        if ancestors.contains(&canonical) {
+            return Ok(());
+        }
+        ancestors.insert(canonical.clone());
+        for entry in fs::read_dir(fs_dir)? {
+            let entry = entry?;
+            let name = if let Some(s) = entry.file_name().to_str() {
+                s.to_ascii_lowercase()
+            } else {
+                continue;
+            };
+            let path = if dir.is_empty() {
+                name
+            } else {
+                format!("{}/{}", dir, name)
+            };
+            if entry.path().is_dir() {
+                if pattern.matches_dir(&path) {
+                    Self::walk_dir(&entry.path(), &path, pattern, ancestors, out)?;
+                }
+            } else if pattern.matches(&path) {
+                out.push(path);
+            }
+        }
+        ancestors.remove(&canonical);
+        Ok(())
+    }
 }
 
 impl Provider for StdFileSystem {
+    fn name(&self) -> String {
+        self.root.display().to_string()
+    }
+
     fn reader(&self, path: &str) -> Result<Box<dyn BufRead + Send>> {
-        Ok(Box::new(BufReader::new(File::open(self.to_fs_path(path))?)))
+        Ok(Box::new(BufReader::new(File::open(self.resolve(path))?)))
+    }
+
+    fn seekable_reader(&self, path: &str) -> Result<Box<dyn ReadSeek>> {
+        Ok(Box::new(BufReader::new(File::open(self.resolve(path))?)))
     }
 
     fn metadata(&self, path: &str) -> Result<Metadata> {
-        let len = self.to_fs_path(path).metadata()?.len();
+        let len = self.resolve(path).metadata()?.len();
         Ok(Metadata { len })
     }
+
+    fn list(&self, dir: &str) -> Result<Vec<DirEntry>> {
+        let fs_dir = self.resolve(dir);
+        if !fs_dir.is_dir() {
+            return Err(Error::new(ErrorKind::NotFound, "directory not found"));
+        }
+        let mut r = Vec::new();
+        for entry in fs::read_dir(fs_dir)? {
+            let entry = entry?;
+            if let Some(name) = entry.file_name().to_str() {
+                r.push(DirEntry::new(name.to_ascii_lowercase(), entry.path().is_dir()));
+            }
+        }
+        r.sort();
+        r.dedup_by(|a, b| a.name() == b.name());
+        Ok(r)
+    }
+
+    fn walk(&self, pattern: &Pattern) -> Result<Vec<String>> {
+        let mut r = Vec::new();
+        Self::walk_dir(&self.root, "", pattern, &mut HashSet::new(), &mut r)?;
+        r.sort();
+        r.dedup();
+        Ok(r)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::prelude::*;
+
+    #[test]
+    fn case_insensitive() {
+        let dir = std::env::temp_dir().join(format!("vault13-test-{}-std-fs", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("DATA/Maps")).unwrap();
+        fs::write(dir.join("DATA/Maps/ARTEMPLE.MAP"), b"map").unwrap();
+
+        let fs = StdFileSystem::new(&dir);
+        assert_eq!(fs.resolve("data/maps/artemple.map"), dir.join("DATA/Maps/ARTEMPLE.MAP"));
+        assert_eq!(fs.resolve("Data\\MAPS\\new.sav"), dir.join("DATA/Maps/new.sav"));
+        assert_eq!(fs.resolve("data/x/y"), dir.join("DATA/x/y"));
+        assert_eq!(fs.metadata("data/maps/artemple.map").unwrap().len(), 3);
+        let mut s = String::new();
+        fs.reader("./data/maps/ArTeMpLe.map").unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "map");
+        assert_eq!(fs.list("data").unwrap(), vec![DirEntry::new("maps", true)]);
+        assert_eq!(fs.walk(&Pattern::new("**/*.map")).unwrap(), vec!["data/maps/artemple.map"]);
+
+        // Read-only.
+        assert_eq!(fs.create("data/maps/new.sav").unwrap_err().kind(), ErrorKind::PermissionDenied);
+
+        // Files created externally are found after rescan.
+        assert!(!fs.exists("data/maps/Other.map"));
+        fs::write(dir.join("DATA/Maps/OTHER.MAP"), b"").unwrap();
+        fs.index.borrow_mut().get_mut(&dir.join("DATA/Maps")).unwrap().modified = None;
+        assert!(fs.exists("data/maps/other.map"));
+
+        let fs = StdFileSystem::new_writable(&dir);
+        fs.create("data/savegame/slot01/save.dat").unwrap().write_all(b"save").unwrap();
+        assert_eq!(fs::read(dir.join("DATA/savegame/slot01/save.dat")).unwrap(), b"save");
+        assert_eq!(fs.create_dir_all("DATA/MAPS").unwrap(), dir.join("DATA/Maps"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_symlink_cycle() {
+        let dir = std::env::temp_dir().join(format!("vault13-test-{}-std-fs-cycle",
+            std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("data/maps")).unwrap();
+        fs::write(dir.join("data/maps/artemple.map"), b"map").unwrap();
+        std::os::unix::fs::symlink(dir.join("data"), dir.join("data/maps/loop")).unwrap();
+        std::os::unix::fs::symlink(dir.join("data/maps"), dir.join("maps")).unwrap();
+
+        let fs = StdFileSystem::new(&dir);
+        assert_eq!(fs.walk(&Pattern::new("**/*.map")).unwrap(),
+            vec!["data/maps/artemple.map", "maps/artemple.map"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,14 +1,21 @@
+pub mod ai;
+pub mod combat;
 pub mod dialog;
 pub mod fidget;
 pub mod inventory;
+pub mod movie;
 pub mod object;
 pub mod rpg;
+pub mod say;
 pub mod script;
+pub mod script_window;
 pub mod sequence;
 pub mod skilldex;
+pub mod sound;
 pub mod state;
 pub mod ui;
 pub mod world;
+pub mod worldmap;
 
 use crate::util::random::RollChecker;
 
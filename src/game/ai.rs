@@ -0,0 +1,669 @@
+use std::cmp;
+
+use crate::asset::{AttackCategory, AttackGroup, AttackKind, Flag, Stat};
+use crate::asset::ai::*;
+use crate::asset::proto::{DrugEffectModifier, Weapon};
+use crate::game::combat::{self, UNARMED_AP_COST, USE_DRUG_AP_COST, WIELD_AP_COST};
+use crate::game::object::{EquipmentSlot, Handle, Hand, Object, Objects, PathTo};
+use crate::game::rpg::Rpg;
+use crate::graphics::Point;
+use crate::graphics::geometry::hex;
+use crate::util::random::random;
+
+/// What critter wants to do next during its combat turn.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Decision {
+    Attack(Handle),
+    /// Walk to the point spending one AP per step.
+    Move {
+        to: Point,
+        steps: i32,
+    },
+    Wield(Handle),
+    /// Use healing drug from the inventory.
+    UseDrug(Handle),
+    EndTurn,
+}
+
+/// Critters with `Distance::Snipe` retreat from targets closer than this.
+const SNIPE_DISTANCE: i32 = 10;
+
+pub struct Ai {
+    db: AiDb,
+    default_packet: Packet,
+}
+
+impl Ai {
+    pub fn new(db: AiDb) -> Self {
+        Self {
+            db,
+            default_packet: Packet::default(),
+        }
+    }
+
+    // ai_cap()
+    pub fn packet(&self, obj: &Object) -> &Packet {
+        obj.sub.as_critter()
+            .and_then(|c| self.db.get(c.combat.ai_packet))
+            .unwrap_or(&self.default_packet)
+    }
+
+    /// Chooses whom `obj` should attack according to its AI packet. `attackers` are the critters
+    /// attacking `obj`, `enemies` are the critters attacking its team mates. The latter are
+    /// considered only if the aggression roll succeeds.
+    // ai_danger_source()
+    pub fn choose_target(&self,
+        obj: Handle,
+        current: Option<Handle>,
+        attackers: &[Handle],
+        enemies: &[Handle],
+        rpg: &Rpg,
+        objs: &Objects,
+    ) -> Option<Handle> {
+        let objo = objs.get(obj);
+        let packet = self.packet(&objo);
+        let alive = |h: Handle| h != obj
+            && objs.get(h).sub.as_critter().map(|c| !c.is_dead()) == Some(true);
+        let distance = |h: Handle| objs.distance(obj, h).unwrap_or(u32::MAX);
+        let hit_points = |h: Handle| rpg.stat(Stat::CurrentHitPoints, &objs.get(h), objs);
+
+        let current = current.filter(|&h| alive(h));
+        let enemies = if packet.attack_who != Some(AttackWho::WhomeverAttackingMe)
+            && random(1, 100) <= packet.aggression
+        {
+            enemies
+        } else {
+            &[]
+        };
+        let candidates = current.into_iter()
+            .chain(attackers.iter().copied())
+            .chain(enemies.iter().copied())
+            .filter(|&h| alive(h));
+        match packet.attack_who {
+            None | Some(AttackWho::WhomeverAttackingMe) | Some(AttackWho::Whomever) =>
+                current.or_else(|| candidates.min_by_key(|&h| distance(h))),
+            Some(AttackWho::Closest) => candidates.min_by_key(|&h| distance(h)),
+            Some(AttackWho::Strongest) => candidates.max_by_key(|&h| hit_points(h)),
+            Some(AttackWho::Weakest) => candidates.min_by_key(|&h| hit_points(h)),
+        }
+    }
+
+    /// Returns the weapon from `obj`'s inventory that fits its AI packet best.
+    /// Returns `None` if the currently wielded weapon is the best one.
+    // ai_best_weapon()
+    pub fn best_weapon(&self, obj: &Object, objs: &Objects) -> Option<Handle> {
+        let pref = self.packet(obj).best_weapon.unwrap_or(BestWeapon::NoPref);
+        let current = wielded_weapon(obj, objs);
+        let score = |h: Handle| {
+            let o = objs.get(h);
+            let proto = o.proto().unwrap();
+            let weapon = proto.sub.as_weapon()?;
+            if proto.max_ammo_count().unwrap_or(0) > 0 && o.ammo_count() == Some(0) {
+                return None;
+            }
+            weapon_score(weapon, pref)
+                .map(|s| s + (Some(h) == current) as i32)
+        };
+        let best = obj.inventory.items.iter()
+            .map(|i| i.object)
+            .filter_map(|h| score(h).map(|s| (h, s)))
+            .max_by_key(|&(_, s)| s)
+            .map(|(h, _)| h);
+        if best != current {
+            best
+        } else {
+            None
+        }
+    }
+
+    /// Decides the next action for `obj` that has `action_points` left.
+    // combat_ai()
+    pub fn decide(&self,
+        obj: Handle,
+        target: Option<Handle>,
+        action_points: i32,
+        rpg: &Rpg,
+        objs: &Objects,
+    ) -> Decision {
+        if action_points <= 0 {
+            return Decision::EndTurn;
+        }
+        let objo = objs.get(obj);
+        let packet = self.packet(&objo);
+
+        let hit_points = rpg.stat(Stat::CurrentHitPoints, &objo, objs);
+        let max_hit_points = rpg.stat(Stat::HitPoints, &objo, objs);
+        if action_points >= USE_DRUG_AP_COST
+            && hit_points < heal_hit_points(packet.chem_use, max_hit_points)
+        {
+            if let Some(drug) = healing_drug(&objo, objs) {
+                return Decision::UseDrug(drug);
+            }
+        }
+
+        if hit_points < run_away_hit_points(packet, max_hit_points) {
+            return if let Some(target) = target {
+                run_away(obj, target, action_points, objs)
+            } else {
+                Decision::EndTurn
+            };
+        }
+
+        let target = if let Some(target) = target {
+            target
+        } else {
+            return Decision::EndTurn;
+        };
+
+        if action_points >= WIELD_AP_COST {
+            if let Some(weapon) = self.best_weapon(&objo, objs) {
+                return Decision::Wield(weapon);
+            }
+        }
+
+        let weapono = wielded_weapon(&objo, objs).map(|h| objs.get(h));
+        let weapon_proto = weapono.as_ref().map(|o| o.proto().unwrap());
+        let w = weapon_proto.as_ref().map(|p| p.sub.as_weapon().unwrap());
+        let (attack_kind, range, ap_cost) = if let (Some(weapono), Some(w)) = (&weapono, w) {
+            (w.attack_kinds[AttackGroup::Primary],
+                weapono.weapon_range(AttackGroup::Primary, rpg, objs).unwrap(),
+                w.ap_costs[AttackGroup::Primary])
+        } else {
+            (AttackKind::Punch, 1, UNARMED_AP_COST)
+        };
+        let ranged = !attack_kind.category().is_melee();
+
+        let distance = if let Some(v) = objs.distance(obj, target) {
+            v as i32
+        } else {
+            return Decision::EndTurn;
+        };
+
+        if distance <= range && !(ranged && objs.is_shot_blocked(obj, target)) {
+            if action_points < ap_cost {
+                return self.spend_action_points(obj, target, distance, packet, action_points,
+                    objs);
+            }
+            let hit_chance = hit_chance(&objo, &objs.get(target), w, attack_kind,
+                distance as u32, rpg, objs);
+            if hit_chance >= packet.min_to_hit {
+                return Decision::Attack(target);
+            }
+            // Get closer to improve the chance but leave enough AP for the attack.
+            return if packet.distance != Some(Distance::Stay) {
+                approach(obj, target, cmp::min(action_points - ap_cost, distance - 1), objs)
+            } else {
+                Decision::EndTurn
+            };
+        }
+
+        if packet.distance == Some(Distance::Stay) {
+            return Decision::EndTurn;
+        }
+        if let Some(d) = self.stay_close(obj, packet, action_points, objs) {
+            return d;
+        }
+
+        approach(obj, target, cmp::min(action_points, cmp::max(distance - range, 1)), objs)
+    }
+
+    /// Decides what to do with the action points left after `obj` can't attack anymore.
+    // combat_ai()
+    fn spend_action_points(&self,
+        obj: Handle,
+        target: Handle,
+        distance: i32,
+        packet: &Packet,
+        action_points: i32,
+        objs: &Objects,
+    ) -> Decision {
+        match packet.distance {
+            Some(Distance::Charge) =>
+                approach(obj, target, cmp::min(action_points, distance - 1), objs),
+            Some(Distance::Snipe) if distance < SNIPE_DISTANCE =>
+                run_away(obj, target, action_points, objs),
+            Some(Distance::StayClose) => self.stay_close(obj, packet, action_points, objs)
+                .unwrap_or(Decision::EndTurn),
+            Some(Distance::Snipe)
+            | Some(Distance::OnYourOwn)
+            | Some(Distance::Stay)
+            | None
+            => Decision::EndTurn,
+        }
+    }
+
+    /// Returns the decision to walk back to the dude if `obj` has `Distance::StayClose` and is
+    /// farther than `max_dist` from the dude.
+    fn stay_close(&self,
+        obj: Handle,
+        packet: &Packet,
+        action_points: i32,
+        objs: &Objects,
+    ) -> Option<Decision> {
+        if packet.distance != Some(Distance::StayClose) {
+            return None;
+        }
+        let dude = objs.dude();
+        if dude == obj {
+            return None;
+        }
+        let distance = objs.distance(obj, dude)? as i32;
+        let max_distance = cmp::max(packet.max_dist, 1);
+        if distance <= max_distance {
+            return None;
+        }
+        Some(approach(obj, dude, cmp::min(action_points, distance - max_distance), objs))
+    }
+}
+
+/// Returns the chance in percents of `attacker` hitting `target` that is `distance` hexes away
+/// with `attack_kind` of `weapon`.
+// determine_to_hit()
+pub fn hit_chance(
+    attacker: &Object,
+    target: &Object,
+    weapon: Option<&Weapon>,
+    attack_kind: AttackKind,
+    distance: u32,
+    rpg: &Rpg,
+    objs: &Objects,
+) -> i32 {
+    let category = attack_kind.category();
+    combat::hit_chance(combat::ToHit {
+        skill: rpg.skill(combat::weapon_skill(weapon, attack_kind), attacker, objs),
+        perception: rpg.stat(Stat::Perception, attacker, objs),
+        attacker_is_dude: attacker.is_dude(),
+        ranged: category == AttackCategory::Fire || category == AttackCategory::Throw,
+        distance,
+        armor_class: rpg.defense_stat(Stat::ArmorClass, target, objs),
+        target_multi_hex: target.flags.contains(Flag::MultiHex),
+    })
+}
+
+/// Returns the weapon `obj` currently holds.
+/// The dude attacks with the active hand, other critters - with the right hand.
+pub fn wielded_weapon(obj: &Object, objs: &Objects) -> Option<Handle> {
+    let hand = obj.sub.as_critter()
+        .and_then(|c| c.try_dude())
+        .map(|d| d.active_hand)
+        .unwrap_or(Hand::Right);
+    obj.equipment(EquipmentSlot::Hand(hand), objs)
+        .filter(|&h| objs.get(h).proto().unwrap().sub.as_weapon().is_some())
+}
+
+fn weapon_score(weapon: &Weapon, pref: BestWeapon) -> Option<i32> {
+    const PREFERRED: i32 = 1000;
+
+    let category = weapon.attack_kinds[AttackGroup::Primary].category();
+    let ranged = category == AttackCategory::Fire || category == AttackCategory::Throw;
+    let bonus = match pref {
+        BestWeapon::NoPref | BestWeapon::Random => 0,
+        BestWeapon::Melee if category != AttackCategory::MeleeWeapon => return None,
+        BestWeapon::Melee => 0,
+        BestWeapon::MeleeOverRanged if category.is_melee() => PREFERRED,
+        BestWeapon::MeleeOverRanged => 0,
+        BestWeapon::RangedOverMelee if ranged => PREFERRED,
+        BestWeapon::RangedOverMelee => 0,
+        BestWeapon::Ranged if !ranged => return None,
+        BestWeapon::Ranged => 0,
+        BestWeapon::Unarmed if category != AttackCategory::MeleeUnarmed => return None,
+        BestWeapon::Unarmed => 0,
+        BestWeapon::UnarmedOverThrown => match category {
+            AttackCategory::MeleeUnarmed => PREFERRED,
+            AttackCategory::Throw => PREFERRED / 2,
+            _ => 0,
+        }
+    };
+    Some(bonus + 2 * weapon.damage.end)
+}
+
+fn walk(from: Point, path: &[hex::Direction], steps: i32) -> Decision {
+    let steps = cmp::min(steps as usize, path.len());
+    if steps == 0 {
+        return Decision::EndTurn;
+    }
+    let to = path[..steps].iter().fold(from, |p, &dir| hex::go(p, dir, 1));
+    Decision::Move {
+        to,
+        steps: steps as i32,
+    }
+}
+
+fn approach(obj: Handle, target: Handle, steps: i32, objs: &Objects) -> Decision {
+    if steps <= 0 {
+        return Decision::EndTurn;
+    }
+    objs.path(obj, PathTo::Object(target), false)
+        .map(|path| walk(objs.get(obj).pos().point, &path, steps))
+        .unwrap_or(Decision::EndTurn)
+}
+
+/// Returns hit points below which the critter runs away.
+// ai_check_run_away()
+fn run_away_hit_points(packet: &Packet, max_hit_points: i32) -> i32 {
+    let percent = match packet.run_away_mode {
+        None | Some(RunAwayMode::None) => return packet.min_hp,
+        Some(RunAwayMode::Coward) => 0,
+        Some(RunAwayMode::FingerHurts) => 25,
+        Some(RunAwayMode::Bleeding) => 40,
+        Some(RunAwayMode::NotFeelingGood) => 60,
+        Some(RunAwayMode::Tourniquet) => 75,
+        Some(RunAwayMode::Never) => 100,
+    };
+    max_hit_points - max_hit_points * percent / 100
+}
+
+/// Returns hit points below which the critter uses healing drugs.
+// ai_check_drugs()
+fn heal_hit_points(chem_use: Option<ChemUse>, max_hit_points: i32) -> i32 {
+    let percent = match chem_use {
+        None | Some(ChemUse::Clean) => return 0,
+        Some(ChemUse::StimsWhenHurtLittle) => 60,
+        Some(ChemUse::StimsWhenHurtLots) => 30,
+        Some(ChemUse::Sometimes) | Some(ChemUse::Anytime) | Some(ChemUse::Always) => 50,
+    };
+    max_hit_points * percent / 100
+}
+
+/// Returns a drug from `obj`'s inventory that restores hit points immediately.
+/// Drugs with other effects aren't used since timed drug effects aren't implemented.
+fn healing_drug(obj: &Object, objs: &Objects) -> Option<Handle> {
+    obj.inventory.items.iter()
+        .map(|i| i.object)
+        .find(|&h| {
+            let o = objs.get(h);
+            let proto = o.proto().unwrap();
+            proto.sub.as_item()
+                .and_then(|i| i.sub.as_drug())
+                .map(|d| d.effects.iter().any(|e| e.delay == 0
+                    && e.stat == Stat::CurrentHitPoints
+                    && match e.modifier {
+                        DrugEffectModifier::Fixed(v) => v > 0,
+                        DrugEffectModifier::Random(_, max) => max > 0,
+                    }))
+                .unwrap_or(false)
+        })
+}
+
+// ai_run_away()
+fn run_away(obj: Handle, from: Handle, action_points: i32, objs: &Objects) -> Decision {
+    let pos = objs.get(obj).pos();
+    let from_pos = objs.get(from).pos();
+    if from_pos.elevation != pos.elevation {
+        return Decision::EndTurn;
+    }
+    let distance = hex::distance(from_pos.point, pos.point);
+    let to = hex::beyond(from_pos.point, pos.point, distance + action_points as u32);
+    objs.path(obj, PathTo::Point { point: to, neighbor_if_blocked: true }, false)
+        .map(|path| walk(pos.point, &path, action_points))
+        .unwrap_or(Decision::EndTurn)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use byteorder::{BigEndian, WriteBytesExt};
+    use enum_map::enum_map;
+    use std::fs;
+    use std::rc::Rc;
+    use crate::asset::{DamageKind, EntityKind, WeaponKind};
+    use crate::asset::frame::FrameDb;
+    use crate::asset::map::ELEVATION_COUNT;
+    use crate::asset::proto::{self, ProtoDb, ProtoId, ProtoRef};
+    use crate::fs::FileSystem;
+    use crate::graphics::color::palette::overlay::PaletteOverlay;
+    use crate::graphics::geometry::hex::TileGrid;
+    use crate::graphics::render::software::Backend;
+    use crate::util::{EnumExt, RangeInclusive};
+    use crate::util::test::ungz;
+
+    fn write_i32s(w: &mut Vec<u8>, vals: &[i32]) {
+        for &v in vals {
+            w.write_i32::<BigEndian>(v).unwrap();
+        }
+    }
+
+    struct World {
+        rpg: Rpg,
+        objs: Objects,
+        npc_proto: ProtoRef,
+        npc: Handle,
+        dude: Handle,
+    }
+
+    impl World {
+        /// Places critter with all base stats 5 at (100, 100) and the dude `dude_distance` hexes
+        /// east of it.
+        fn new(name: &str, dude_distance: u32) -> Self {
+            let dir = std::env::temp_dir().join(
+                format!("vault13-test-{}-ai-{}", std::process::id(), name));
+            let write = |path: &str, data: &[u8]| {
+                let path = dir.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, data).unwrap();
+            };
+
+            for kind in EntityKind::iter() {
+                write(&format!("art/{0}/{0}.lst", kind.dir()), b"");
+            }
+            for kind in proto::proto_entity_kinds() {
+                let lst: &[u8] = if kind == EntityKind::Critter { b"npc.pro\n" } else { b"" };
+                write(&format!("proto/{0}/{0}.lst", kind.dir()), lst);
+                write(&format!("text/english/game/pro_{}.msg", &kind.dir()[..4]), b"");
+            }
+            for msgs in &["proto", "stat", "skill", "perk"] {
+                write(&format!("text/english/game/{}.msg", msgs), b"");
+            }
+
+            let mut npc = Vec::new();
+            write_i32s(&mut npc, &[0x0100_0001, 100, 0x0100_0000, 0, 0, 0, 0, -1, -1, 0, 0, 0]);
+            write_i32s(&mut npc, &[5; 35]);
+            write_i32s(&mut npc, &[0; 35 + 18]);
+            write_i32s(&mut npc, &[0, 0, 0, 0]);
+            write("proto/critters/npc.pro", &npc);
+
+            let mut fs = FileSystem::new();
+            fs.register_provider(crate::fs::std::new_provider(&dir).unwrap());
+            let fs = Rc::new(fs);
+            let palette = crate::asset::palette::read_palette(&mut &ungz(
+                include_bytes!("../graphics/color/color.pal.gz"))[..]).unwrap();
+            let backend = Backend::new_headless(1, 1, Box::new(palette),
+                PaletteOverlay::standard());
+            let frm_db = Rc::new(FrameDb::new(fs.clone(), "english",
+                backend.new_texture_factory()).unwrap());
+            let proto_db = Rc::new(ProtoDb::new(fs.clone(), "english").unwrap());
+            let rpg = Rpg::new(&fs, "english").unwrap();
+            let mut objs = Objects::new(TileGrid::default(), ELEVATION_COUNT, frm_db,
+                proto_db.clone());
+
+            let npc_proto = proto_db.proto(ProtoId::from_packed(0x0100_0001).unwrap()).unwrap();
+            let pos = Point::new(100, 100);
+            let npc = objs.create(None, Some(npc_proto.clone()), Some(pos.elevated(0)),
+                Some(&rpg)).handle();
+            let dude_pos = hex::go(pos, hex::Direction::E, dude_distance);
+            let dude = objs.create(None, Some(proto_db.dude()), Some(dude_pos.elevated(0)),
+                Some(&rpg)).handle();
+
+            Self {
+                rpg,
+                objs,
+                npc_proto,
+                npc,
+                dude,
+            }
+        }
+
+        /// Places another critter `distance` hexes west of the NPC.
+        fn add_enemy(&mut self, distance: u32) -> Handle {
+            let pos = hex::go(self.objs.get(self.npc).pos().point, hex::Direction::W, distance);
+            self.objs.create(None, Some(self.npc_proto.clone()), Some(pos.elevated(0)),
+                Some(&self.rpg)).handle()
+        }
+
+        fn set_hit_points(&self, v: i32) {
+            self.objs.get_mut(self.npc).sub.as_critter_mut().unwrap().hit_points = v;
+        }
+
+        fn max_hit_points(&self) -> i32 {
+            self.rpg.stat(Stat::HitPoints, &self.objs.get(self.npc), &self.objs)
+        }
+
+        fn decide(&self, packet: Packet, target: Handle, action_points: i32) -> Decision {
+            let ai = Ai {
+                db: AiDb::default(),
+                default_packet: packet,
+            };
+            ai.decide(self.npc, Some(target), action_points, &self.rpg, &self.objs)
+        }
+
+        fn moved_closer_to(&self, d: Decision, to: Handle) -> bool {
+            let pos = self.objs.get(self.npc).pos().point;
+            let to = self.objs.get(to).pos().point;
+            match d {
+                Decision::Move { to: new_pos, .. } =>
+                    hex::distance(new_pos, to) < hex::distance(pos, to),
+                _ => false,
+            }
+        }
+    }
+
+    fn weapon(attack_kind: AttackKind, damage: i32) -> Weapon {
+        Weapon {
+            attack_kinds: enum_map! { _ => attack_kind },
+            kind: WeaponKind::Unarmed,
+            damage: RangeInclusive { start: 1, end: damage },
+            damage_kind: DamageKind::Melee,
+            max_ranges: enum_map! { _ => 1 },
+            projectile_pid: None,
+            min_strength: 0,
+            ap_costs: enum_map! { _ => 3 },
+            crit_failure_table: 0,
+            perk: None,
+            burst_bullet_count: 0,
+            caliber: 0,
+            ammo_proto_id: None,
+            max_ammo_count: 0,
+            sound_id: 0,
+        }
+    }
+
+    #[test]
+    fn weapon_score_() {
+        let knife = weapon(AttackKind::Thrust, 5);
+        let pistol = weapon(AttackKind::FireSingle, 10);
+        let knuckles = weapon(AttackKind::Punch, 4);
+
+        let best = |pref| [&knife, &pistol, &knuckles].iter()
+            .enumerate()
+            .filter_map(|(i, w)| weapon_score(w, pref).map(|s| (i, s)))
+            .max_by_key(|&(_, s)| s)
+            .map(|(i, _)| i);
+
+        assert_eq!(best(BestWeapon::NoPref), Some(1));
+        assert_eq!(best(BestWeapon::Melee), Some(0));
+        assert_eq!(best(BestWeapon::MeleeOverRanged), Some(0));
+        assert_eq!(best(BestWeapon::Unarmed), Some(2));
+        assert_eq!(best(BestWeapon::UnarmedOverThrown), Some(2));
+        assert_eq!(weapon_score(&knife, BestWeapon::Ranged), None);
+    }
+
+    #[test]
+    fn walk_() {
+        use hex::Direction::*;
+        let from = Point::new(10, 10);
+        assert_eq!(walk(from, &[], 3), Decision::EndTurn);
+        assert_eq!(walk(from, &[NE, NE], 0), Decision::EndTurn);
+        assert_eq!(walk(from, &[NE, NE, E], 2), Decision::Move {
+            to: hex::go(from, NE, 2),
+            steps: 2,
+        });
+        assert_eq!(walk(from, &[NE], 5), Decision::Move {
+            to: hex::go(from, NE, 1),
+            steps: 1,
+        });
+    }
+
+    #[test]
+    fn run_away_hit_points_() {
+        let packet = |run_away_mode| Packet {
+            run_away_mode,
+            min_hp: 7,
+            ..Default::default()
+        };
+        assert_eq!(run_away_hit_points(&packet(None), 40), 7);
+        assert_eq!(run_away_hit_points(&packet(Some(RunAwayMode::None)), 40), 7);
+        assert_eq!(run_away_hit_points(&packet(Some(RunAwayMode::Coward)), 40), 40);
+        assert_eq!(run_away_hit_points(&packet(Some(RunAwayMode::Bleeding)), 40), 24);
+        assert_eq!(run_away_hit_points(&packet(Some(RunAwayMode::Tourniquet)), 40), 10);
+        assert_eq!(run_away_hit_points(&packet(Some(RunAwayMode::Never)), 40), 0);
+    }
+
+    #[test]
+    fn heal_hit_points_() {
+        assert_eq!(heal_hit_points(None, 40), 0);
+        assert_eq!(heal_hit_points(Some(ChemUse::Clean), 40), 0);
+        assert_eq!(heal_hit_points(Some(ChemUse::StimsWhenHurtLittle), 40), 24);
+        assert_eq!(heal_hit_points(Some(ChemUse::StimsWhenHurtLots), 40), 12);
+        assert_eq!(heal_hit_points(Some(ChemUse::Always), 40), 20);
+    }
+
+    #[test]
+    fn decide_run_away_mode() {
+        let mut w = World::new("run-away", 20);
+        let enemy = w.add_enemy(1);
+        w.set_hit_points(w.max_hit_points() / 2);
+
+        let packet = |run_away_mode| Packet {
+            run_away_mode: Some(run_away_mode),
+            ..Default::default()
+        };
+        let d = w.decide(packet(RunAwayMode::Coward), enemy, 5);
+        assert!(matches!(d, Decision::Move { .. }));
+        assert!(!w.moved_closer_to(d, enemy));
+        assert_eq!(w.decide(packet(RunAwayMode::Never), enemy, 5), Decision::Attack(enemy));
+    }
+
+    #[test]
+    fn decide_min_to_hit() {
+        let mut w = World::new("min-to-hit", 20);
+        let near = w.add_enemy(1);
+        let far = w.add_enemy(3);
+
+        let packet = |min_to_hit| Packet {
+            min_to_hit,
+            ..Default::default()
+        };
+        assert_eq!(w.decide(packet(0), near, 5), Decision::Attack(near));
+        assert_eq!(w.decide(packet(100), near, 5), Decision::EndTurn);
+
+        // Gets closer to improve the chance, keeping AP for the attack.
+        let d = w.decide(packet(100), far, 5);
+        assert!(w.moved_closer_to(d, far));
+        assert!(matches!(d, Decision::Move { steps: 2, .. }));
+    }
+
+    #[test]
+    fn decide_distance() {
+        let mut w = World::new("distance", 10);
+        let near = w.add_enemy(1);
+        let far = w.add_enemy(5);
+
+        let packet = |distance| Packet {
+            distance: Some(distance),
+            max_dist: 3,
+            ..Default::default()
+        };
+
+        assert_eq!(w.decide(packet(Distance::Stay), far, 5), Decision::EndTurn);
+        assert!(w.moved_closer_to(w.decide(packet(Distance::OnYourOwn), far, 5), far));
+
+        // Not enough AP to attack.
+        assert_eq!(w.decide(packet(Distance::OnYourOwn), near, 2), Decision::EndTurn);
+        assert_eq!(w.decide(packet(Distance::Charge), near, 2), Decision::EndTurn);
+        let d = w.decide(packet(Distance::Snipe), near, 2);
+        assert!(matches!(d, Decision::Move { .. }));
+        assert!(!w.moved_closer_to(d, near));
+        assert!(w.moved_closer_to(w.decide(packet(Distance::StayClose), near, 2), w.dude));
+        assert!(w.moved_closer_to(w.decide(packet(Distance::StayClose), far, 5), w.dude));
+    }
+}
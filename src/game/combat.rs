@@ -0,0 +1,327 @@
+use num_traits::clamp;
+use std::cmp;
+
+use crate::asset::{AttackCategory, AttackKind, CritterAnim, DamageKind, Skill, WeaponKind};
+use crate::asset::proto::Weapon;
+use crate::game::object::Handle;
+use crate::util::RangeInclusive;
+
+/// Value of `fixed_param` passed to `combat_p_proc` at the start of critter's turn.
+pub const COMBAT_SUBTYPE_TURN: i32 = 4;
+
+/// AP cost of unarmed attack.
+pub const UNARMED_AP_COST: i32 = 3;
+
+/// AP cost of changing the wielded weapon.
+pub const WIELD_AP_COST: i32 = 2;
+
+/// AP cost of using a drug from the inventory.
+pub const USE_DRUG_AP_COST: i32 = 2;
+
+/// Damage range of unarmed attack (before `Stat::MeleeDmg` bonus).
+pub const UNARMED_DAMAGE: RangeInclusive<i32> = RangeInclusive { start: 1, end: 2 };
+
+/// Combat-related request issued from script context.
+/// The requests are deferred and handled by the game state after the script procedure returns.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Request {
+    /// `attacker` starts attacking `target`. Begins combat if it's not started yet.
+    Attack {
+        attacker: Handle,
+        target: Handle,
+    },
+    /// `obj` forgets its current target.
+    StopAttacking {
+        obj: Handle,
+    },
+    Terminate,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Combatant {
+    pub obj: Handle,
+    pub action_points: i32,
+    /// Critter this combatant wants to attack.
+    pub target: Option<Handle>,
+}
+
+impl Combatant {
+    pub fn new(obj: Handle) -> Self {
+        Self {
+            obj,
+            action_points: 0,
+            target: None,
+        }
+    }
+}
+
+/// Turn-based combat state.
+#[derive(Default)]
+pub struct Combat {
+    /// Combatants in the order of their turns. Empty if combat is not running.
+    combatants: Vec<Combatant>,
+    current: usize,
+    round: u32,
+    requests: Vec<Request>,
+}
+
+impl Combat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // isInCombat()
+    pub fn is_active(&self) -> bool {
+        !self.combatants.is_empty()
+    }
+
+    /// Starts combat. `initiator` gets the first turn, the rest are ordered by `Stat::Sequence`
+    /// which is specified in `sequence` tuple field.
+    pub fn begin(&mut self, initiator: Handle, combatants: Vec<(Combatant, i32)>) {
+        assert!(!self.is_active());
+        self.combatants = initiative_order(initiator, combatants);
+        self.current = 0;
+        self.round = 1;
+    }
+
+    pub fn end(&mut self) {
+        self.combatants.clear();
+        self.current = 0;
+        self.round = 0;
+    }
+
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+
+    pub fn combatants(&self) -> &[Combatant] {
+        &self.combatants
+    }
+
+    pub fn current(&self) -> Option<&Combatant> {
+        self.combatants.get(self.current)
+    }
+
+    pub fn current_mut(&mut self) -> Option<&mut Combatant> {
+        self.combatants.get_mut(self.current)
+    }
+
+    pub fn is_turn_of(&self, obj: Handle) -> bool {
+        self.current().map(|c| c.obj) == Some(obj)
+    }
+
+    pub fn get(&self, obj: Handle) -> Option<&Combatant> {
+        self.combatants.iter().find(|c| c.obj == obj)
+    }
+
+    pub fn get_mut(&mut self, obj: Handle) -> Option<&mut Combatant> {
+        self.combatants.iter_mut().find(|c| c.obj == obj)
+    }
+
+    /// Adds `obj` to the end of the turn order if it's not a combatant already.
+    pub fn join(&mut self, obj: Handle) -> &mut Combatant {
+        assert!(self.is_active());
+        let i = if let Some(i) = self.combatants.iter().position(|c| c.obj == obj) {
+            i
+        } else {
+            self.combatants.push(Combatant::new(obj));
+            self.combatants.len() - 1
+        };
+        &mut self.combatants[i]
+    }
+
+    /// Removes `obj` from combat. Targets of the other combatants pointing to `obj` are reset.
+    pub fn remove(&mut self, obj: Handle) {
+        if let Some(i) = self.combatants.iter().position(|c| c.obj == obj) {
+            self.combatants.remove(i);
+            if i < self.current {
+                self.current -= 1;
+            }
+            if self.current >= self.combatants.len() {
+                self.current = 0;
+            }
+        }
+        for c in &mut self.combatants {
+            if c.target == Some(obj) {
+                c.target = None;
+            }
+        }
+    }
+
+    /// Passes turn to the next combatant. Returns `true` if a new round has begun.
+    pub fn next_turn(&mut self) -> bool {
+        assert!(self.is_active());
+        self.current += 1;
+        if self.current >= self.combatants.len() {
+            self.current = 0;
+            self.round += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn push_request(&mut self, request: Request) {
+        self.requests.push(request);
+    }
+
+    pub fn take_requests(&mut self) -> Vec<Request> {
+        std::mem::take(&mut self.requests)
+    }
+}
+
+fn initiative_order(initiator: Handle, mut combatants: Vec<(Combatant, i32)>) -> Vec<Combatant> {
+    // Stable sort keeps the original order of combatants with equal sequence.
+    combatants.sort_by_key(|&(c, sequence)| (c.obj != initiator, cmp::Reverse(sequence)));
+    combatants.into_iter().map(|(c, _)| c).collect()
+}
+
+/// Parameters of to-hit chance computation.
+#[derive(Clone, Copy, Debug)]
+pub struct ToHit {
+    /// Attacker's skill level for the weapon used.
+    pub skill: i32,
+    /// Attacker's Perception.
+    pub perception: i32,
+    pub attacker_is_dude: bool,
+    /// Whether this is a ranged (fire or throw) attack.
+    pub ranged: bool,
+    /// Distance between attacker and target in hexes.
+    pub distance: u32,
+    /// Target's armor class.
+    pub armor_class: i32,
+    pub target_multi_hex: bool,
+}
+
+// determine_to_hit_func()
+pub fn hit_chance(to_hit: ToHit) -> i32 {
+    let mut r = to_hit.skill;
+    if to_hit.ranged {
+        let perception = if to_hit.attacker_is_dude {
+            to_hit.perception - 2
+        } else {
+            to_hit.perception
+        };
+        let distance_mod = to_hit.distance as i32 - 2 * perception;
+        r -= 4 * cmp::max(distance_mod, -2 * to_hit.perception);
+    }
+    r -= cmp::max(to_hit.armor_class, 0);
+    if to_hit.target_multi_hex {
+        r += 15;
+    }
+    clamp(r, 0, 95)
+}
+
+/// Reduces `damage` by damage threshold and then by damage resistance percent.
+pub fn resist_damage(damage: i32, threshold: i32, resistance: i32) -> i32 {
+    let resistance = clamp(resistance, 0, 100);
+    let r = damage - threshold;
+    if r > 0 {
+        r - r * resistance / 100
+    } else {
+        0
+    }
+}
+
+// item_w_skill()
+pub fn weapon_skill(weapon: Option<&Weapon>, attack_kind: AttackKind) -> Skill {
+    match attack_kind.category() {
+        AttackCategory::Stand | AttackCategory::MeleeUnarmed => Skill::UnarmedCombat,
+        AttackCategory::MeleeWeapon => Skill::Melee,
+        AttackCategory::Throw => Skill::Throwing,
+        AttackCategory::Fire => {
+            let weapon = weapon.unwrap();
+            match weapon.kind {
+                WeaponKind::BigGun | WeaponKind::Minigun | WeaponKind::Launcher => Skill::BigGuns,
+                _ => match weapon.damage_kind {
+                    DamageKind::Laser | DamageKind::Plasma | DamageKind::Electric
+                        => Skill::EnergyWeapons,
+                    _ => Skill::SmallGuns,
+                }
+            }
+        }
+    }
+}
+
+pub fn attack_anim(attack_kind: AttackKind) -> CritterAnim {
+    use AttackKind::*;
+    match attack_kind {
+        Stand | Punch => CritterAnim::ThrowPunch,
+        Kick => CritterAnim::KickLeg,
+        Swing => CritterAnim::SwingAnim,
+        Thrust => CritterAnim::ThrustAnim,
+        Throw => CritterAnim::ThrowAnim,
+        FireSingle => CritterAnim::FireSingle,
+        FireBurst => CritterAnim::FireBurst,
+        FireContinuous => CritterAnim::FireContinuous,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slotmap::SlotMap;
+
+    fn handles(n: usize) -> Vec<Handle> {
+        let mut m = SlotMap::<Handle, ()>::with_key();
+        (0..n).map(|_| m.insert(())).collect()
+    }
+
+    #[test]
+    fn initiative_order_() {
+        let h = handles(4);
+        let c = |i: usize, seq| (Combatant::new(h[i]), seq);
+        let r: Vec<_> = initiative_order(h[2], vec![c(0, 6), c(1, 10), c(2, 2), c(3, 6)])
+            .into_iter()
+            .map(|c| c.obj)
+            .collect();
+        assert_eq!(r, vec![h[2], h[1], h[0], h[3]]);
+    }
+
+    #[test]
+    fn next_turn_and_remove() {
+        let h = handles(3);
+        let mut c = Combat::new();
+        assert!(!c.is_active());
+        c.begin(h[0], h.iter().map(|&h| (Combatant::new(h), 0)).collect());
+        c.get_mut(h[2]).unwrap().target = Some(h[1]);
+        assert!(c.is_turn_of(h[0]));
+        assert!(!c.next_turn());
+        assert!(c.is_turn_of(h[1]));
+
+        c.remove(h[1]);
+        assert!(c.is_turn_of(h[2]));
+        assert_eq!(c.get(h[2]).unwrap().target, None);
+
+        assert!(c.next_turn());
+        assert_eq!(c.round(), 2);
+        assert!(c.is_turn_of(h[0]));
+    }
+
+    #[test]
+    fn hit_chance_() {
+        let to_hit = ToHit {
+            skill: 80,
+            perception: 6,
+            attacker_is_dude: true,
+            ranged: true,
+            distance: 8,
+            armor_class: 10,
+            target_multi_hex: false,
+        };
+        assert_eq!(hit_chance(to_hit), 70);
+        assert_eq!(hit_chance(ToHit { distance: 20, ..to_hit }), 80 - 4 * 12 - 10);
+        assert_eq!(hit_chance(ToHit { distance: 0, ..to_hit }), 95);
+        assert_eq!(hit_chance(ToHit { ranged: false, distance: 20, ..to_hit }), 70);
+        assert_eq!(hit_chance(ToHit { armor_class: -5, target_multi_hex: true, ..to_hit }), 95);
+        assert_eq!(hit_chance(ToHit { distance: 50, ..to_hit }), 0);
+    }
+
+    #[test]
+    fn resist_damage_() {
+        assert_eq!(resist_damage(20, 4, 25), 12);
+        assert_eq!(resist_damage(20, 0, 150), 0);
+        assert_eq!(resist_damage(20, -5, -10), 25);
+        assert_eq!(resist_damage(3, 4, 0), 0);
+    }
+}
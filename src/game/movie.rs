@@ -0,0 +1,172 @@
+use log::*;
+use std::io;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use crate::asset::mve::{MveReader, Subtitles};
+use crate::asset::palette::read_palette;
+use crate::asset::save::MOVIE_COUNT;
+use crate::fs::FileSystem;
+use crate::game::ui::movie::MovieView;
+use crate::graphics::Rect;
+use crate::graphics::color::palette::Palette;
+use crate::sound::mixer::Mixer;
+use crate::ui::{self, Ui};
+
+/// Movies played by `play_gmovie` opcode by their index.
+pub const GAME_MOVIES: [&str; MOVIE_COUNT] = [
+    "iplogo",
+    "intro",
+    "elder",
+    "vsuit",
+    "afailed",
+    "adestroy",
+    "car",
+    "cartucci",
+    "timeout",
+    "tanker",
+    "enclave",
+    "derrick",
+    "artimer1",
+    "artimer2",
+    "artimer3",
+    "artimer4",
+    "credits",
+];
+
+/// Returns path to the movie file. The `name` can be a path with or without extension,
+/// otherwise it's looked up in `art/cuts`.
+pub fn movie_path(name: &str) -> String {
+    let mut path = name.replace('\\', "/");
+    if !path.contains('/') {
+        path = format!("art/cuts/{}", path);
+    }
+    if !path.contains('.') {
+        path.push_str(".mve");
+    }
+    path.to_ascii_lowercase()
+}
+
+/// Plays movies in a modal window on top of the game.
+pub struct Movies {
+    fs: Rc<FileSystem>,
+    language: String,
+    mixer: Option<Arc<Mutex<Mixer>>>,
+    palette: Option<Rc<Palette>>,
+    /// Flags set by `movieflags` opcode.
+    flags: u32,
+    /// Whether each of the `GAME_MOVIES` has been played.
+    seen: [bool; MOVIE_COUNT],
+    /// Movie window and the `MovieView` widget.
+    playing: Option<(ui::Handle, ui::Handle)>,
+}
+
+impl Movies {
+    pub fn new(fs: Rc<FileSystem>, language: &str, mixer: Option<Arc<Mutex<Mixer>>>) -> Self {
+        Self {
+            fs,
+            language: language.into(),
+            mixer,
+            palette: None,
+            flags: 0,
+            seen: [false; MOVIE_COUNT],
+            playing: None,
+        }
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        self.flags = flags;
+    }
+
+    pub fn seen(&self) -> &[bool; MOVIE_COUNT] {
+        &self.seen
+    }
+
+    pub fn set_seen(&mut self, seen: [bool; MOVIE_COUNT]) {
+        self.seen = seen;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.is_some()
+    }
+
+    // gmovie_play()
+    pub fn play_game_movie(&mut self, movie: usize, ui: &mut Ui) -> io::Result<()> {
+        let name = GAME_MOVIES.get(movie)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                format!("bad game movie: {}", movie)))?;
+        self.play(name, None, ui)?;
+        self.seen[movie] = true;
+        Ok(())
+    }
+
+    /// Plays movie in the `rect` or full screen. Stops the currently playing movie.
+    pub fn play(&mut self, name: &str, rect: Option<Rect>, ui: &mut Ui) -> io::Result<()> {
+        self.stop(ui);
+
+        let path = movie_path(name);
+        debug!("playing movie {}", path);
+        let reader = MveReader::new(self.fs.reader(&path)?)?;
+        let subtitles = self.subtitles(&path);
+        let palette = if let Some(p) = &self.palette {
+            p.clone()
+        } else {
+            let p = Rc::new(read_palette(&mut self.fs.reader("color.pal")?)?);
+            self.palette = Some(p.clone());
+            p
+        };
+
+        let rect = rect.unwrap_or_else(|| Rect::with_size(0, 0, 640, 480));
+        let win = ui.new_window(rect, None);
+        ui.widget_base_mut(win).set_modal(true);
+        let view = ui.new_widget(win, Rect::with_size(0, 0, rect.width(), rect.height()),
+            None, None, MovieView::new(reader, subtitles, palette, self.mixer.clone()));
+        ui.set_keyboard_focus(Some(view));
+        self.playing = Some((win, view));
+        Ok(())
+    }
+
+    pub fn stop(&mut self, ui: &mut Ui) {
+        if let Some((win, _)) = self.playing.take() {
+            ui.remove(win);
+        }
+    }
+
+    /// Closes the movie window once the movie has finished.
+    pub fn update(&mut self, ui: &mut Ui) {
+        if let Some((_, view)) = self.playing {
+            if ui.widget_ref::<MovieView>(view).is_finished() {
+                self.stop(ui);
+            }
+        }
+    }
+
+    /// Loads subtitles of the movie at `path` from `text/<language>/cuts/<name>.sve`.
+    fn subtitles(&self, path: &str) -> Option<Subtitles> {
+        let name = path.rsplit('/').next().unwrap();
+        let name = name.rsplitn(2, '.').last().unwrap();
+        let path = format!("text/{}/cuts/{}.sve", self.language, name);
+        let mut rd = self.fs.reader(&path)
+            .map_err(|e| debug!("no subtitles {}: {}", path, e))
+            .ok()?;
+        Subtitles::read(&mut rd)
+            .map_err(|e| warn!("couldn't read subtitles {}: {}", path, e))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn movie_path_() {
+        assert_eq!(movie_path("Intro"), "art/cuts/intro.mve");
+        assert_eq!(movie_path("art\\cuts\\Credits.MVE"), "art/cuts/credits.mve");
+        assert_eq!(movie_path("art/cuts/tanker"), "art/cuts/tanker.mve");
+    }
+}
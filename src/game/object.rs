@@ -284,8 +284,7 @@ impl Object {
     // critter_is_dead()
     #[must_use]
     pub fn is_critter_dead(&self) -> bool {
-        // FIXME
-        false
+        self.sub.as_critter().map(|c| c.is_dead()).unwrap_or(false)
     }
 
     // critter_is_prone()
@@ -873,6 +872,19 @@ impl Objects {
         }
     }
 
+    /// Returns empty `Objects` with the same grid and databases. Handles of `self` are never
+    /// valid in the result so stale handles can't refer to the new objects.
+    pub fn new_empty_like(&self) -> Self {
+        let mut r = Self::new(
+            self.tile_grid.clone(),
+            self.elevation_count(),
+            self.frm_db.clone(),
+            self.proto_db.clone());
+        r.handles = self.handles.clone();
+        r.handles.clear();
+        r
+    }
+
     pub fn elevation_count(&self) -> u32 {
         self.by_pos.len() as u32
     }
@@ -13,6 +13,7 @@ use crate::asset::message::{Messages, MessageId};
 use crate::asset::proto::ProtoId;
 use crate::game::object::{DamageFlag, EquipmentSlot, Hand, Object, Objects};
 use crate::fs::FileSystem;
+use crate::util::EnumExt;
 use crate::util::random::*;
 
 use def::perk::*;
@@ -174,14 +175,41 @@ impl Rpg {
         any && all
     }
 
+    pub fn perks(&self, pid: ProtoId) -> EnumMap<Perk, u32> {
+        self.perks.get(&pid).cloned().unwrap_or_default()
+    }
+
+    pub fn set_perks(&mut self, pid: ProtoId, perks: EnumMap<Perk, u32>) {
+        self.perks.insert(pid, perks);
+    }
+
     pub fn has_trait(&self, tr: Trait) -> bool {
         self.traits[tr]
     }
 
+    pub fn traits(&self) -> Vec<Trait> {
+        Trait::iter().filter(|&t| self.traits[t]).collect()
+    }
+
+    pub fn set_traits(&mut self, traits: &[Trait]) {
+        self.traits = EnumMap::from(|t| traits.contains(&t));
+    }
+
     pub fn is_tagged(&self, skill: Skill) -> bool {
         self.tagged[skill].tagged
     }
 
+    pub fn tagged_skills(&self) -> Vec<Skill> {
+        Skill::iter().filter(|&s| self.is_tagged(s)).collect()
+    }
+
+    pub fn set_tagged_skills(&mut self, skills: &[Skill]) {
+        self.tagged = EnumMap::from(|s| Tagged {
+            tagged: skills.contains(&s),
+            .. Default::default()
+        });
+    }
+
     // stat_level()
     pub fn stat(&self, stat: Stat, obj: &Object, objs: &Objects) -> i32 {
         use Perk::*;
@@ -373,6 +401,21 @@ impl Rpg {
         bs[Sequence] = 2 * per;
     }
 
+    /// Returns defensive `stat` of the critter including its worn armor. Armor of the dude is
+    /// already folded into its bonus stats by `apply_armor_change()` which is only applied to the
+    /// dude. Bonus stats of other critters are shared by all critters of the same proto so their
+    /// armor is added here instead.
+    pub fn defense_stat(&self, stat: Stat, obj: &Object, objs: &Objects) -> i32 {
+        let mut r = self.stat(stat, obj, objs);
+        if !obj.is_dude() {
+            if let Some(armor) = obj.equipment(EquipmentSlot::Armor, objs) {
+                r += objs.get(armor).proto().unwrap().sub.as_armor().unwrap()
+                    .stat(stat).unwrap_or(0);
+            }
+        }
+        r
+    }
+
     // adjust_ac
     pub fn apply_armor_change(&self,
         obj: &mut Object,
@@ -481,6 +524,15 @@ impl Rpg {
         self.pc_stats[pc_stat]
     }
 
+    pub fn pc_stats(&self) -> &EnumMap<PCStat, i32> {
+        &self.pc_stats
+    }
+
+    /// Sets PC stats without any validation. Used when loading saved game.
+    pub fn set_pc_stats(&mut self, pc_stats: EnumMap<PCStat, i32>) {
+        self.pc_stats = pc_stats;
+    }
+
     // stat_pc_set
     pub fn try_set_pc_stat(&mut self, pc_stat: PCStat, value: i32) -> bool {
         let def = &self.pc_stat_defs[pc_stat];
@@ -613,6 +665,121 @@ fn try_level_experience(level: u32) -> Option<u32> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use byteorder::{BigEndian, WriteBytesExt};
+    use std::fs;
+    use std::rc::Rc;
+    use crate::asset::{EntityKind, Flag, ItemKind};
+    use crate::asset::frame::FrameDb;
+    use crate::asset::proto::{self, ProtoDb};
+    use crate::asset::map::ELEVATION_COUNT;
+    use crate::graphics::color::palette::overlay::PaletteOverlay;
+    use crate::graphics::geometry::hex::TileGrid;
+    use crate::graphics::render::software::Backend;
+    use crate::util::test::ungz;
+
+    fn write_i32s(w: &mut Vec<u8>, vals: &[i32]) {
+        for &v in vals {
+            w.write_i32::<BigEndian>(v).unwrap();
+        }
+    }
+
+    #[test]
+    fn defense_stat_armor() {
+        let dir = std::env::temp_dir().join(format!("vault13-test-{}-rpg", std::process::id()));
+        let write = |path: &str, data: &[u8]| {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, data).unwrap();
+        };
+
+        for kind in EntityKind::iter() {
+            write(&format!("art/{0}/{0}.lst", kind.dir()), b"");
+        }
+        for kind in proto::proto_entity_kinds() {
+            let lst: &[u8] = match kind {
+                EntityKind::Item => b"armor.pro\n",
+                EntityKind::Critter => b"npc.pro\n",
+                _ => b"",
+            };
+            write(&format!("proto/{0}/{0}.lst", kind.dir()), lst);
+            write(&format!("text/english/game/pro_{}.msg", &kind.dir()[..4]), b"");
+        }
+        for msgs in &["proto", "stat", "skill", "perk"] {
+            write(&format!("text/english/game/{}.msg", msgs), b"");
+        }
+
+        // Armor with AC 20, 30% fire resistance and fire threshold 4.
+        let mut armor = Vec::new();
+        write_i32s(&mut armor, &[1, 100, 0, 0, 0, 0, 0, -1,
+            ItemKind::Armor as i32, 0, 1, 1, 1, -1]);
+        armor.push(0);
+        write_i32s(&mut armor, &[20]);
+        for &dmg in DamageKind::basic() {
+            write_i32s(&mut armor, &[if dmg == DamageKind::Fire { 30 } else { 0 }]);
+        }
+        for &dmg in DamageKind::basic() {
+            write_i32s(&mut armor, &[if dmg == DamageKind::Fire { 4 } else { 0 }]);
+        }
+        write_i32s(&mut armor, &[-1, 0, 0]);
+        write("proto/items/armor.pro", &armor);
+
+        // Critter with all base stats 5, AC is derived from agility.
+        let mut npc = Vec::new();
+        write_i32s(&mut npc, &[0x0100_0001, 100, 0x0100_0000, 0, 0, 0, 0, -1, -1, 0, 0, 0]);
+        write_i32s(&mut npc, &[5; 35]);
+        write_i32s(&mut npc, &[0; 35 + 18]);
+        write_i32s(&mut npc, &[0, 0, 0, 0]);
+        write("proto/critters/npc.pro", &npc);
+
+        let mut fs = FileSystem::new();
+        fs.register_provider(crate::fs::std::new_provider(&dir).unwrap());
+        let fs = Rc::new(fs);
+        let palette = crate::asset::palette::read_palette(&mut &ungz(
+            include_bytes!("../graphics/color/color.pal.gz"))[..]).unwrap();
+        let backend = Backend::new_headless(1, 1, Box::new(palette), PaletteOverlay::standard());
+        let frm_db = Rc::new(FrameDb::new(fs.clone(), "english", backend.new_texture_factory())
+            .unwrap());
+        let proto_db = Rc::new(ProtoDb::new(fs.clone(), "english").unwrap());
+        let rpg = Rpg::new(&fs, "english").unwrap();
+        let mut objs = Objects::new(TileGrid::default(), ELEVATION_COUNT, frm_db, proto_db.clone());
+
+        let armor_proto = proto_db.proto(ProtoId::from_packed(1).unwrap()).unwrap();
+        let npc_proto = proto_db.proto(ProtoId::from_packed(0x0100_0001).unwrap()).unwrap();
+        let npc = objs.create(None, Some(npc_proto.clone()), None, Some(&rpg)).handle();
+        let npc2 = objs.create(None, Some(npc_proto), None, Some(&rpg)).handle();
+        let dude = objs.create(None, Some(proto_db.dude()), None, Some(&rpg)).handle();
+        for &owner in &[npc, dude] {
+            let armor = objs.create(None, Some(armor_proto.clone()), None, None).handle();
+            objs.get_mut(armor).flags.insert(Flag::Worn);
+            objs.move_into_inventory(owner, armor, 1);
+        }
+
+        let fire_resist = DamageKind::Fire.resist_stat();
+        let fire_thresh = DamageKind::Fire.thresh_stat().unwrap();
+
+        let npco = objs.get(npc);
+        assert_eq!(rpg.stat(Stat::ArmorClass, &npco, &objs), 5);
+        assert_eq!(rpg.defense_stat(Stat::ArmorClass, &npco, &objs), 25);
+        assert_eq!(rpg.defense_stat(fire_resist, &npco, &objs),
+            rpg.stat(fire_resist, &npco, &objs) + 30);
+        assert_eq!(rpg.defense_stat(fire_thresh, &npco, &objs),
+            rpg.stat(fire_thresh, &npco, &objs) + 4);
+
+        // Critter of the same proto without armor.
+        let npc2o = objs.get(npc2);
+        assert_eq!(rpg.defense_stat(Stat::ArmorClass, &npc2o, &objs), 5);
+
+        // Armor of the dude is counted once.
+        let dude_ac = rpg.stat(Stat::ArmorClass, &objs.get(dude), &objs);
+        {
+            let mut dudeo = objs.get_mut(dude);
+            let armor = objs.get(dudeo.equipment(EquipmentSlot::Armor, &objs).unwrap());
+            rpg.apply_armor_change(&mut dudeo, Some(&armor), None, &objs);
+        }
+        assert_eq!(rpg.defense_stat(Stat::ArmorClass, &objs.get(dude), &objs), dude_ac + 20);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 
     #[test]
     fn try_level_experience_() {
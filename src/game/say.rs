@@ -0,0 +1,556 @@
+//! Dialog engine of the `say*` opcodes.
+//!
+//! The script defines the dialog between `saystart` and `sayend` as a list of named replies each
+//! having a list of options. Picking an option calls the option procedure which can go to another
+//! reply with `saygotoreply` or `sayrestart`. The dialog ends when the picked option doesn't go
+//! to another reply or calls `sayquit`.
+//! The script is suspended in `sayend` and `saymessage` until the dialog or message is closed.
+//! The option procedures are called when the option is picked in the UI.
+
+use bstring::{bstr, BString};
+use std::time::{Duration, Instant};
+
+use crate::asset::frame::FrameId;
+use crate::game::script::ScriptIid;
+use crate::game::ui::script_window::ScriptCanvas;
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::{BLACK, GREEN, Rgb15};
+use crate::graphics::font::{FontKey, HorzAlign};
+use crate::ui::{self, Ui};
+use crate::ui::button::{self, Button};
+use crate::ui::command::{SayCommand, UiCommandData};
+use crate::ui::message_panel::{MessagePanel, MouseControl, Scroll};
+use crate::vm::ProcedureId;
+
+#[derive(Clone, Copy, Debug)]
+pub struct WindowConfig {
+    pub rect: Rect,
+    pub background: Option<FrameId>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ScrollButton {
+    /// Position within the option window.
+    pub pos: Point,
+    pub up: FrameId,
+    pub down: FrameId,
+}
+
+struct ReplyOption {
+    text: BString,
+    proc_id: Option<ProcedureId>,
+}
+
+struct Reply {
+    name: BString,
+    text: BString,
+    options: Vec<ReplyOption>,
+}
+
+struct DialogUi {
+    reply_window: ui::Handle,
+    reply: ui::Handle,
+    option_window: ui::Handle,
+    options: ui::Handle,
+}
+
+struct Dialog {
+    sid: ScriptIid,
+    title: Option<BString>,
+    replies: Vec<Reply>,
+    /// Reply currently shown.
+    current: usize,
+    /// Reply to show after the option procedure returns.
+    next: Option<usize>,
+    quit: bool,
+    /// Whether the option procedure has been suspended and the next reply must be shown when
+    /// it finishes.
+    option_suspended: bool,
+    /// Set after `sayend`.
+    ui: Option<DialogUi>,
+}
+
+struct Message {
+    window: ui::Handle,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+pub struct SayDialog {
+    reply_window: WindowConfig,
+    option_window: WindowConfig,
+    border: Point,
+    option_spacing: i32,
+    reply_color: Rgb15,
+    option_color: Rgb15,
+    reply_align: HorzAlign,
+    option_align: HorzAlign,
+    scroll_up: Option<ScrollButton>,
+    scroll_down: Option<ScrollButton>,
+    message_timeout: Option<Duration>,
+    start_pos: usize,
+    last_pos: usize,
+    dialog: Option<Dialog>,
+    message: Option<Message>,
+}
+
+impl Default for SayDialog {
+    fn default() -> Self {
+        Self {
+            // Scripts are expected to set up the windows, these are just sensible defaults.
+            reply_window: WindowConfig {
+                rect: Rect::with_size(80, 60, 480, 120),
+                background: None,
+            },
+            option_window: WindowConfig {
+                rect: Rect::with_size(80, 200, 480, 200),
+                background: None,
+            },
+            border: Point::new(8, 8),
+            option_spacing: 2,
+            reply_color: GREEN,
+            option_color: GREEN,
+            reply_align: HorzAlign::Left,
+            option_align: HorzAlign::Left,
+            scroll_up: None,
+            scroll_down: None,
+            message_timeout: None,
+            start_pos: 0,
+            last_pos: 0,
+            dialog: None,
+            message: None,
+        }
+    }
+}
+
+impl SayDialog {
+    /// Whether the dialog or message is shown.
+    pub fn is_running(&self) -> bool {
+        self.dialog.as_ref().map(|d| d.ui.is_some()).unwrap_or(false) || self.message.is_some()
+    }
+
+    pub fn set_reply_window(&mut self, config: WindowConfig) {
+        self.reply_window = config;
+    }
+
+    pub fn set_option_window(&mut self, config: WindowConfig) {
+        self.option_window = config;
+    }
+
+    /// Sets offset of the text from the window edges.
+    pub fn set_border(&mut self, border: Point) {
+        self.border = border;
+    }
+
+    pub fn set_option_spacing(&mut self, spacing: i32) {
+        self.option_spacing = spacing;
+    }
+
+    pub fn set_reply_color(&mut self, color: Rgb15) {
+        self.reply_color = color;
+    }
+
+    pub fn set_option_color(&mut self, color: Rgb15) {
+        self.option_color = color;
+    }
+
+    pub fn set_reply_align(&mut self, align: HorzAlign) {
+        self.reply_align = align;
+    }
+
+    pub fn set_option_align(&mut self, align: HorzAlign) {
+        self.option_align = align;
+    }
+
+    pub fn set_scroll_button(&mut self, scroll: Scroll, button: Option<ScrollButton>) {
+        match scroll {
+            Scroll::Up => self.scroll_up = button,
+            Scroll::Down => self.scroll_down = button,
+        }
+    }
+
+    pub fn set_message_timeout(&mut self, timeout: Option<Duration>) {
+        self.message_timeout = timeout;
+    }
+
+    /// Sets index of the reply the next dialog starts with.
+    pub fn set_start_pos(&mut self, pos: usize) {
+        self.start_pos = pos;
+    }
+
+    /// Returns index of the reply shown last.
+    pub fn last_pos(&self) -> usize {
+        self.last_pos
+    }
+
+    /// Starts defining new dialog. Returns `false` if there's a dialog already.
+    pub fn start(&mut self, sid: ScriptIid) -> bool {
+        if self.dialog.is_some() {
+            return false;
+        }
+        self.dialog = Some(Dialog {
+            sid,
+            title: None,
+            replies: Vec::new(),
+            current: 0,
+            next: Some(self.start_pos),
+            quit: false,
+            option_suspended: false,
+            ui: None,
+        });
+        self.start_pos = 0;
+        true
+    }
+
+    /// Returns `false` if there's no dialog.
+    pub fn set_title(&mut self, title: BString) -> bool {
+        self.dialog.as_mut().map(|d| d.title = Some(title)).is_some()
+    }
+
+    /// Returns `false` if there's no dialog.
+    pub fn add_reply(&mut self, name: BString, text: BString) -> bool {
+        self.dialog.as_mut()
+            .map(|d| d.replies.push(Reply {
+                name,
+                text,
+                options: Vec::new(),
+            }))
+            .is_some()
+    }
+
+    /// Adds option to the last added reply. Returns `false` if there's no dialog or reply.
+    pub fn add_option(&mut self, text: BString, proc_id: Option<ProcedureId>) -> bool {
+        self.dialog.as_mut()
+            .and_then(|d| d.replies.last_mut())
+            .map(|r| r.options.push(ReplyOption {
+                text,
+                proc_id,
+            }))
+            .is_some()
+    }
+
+    /// Sets the reply to show next. Returns `false` if there's no dialog or such reply.
+    pub fn go_to_reply(&mut self, name: &bstr) -> bool {
+        if let Some(dialog) = self.dialog.as_mut() {
+            let i = dialog.replies.iter()
+                .position(|r| r.name.as_bytes().eq_ignore_ascii_case(name.as_bytes()));
+            if i.is_some() {
+                dialog.next = i;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Makes the first reply the next to show. Returns `false` if there's no dialog.
+    pub fn restart(&mut self) -> bool {
+        self.dialog.as_mut().map(|d| d.next = Some(0)).is_some()
+    }
+
+    /// Ends the dialog after the current option procedure returns. Returns `false` if there's
+    /// no dialog.
+    pub fn quit(&mut self) -> bool {
+        self.dialog.as_mut().map(|d| d.quit = true).is_some()
+    }
+
+    /// Shows the dialog defined since `start()`. Returns `false` if there's no dialog or it's
+    /// already shown.
+    pub fn end(&mut self, ui: &mut Ui) -> bool {
+        if self.dialog.as_ref().map(|d| d.ui.is_some()).unwrap_or(true) {
+            return false;
+        }
+
+        let reply_window = self.new_window(self.reply_window, ui);
+        let reply = self.new_text(reply_window, self.reply_color, self.reply_align, ui);
+
+        let option_window = self.new_window(self.option_window, ui);
+        ui.widget_base_mut(option_window).set_modal(true);
+        let options = self.new_text(option_window, self.option_color, self.option_align, ui);
+        {
+            let mut options = ui.widget_mut::<MessagePanel>(options);
+            options.set_mouse_control(MouseControl::Pick);
+            options.set_highlight_color(Rgb15::new(31, 31, 15));
+            options.set_message_spacing(self.option_spacing);
+        }
+        for &(scroll, button) in &[(Scroll::Up, self.scroll_up), (Scroll::Down, self.scroll_down)] {
+            if let Some(b) = button {
+                let size = ui.frm_db().get(b.up)
+                    .map(|f| f.first().size())
+                    .unwrap_or_else(|_| Point::new(1, 1));
+                ui.new_widget(option_window, Rect::with_size(b.pos.x, b.pos.y, size.x, size.y),
+                    None, None,
+                    Button::new(b.up, b.down, Some(UiCommandData::Say(SayCommand::Scroll(scroll)))));
+            }
+        }
+
+        self.dialog.as_mut().unwrap().ui = Some(DialogUi {
+            reply_window,
+            reply,
+            option_window,
+            options,
+        });
+        // Ends the dialog right away if there are no replies.
+        let _ = self.next_reply(ui);
+        true
+    }
+
+    /// Whether the `widget` is the option list of the dialog.
+    pub fn is_options(&self, widget: ui::Handle) -> bool {
+        self.dialog.as_ref()
+            .and_then(|d| d.ui.as_ref())
+            .map(|u| u.options == widget)
+            .unwrap_or(false)
+    }
+
+    /// Handles pick of the option `id` of the current reply. Returns the option procedure
+    /// that must be called followed by `next_reply()`. If the procedure suspends,
+    /// `suspend_option()` must be called instead and `next_reply()` deferred until it finishes.
+    pub fn pick(&mut self, id: u32) -> Option<(ScriptIid, ProcedureId)> {
+        let dialog = self.dialog.as_mut()?;
+        dialog.next = None;
+        dialog.quit = false;
+        let proc_id = dialog.replies[dialog.current].options.get(id as usize)?.proc_id?;
+        Some((dialog.sid, proc_id))
+    }
+
+    /// Marks the option procedure as suspended.
+    pub fn suspend_option(&mut self) {
+        if let Some(d) = self.dialog.as_mut() {
+            d.option_suspended = true;
+        }
+    }
+
+    /// Returns `true` if the option procedure has been suspended with `suspend_option()` and
+    /// resets the flag.
+    pub fn take_suspended_option(&mut self) -> bool {
+        self.dialog.as_mut()
+            .map(|d| std::mem::replace(&mut d.option_suspended, false))
+            .unwrap_or(false)
+    }
+
+    /// Shows the next reply set by `go_to_reply()` or `restart()` or ends the dialog if there's
+    /// none or `quit()` has been called. Returns `true` if the shown dialog has ended.
+    #[must_use]
+    pub fn next_reply(&mut self, ui: &mut Ui) -> bool {
+        let dialog = if let Some(d) = self.dialog.as_mut() {
+            d
+        } else {
+            return false;
+        };
+        let next = dialog.next.take()
+            .filter(|&i| !dialog.quit && i < dialog.replies.len());
+        let (next, dialog_ui) = if let (Some(next), Some(ui)) = (next, &dialog.ui) {
+            (next, ui)
+        } else {
+            return self.end_dialog(ui);
+        };
+
+        dialog.current = next;
+        self.last_pos = next;
+
+        let reply = &dialog.replies[next];
+        {
+            let mut w = ui.widget_mut::<MessagePanel>(dialog_ui.reply);
+            w.clear_messages();
+            if let Some(title) = &dialog.title {
+                w.push_message(title);
+            }
+            w.push_message(&reply.text);
+        }
+        let mut w = ui.widget_mut::<MessagePanel>(dialog_ui.options);
+        w.clear_messages();
+        for option in &reply.options {
+            w.push_message(&option.text);
+        }
+        false
+    }
+
+    /// Shows the message window. It's closed on mouse click or after the timeout set with
+    /// `set_message_timeout()`.
+    pub fn show_message(&mut self, title: &bstr, text: &bstr, ui: &mut Ui) {
+        self.close_message(ui);
+
+        let window = self.new_window(self.reply_window, ui);
+        ui.widget_base_mut(window).set_modal(true);
+        let panel = self.new_text(window, self.reply_color, self.reply_align, ui);
+        {
+            let mut panel = ui.widget_mut::<MessagePanel>(panel);
+            if !title.is_empty() {
+                panel.push_message(title);
+            }
+            panel.push_message(text);
+        }
+        let rect = self.reply_window.rect;
+        let mut button = Button::new(FrameId::BLANK, FrameId::BLANK,
+            Some(UiCommandData::Say(SayCommand::CloseMessage)));
+        button.config_mut(button::State::Up).background = None;
+        button.config_mut(button::State::Down).background = None;
+        ui.new_widget(window, Rect::with_size(0, 0, rect.width(), rect.height()), None, None,
+            button);
+
+        self.message = Some(Message {
+            window,
+            timeout: self.message_timeout,
+            deadline: None,
+        });
+    }
+
+    /// Returns `true` if the message has been closed.
+    #[must_use]
+    pub fn handle(&mut self, command: SayCommand, ui: &mut Ui) -> bool {
+        match command {
+            SayCommand::Scroll(scroll) => {
+                if let Some(dialog_ui) = self.dialog.as_ref().and_then(|d| d.ui.as_ref()) {
+                    ui.widget_mut::<MessagePanel>(dialog_ui.options).scroll(scroll);
+                }
+                false
+            }
+            SayCommand::CloseMessage => self.close_message(ui),
+        }
+    }
+
+    /// Returns `true` if the message has been closed on timeout.
+    #[must_use]
+    pub fn update(&mut self, now: Instant, ui: &mut Ui) -> bool {
+        if let Some(msg) = &mut self.message {
+            if let Some(timeout) = msg.timeout {
+                let deadline = *msg.deadline.get_or_insert(now + timeout);
+                if now >= deadline {
+                    return self.close_message(ui);
+                }
+            }
+        }
+        false
+    }
+
+    /// Closes the dialog and message. The dialog configuration is preserved.
+    pub fn clear(&mut self, ui: &mut Ui) {
+        self.end_dialog(ui);
+        self.close_message(ui);
+    }
+
+    /// Returns `true` if the dialog was shown.
+    fn end_dialog(&mut self, ui: &mut Ui) -> bool {
+        if let Some(dialog_ui) = self.dialog.take().and_then(|d| d.ui) {
+            ui.remove(dialog_ui.option_window);
+            ui.remove(dialog_ui.reply_window);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns `true` if the message was shown.
+    fn close_message(&mut self, ui: &mut Ui) -> bool {
+        if let Some(msg) = self.message.take() {
+            ui.remove(msg.window);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn new_window(&self, config: WindowConfig, ui: &mut Ui) -> ui::Handle {
+        let size = Point::new(config.rect.width(), config.rect.height());
+        let window = ui.new_window(config.rect, None);
+        let mut canvas = ScriptCanvas::new();
+        canvas.fill_all(size, BLACK);
+        if let Some(fid) = config.background {
+            canvas.image(fid, Rect::with_size(0, 0, size.x, size.y));
+        }
+        ui.new_widget(window, Rect::with_size(0, 0, size.x, size.y), None, None, canvas);
+        window
+    }
+
+    fn new_text(&self, window: ui::Handle, color: Rgb15, align: HorzAlign, ui: &mut Ui)
+        -> ui::Handle
+    {
+        let rect = ui.widget_base_ref(window).rect();
+        let mut panel = MessagePanel::new(ui.fonts().clone(), FontKey::antialiased(1), color);
+        panel.set_horz_align(align);
+        ui.new_widget(window, Rect::with_size(self.border.x, self.border.y,
+            rect.width() - self.border.x * 2, rect.height() - self.border.y * 2), None, None, panel)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::script::ScriptKind;
+
+    fn next(say: &SayDialog) -> Option<usize> {
+        say.dialog.as_ref().unwrap().next
+    }
+
+    #[test]
+    fn define_and_pick() {
+        let sid = ScriptIid::new(ScriptKind::Spatial, 1);
+        let mut say = SayDialog::default();
+
+        assert!(!say.set_title("title".into()));
+        assert!(!say.add_reply("a".into(), "A".into()));
+        assert!(!say.go_to_reply("a".into()));
+        assert!(!say.restart());
+        assert!(!say.quit());
+        assert!(say.pick(0).is_none());
+
+        say.set_start_pos(1);
+        assert!(say.start(sid));
+        assert!(!say.start(sid));
+        assert_eq!(next(&say), Some(1));
+        assert!(!say.is_running());
+
+        assert!(!say.add_option("no reply".into(), Some(1)));
+        assert!(say.set_title("title".into()));
+        assert!(say.add_reply("First".into(), "first".into()));
+        assert!(say.add_option("one".into(), Some(10)));
+        assert!(say.add_option("two".into(), None));
+        assert!(say.add_reply("Second".into(), "second".into()));
+        assert!(say.add_option("three".into(), Some(30)));
+        {
+            let d = say.dialog.as_ref().unwrap();
+            assert_eq!(d.title.as_ref().unwrap(), "title");
+            assert_eq!(d.replies.len(), 2);
+            assert_eq!(d.replies[0].options.len(), 2);
+            assert_eq!(d.replies[1].options[0].text, "three");
+        }
+
+        assert!(say.go_to_reply("second".into()));
+        assert_eq!(next(&say), Some(1));
+        assert!(say.go_to_reply("FIRST".into()));
+        assert_eq!(next(&say), Some(0));
+        assert!(!say.go_to_reply("third".into()));
+        assert_eq!(next(&say), Some(0));
+
+        assert!(say.quit());
+        assert_eq!(say.pick(0), Some((sid, 10)));
+        assert_eq!(next(&say), None);
+        assert!(!say.dialog.as_ref().unwrap().quit);
+
+        assert!(say.restart());
+        assert_eq!(say.pick(1), None);
+        assert_eq!(next(&say), None);
+        assert_eq!(say.pick(2), None);
+
+        say.dialog.as_mut().unwrap().current = 1;
+        assert_eq!(say.pick(0), Some((sid, 30)));
+        assert_eq!(say.pick(1), None);
+
+        say.suspend_option();
+        assert!(say.take_suspended_option());
+        assert!(!say.take_suspended_option());
+    }
+
+    #[test]
+    fn start_pos() {
+        let sid = ScriptIid::new(ScriptKind::Spatial, 1);
+        let mut say = SayDialog::default();
+        say.set_start_pos(2);
+        assert!(say.start(sid));
+        assert_eq!(next(&say), Some(2));
+
+        say.dialog = None;
+        assert!(say.start(sid));
+        assert_eq!(next(&say), Some(0));
+    }
+}
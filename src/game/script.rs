@@ -5,6 +5,7 @@ use enum_map_derive::Enum;
 use enum_primitive_derive::Primitive;
 use num_traits::FromPrimitive;
 use log::*;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
 use std::fmt;
@@ -16,8 +17,10 @@ use crate::asset::proto::ProtoDb;
 use crate::asset::script::ProgramId;
 use crate::asset::script::db::ScriptDb;
 use crate::game::object;
+use crate::graphics::EPoint;
 use crate::util::EnumExt;
 use crate::vm::{self, *};
+use crate::vm::{debug, profile};
 use crate::vm::value::Value;
 
 pub const GVAR_PLAYER_REPUTATION: usize = 0;
@@ -91,6 +94,10 @@ impl ScriptIid {
         SidInternal::from_packed(v).map(Self)
     }
 
+    pub fn pack(self) -> u32 {
+        self.0.pack()
+    }
+
     pub fn read(rd: &mut impl Read) -> io::Result<Self> {
         SidInternal::read(rd).map(Self)
     }
@@ -160,7 +167,13 @@ pub struct Context<'a> {
     pub source_obj: Option<object::Handle>,
     pub target_obj: Option<object::Handle>,
     pub skill: Option<crate::asset::Skill>,
+    pub fixed_param: i32,
     pub rpg: &'a mut crate::game::rpg::Rpg,
+    pub combat: &'a mut crate::game::combat::Combat,
+    pub sound: &'a mut crate::game::sound::Sound,
+    pub movies: &'a mut crate::game::movie::Movies,
+    pub script_windows: &'a mut crate::game::script_window::ScriptWindows,
+    pub say_dialog: &'a mut crate::game::say::SayDialog,
 }
 
 pub struct Vars {
@@ -179,6 +192,13 @@ impl Vars {
     }
 }
 
+/// Area that triggers spatial script.
+#[derive(Clone, Copy, Debug)]
+pub struct Spatial {
+    pub pos: EPoint,
+    pub radius: u32,
+}
+
 pub struct Script {
     /// Whether the program's initialization code has been run.
     pub inited: bool,
@@ -186,6 +206,8 @@ pub struct Script {
     pub program: vm::Handle,
     pub local_vars: Box<[i32]>,
     pub object: Option<object::Handle>,
+    /// Only for `ScriptKind::Spatial` scripts.
+    pub spatial: Option<Spatial>,
 }
 
 /// Interface for instantiating new scripts from within a script context.
@@ -259,6 +281,14 @@ impl Scripts {
         }
     }
 
+    pub fn set_debugger(&mut self, debugger: Option<Rc<RefCell<debug::Debugger>>>) {
+        self.vm.set_debugger(debugger);
+    }
+
+    pub fn set_profiler(&mut self, profiler: Option<Rc<RefCell<profile::Profiler>>>) {
+        self.vm.set_profiler(profiler);
+    }
+
     pub fn map_sid(&self) -> Option<ScriptIid> {
         self.map_sid
     }
@@ -307,6 +337,7 @@ impl Scripts {
             program,
             local_vars,
             object: None,
+            spatial: None,
         });
         if let Some(existing) = existing {
             panic!("{:?} program #{} duplicates existing program #{}",
@@ -323,10 +354,30 @@ impl Scripts {
         Ok(sid)
     }
 
+    /// Instantiates a new script outside of script context and attaches it to `obj`.
+    pub fn instantiate_object_script(&mut self,
+        kind: ScriptKind,
+        program_id: ProgramId,
+        obj: object::Handle,
+    ) -> io::Result<ScriptIid> {
+        let sid = NewScripts::new(self).unused_sid(kind);
+        self.instantiate(sid, program_id, None)?;
+        self.attach_to_object(sid, obj);
+        Ok(sid)
+    }
+
     pub fn get(&self, sid: ScriptIid) -> Option<&Script> {
         self.scripts.get(&sid)
     }
 
+    pub fn get_mut(&mut self, sid: ScriptIid) -> Option<&mut Script> {
+        self.scripts.get_mut(&sid)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(ScriptIid, &Script)> {
+        self.scripts.iter().map(|(&sid, script)| (sid, script))
+    }
+
     pub fn attach_to_object(&mut self, sid: ScriptIid, obj: object::Handle) {
         self.scripts.get_mut(&sid).unwrap().object = Some(obj);
     }
@@ -343,6 +394,7 @@ impl Scripts {
                 &mut self.db,
                 new_scripts,
                 &self.proto_db,
+                sid,
                 script.object,
                 ctx);
             if !script.inited {
@@ -448,9 +500,13 @@ impl Scripts {
                 &mut self.db,
                 new_scripts,
                 &self.proto_db,
+                sid,
                 script.object,
                 ctx);
             let r = self.vm.program_state_mut(script.program).resume(&mut vm_ctx).unwrap();
+            if r.suspend.is_some() {
+                self.suspend_stack.push(sid);
+            }
             (r, vm_ctx.new_scripts)
         };
         new_scripts.instantiate(self);
@@ -464,6 +520,7 @@ impl Scripts {
         script_db: &'a mut ScriptDb,
         new_scripts: NewScripts,
         proto_db: &'a ProtoDb,
+        sid: ScriptIid,
         self_obj: Option<object::Handle>,
         ctx: &'a mut Context,
     ) -> vm::Context<'a> {
@@ -477,6 +534,8 @@ impl Scripts {
             source_obj: ctx.source_obj,
             target_obj: ctx.target_obj,
             skill: ctx.skill,
+            fixed_param: ctx.fixed_param,
+            sid,
             ui: ctx.ui,
             world: ctx.world,
             obj_sequencer: ctx.obj_sequencer,
@@ -487,6 +546,11 @@ impl Scripts {
             proto_db,
             map_id: ctx.map_id,
             rpg: ctx.rpg,
+            combat: ctx.combat,
+            sound: ctx.sound,
+            movies: ctx.movies,
+            script_windows: ctx.script_windows,
+            say_dialog: ctx.say_dialog,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,521 @@
+//! Windows created by scripts with `createwin` and the buttons and regions in them.
+
+use bstring::{bstr, BString};
+use enum_map::EnumMap;
+
+use crate::asset::EntityKind;
+use crate::asset::frame::{FrameDb, FrameId};
+use crate::game::script::ScriptIid;
+use crate::game::ui::script_window::{scale_point, ScriptButton, ScriptCanvas, ScriptRegion};
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::{Rgb15, WHITE};
+use crate::graphics::font::{DrawOptions, FontKey, HorzAlign, Overflow, OverflowAction,
+    OverflowBoundary};
+use crate::graphics::sprite::Sprite;
+use crate::ui::{self, Ui};
+use crate::ui::button;
+use crate::ui::command::ScriptWindowCommand;
+use crate::vm::ProcedureId;
+
+/// Returns frame of the image file at `path`. Only the images listed in `art/intrface/intrface.lst`
+/// are supported.
+pub fn image_fid(frm_db: &FrameDb, path: &bstr) -> Option<FrameId> {
+    let name = path.as_bytes().rsplit(|&c| c == b'\\' || c == b'/').next()?;
+    let idx = frm_db.find_id(EntityKind::Interface, &String::from_utf8_lossy(name))?;
+    FrameId::new_generic(EntityKind::Interface, idx)
+}
+
+/// Flag of `addbuttonflag` that disables the button.
+pub const BUTTON_FLAG_DISABLED: i32 = 0x08;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Right,
+    Center,
+}
+
+/// Button or region.
+struct Control {
+    name: BString,
+    widget: ui::Handle,
+    procs: EnumMap<ScriptWindowCommand, Option<(ScriptIid, ProcedureId)>>,
+    /// Flags set by `addbuttonflag` or `addregionflag`.
+    flags: i32,
+}
+
+impl Control {
+    fn new(name: &bstr, widget: ui::Handle) -> Self {
+        Self {
+            name: name.into(),
+            widget,
+            procs: EnumMap::new(),
+            flags: 0,
+        }
+    }
+
+    fn set_procs(&mut self, sid: ScriptIid, procs: &[(ScriptWindowCommand, Option<ProcedureId>)]) {
+        for &(cmd, proc_id) in procs {
+            self.procs[cmd] = proc_id.map(|p| (sid, p));
+        }
+    }
+}
+
+pub struct Window {
+    name: BString,
+    window: ui::Handle,
+    canvas: ui::Handle,
+    buttons: Vec<Control>,
+    regions: Vec<Control>,
+    text_pos: Point,
+    font: FontKey,
+    text_color: Rgb15,
+}
+
+impl Window {
+    pub fn rect(&self, ui: &Ui) -> Rect {
+        ui.widget_base_ref(self.window).rect()
+    }
+
+    pub fn show(&self, ui: &Ui) {
+        ui.widget_base_mut(self.window).set_visible(true);
+    }
+
+    /// Moves and resizes the window. If `scale` is `true` the window contents including buttons
+    /// and regions are scaled to the new size.
+    pub fn set_rect(&self, rect: Rect, scale: bool, ui: &Ui) {
+        let old = self.rect(ui);
+        let old_size = Point::new(old.width(), old.height());
+        let size = if scale {
+            Point::new(rect.width(), rect.height())
+        } else {
+            old_size
+        };
+        // Maps screen point within the old window rect to the new one.
+        let map = |p: Point| scale_point(p - old.top_left(), old_size, size) + rect.top_left();
+        let map_rect = |r: Rect| Rect::with_points(map(r.top_left()),
+            map(Point::new(r.right, r.bottom)));
+
+        ui.widget_base_mut(self.window).set_rect(rect);
+        ui.widget_base_mut(self.canvas).set_rect(Rect::with_size(rect.left, rect.top,
+            rect.width(), rect.height()));
+        if scale {
+            ui.widget_mut::<ScriptCanvas>(self.canvas).scale(old_size, size);
+        }
+        for c in &self.buttons {
+            let r = map_rect(ui.widget_base_ref(c.widget).rect());
+            ui.widget_base_mut(c.widget).set_rect(r);
+        }
+        for c in &self.regions {
+            let r = map_rect(ui.widget_base_ref(c.widget).rect());
+            ui.widget_base_mut(c.widget).set_rect(r);
+            ui.widget_mut::<ScriptRegion>(c.widget).map_points(map);
+        }
+    }
+
+    /// Fills `rect` or the whole window if `None`.
+    pub fn fill(&self, rect: Option<Rect>, color: Rgb15, ui: &Ui) {
+        let mut canvas = ui.widget_mut::<ScriptCanvas>(self.canvas);
+        if let Some(rect) = rect {
+            canvas.fill(rect, color);
+        } else {
+            let r = self.rect(ui);
+            canvas.fill_all(Point::new(r.width(), r.height()), color);
+        }
+    }
+
+    /// Draws image scaled to `rect` or to the whole window if `None`.
+    pub fn display(&self, fid: FrameId, rect: Option<Rect>, ui: &Ui) {
+        let rect = rect.unwrap_or_else(|| {
+            let r = self.rect(ui);
+            Rect::with_size(0, 0, r.width(), r.height())
+        });
+        ui.widget_mut::<ScriptCanvas>(self.canvas).image(fid, rect);
+    }
+
+    pub fn move_text_to(&mut self, pos: Point) {
+        self.text_pos = pos;
+    }
+
+    /// Returns `false` if there's no such font.
+    pub fn set_font(&mut self, font: FontKey, ui: &Ui) -> bool {
+        let ok = ui.fonts().contains(font);
+        if ok {
+            self.font = font;
+        }
+        ok
+    }
+
+    pub fn set_text_color(&mut self, color: Rgb15) {
+        self.text_color = color;
+    }
+
+    /// Prints `text` at the current text position. If `width` is specified the text is wrapped
+    /// and aligned within `width`.
+    pub fn print(&self, text: BString, width: Option<(i32, TextAlign)>, ui: &Ui) {
+        let mut pos = self.text_pos;
+        let mut options = DrawOptions::default();
+        if let Some((width, align)) = width {
+            options.horz_overflow = Some(Overflow {
+                size: width,
+                boundary: OverflowBoundary::Word,
+                action: OverflowAction::Wrap,
+            });
+            match align {
+                TextAlign::Left => {}
+                TextAlign::Right => {
+                    options.horz_align = HorzAlign::Right;
+                    pos.x += width;
+                }
+                TextAlign::Center => {
+                    options.horz_align = HorzAlign::Center;
+                    pos.x += width / 2;
+                }
+            }
+        }
+        ui.widget_mut::<ScriptCanvas>(self.canvas)
+            .text(text, pos, self.font, self.text_color, options);
+    }
+
+    /// Adds button replacing the existing one with the same name.
+    pub fn add_button(&mut self, name: &bstr, rect: Rect, ui: &mut Ui) {
+        self.delete_button(Some(name), ui);
+        let widget = ui.new_widget(self.window, rect, None, None, ScriptButton::new());
+        self.buttons.push(Control::new(name, widget));
+    }
+
+    /// Deletes button with the `name` or all buttons if `None`. Returns `false` if there's
+    /// no such button.
+    pub fn delete_button(&mut self, name: Option<&bstr>, ui: &mut Ui) -> bool {
+        delete_controls(&mut self.buttons, name, ui)
+    }
+
+    /// Sets text of the button. Returns `false` if there's no such button.
+    pub fn set_button_text(&self, name: &bstr, text: BString, ui: &Ui) -> bool {
+        self.with_button(name, ui, |b| {
+            let mut text = button::Text::new(text, self.font);
+            text.color = self.text_color;
+            text.options.horz_align = HorzAlign::Center;
+            text.options.vert_align = crate::graphics::font::VertAlign::Middle;
+            let b = b.button_mut();
+            b.config_mut(button::State::Disabled).text = Some(text.clone());
+            b.set_text(Some(text));
+        })
+    }
+
+    /// Sets images of the button in up, down and hover states.
+    /// Returns `false` if there's no such button.
+    pub fn set_button_images(&self,
+        name: &bstr,
+        up: Option<FrameId>,
+        down: Option<FrameId>,
+        hover: Option<FrameId>,
+        ui: &Ui,
+    ) -> bool {
+        self.with_button(name, ui, |b| {
+            b.set_images(up.map(Sprite::new), down.map(Sprite::new), hover.map(Sprite::new));
+        })
+    }
+
+    /// Adds `flags` to the button. Only `BUTTON_FLAG_DISABLED` has effect.
+    /// Returns `false` if there's no such button.
+    pub fn add_button_flags(&mut self, name: &bstr, flags: i32, ui: &Ui) -> bool {
+        if let Some(c) = find_control(&mut self.buttons, name) {
+            c.flags |= flags;
+            if c.flags & BUTTON_FLAG_DISABLED != 0 {
+                ui.widget_mut::<ScriptButton>(c.widget).button_mut().set_enabled(false);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Sets the procedures of the `sid` script called on mouse events of the button.
+    /// Returns `false` if there's no such button.
+    pub fn set_button_procs(&mut self, name: &bstr, sid: ScriptIid,
+        procs: &[(ScriptWindowCommand, Option<ProcedureId>)]) -> bool
+    {
+        find_control(&mut self.buttons, name)
+            .map(|c| c.set_procs(sid, procs))
+            .is_some()
+    }
+
+    /// Adds region with the polygon `points` specified in window coordinates replacing
+    /// the existing region with the same name.
+    pub fn add_region(&mut self, name: &bstr, points: Vec<Point>, ui: &mut Ui) {
+        self.delete_region(Some(name), ui);
+        let rect = ScriptRegion::bounds(&points);
+        let mut region = ScriptRegion::new(points);
+        region.translate(self.rect(ui).top_left());
+        let widget = ui.new_widget(self.window, rect, None, None, region);
+        self.regions.push(Control::new(name, widget));
+    }
+
+    /// Deletes region with the `name` or all regions if `None`. Returns `false` if there's
+    /// no such region.
+    pub fn delete_region(&mut self, name: Option<&bstr>, ui: &mut Ui) -> bool {
+        delete_controls(&mut self.regions, name, ui)
+    }
+
+    /// Enables or disables mouse events of the region. Returns `false` if there's no such region.
+    pub fn set_region_active(&mut self, name: &bstr, active: bool, ui: &Ui) -> bool {
+        if let Some(c) = find_control(&mut self.regions, name) {
+            ui.widget_mut::<ScriptRegion>(c.widget).set_active(active);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Adds `flags` to the region. The region flags have no effect.
+    /// Returns `false` if there's no such region.
+    pub fn add_region_flags(&mut self, name: &bstr, flags: i32) -> bool {
+        find_control(&mut self.regions, name)
+            .map(|c| c.flags |= flags)
+            .is_some()
+    }
+
+    /// Sets the procedures of the `sid` script called on mouse events of the region.
+    /// Returns `false` if there's no such region.
+    pub fn set_region_procs(&mut self, name: &bstr, sid: ScriptIid,
+        procs: &[(ScriptWindowCommand, Option<ProcedureId>)]) -> bool
+    {
+        find_control(&mut self.regions, name)
+            .map(|c| c.set_procs(sid, procs))
+            .is_some()
+    }
+
+    fn with_button(&self, name: &bstr, ui: &Ui, f: impl FnOnce(&mut ScriptButton)) -> bool {
+        if let Some(c) = self.buttons.iter().find(|c| eq_name(&c.name, name)) {
+            f(&mut ui.widget_mut::<ScriptButton>(c.widget));
+            true
+        } else {
+            false
+        }
+    }
+
+    fn controls(&self) -> impl Iterator<Item=&Control> {
+        self.buttons.iter().chain(self.regions.iter())
+    }
+}
+
+/// Windows created by scripts. Each script has its own current window that is the target of
+/// the drawing instructions.
+#[derive(Default)]
+pub struct ScriptWindows {
+    windows: Vec<Window>,
+    current: Vec<(ScriptIid, ui::Handle)>,
+}
+
+impl ScriptWindows {
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    /// Creates window replacing the existing one with the same name and makes it current for `sid`.
+    pub fn create(&mut self, sid: ScriptIid, name: &bstr, rect: Rect, ui: &mut Ui) {
+        self.delete(name, ui);
+        let window = ui.new_window(rect, None);
+        let canvas = ui.new_widget(window, Rect::with_size(0, 0, rect.width(), rect.height()),
+            None, None, ScriptCanvas::new());
+        self.windows.push(Window {
+            name: name.into(),
+            window,
+            canvas,
+            buttons: Vec::new(),
+            regions: Vec::new(),
+            text_pos: Point::new(0, 0),
+            font: FontKey::antialiased(1),
+            text_color: WHITE,
+        });
+        self.set_current(sid, window);
+    }
+
+    /// Returns `false` if there's no such window.
+    pub fn delete(&mut self, name: &bstr, ui: &mut Ui) -> bool {
+        if let Some(i) = self.windows.iter().position(|w| eq_name(&w.name, name)) {
+            let w = self.windows.remove(i);
+            ui.remove(w.window);
+            self.current.retain(|&(_, h)| h != w.window);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Makes the window current for `sid`. Returns `false` if there's no such window.
+    pub fn select(&mut self, sid: ScriptIid, name: &bstr) -> bool {
+        if let Some(h) = self.windows.iter().find(|w| eq_name(&w.name, name)).map(|w| w.window) {
+            self.set_current(sid, h);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the current window of `sid`.
+    pub fn current(&mut self, sid: ScriptIid) -> Option<&mut Window> {
+        let h = self.current.iter().find(|&&(s, _)| s == sid)?.1;
+        self.windows.iter_mut().find(|w| w.window == h)
+    }
+
+    /// Returns script procedure to call for the `cmd` fired by the `widget`.
+    pub fn handler(&self, widget: ui::Handle, cmd: ScriptWindowCommand)
+        -> Option<(ScriptIid, ProcedureId)>
+    {
+        self.windows.iter()
+            .flat_map(|w| w.controls())
+            .find(|c| c.widget == widget)?
+            .procs[cmd]
+    }
+
+    pub fn clear(&mut self, ui: &mut Ui) {
+        for w in self.windows.drain(..) {
+            ui.remove(w.window);
+        }
+        self.current.clear();
+    }
+
+    fn set_current(&mut self, sid: ScriptIid, window: ui::Handle) {
+        self.current.retain(|&(s, _)| s != sid);
+        self.current.push((sid, window));
+    }
+}
+
+fn eq_name(a: &bstr, b: &bstr) -> bool {
+    a.as_bytes().eq_ignore_ascii_case(b.as_bytes())
+}
+
+fn find_control<'a>(controls: &'a mut [Control], name: &bstr) -> Option<&'a mut Control> {
+    controls.iter_mut().find(|c| eq_name(&c.name, name))
+}
+
+fn delete_controls(controls: &mut Vec<Control>, name: Option<&bstr>, ui: &mut Ui) -> bool {
+    let mut found = false;
+    controls.retain(|c| {
+        let delete = name.map(|n| eq_name(&c.name, n)).unwrap_or(true);
+        if delete {
+            ui.remove(c.widget);
+            found = true;
+        }
+        !delete
+    });
+    found || name.is_none()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::rc::Rc;
+    use crate::asset::frame::FrameDb;
+    use crate::fs::FileSystem;
+    use crate::game::script::ScriptKind;
+    use crate::graphics::color::palette::overlay::PaletteOverlay;
+    use crate::graphics::font::Fonts;
+    use crate::graphics::render::software::Backend;
+    use crate::util::EnumExt;
+    use crate::util::test::ungz;
+
+    fn new_ui(name: &str) -> Ui {
+        let dir = std::env::temp_dir().join(format!("vault13-test-{}-{}", std::process::id(),
+            name));
+        for kind in EntityKind::iter() {
+            let path = dir.join(format!("art/{0}/{0}.lst", kind.dir()));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        let mut fs = FileSystem::new();
+        fs.register_provider(crate::fs::std::new_provider(&dir).unwrap());
+        let palette = crate::asset::palette::read_palette(&mut &ungz(
+            include_bytes!("../graphics/color/color.pal.gz"))[..]).unwrap();
+        let backend = Backend::new_headless(1, 1, Box::new(palette), PaletteOverlay::standard());
+        let frm_db = Rc::new(FrameDb::new(Rc::new(fs), "english", backend.new_texture_factory())
+            .unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        Ui::new(frm_db, Rc::new(Fonts::new()), 640, 480)
+    }
+
+    #[test]
+    fn windows() {
+        let ui = &mut new_ui("script-windows");
+        let sid1 = ScriptIid::new(ScriptKind::Spatial, 1);
+        let sid2 = ScriptIid::new(ScriptKind::Spatial, 2);
+        let mut w = ScriptWindows::default();
+
+        w.create(sid1, "one".into(), Rect::with_size(0, 0, 100, 100), ui);
+        w.create(sid2, "two".into(), Rect::with_size(0, 0, 100, 100), ui);
+        assert_eq!(w.current(sid1).unwrap().name, "one");
+        assert_eq!(w.current(sid2).unwrap().name, "two");
+
+        assert!(w.select(sid1, "TWO".into()));
+        assert!(!w.select(sid1, "three".into()));
+        assert_eq!(w.current(sid1).unwrap().name, "two");
+
+        assert!(w.delete("two".into(), ui));
+        assert!(!w.delete("two".into(), ui));
+        assert!(w.current(sid1).is_none());
+        assert!(w.current(sid2).is_none());
+
+        assert!(w.select(sid2, "one".into()));
+        let win = w.current(sid2).unwrap();
+        win.add_button("b".into(), Rect::with_size(10, 10, 20, 20), ui);
+        assert!(win.set_button_procs("B".into(), sid2, &[
+            (ScriptWindowCommand::Press, Some(3)),
+            (ScriptWindowCommand::Release, None),
+        ]));
+        assert!(!win.set_button_procs("c".into(), sid2, &[]));
+        win.add_region("r".into(), vec![Point::new(50, 50), Point::new(60, 50),
+            Point::new(60, 60)], ui);
+        assert!(win.set_region_procs("r".into(), sid1, &[(ScriptWindowCommand::Enter, Some(4))]));
+        let button = win.buttons[0].widget;
+        let region = win.regions[0].widget;
+
+        assert_eq!(w.handler(button, ScriptWindowCommand::Press), Some((sid2, 3)));
+        assert_eq!(w.handler(button, ScriptWindowCommand::Release), None);
+        assert_eq!(w.handler(region, ScriptWindowCommand::Enter), Some((sid1, 4)));
+        assert_eq!(w.handler(region, ScriptWindowCommand::Press), None);
+
+        let win = w.current(sid2).unwrap();
+        assert!(win.add_button_flags("b".into(), 0x01, ui));
+        assert!(ui.widget_ref::<ScriptButton>(button).button().is_enabled());
+        assert!(win.add_button_flags("b".into(), BUTTON_FLAG_DISABLED, ui));
+        assert!(!ui.widget_ref::<ScriptButton>(button).button().is_enabled());
+        assert_eq!(win.buttons[0].flags, 0x01 | BUTTON_FLAG_DISABLED);
+        assert!(!win.add_button_flags("r".into(), BUTTON_FLAG_DISABLED, ui));
+        assert!(win.add_region_flags("R".into(), 0x02));
+        assert!(!win.add_region_flags("b".into(), 0x02));
+
+        w.clear(ui);
+        assert!(w.is_empty());
+        assert_eq!(w.handler(button, ScriptWindowCommand::Press), None);
+    }
+
+    #[test]
+    fn set_rect_scale() {
+        let ui = &mut new_ui("script-window-scale");
+        let sid = ScriptIid::new(ScriptKind::Spatial, 1);
+        let mut w = ScriptWindows::default();
+        w.create(sid, "w".into(), Rect::with_size(10, 10, 100, 100), ui);
+        let win = w.current(sid).unwrap();
+        win.add_button("b".into(), Rect::with_size(10, 10, 20, 20), ui);
+        win.add_region("r".into(), vec![Point::new(50, 50), Point::new(60, 50),
+            Point::new(60, 60), Point::new(50, 60)], ui);
+        let button = win.buttons[0].widget;
+        let region = win.regions[0].widget;
+
+        win.set_rect(Rect::with_size(20, 30, 100, 100), false, ui);
+        assert_eq!(ui.widget_base_ref(button).rect(), Rect::with_size(30, 40, 20, 20));
+        assert_eq!(ui.widget_base_ref(region).rect(), Rect::with_size(70, 80, 11, 11));
+        assert!(ui.widget_ref::<ScriptRegion>(region).contains(Point::new(75, 85)));
+
+        win.set_rect(Rect::with_size(0, 0, 200, 50), true, ui);
+        assert_eq!(ui.widget_base_ref(win.canvas).rect(), Rect::with_size(0, 0, 200, 50));
+        assert_eq!(ui.widget_base_ref(button).rect(), Rect::with_size(20, 5, 40, 10));
+        assert_eq!(ui.widget_base_ref(region).rect(), Rect::with_size(100, 25, 22, 5));
+        let r = ui.widget_ref::<ScriptRegion>(region);
+        assert!(r.contains(Point::new(110, 27)));
+        assert!(!r.contains(Point::new(75, 85)));
+    }
+}
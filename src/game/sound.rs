@@ -0,0 +1,374 @@
+use enum_primitive_derive::Primitive;
+use num_traits::FromPrimitive;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::asset::{CritterAnim, EntityKind, Material, WeaponKind};
+use crate::asset::frame::{critter_anim_codes, FrameDb, FrameId};
+use crate::asset::map::MapId;
+use crate::asset::map::db::MapDef;
+use crate::asset::proto::{SubProto, Weapon};
+use crate::game::object::Object;
+use crate::sound::Audio;
+use crate::sound::mixer::{Channel, PlayOptions, SoundId};
+use crate::util::random::random;
+
+/// Interval in seconds between ambient sound effects.
+const AMBIENT_INTERVAL: (i32, i32) = (15, 20);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+pub enum CharacterSfx {
+    Unused = 0,
+    KnockDown = 1,
+    PassOut = 2,
+    Die = 3,
+    Contact = 4,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+pub enum WeaponSfx {
+    Ready = 0,
+    Attack = 1,
+    OutOfAmmo = 2,
+    Flying = 3,
+    Hit = 4,
+}
+
+impl WeaponSfx {
+    fn code(self) -> char {
+        b"RAOFH"[self as usize] as char
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+pub enum SceneryActionKind {
+    Active = 0,
+    Passive = 1,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+pub enum SceneryAction {
+    Open = 0,
+    Close = 1,
+    Lock = 2,
+    Unlock = 3,
+    Use = 4,
+}
+
+impl SceneryAction {
+    fn code(self) -> char {
+        b"OCLNU"[self as usize] as char
+    }
+}
+
+// gsnd_build_character_sfx_name()
+/// Builds sound effect name for critter `fid` playing `anim`. For `TakeOut` animation `extra`
+/// is the weapon kind, otherwise it's `CharacterSfx`.
+pub fn character_sfx_name(frm_db: &FrameDb, fid: FrameId, anim: CritterAnim, extra: i32)
+    -> Option<String>
+{
+    use CritterAnim::*;
+
+    let critter_fid = fid.critter()?;
+    let base_name = frm_db.base_name(fid)?;
+    let weapon = if anim == TakeOut {
+        WeaponKind::from_i32(extra)?
+    } else {
+        critter_fid.weapon()
+    };
+    let (mut c1, c2) = critter_anim_codes(weapon, anim)?;
+    let extra = CharacterSfx::from_i32(extra);
+    match anim {
+        FallFront | FallBack => match extra {
+            Some(CharacterSfx::PassOut) => c1 = 'y',
+            Some(CharacterSfx::Die) => c1 = 'z',
+            _ => {}
+        }
+        ThrowPunch | KickLeg if extra == Some(CharacterSfx::Contact) => c1 = 'z',
+        _ => {}
+    }
+    Some(format!("{}{}{}", base_name, c1, c2).to_ascii_uppercase())
+}
+
+// gsnd_build_ambient_sfx_name()
+pub fn ambient_sfx_name(name: &str) -> String {
+    format!("A{:>6}1", name).to_ascii_uppercase()
+}
+
+// gsnd_build_interface_sfx_name()
+pub fn interface_sfx_name(name: &str) -> String {
+    format!("N{:>6}1", name).to_ascii_uppercase()
+}
+
+// gsnd_build_item_sfx_name()
+pub fn item_sfx_name(name: &str) -> String {
+    format!("I{:>6}1", name).to_ascii_uppercase()
+}
+
+// gsnd_build_scenery_sfx_name()
+pub fn scenery_sfx_name(kind: SceneryActionKind, action: SceneryAction, name: &str) -> String {
+    let kind = match kind {
+        SceneryActionKind::Active => 'A',
+        SceneryActionKind::Passive => 'P',
+    };
+    format!("S{}{}{:>4}1", kind, action.code(), name).to_ascii_uppercase()
+}
+
+// gsnd_build_open_sfx_name()
+/// Builds sound effect name for opening/closing door (if `scenery` is `true`) or container.
+pub fn open_sfx_name(action: SceneryAction, scenery: bool, sound_id: u8) -> String {
+    if scenery {
+        format!("S{}DOORS{}", action.code(), sound_id as char)
+    } else {
+        format!("I{}CNTNR{}", action.code(), sound_id as char)
+    }.to_ascii_uppercase()
+}
+
+/// Builds `open_sfx_name()` for the door or container `obj`.
+pub fn object_open_sfx_name(obj: &Object, action: SceneryAction) -> Option<String> {
+    let proto = obj.proto();
+    let sub = proto.as_ref().map(|p| &p.sub);
+    match obj.fid.kind() {
+        EntityKind::Scenery => {
+            let sound_id = sub.and_then(|s| s.as_scenery()).map(|s| s.sound_id).unwrap_or(b'A');
+            Some(open_sfx_name(action, true, sound_id))
+        }
+        EntityKind::Item => sub?.as_item().map(|i| open_sfx_name(action, false, i.sound_id)),
+        _ => None,
+    }
+}
+
+/// Material of `obj` for the weapon hit sounds. `None` for objects without material (critters).
+pub fn object_material(obj: &Object) -> Option<Material> {
+    let proto = obj.proto()?;
+    match &proto.sub {
+        SubProto::Item(v) => Some(v.material),
+        SubProto::Scenery(v) => Some(v.material),
+        SubProto::Wall(v) => Some(v.material),
+        _ => None,
+    }
+}
+
+// gsnd_build_weapon_sfx_name()
+/// Builds sound effect name for the `weapon`. `target` is the material of the object being hit,
+/// `Some(None)` for critters, `None` if there's no target.
+pub fn weapon_sfx_name(
+    sfx: WeaponSfx,
+    weapon: &Weapon,
+    secondary: bool,
+    target: Option<Option<Material>>,
+) -> String {
+    use crate::asset::DamageKind::*;
+    use crate::asset::Material::*;
+
+    let attack_group = if secondary && sfx != WeaponSfx::Ready && sfx != WeaponSfx::OutOfAmmo {
+        2
+    } else {
+        1
+    };
+    let material = match target {
+        Some(_) if sfx != WeaponSfx::Hit => 'X',
+        Some(_) if weapon.damage_kind == Explosion
+            || weapon.damage_kind == Plasma
+            || weapon.damage_kind == Emp => 'X',
+        None => 'X',
+        Some(Some(Glass)) | Some(Some(Metal)) | Some(Some(Plastic)) => 'M',
+        Some(Some(Wood)) => 'W',
+        Some(Some(Dirt)) | Some(Some(Stone)) | Some(Some(Cement)) => 'S',
+        Some(_) => 'F',
+    };
+    format!("W{}{}{}{}XX1", sfx.code(), weapon.sound_id as char, attack_group, material)
+        .to_ascii_uppercase()
+}
+
+/// Game sounds: map music, ambient and sound effects.
+pub struct Sound {
+    audio: Audio,
+    music: Option<(String, SoundId)>,
+    /// Music set by scripts to override the `MapDef::music`.
+    map_music: HashMap<MapId, String>,
+    ambient_sfx: Vec<(String, u32)>,
+    next_ambient: Option<Instant>,
+}
+
+impl Sound {
+    pub fn new(audio: Audio) -> Self {
+        Self {
+            audio,
+            music: None,
+            map_music: HashMap::new(),
+            ambient_sfx: Vec::new(),
+            next_ambient: None,
+        }
+    }
+
+    pub fn audio(&self) -> &Audio {
+        &self.audio
+    }
+
+    // gsound_play_sfx_file()
+    pub fn play_sfx(&self, name: &str) -> Option<SoundId> {
+        self.play_sfx_delayed(name, Duration::from_secs(0))
+    }
+
+    pub fn play_sfx_delayed(&self, name: &str, delay: Duration) -> Option<SoundId> {
+        self.audio.play(&format!("sound/sfx/{}.acm", name), Channel::Sfx, PlayOptions {
+            delay,
+            ..Default::default()
+        })
+    }
+
+    /// Plays sound by name as used by `soundplay` opcode. The `name` can be a path with or
+    /// without extension, otherwise it's looked up in sound effects.
+    pub fn play_sound(&self, name: &str, looping: bool) -> Option<SoundId> {
+        let mut path = name.replace('\\', "/");
+        if !path.contains('/') {
+            path = format!("sound/sfx/{}", path);
+        }
+        if !path.contains('.') {
+            path.push_str(".acm");
+        }
+        self.audio.play(&path, Channel::Sfx, PlayOptions {
+            looping,
+            ..Default::default()
+        })
+    }
+
+    pub fn play_speech(&self, path: &str) -> Option<SoundId> {
+        self.audio.play(&format!("sound/speech/{}.acm", path), Channel::Speech, Default::default())
+    }
+
+    // gsound_background_play()
+    /// Starts looping `name` music. Does nothing if the music is already playing.
+    pub fn play_music(&mut self, name: &str) {
+        if let Some((cur, id)) = &self.music {
+            if cur.eq_ignore_ascii_case(name) && self.audio.mixer().is_playing(*id) {
+                return;
+            }
+        }
+        self.stop_music();
+        self.music = self.audio.play(&format!("sound/music/{}.acm", name), Channel::Music,
+            PlayOptions {
+                looping: true,
+                ..Default::default()
+            })
+            .map(|id| (name.to_owned(), id));
+    }
+
+    // gsound_background_stop()
+    pub fn stop_music(&mut self) {
+        if let Some((_, id)) = self.music.take() {
+            self.audio.mixer().stop(id);
+        }
+    }
+
+    // wmSetMapMusic()
+    pub fn set_map_music(&mut self, map_id: MapId, name: String) {
+        self.map_music.insert(map_id, name);
+    }
+
+    pub fn map_music<'a>(&'a self, map_id: MapId, map_def: &'a MapDef) -> Option<&'a str> {
+        self.map_music.get(&map_id)
+            .or(map_def.music.as_ref())
+            .map(|s| s.as_str())
+    }
+
+    /// Starts music and ambient sound effects of the map.
+    pub fn start_map(&mut self, map_id: MapId, map_def: &MapDef, now: Instant) {
+        if let Some(music) = self.map_music(map_id, map_def).map(|s| s.to_owned()) {
+            self.play_music(&music);
+        } else {
+            self.stop_music();
+        }
+        self.audio.mixer().stop_channel(Channel::Ambient);
+        self.ambient_sfx = map_def.ambient_sfx.clone();
+        self.next_ambient = Some(now);
+    }
+
+    // gsound_sfx_q_process()
+    /// Plays random ambient sound effect of the map from time to time.
+    pub fn update(&mut self, now: Instant) {
+        let next = if let Some(next) = self.next_ambient {
+            next
+        } else {
+            return;
+        };
+        if now < next {
+            return;
+        }
+        if let Some(name) = self.roll_ambient_sfx() {
+            self.audio.play(&format!("sound/sfx/{}.acm", ambient_sfx_name(&name)),
+                Channel::Ambient, Default::default());
+        }
+        let delay = random(AMBIENT_INTERVAL.0, AMBIENT_INTERVAL.1) as u64;
+        self.next_ambient = Some(now + Duration::from_secs(delay));
+    }
+
+    // wmSfxRollNextIdx()
+    fn roll_ambient_sfx(&self) -> Option<String> {
+        let total: u32 = self.ambient_sfx.iter().map(|&(_, chance)| chance).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut roll = random(1, total as i32) as u32;
+        for (name, chance) in &self.ambient_sfx {
+            if roll <= *chance {
+                return Some(name.clone());
+            }
+            roll -= chance;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asset::{AttackGroup, AttackKind, DamageKind};
+    use crate::util::RangeInclusive;
+    use enum_map::enum_map;
+
+    #[test]
+    fn names() {
+        assert_eq!(ambient_sfx_name("gustwd"), "AGUSTWD1");
+        assert_eq!(ambient_sfx_name("wind"), "A  WIND1");
+        assert_eq!(interface_sfx_name("butin1"), "NBUTIN11");
+        assert_eq!(item_sfx_name("ammo"), "I  AMMO1");
+        assert_eq!(scenery_sfx_name(SceneryActionKind::Passive, SceneryAction::Use, "comp"),
+            "SPUCOMP1");
+        assert_eq!(open_sfx_name(SceneryAction::Open, true, b'a'), "SODOORSA");
+        assert_eq!(open_sfx_name(SceneryAction::Lock, false, b'B'), "ILCNTNRB");
+    }
+
+    #[test]
+    fn weapon_sfx_name_() {
+        use WeaponSfx::*;
+
+        let mut weapon = Weapon {
+            attack_kinds: enum_map! { _ => AttackKind::FireSingle },
+            kind: WeaponKind::Pistol,
+            damage: RangeInclusive { start: 1, end: 2 },
+            damage_kind: DamageKind::Laser,
+            max_ranges: enum_map! { _ => 10 },
+            projectile_pid: None,
+            min_strength: 0,
+            ap_costs: enum_map! { AttackGroup::Primary => 5, AttackGroup::Secondary => 6 },
+            crit_failure_table: 0,
+            perk: None,
+            burst_bullet_count: 0,
+            caliber: 0,
+            ammo_proto_id: None,
+            max_ammo_count: 0,
+            sound_id: b'p',
+        };
+        assert_eq!(weapon_sfx_name(Attack, &weapon, false, None), "WAP1XXX1");
+        assert_eq!(weapon_sfx_name(Attack, &weapon, true, Some(None)), "WAP2XXX1");
+        assert_eq!(weapon_sfx_name(Ready, &weapon, true, None), "WRP1XXX1");
+        assert_eq!(weapon_sfx_name(Hit, &weapon, false, Some(None)), "WHP1FXX1");
+        assert_eq!(weapon_sfx_name(Hit, &weapon, false, Some(Some(Material::Metal))), "WHP1MXX1");
+        assert_eq!(weapon_sfx_name(Hit, &weapon, false, Some(Some(Material::Wood))), "WHP1WXX1");
+        assert_eq!(weapon_sfx_name(Hit, &weapon, false, Some(Some(Material::Stone))), "WHP1SXX1");
+        weapon.damage_kind = DamageKind::Plasma;
+        assert_eq!(weapon_sfx_name(Hit, &weapon, false, Some(Some(Material::Wood))), "WHP1XXX1");
+    }
+}
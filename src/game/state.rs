@@ -7,37 +7,57 @@ use sdl2::event::{Event as SdlEvent};
 use sdl2::keyboard::Keycode;
 use std::cell::RefCell;
 use std::cmp;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, prelude::*};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::time::{Instant, Duration};
 
 use crate::asset::{self, *};
+use crate::asset::ai::AiDb;
 use crate::asset::frame::{FrameDb, FrameId};
-use crate::asset::map::{ELEVATION_COUNT, MapId, MapReader};
-use crate::asset::map::db::MapDb;
+use crate::asset::map::{ELEVATION_COUNT, Map, MapId, MapReader, MapWriter};
+use crate::asset::map::db::{MapDb, MapDef};
 use crate::asset::message::{BULLET, Messages};
 use crate::asset::proto::*;
+use crate::asset::save::{self, SaveGame, SaveReader, SaveWriter};
 use crate::asset::script::db::ScriptDb;
 use crate::fs::FileSystem;
+use crate::fs::std::StdFileSystem;
+use crate::game::GameTime;
+use crate::game::ai::{self, Ai, Decision};
+use crate::game::combat::{self, Combat, Combatant};
 use crate::game::dialog::Dialog;
 use crate::game::fidget::Fidget;
 use crate::game::inventory::Inventory;
+use crate::game::movie::Movies;
 use crate::game::object::{self, *};
 use crate::game::rpg::Rpg;
+use crate::game::say::SayDialog;
 use crate::game::sequence::ObjSequencer;
 use crate::game::sequence::frame_anim::{AnimDirection, FrameAnim, FrameAnimOptions};
 use crate::game::sequence::move_seq::Move;
 use crate::game::sequence::stand::Stand;
 use crate::game::script::{self, Scripts, ScriptKind};
+use crate::game::script_window::ScriptWindows;
 use crate::game::skilldex::{self, Skilldex};
+use crate::game::sound::{self, SceneryAction, Sound};
 use crate::game::ui::action_menu::{self, Action};
 use crate::game::ui::hud;
+use crate::game::ui::message_box;
 use crate::game::ui::scroll_area::ScrollArea;
 use crate::game::ui::world::{HexCursorStyle, WorldView};
+use crate::game::ui::worldmap;
 use crate::game::world::{ScrollDirection, World, WorldRef};
-use crate::graphics::{EPoint, Rect};
+use crate::game::worldmap::{WorldMap, WorldMapRef};
+use crate::game::worldmap::def::*;
+use crate::game::worldmap::encounter;
+use crate::graphics::{EPoint, Point, Rect};
 use crate::graphics::font::Fonts;
 use crate::graphics::geometry::hex::{self, Direction};
+use crate::graphics::render::Canvas;
 use crate::sequence::{self, Sequencer};
+use crate::sound::Audio;
 use crate::sequence::event::PushEvent;
 use crate::sequence::chain::Chain;
 use crate::state::{self, *};
@@ -47,16 +67,35 @@ use crate::ui::command::inventory::Command;
 use crate::ui::message_panel::MessagePanel;
 use crate::util::{EnumExt, sprintf};
 use crate::util::random::random;
-use crate::vm::{Vm, PredefinedProc, Suspend};
+use crate::vm::{Vm, VmConfig, PredefinedProc, Suspend};
+use crate::vm::{debug, profile};
 
 const SCROLL_STEP: i32 = 10;
 
+/// Directory in the `data` dir where the map states of the current game are kept.
+const MAP_STATES_DIR: &str = "maps";
+/// Directories in the `data` dir used to replace the map states when loading a game.
+const MAP_STATES_STAGING_DIR: &str = "maps/staging";
+const MAP_STATES_BACKUP_DIR: &str = "maps/backup";
+const SAVE_FILE: &str = "SAVE.DAT";
+
+/// Map that is read but not yet entered. See `GameState::read_map()`.
+struct LoadedMap {
+    map: Map,
+    objects: Objects,
+    scripts: Scripts,
+}
+
 pub struct GameState {
     time: PausableTime,
     fs: Rc<FileSystem>,
+    data_fs: StdFileSystem,
+    language: String,
+    vm_config: Rc<VmConfig>,
     proto_db: Rc<ProtoDb>,
     frm_db: Rc<FrameDb>,
     map_db: MapDb,
+    ai: Ai,
     world: WorldRef,
     scripts: Scripts,
     obj_sequencer: ObjSequencer,
@@ -69,7 +108,9 @@ pub struct GameState {
     object_action_menu: Option<ObjectActionMenu>,
     user_paused: bool,
     map_id: Option<MapId>,
-    in_combat: bool,
+    map: Option<Map>,
+    kill_counts: [i32; save::KILL_KIND_COUNT],
+    combat: Combat,
     seq_events: Vec<sequence::Event>,
     misc_msgs: Rc<Messages>,
     scroll_areas: EnumMap<ScrollDirection, ui::Handle>,
@@ -77,20 +118,40 @@ pub struct GameState {
     skilldex: Skilldex,
     inventory: Inventory,
     ui_sequencer: Sequencer,
+    worldmap: WorldMapRef,
+    worldmap_window: Option<ui::Handle>,
+    /// Window asking whether to enter the encounter from the table, see `random_encounter()`.
+    encounter_prompt: Option<(ui::Handle, usize)>,
+    worldmap_msgs: Messages,
+    dbox_msgs: Messages,
+    sound: Sound,
+    movies: Movies,
+    script_windows: ScriptWindows,
+    say_dialog: SayDialog,
+    script_debugger: Option<Rc<RefCell<debug::Debugger>>>,
+    script_profiler: Option<Rc<RefCell<profile::Profiler>>>,
+    /// Party member PIDs from `party.txt`. The first one is the dude.
+    party: Vec<ProtoId>,
+    /// World map state from the last loaded save. Keeps the parts `WorldMap` doesn't implement.
+    worldmap_state: save::WorldMapState,
+    retained: save::Retained,
 }
 
 impl GameState {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         fs: Rc<FileSystem>,
+        data_fs: StdFileSystem,
         language: &str,
         proto_db: Rc<ProtoDb>,
         frm_db: Rc<FrameDb>,
         fonts: Rc<Fonts>,
         misc_msgs: Rc<Messages>,
+        audio: Audio,
+        vm_config: VmConfig,
         now: Instant,
         ui: &mut Ui,
-    ) -> Self {
+    ) -> io::Result<Self> {
         let time = PausableTime::new(now);
 
         let viewport = Rect::with_size(0, 0, 640, 380);
@@ -99,10 +160,15 @@ impl GameState {
         let critter_names = Messages::read_file(&fs, language, "game/scrname.msg").unwrap();
 
         let map_db = MapDb::new(&fs).unwrap();
+        let ai = Ai::new(AiDb::new(&fs).unwrap_or_else(|e| {
+            warn!("couldn't read AI packets, using defaults: {}", e);
+            AiDb::default()
+        }));
+        let vm_config = Rc::new(vm_config);
         let scripts = Scripts::new(
             proto_db.clone(),
             ScriptDb::new(fs.clone(), language).unwrap(),
-            Vm::default());
+            Vm::new(vm_config.clone()));
         let world = World::new(
             proto_db.clone(),
             frm_db.clone(),
@@ -132,12 +198,30 @@ impl GameState {
 
         let ui_sequencer = Sequencer::new(now);
 
-        Self {
+        let worldmap = Rc::new(RefCell::new(WorldMap::new(WorldMapDef::new(&fs)?)));
+        let worldmap_msgs = Messages::read_file(&fs, language, "game/worldmap.msg")?;
+        let dbox_msgs = Messages::read_file(&fs, language, "game/dbox.msg")?;
+
+        let movies = Movies::new(fs.clone(), language, audio.shared_mixer());
+
+        let party = fs.reader("data/party.txt")
+            .and_then(|mut rd| asset::read_party_member_pids(&mut rd))
+            .unwrap_or_else(|e| {
+                warn!("couldn't read party members: {}", e);
+                Vec::new()
+            });
+        let party = if party.is_empty() { vec![ProtoId::DUDE] } else { party };
+
+        Ok(Self {
             time,
             fs,
+            data_fs,
+            language: language.into(),
+            vm_config,
             frm_db,
             proto_db,
             map_db,
+            ai,
             world,
             scripts,
             obj_sequencer,
@@ -150,7 +234,9 @@ impl GameState {
             object_action_menu: None,
             user_paused: false,
             map_id: None,
-            in_combat: false,
+            map: None,
+            kill_counts: [0; save::KILL_KIND_COUNT],
+            combat: Combat::new(),
             seq_events: Vec::new(),
             misc_msgs,
             scroll_areas,
@@ -158,7 +244,21 @@ impl GameState {
             skilldex,
             inventory,
             ui_sequencer,
-        }
+            worldmap,
+            worldmap_window: None,
+            encounter_prompt: None,
+            worldmap_msgs,
+            dbox_msgs,
+            sound: Sound::new(audio),
+            movies,
+            script_windows: ScriptWindows::default(),
+            say_dialog: SayDialog::default(),
+            script_debugger: None,
+            script_profiler: None,
+            party,
+            worldmap_state: Default::default(),
+            retained: Default::default(),
+        })
     }
 
     pub fn world(&self) -> &RefCell<World> {
@@ -169,6 +269,24 @@ impl GameState {
         self.world_view
     }
 
+    pub fn script_debugger(&self) -> Option<&Rc<RefCell<debug::Debugger>>> {
+        self.script_debugger.as_ref()
+    }
+
+    pub fn set_script_debugger(&mut self, debugger: Option<Rc<RefCell<debug::Debugger>>>) {
+        self.scripts.set_debugger(debugger.clone());
+        self.script_debugger = debugger;
+    }
+
+    pub fn script_profiler(&self) -> Option<&Rc<RefCell<profile::Profiler>>> {
+        self.script_profiler.as_ref()
+    }
+
+    pub fn set_script_profiler(&mut self, profiler: Option<Rc<RefCell<profile::Profiler>>>) {
+        self.scripts.set_profiler(profiler.clone());
+        self.script_profiler = profiler;
+    }
+
     pub fn time(&self) -> &PausableTime {
         &self.time
     }
@@ -177,6 +295,10 @@ impl GameState {
         self.scripts.vars.global_vars =
             asset::read_game_global_vars(&mut self.fs.reader("data/vault13.gam").unwrap()).unwrap().into();
 
+        if let Err(e) = remove_map_states(&self.map_states_dir()) {
+            warn!("couldn't remove map states: {}", e);
+        }
+
         let dude_fid = FrameId::from_packed(0x100003E).unwrap();
         //    let dude_fid = FrameId::from_packed(0x101600A).unwrap();
         let _ = self.world.borrow_mut().objects_mut().create(
@@ -206,7 +328,13 @@ impl GameState {
         c.base_stats[Stat::CarryWeight] = 250;
     }
 
-    pub fn switch_map(&mut self, map_name: &str, ui: &mut Ui) {
+    /// Returns `true` if the map file exists. Map names come from editable text files, so they
+    /// are checked before leaving the current map.
+    fn map_exists(&self, map_name: &str) -> bool {
+        self.fs.exists(&format!("maps/{}.map", map_name))
+    }
+
+    pub fn switch_map(&mut self, map_name: &str, ui: &mut Ui) -> io::Result<()> {
         debug!("switching map to `{}`", map_name);
 
         if let Some(map_id) = self.map_id {
@@ -220,42 +348,67 @@ impl GameState {
                 source_obj: None,
                 target_obj: None,
                 skill: None,
+                fixed_param: 0,
                 rpg: &mut self.rpg,
+                combat: &mut self.combat,
+                sound: &mut self.sound,
+                movies: &mut self.movies,
+                script_windows: &mut self.script_windows,
+                say_dialog: &mut self.say_dialog,
             };
             self.scripts.execute_map_procs(PredefinedProc::MapExit, ctx);
         }
 
-        let mut dude_obj = {
-            let mut world = self.world.borrow_mut();
-            let dude_obj = world.objects().dude();
-            let dude_obj = world.objects_mut().remove_deep(dude_obj);
-            world.clear();
-            dude_obj
-        };
+        if self.map.is_some() {
+            if let Err(e) = self.save_map_state() {
+                error!("couldn't save map state: {}", e);
+            }
+        }
 
-        self.scripts.reset();
-        self.obj_sequencer.clear();
+        self.load_map(map_name, None, ui)
+    }
 
-        // Reinsert the hex cursor. Needs `world` to be not borrowed.
-        ui.widget_mut::<WorldView>(self.world_view).ensure_hex_cursor();
+    /// Loads map, placing the dude at `dude_pos` or at the map entrance if `None`.
+    /// Uses the saved map state if present. The current map is kept on failure.
+    fn load_map(&mut self, map_name: &str, dude_pos: Option<(EPoint, Direction)>, ui: &mut Ui)
+        -> io::Result<()>
+    {
+        let loaded = self.read_map(map_name, &self.map_states_dir())?;
+        let dude = {
+            let mut world = self.world.borrow_mut();
+            let dude = world.objects().dude();
+            world.objects_mut().remove_deep(dude)
+        };
+        self.enter_map(loaded, dude, dude_pos, ui);
+        Ok(())
+    }
 
-        let world = &mut self.world.borrow_mut();
+    /// Reads the map without modifying the current game. The saved map state is read from
+    /// `states_dir` if present.
+    fn read_map(&self, map_name: &str, states_dir: &Path) -> io::Result<LoadedMap> {
+        let mut objects = self.world.borrow().objects().new_empty_like();
+        let mut scripts = self.new_scripts()?;
 
+        let map_state_path = states_dir.join(map_state_file_name(map_name));
+        let reader: &mut Box<dyn BufRead + Send> = &mut if map_state_path.is_file() {
+            debug!("loading saved map state from {}", map_state_path.display());
+            Box::new(BufReader::new(File::open(&map_state_path)?))
+        } else {
+            self.fs.reader(&format!("maps/{}.map", map_name))?
+        };
         let map = MapReader {
-            reader: &mut self.fs.reader(&format!("maps/{}.map", map_name)).unwrap(),
-            objects: world.objects_mut(),
+            reader,
+            objects: &mut objects,
             proto_db: &self.proto_db,
             frm_db: &self.frm_db,
-            scripts: &mut self.scripts,
-        }.read().unwrap();
-
-        self.map_id = Some(map.id);
+            scripts: &mut scripts,
+        }.read()?;
 
         for elev in &map.sqr_tiles {
             if let Some(ref elev) = elev {
                 for &(floor, roof) in elev.as_slice() {
-                    self.frm_db.get(FrameId::new_generic(EntityKind::SqrTile, floor).unwrap()).unwrap();
-                    self.frm_db.get(FrameId::new_generic(EntityKind::SqrTile, roof).unwrap()).unwrap();
+                    self.frm_db.get(FrameId::new_generic(EntityKind::SqrTile, floor).unwrap())?;
+                    self.frm_db.get(FrameId::new_generic(EntityKind::SqrTile, roof).unwrap())?;
                 }
             } else {}
         }
@@ -269,41 +422,79 @@ impl GameState {
         }
         {
             debug_time!("preloading object FIDs");
-            for obj in world.objects().iter() {
-                for_each_direction(world.objects().get(obj).fid, |fid| {
+            for obj in objects.iter() {
+                for_each_direction(objects.get(obj).fid, |fid| {
                     if let Err(e) = self.frm_db.get(fid) {
                         warn!("error preloading {:?}: {:?}", fid, e);
                     }
                 });
             }
         }
-        self.frm_db.get(FrameId::EGG).unwrap();
+        self.frm_db.get(FrameId::EGG)?;
+
+        scripts.vars.map_vars = if map.savegame {
+            map.map_vars.clone()
+        } else {
+            let path = format!("maps/{}.gam", map_name);
+            if self.fs.exists(&path) {
+                asset::read_map_global_vars(&mut self.fs.reader(&path)?)?.into()
+            } else {
+                Vec::new().into()
+            }
+        };
+
+        Ok(LoadedMap {
+            map,
+            objects,
+            scripts,
+        })
+    }
+
+    /// Replaces the current map with the `loaded` one and places the `dude` at `dude_pos` or at
+    /// the map entrance if `None`.
+    fn enter_map(&mut self,
+        loaded: LoadedMap,
+        dude: ObjectGraph,
+        dude_pos: Option<(EPoint, Direction)>,
+        ui: &mut Ui,
+    ) {
+        let LoadedMap { map, objects, scripts } = loaded;
 
-        world.set_sqr_tiles(map.sqr_tiles);
+        self.scripts = scripts;
+        self.script_windows.clear(ui);
+        self.say_dialog.clear(ui);
+        self.obj_sequencer.clear();
+        self.combat.end();
 
         {
-            let mut dude_obj = dude_obj.objects.get_mut(dude_obj.root).unwrap();
-            dude_obj.direction = map.entrance_direction;
+            let mut world = self.world.borrow_mut();
+            world.clear();
+            *world.objects_mut() = objects;
+            world.set_sqr_tiles(map.sqr_tiles.clone());
+        }
+
+        // Reinsert the hex cursor. Needs `world` to be not borrowed.
+        ui.widget_mut::<WorldView>(self.world_view).ensure_hex_cursor();
+
+        let world = &mut self.world.borrow_mut();
+
+        self.map_id = Some(map.id);
+
+        let mut dude = dude;
+        {
+            let (pos, direction) = dude_pos.unwrap_or((map.entrance, map.entrance_direction));
+            let dude_obj = dude.objects.get_mut(dude.root).unwrap();
+            dude_obj.direction = direction;
             dude_obj.set_light_emitter(LightEmitter {
                 intensity: 0x10000,
                 radius: 4,
             });
-            dude_obj.set_pos(Some(map.entrance));
+            dude_obj.set_pos(Some(pos));
         }
-        let dude_obj = world.objects_mut().insert_graph(dude_obj);
+        let dude_obj = world.objects_mut().insert_graph(dude);
 
         world.objects_mut().make_standing(dude_obj);
 
-        {
-            assert!(!map.savegame);
-            let path = format!("maps/{}.gam", map_name);
-            self.scripts.vars.map_vars = if self.fs.exists(&path) {
-                asset::read_map_global_vars(&mut self.fs.reader(&path).unwrap()).unwrap().into()
-            } else {
-                Vec::new().into()
-            };
-        }
-
         // Init scripts.
         {
             let ctx = &mut script::Context {
@@ -316,7 +507,13 @@ impl GameState {
                 source_obj: None,
                 target_obj: None,
                 skill: None,
+                fixed_param: 0,
                 rpg: &mut self.rpg,
+                combat: &mut self.combat,
+                sound: &mut self.sound,
+                movies: &mut self.movies,
+                script_windows: &mut self.script_windows,
+                say_dialog: &mut self.say_dialog,
             };
 
             // PredefinedProc::Start for map script is never called.
@@ -332,6 +529,254 @@ impl GameState {
         }
 
         world.camera_look_at_dude();
+
+        if let Some(map_def) = self.map_db.get(map.id) {
+            self.sound.start_map(map.id, map_def, self.time.time());
+        }
+
+        self.map = Some(map);
+    }
+
+    /// Creates empty `Scripts` that share the global vars and debugging tools with the current
+    /// ones.
+    fn new_scripts(&self) -> io::Result<Scripts> {
+        let mut r = Scripts::new(
+            self.proto_db.clone(),
+            ScriptDb::new(self.fs.clone(), &self.language)?,
+            Vm::new(self.vm_config.clone()));
+        r.set_debugger(self.script_debugger.clone());
+        r.set_profiler(self.script_profiler.clone());
+        r.vars.global_vars = self.scripts.vars.global_vars.clone();
+        Ok(r)
+    }
+
+    fn map_states_dir(&self) -> PathBuf {
+        self.data_fs.resolve(MAP_STATES_DIR)
+    }
+
+    fn map_state_path(&self, map_name: &str) -> PathBuf {
+        self.map_states_dir().join(map_state_file_name(map_name))
+    }
+
+    fn slot_dir(&self, slot: u32) -> PathBuf {
+        self.data_fs.resolve(&slot_dir_path(slot))
+    }
+
+    fn map_def(&self) -> io::Result<&MapDef> {
+        self.map_id
+            .and_then(|id| self.map_db.get(id))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                format!("current map ({:?}) is not in maps.txt", self.map_id)))
+    }
+
+    // map_save_in_game
+    fn save_map_state(&mut self) -> io::Result<()> {
+        let map_def = self.map_def()?;
+        if !map_def.saved {
+            return Ok(());
+        }
+        let path = self.map_state_path(&map_def.name);
+        debug!("saving map state to {}", path.display());
+        self.data_fs.create_dir_all(MAP_STATES_DIR)?;
+
+        let world = self.world.borrow();
+        let map = self.map.as_mut().unwrap();
+        map.savegame = true;
+        map.map_vars = self.scripts.vars.map_vars.clone();
+        map.time = world.game_time.as_decis();
+
+        let writer = &mut BufWriter::new(File::create(&path)?);
+        MapWriter {
+            writer,
+            objects: world.objects(),
+            scripts: &self.scripts,
+        }.write(map)?;
+        writer.flush()
+    }
+
+    /// Saves the game to the `slot`. The thumbnail is made from the world view area of the last
+    /// frame rendered to the `canvas`.
+    // SaveGame
+    pub fn save_game(&mut self, slot: u32, description: &bstr, canvas: &dyn Canvas, ui: &Ui)
+        -> io::Result<()>
+    {
+        info!("saving game to slot {}", slot);
+
+        if self.combat.is_active() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "can't save during combat"));
+        }
+
+        self.save_map_state()?;
+
+        let thumbnail = {
+            let (width, height) = canvas.size();
+            let rect = ui.widget_base_ref(self.world_view).rect()
+                .intersect(Rect::with_size(0, 0, width, height));
+            save::make_thumbnail(canvas.back_buffer(), width, rect)
+        };
+
+        let mut party_ai = Vec::new();
+        for &pid in &self.party {
+            if pid.kind() == EntityKind::Critter {
+                let proto = self.proto_db.proto(pid)?;
+                let critter = proto.borrow();
+                let critter = critter.sub.as_critter().unwrap();
+                party_ai.push(save::PartyMemberAi {
+                    packet: critter.ai_packet,
+                    team: critter.team_id,
+                });
+            }
+        }
+
+        let mut worldmap = self.worldmap_state.clone();
+        self.worldmap.borrow().save_state(&mut worldmap);
+
+        let slot_dir = self.data_fs.create_dir_all(&slot_dir_path(slot))?;
+        remove_map_states(&slot_dir)?;
+        let map_files = copy_map_states(&self.map_states_dir(), &slot_dir)?;
+
+        let world = self.world.borrow();
+        let dude = world.objects().dude();
+        let dude_pos = world.objects().get(dude).pos();
+        let active_hand = world.objects().get(dude).sub.as_critter().unwrap().dude.as_ref()
+            .map(|d| d.active_hand)
+            .unwrap_or(Hand::Left);
+        let map_name = &self.map_def()?.name;
+        let (file_date, file_time) = save::Date::now();
+        let character_name = self.proto_db.dude().borrow().name()
+            .map(|s| s.to_owned())
+            .unwrap_or_default();
+        let save = SaveGame {
+            header: save::Header {
+                character_name,
+                description: description.to_owned(),
+                file_date,
+                file_time,
+                game_time: world.game_time,
+                elevation: dude_pos.elevation,
+                map_id: self.map_id.unwrap(),
+                map_file_name: map_state_file_name(map_name).into(),
+                thumbnail,
+            },
+            global_vars: self.scripts.vars.global_vars.clone(),
+            map_files,
+            automap_size: 0,
+            dude,
+            center_tile: world.hex_grid().to_linear_inv(dude_pos.point)
+                .map(|v| v as i32)
+                .unwrap_or(-1),
+            sneak_working: false,
+            dude_critter: self.proto_db.dude().borrow().sub.as_critter().unwrap().clone(),
+            kill_counts: self.kill_counts,
+            tagged_skills: self.rpg.tagged_skills(),
+            perks: self.party.iter().map(|&pid| self.rpg.perks(pid)).collect(),
+            party_ai,
+            pc_stats: *self.rpg.pc_stats(),
+            traits: self.rpg.traits(),
+            worldmap,
+            movies_seen: *self.movies.seen(),
+            active_hand,
+            retained: self.retained.clone(),
+        };
+
+        let writer = &mut BufWriter::new(
+            self.data_fs.create(&format!("{}/{}", slot_dir_path(slot), SAVE_FILE))?);
+        SaveWriter {
+            writer,
+            objects: world.objects(),
+            scripts: &self.scripts,
+            party: &self.party,
+        }.write(&save)?;
+        writer.flush()
+    }
+
+    /// Loads the game from the `slot`. The current game is kept on failure.
+    // LoadGame
+    pub fn load_game(&mut self, slot: u32, ui: &mut Ui) -> io::Result<()> {
+        info!("loading game from slot {}", slot);
+
+        let save_path = self.data_fs.resolve(&format!("{}/{}", slot_dir_path(slot), SAVE_FILE));
+
+        let slot_dir = self.slot_dir(slot);
+
+        // Read everything into temporaries and replace the map state files before touching
+        // the current game.
+        let mut objects = Objects::new(
+            self.world.borrow().hex_grid().clone(),
+            ELEVATION_COUNT,
+            self.frm_db.clone(),
+            self.proto_db.clone());
+        let save = SaveReader {
+            reader: &mut BufReader::new(File::open(&save_path)?),
+            objects: &mut objects,
+            proto_db: &self.proto_db,
+            frm_db: &self.frm_db,
+            scripts: &mut self.new_scripts()?,
+            party: &self.party,
+        }.read()?;
+        for file in &save.map_files {
+            let path = slot_dir.join(&*String::from_utf8_lossy(file.as_bytes()));
+            if !path.is_file() {
+                return Err(io::Error::new(io::ErrorKind::NotFound,
+                    format!("missing map state file: {}", path.display())));
+            }
+        }
+
+        let mut party_protos = Vec::with_capacity(save.party_ai.len());
+        for &pid in self.party.iter().filter(|pid| pid.kind() == EntityKind::Critter) {
+            party_protos.push(self.proto_db.proto(pid)?);
+        }
+
+        let mut worldmap = WorldMap::new(self.worldmap.borrow().def().clone());
+        worldmap.load_state(&save.worldmap)?;
+
+        let mut loaded = self.read_map(&save.header.map_name(), &slot_dir)?;
+        loaded.scripts.vars.global_vars = save.global_vars.clone();
+
+        let staging_dir = self.data_fs.create_dir_all(MAP_STATES_STAGING_DIR)?;
+        remove_map_states(&staging_dir)?;
+        for file in &save.map_files {
+            let file = String::from_utf8_lossy(file.as_bytes());
+            fs::copy(slot_dir.join(&*file), staging_dir.join(&*file))?;
+        }
+        replace_map_states(
+            &self.data_fs.create_dir_all(MAP_STATES_DIR)?,
+            &staging_dir,
+            &self.data_fs.create_dir_all(MAP_STATES_BACKUP_DIR)?)?;
+
+        let mut dude = objects.remove_deep(save.dude);
+        let dude_pos = {
+            let dude = &mut dude.objects[dude.root];
+            dude.sub.as_critter_mut().unwrap().dude = Some(Box::new(object::Dude {
+                naked_fidx: 0x3e,
+                active_hand: save.active_hand,
+            }));
+            (dude.pos(), dude.direction)
+        };
+
+        self.world.borrow_mut().game_time = save.header.game_time;
+        *self.proto_db.dude().borrow_mut().sub.as_critter_mut().unwrap() = save.dude_critter;
+        self.kill_counts = save.kill_counts;
+        self.rpg.set_tagged_skills(&save.tagged_skills);
+        for (&pid, perks) in self.party.iter().zip(save.perks) {
+            self.rpg.set_perks(pid, perks);
+        }
+        for (proto, ai) in party_protos.iter().zip(&save.party_ai) {
+            let mut proto = proto.borrow_mut();
+            let critter = proto.sub.as_critter_mut().unwrap();
+            critter.ai_packet = ai.packet;
+            critter.team_id = ai.team;
+        }
+        self.rpg.set_pc_stats(save.pc_stats);
+        self.rpg.set_traits(&save.traits);
+        *self.worldmap.borrow_mut() = worldmap;
+        self.worldmap_state = save.worldmap;
+        self.movies.set_seen(save.movies_seen);
+        self.retained = save.retained;
+
+        self.enter_map(loaded, dude, Some(dude_pos), ui);
+
+        Ok(())
     }
 
     fn handle_action(
@@ -405,6 +850,9 @@ impl GameState {
                 UseSkill { skill, user, target } => {
                     self.use_skill_on(skill, user, target, ctx.ui);
                 }
+                Attack { attacker, target } => {
+                    self.attack(attacker, target, ctx.ui);
+                }
             }
         }
         std::mem::replace(&mut self.seq_events, events);
@@ -420,7 +868,7 @@ impl GameState {
                     r.push(Action::Rotate);
                 } else {
                     if world.objects().get(objh).can_talk_to() {
-                        if !self.in_combat {
+                        if !self.combat.is_active() {
                             r.push(Action::Talk);
                         }
                     } else if !obj.proto().unwrap()
@@ -430,7 +878,7 @@ impl GameState {
                         r.push(Action::UseHand);
                     }
                     if world.objects().can_push(world.objects().dude(), objh,
-                        &self.scripts, self.in_combat)
+                        &self.scripts, self.combat.is_active())
                     {
                         r.push(Action::Push);
                     }
@@ -495,7 +943,13 @@ impl GameState {
                     source_obj: Some(looker),
                     target_obj: Some(looked),
                     skill: None,
+                    fixed_param: 0,
                     rpg: &mut self.rpg,
+                    combat: &mut self.combat,
+                    sound: &mut self.sound,
+                    movies: &mut self.movies,
+                    script_windows: &mut self.script_windows,
+                    say_dialog: &mut self.say_dialog,
                 });
             then {
                 assert!(r.suspend.is_none(), "can't suspend");
@@ -560,7 +1014,13 @@ impl GameState {
                     source_obj: Some(examiner),
                     target_obj: Some(examined),
                     skill: None,
+                    fixed_param: 0,
                     rpg: &mut self.rpg,
+                    combat: &mut self.combat,
+                    sound: &mut self.sound,
+                    movies: &mut self.movies,
+                    script_windows: &mut self.script_windows,
+                    say_dialog: &mut self.say_dialog,
                 });
             then {
                 assert!(r.suspend.is_none(), "can't suspend");
@@ -656,10 +1116,17 @@ impl GameState {
                         source_obj: Some(talker),
                         target_obj: Some(talked),
                         skill: None,
+                        fixed_param: 0,
                         rpg: &mut self.rpg,
+                        combat: &mut self.combat,
+                        sound: &mut self.sound,
+                        movies: &mut self.movies,
+                        script_windows: &mut self.script_windows,
+                        say_dialog: &mut self.say_dialog,
                     }).and_then(|r| r.suspend)
                     {
-                        None | Some(Suspend::GsayEnd) => {}
+                        None | Some(Suspend::GsayEnd) | Some(Suspend::SayEnd)
+                        | Some(Suspend::SayMessage) => {}
                     }
             }
         } else {
@@ -669,6 +1136,44 @@ impl GameState {
         }
     }
 
+    /// Resumes the script suspended in `sayend` or `saymessage` after the dialog or message has
+    /// been closed.
+    fn resume_say(&mut self, ui: &mut Ui) {
+        let map_id = if let Some(v) = self.map_id {
+            v
+        } else {
+            return;
+        };
+        while self.scripts.can_resume() {
+            let r = self.scripts.resume(&mut script::Context {
+                ui,
+                world: &mut self.world.borrow_mut(),
+                obj_sequencer: &mut self.obj_sequencer,
+                dialog: &mut self.dialog,
+                message_panel: self.message_panel,
+                map_id,
+                source_obj: None,
+                target_obj: None,
+                skill: None,
+                fixed_param: 0,
+                rpg: &mut self.rpg,
+                combat: &mut self.combat,
+                sound: &mut self.sound,
+                movies: &mut self.movies,
+                script_windows: &mut self.script_windows,
+                say_dialog: &mut self.say_dialog,
+            });
+            // If the finished procedure is the suspended option procedure show the next reply
+            // and resume `sayend` if that ends the dialog.
+            if r.suspend.is_some()
+                || !self.say_dialog.take_suspended_option()
+                || !self.say_dialog.next_reply(ui)
+            {
+                break;
+            }
+        }
+    }
+
     //  action_use_an_item_on_object_
     fn action_use_obj(&mut self, user: object::Handle, used: object::Handle) {
         let world = self.world.borrow();
@@ -763,7 +1268,13 @@ impl GameState {
                         source_obj: Some(user),
                         target_obj: Some(used),
                         skill: None,
+                        fixed_param: 0,
                         rpg: &mut self.rpg,
+                        combat: &mut self.combat,
+                        sound: &mut self.sound,
+                        movies: &mut self.movies,
+                        script_windows: &mut self.script_windows,
+                        say_dialog: &mut self.say_dialog,
                     }).unwrap().assert_no_suspend().script_overrides
             } else {
                 false
@@ -801,7 +1312,9 @@ impl GameState {
         let script = {
             let dooro = world.objects().get(door);
             if dooro.is_locked().unwrap() {
-                // TODO sfx
+                if let Some(sfx) = sound::object_open_sfx_name(&dooro, SceneryAction::Lock) {
+                    self.sound.play_sfx(&sfx);
+                }
             }
             dooro.script
         };
@@ -818,7 +1331,13 @@ impl GameState {
                     source_obj: Some(user),
                     target_obj: Some(door),
                     skill: None,
+                    fixed_param: 0,
                     rpg: &mut self.rpg,
+                    combat: &mut self.combat,
+                    sound: &mut self.sound,
+                    movies: &mut self.movies,
+                    script_windows: &mut self.script_windows,
+                    say_dialog: &mut self.say_dialog,
                 }).unwrap().assert_no_suspend().script_overrides;
             if script_overrides {
                 return;
@@ -840,6 +1359,11 @@ impl GameState {
             true
         };
 
+        let action = if need_open { SceneryAction::Open } else { SceneryAction::Close };
+        if let Some(sfx) = sound::object_open_sfx_name(&dooro, action) {
+            self.sound.play_sfx(&sfx);
+        }
+
         let seq = Chain::new();
         seq.control()
             .cancellable(FrameAnim::new(door, FrameAnimOptions {
@@ -949,7 +1473,13 @@ impl GameState {
                 source_obj: None,
                 target_obj: None,
                 skill: None,
+                fixed_param: 0,
                 rpg: &mut self.rpg,
+                combat: &mut self.combat,
+                sound: &mut self.sound,
+                movies: &mut self.movies,
+                script_windows: &mut self.script_windows,
+                say_dialog: &mut self.say_dialog,
             };
             self.scripts.execute_map_procs(PredefinedProc::MapUpdate, ctx);
         }
@@ -964,6 +1494,329 @@ impl GameState {
         self.skilldex.show(ui, levels, target);
     }
 
+    fn show_worldmap(&mut self, ui: &mut Ui) {
+        if self.worldmap_window.is_some() {
+            return;
+        }
+        {
+            let mut worldmap = self.worldmap.borrow_mut();
+            let area = self.map_id
+                .and_then(|id| self.map_db.get(id))
+                .and_then(|m| worldmap.def().area_by_map(&m.lookup_name));
+            if let Some(area) = area {
+                let pos = worldmap.def().areas[area].pos;
+                worldmap.set_pos(pos);
+                worldmap.set_area_known(area, true);
+            }
+        }
+        self.obj_sequencer.cancel(self.world.borrow().objects().dude());
+        self.worldmap_window = Some(worldmap::create(self.worldmap.clone(), ui));
+    }
+
+    fn hide_worldmap(&mut self, ui: &mut Ui) {
+        if let Some((win, _)) = self.encounter_prompt.take() {
+            ui.remove(win);
+        }
+        if let Some(win) = self.worldmap_window.take() {
+            ui.remove(win);
+        }
+    }
+
+    /// Enters the map of the first known entrance of the `area`.
+    fn enter_area(&mut self, area: usize, ui: &mut Ui) {
+        let entrance = {
+            let worldmap = self.worldmap.borrow();
+            let area = &worldmap.def().areas[area];
+            if area.locked {
+                debug!("area `{}` is locked", area.name);
+                return;
+            }
+            if let Some(e) = area.entrances.iter().find(|e| e.known) {
+                e.clone()
+            } else {
+                warn!("area `{}` has no known entrances", area.name);
+                return;
+            }
+        };
+        let map_name = if let Some(map_def) = self.map_db.find(&entrance.map) {
+            map_def.name.clone()
+        } else {
+            warn!("unknown map `{}` in area entrance", entrance.map);
+            return;
+        };
+        if !self.map_exists(&map_name) {
+            warn!("missing map file of area entrance: {}", map_name);
+            return;
+        }
+        if let Err(e) = self.switch_map(&map_name, ui) {
+            error!("couldn't enter area: {}", e);
+            return;
+        }
+        self.hide_worldmap(ui);
+        if let Some(pos) = entrance.dude_pos {
+            self.set_dude_pos(pos, entrance.direction, ui);
+        }
+    }
+
+    /// Called when the random encounter roll succeeds during world map travel. If the
+    /// Outdoorsman roll succeeds the player is asked whether to enter the encounter, unless the
+    /// encounter is forced.
+    // wmRndEncounterOccurred()
+    fn random_encounter(&mut self, ui: &mut Ui) {
+        let hour = self.world.borrow().game_time.hour();
+        let (table, frequency) = {
+            let worldmap = self.worldmap.borrow();
+            (worldmap.encounter_table(), worldmap.frequency(hour))
+        };
+        let table = if let Some(v) = table {
+            v
+        } else {
+            return;
+        };
+        if frequency != Frequency::Forced {
+            let detected = {
+                let world = self.world.borrow();
+                let objs = world.objects();
+                let (r, _) = self.rpg.roll_check_skill(Skill::Outdoorsman, 0,
+                    world.game_time.roll_checker(), &objs.dude_ref(), objs);
+                r.is_success()
+            };
+            if detected {
+                let window = message_box::show_yes_no(Point::new(169, 116),
+                    &self.worldmap_msgs.get(2999).unwrap().text,
+                    &[&self.worldmap_msgs.get(3000).unwrap().text],
+                    &self.dbox_msgs,
+                    UiCommandData::WorldMap(WorldMapCommand::Encounter { accept: true }),
+                    UiCommandData::WorldMap(WorldMapCommand::Encounter { accept: false }),
+                    ui);
+                self.encounter_prompt = Some((window, table));
+                return;
+            }
+        }
+        self.pick_encounter(table, ui);
+    }
+
+    /// Picks encounter from the `table` according to the entry conditions and starts it.
+    fn pick_encounter(&mut self, table: usize, ui: &mut Ui) {
+        let entry = {
+            let player_level = self.rpg.pc_stat(PCStat::Level);
+            let global_vars = &self.scripts.vars.global_vars;
+            self.worldmap.borrow_mut().pick_encounter(table,
+                |e| encounter::check_conditions(&e.conditions, player_level, global_vars))
+        };
+        if let Some(entry) = entry {
+            if let Err(e) = self.start_encounter(table, entry, ui) {
+                warn!("couldn't start random encounter: {}", e);
+            }
+        }
+    }
+
+    /// Starts encounter `entry` (index of `enc_XX`) from the encounter table with `table_name`.
+    /// If `entry` is `None` it is picked randomly ignoring the entry conditions.
+    /// Useful for reproducing specific encounters.
+    pub fn force_encounter(&mut self, table_name: &str, entry: Option<usize>, ui: &mut Ui)
+        -> io::Result<()>
+    {
+        let (table, entry) = {
+            let mut worldmap = self.worldmap.borrow_mut();
+            let table = worldmap.def().encounter_table_by_name(table_name)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("unknown encounter table `{}`", table_name)))?;
+            let entry = if let Some(entry) = entry {
+                if entry >= worldmap.def().encounter_tables[table].entries.len() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                        format!("no entry {} in encounter table `{}`", entry, table_name)));
+                }
+                worldmap.use_encounter(table, entry);
+                entry
+            } else {
+                worldmap.pick_encounter(table, |_| true)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                        format!("no encounters left in encounter table `{}`", table_name)))?
+            };
+            (table, entry)
+        };
+        self.start_encounter(table, entry, ui)
+    }
+
+    /// Switches to the encounter map and places the encounter groups around the dude.
+    // wmRndEncounterPick()
+    fn start_encounter(&mut self, table: usize, entry: usize, ui: &mut Ui) -> io::Result<()> {
+        let (map, entry) = {
+            let worldmap = self.worldmap.borrow();
+            let table = &worldmap.def().encounter_tables[table];
+            let entry = table.entries[entry].clone();
+            let map = entry.map.clone()
+                .or_else(|| if table.maps.is_empty() {
+                    None
+                } else {
+                    Some(table.maps[random(0, table.maps.len() as i32 - 1) as usize].clone())
+                })
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                    format!("no maps in encounter table `{}`", table.name)))?;
+            (map, entry)
+        };
+        let (map_name, start_points) = {
+            let map_def = self.map_db.find(&map)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                    format!("unknown encounter map `{}`", map)))?;
+            (map_def.name.clone(), map_def.random_start_points.clone())
+        };
+        if !self.map_exists(&map_name) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("missing encounter map file: {}", map_name)));
+        }
+        debug!("starting encounter {:?} on `{}`", entry, map_name);
+
+        self.worldmap.borrow_mut().stop();
+        self.switch_map(&map_name, ui)?;
+        self.hide_worldmap(ui);
+        if !start_points.is_empty() {
+            let pos = start_points[random(0, start_points.len() as i32 - 1) as usize];
+            let direction = Direction::from_ordinal(random(0, Direction::len() as i32 - 1) as usize);
+            self.set_dude_pos(pos, direction, ui);
+        }
+
+        if entry.special {
+            return Ok(());
+        }
+        let dude_obj = self.world.borrow().objects().dude();
+        let mut prev_group: Vec<object::Handle> = Vec::new();
+        for group in &entry.groups {
+            let critters = self.spawn_encounter_group(group, ui);
+            let targets: Vec<_> = match group.attitude {
+                Attitude::Neutral => vec![],
+                Attitude::Ambush => vec![dude_obj],
+                Attitude::Fighting => prev_group.clone(),
+            };
+            if !targets.is_empty() {
+                for (i, &attacker) in critters.iter().enumerate() {
+                    self.combat.push_request(combat::Request::Attack {
+                        attacker,
+                        target: targets[i % targets.len()],
+                    });
+                }
+            }
+            prev_group = critters;
+        }
+        Ok(())
+    }
+
+    /// Places critters of the encounter group in the group formation.
+    /// Returns the alive critters placed.
+    // wmSetupCritterObjs()
+    fn spawn_encounter_group(&mut self, group: &EncounterTableGroup, ui: &mut Ui)
+        -> Vec<object::Handle>
+    {
+        let def = self.worldmap.borrow().def().encounter_groups[group.group].clone();
+        let critters: Vec<_> = {
+            let player_level = self.rpg.pc_stat(PCStat::Level);
+            let global_vars = &self.scripts.vars.global_vars;
+            def.critters.iter()
+                .filter(|c| encounter::check_conditions(&c.conditions, player_level, global_vars))
+                .collect()
+        };
+        let count = cmp::max(random(group.count.start, group.count.end), 0) as usize;
+        let types = encounter::critter_types(&critters, count);
+
+        let dude_pos = self.world.borrow().objects().dude_ref().pos();
+        let direction = Direction::from_ordinal(random(0, Direction::len() as i32 - 1) as usize);
+        let distance = def.distance.unwrap_or_else(|| random(3, 8) as u32);
+        let positions = encounter::formation(def.formation, dude_pos.point, direction,
+            distance, def.spacing, types.len());
+
+        let mut r = Vec::new();
+        for (&ty, &pos) in types.iter().zip(&positions) {
+            let critter = critters[ty];
+            match self.spawn_encounter_critter(critter, pos.elevated(dude_pos.elevation),
+                dude_pos.point, ui)
+            {
+                Ok(Some(obj)) => if !critter.dead {
+                    r.push(obj);
+                }
+                Ok(None) => debug!("no room for {:?} of encounter group `{}` near {:?}",
+                    critter.pid, def.name, pos),
+                Err(e) => warn!("couldn't create {:?} of encounter group `{}`: {}",
+                    critter.pid, def.name, e),
+            }
+        }
+        r
+    }
+
+    /// Creates encounter critter at the nearest free hex to `pos` facing `face`.
+    fn spawn_encounter_critter(&mut self,
+        critter: &EncounterCritter,
+        pos: EPoint,
+        face: Point,
+        ui: &mut Ui,
+    ) -> io::Result<Option<object::Handle>> {
+        let proto = self.proto_db.proto(critter.pid)?;
+        let obj = {
+            let world = &mut self.world.borrow_mut();
+            let point = {
+                let objs = world.objects();
+                let hex_grid = world.hex_grid();
+                encounter::attempt_placement(pos.point, encounter::PLACEMENT_DISTANCE, |p|
+                    hex_grid.is_in_bounds(p)
+                        && !objs.has_blocker_at(p.elevated(pos.elevation), None))
+            };
+            let point = if let Some(v) = point {
+                v
+            } else {
+                return Ok(None);
+            };
+            let mut obj = world.objects_mut().create(None, Some(proto.clone()),
+                Some(point.elevated(pos.elevation)), Some(&self.rpg));
+            obj.direction = hex::direction(point, face);
+            obj.handle()
+        };
+
+        for item in &critter.items {
+            let count = random(item.count.start, item.count.end);
+            if count <= 0 {
+                continue;
+            }
+            let proto = self.proto_db.proto(item.pid)?;
+            let is_weapon = proto.borrow().sub.as_weapon().is_some();
+            let item_obj = {
+                let world = &mut self.world.borrow_mut();
+                let item_obj = world.objects_mut().create(None, Some(proto), None, None).handle();
+                world.objects_mut().move_into_inventory(obj, item_obj, count as u32);
+                item_obj
+            };
+            if item.wielded {
+                if is_weapon {
+                    self.wield(obj, item_obj);
+                } else {
+                    warn!("can't wield non-weapon {:?}", item.pid);
+                }
+            }
+        }
+
+        if critter.dead {
+            let world = self.world.borrow();
+            let mut objo = world.objects().get_mut(obj);
+            let c = objo.sub.as_critter_mut().unwrap();
+            c.hit_points = 0;
+            c.combat.damage_flags.insert(DamageFlag::Dead);
+            if let Some(fid) = objo.fid.critter() {
+                let fid = fid.with_anim(CritterAnim::FallBackSf).into();
+                if self.frm_db.exists(fid) {
+                    objo.fid = fid;
+                }
+            }
+        }
+
+        let prg_id = critter.script.or_else(|| proto.borrow().script.map(|s| s.program_id()));
+        if let Some(prg_id) = prg_id {
+            let sid = self.scripts.instantiate_object_script(ScriptKind::Critter, prg_id, obj)?;
+            self.world.borrow().objects().get_mut(obj).script = Some((sid, prg_id));
+            self.execute_obj_proc(obj, PredefinedProc::Start, 0, None, ui);
+        }
+
+        Ok(Some(obj))
+    }
+
     // action_use_skill_on
     fn action_use_skill_on(&mut self, skill: Skill, target: object::Handle) {
         let world = self.world.borrow();
@@ -1049,7 +1902,13 @@ impl GameState {
                         source_obj: Some(user),
                         target_obj: Some(target),
                         skill: Some(skill),
+                        fixed_param: 0,
                         rpg: &mut self.rpg,
+                        combat: &mut self.combat,
+                        sound: &mut self.sound,
+                        movies: &mut self.movies,
+                        script_windows: &mut self.script_windows,
+                        say_dialog: &mut self.say_dialog,
                     }).unwrap().assert_no_suspend().script_overrides
             } else {
                 false
@@ -1117,6 +1976,499 @@ impl GameState {
             debug!("TODO");
         }
     }
+    fn execute_obj_proc(&mut self,
+        obj: object::Handle,
+        proc: PredefinedProc,
+        fixed_param: i32,
+        source_obj: Option<object::Handle>,
+        ui: &mut Ui,
+    ) {
+        let world = &mut self.world.borrow_mut();
+        let sid = if let Some((sid, _)) = world.objects().get(obj).script {
+            sid
+        } else {
+            return;
+        };
+        if let Some(r) = self.scripts.execute_predefined_proc(sid, proc,
+            &mut script::Context {
+                world,
+                obj_sequencer: &mut self.obj_sequencer,
+                dialog: &mut self.dialog,
+                ui,
+                message_panel: self.message_panel,
+                map_id: self.map_id.unwrap(),
+                source_obj,
+                target_obj: Some(obj),
+                skill: None,
+                fixed_param,
+                rpg: &mut self.rpg,
+                combat: &mut self.combat,
+                sound: &mut self.sound,
+                movies: &mut self.movies,
+                script_windows: &mut self.script_windows,
+                say_dialog: &mut self.say_dialog,
+            })
+        {
+            assert!(r.suspend.is_none(), "can't suspend in {:?}", proc);
+        }
+    }
+
+    // combat_begin()
+    fn begin_combat(&mut self,
+        initiator: object::Handle,
+        target: Option<object::Handle>,
+        ui: &mut Ui,
+    ) {
+        if self.combat.is_active() {
+            if let Some(target) = target {
+                self.combat.join(initiator).target = Some(target);
+                self.combat.join(target);
+            }
+            return;
+        }
+
+        let combatants = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let elevation = world.elevation();
+            objs.iter()
+                .filter(|&h| {
+                    let o = objs.get(h);
+                    o.try_pos().map(|p| p.elevation) == Some(elevation)
+                        && !o.flags.contains(Flag::TurnedOff)
+                        && o.sub.as_critter().map(|c| !c.is_dead()).unwrap_or(false)
+                })
+                .map(|h| {
+                    let mut c = Combatant::new(h);
+                    if h == initiator {
+                        c.target = target;
+                    }
+                    (c, self.rpg.stat(Stat::Sequence, &objs.get(h), objs))
+                })
+                .collect()
+        };
+        self.combat.begin(initiator, combatants);
+        debug!("combat started by {:?} with {} combatants",
+            initiator, self.combat.combatants().len());
+
+        let objs: Vec<_> = self.combat.combatants().iter().map(|c| c.obj).collect();
+        for obj in objs {
+            self.execute_obj_proc(obj, PredefinedProc::CombatIsStarting, 0, None, ui);
+        }
+
+        self.begin_turn(ui);
+    }
+
+    // combat_over()
+    fn end_combat(&mut self, ui: &mut Ui) {
+        debug!("combat is over after {} rounds", self.combat.round());
+        let objs: Vec<_> = self.combat.combatants().iter().map(|c| c.obj).collect();
+        self.combat.end();
+        for obj in objs {
+            self.execute_obj_proc(obj, PredefinedProc::CombatIsOver, 0, None, ui);
+        }
+    }
+
+    // combat_turn()
+    fn begin_turn(&mut self, ui: &mut Ui) {
+        let obj = self.combat.current().unwrap().obj;
+        let action_points = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let r = self.rpg.stat(Stat::ActionPoints, &objs.get(obj), objs);
+            r
+        };
+        self.combat.current_mut().unwrap().action_points = action_points;
+        debug!("round {}: turn of {:?} with {} AP", self.combat.round(), obj, action_points);
+
+        self.execute_obj_proc(obj, PredefinedProc::Combat, combat::COMBAT_SUBTYPE_TURN, None, ui);
+    }
+
+    fn end_turn(&mut self, ui: &mut Ui) {
+        if !self.combat.combatants().iter().any(|c| c.target.is_some()) {
+            self.end_combat(ui);
+            return;
+        }
+        self.combat.next_turn();
+        self.begin_turn(ui);
+    }
+
+    /// Whether the combat can be ended by the player.
+    fn can_end_combat(&self) -> bool {
+        let dude_obj = self.world.borrow().objects().dude();
+        self.combat.combatants().iter()
+            .all(|c| c.obj == dude_obj || c.target.is_none())
+    }
+
+    fn is_attackable(&self, obj: object::Handle) -> bool {
+        let world = self.world.borrow();
+        obj != world.objects().dude()
+            && world.objects().get(obj).sub.as_critter().map(|c| !c.is_dead()).unwrap_or(false)
+    }
+
+    /// Starts attack sequence. Returns `false` if the attack can't be made due to insufficient
+    /// action points, range or ammo.
+    // combat_attack()
+    fn action_attack(&mut self, attacker: object::Handle, target: object::Handle) -> bool {
+        let attack_kind = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let attackero = objs.get(attacker);
+            let weapon = ai::wielded_weapon(&attackero, objs);
+
+            let (attack_kind, ap_cost, range) = if let Some(weapon) = weapon {
+                let weapono = objs.get(weapon);
+                if weapono.proto().unwrap().max_ammo_count().unwrap_or(0) > 0
+                    && weapono.ammo_count() == Some(0)
+                {
+                    debug!("{:?} is out of ammo", attacker);
+                    return false;
+                }
+                let proto = weapono.proto().unwrap();
+                let w = proto.sub.as_weapon().unwrap();
+                (w.attack_kinds[AttackGroup::Primary],
+                    w.ap_costs[AttackGroup::Primary],
+                    weapono.weapon_range(AttackGroup::Primary, &self.rpg, objs).unwrap())
+            } else {
+                (AttackKind::Punch, combat::UNARMED_AP_COST, 1)
+            };
+
+            if objs.distance(attacker, target).map(|d| d as i32 > range).unwrap_or(true) {
+                return false;
+            }
+
+            if self.combat.is_active() {
+                let combatant = self.combat.join(attacker);
+                if combatant.action_points < ap_cost {
+                    return false;
+                }
+                combatant.action_points -= ap_cost;
+                combatant.target = Some(target);
+            }
+
+            attack_kind
+        };
+
+        {
+            let world = self.world.borrow();
+            let dir = hex::direction(
+                world.objects().get(attacker).pos().point,
+                world.objects().get(target).pos().point);
+            world.objects().get_mut(attacker).direction = dir;
+        }
+
+        // Not every critter art has animations for all attack kinds.
+        let anim = combat::attack_anim(attack_kind);
+        let has_anim = self.world.borrow().objects().get(attacker).fid.critter()
+            .map(|fid| self.frm_db.exists(fid.with_anim(anim).into()))
+            .unwrap_or(false);
+
+        let seq = Chain::new();
+        if has_anim {
+            seq.control().cancellable(FrameAnim::new(attacker, FrameAnimOptions {
+                anim: Some(anim),
+                ..Default::default()
+            }));
+        }
+        seq.control()
+            .cancellable(PushEvent::new(sequence::Event::Attack { attacker, target }))
+            .finalizing(Stand::new(attacker));
+        self.obj_sequencer.replace(attacker, seq);
+
+        true
+    }
+
+    // combat_attack() -> compute_attack()
+    fn attack(&mut self, attacker: object::Handle, target: object::Handle, ui: &mut Ui) {
+        let damage = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let attackero = objs.get(attacker);
+            let targeto = objs.get(target);
+            if targeto.sub.as_critter().map(|c| c.is_dead()).unwrap_or(true) {
+                return;
+            }
+
+            let weapon = ai::wielded_weapon(&attackero, objs);
+            let weapono = weapon.map(|h| objs.get(h));
+            let weapon_proto = weapono.as_ref().map(|o| o.proto().unwrap());
+            let w = weapon_proto.as_ref().map(|p| p.sub.as_weapon().unwrap());
+
+            let (attack_kind, damage_range, damage_kind) = if let Some(w) = w {
+                (w.attack_kinds[AttackGroup::Primary], w.damage, w.damage_kind)
+            } else {
+                let critter = attackero.proto().unwrap();
+                (AttackKind::Punch, combat::UNARMED_DAMAGE, critter.sub.as_critter().unwrap().damage_kind)
+            };
+            let category = attack_kind.category();
+            let skill = combat::weapon_skill(w, attack_kind);
+
+            // The target may have moved to another elevation or into an inventory since the
+            // attack has started.
+            let distance = if let Some(v) = objs.distance(attacker, target) {
+                v
+            } else {
+                debug!("{:?} can't reach {:?}", attacker, target);
+                return;
+            };
+
+            let hit_chance = ai::hit_chance(&attackero, &targeto, w, attack_kind, distance,
+                &self.rpg, objs);
+            let crit_chance = self.rpg.stat(Stat::CritChance, &attackero, objs);
+            let (roll, _) = world.game_time.roll_checker().roll_check(hit_chance, crit_chance);
+            debug!("{:?} attacks {:?} with {:?}: hit chance {}, {:?}",
+                attacker, target, skill, hit_chance, roll);
+
+            if roll.is_success() {
+                let mut damage = random(damage_range.start, damage_range.end);
+                if category.is_melee() {
+                    damage += self.rpg.stat(Stat::MeleeDmg, &attackero, objs);
+                }
+                // TODO Use the critical hit tables (by the target kill kind, hit location and
+                // roll) instead of doubling the damage. Critical effects such as knockdown,
+                // armor bypass and crippled limbs aren't implemented.
+                if roll.is_critical() {
+                    damage *= 2;
+                }
+                let threshold = damage_kind.thresh_stat()
+                    .map(|s| self.rpg.defense_stat(s, &targeto, objs))
+                    .unwrap_or(0);
+                let resistance = self.rpg.defense_stat(damage_kind.resist_stat(), &targeto, objs);
+                Some(combat::resist_damage(damage, threshold, resistance))
+            } else {
+                None
+            }
+        };
+
+        {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let weapon = ai::wielded_weapon(&objs.get(attacker), objs);
+            if let Some(weapon) = weapon {
+                let mut weapono = objs.get_mut(weapon);
+                if let Some(ammo_count) = weapono.ammo_count().filter(|&v| v > 0) {
+                    weapono.set_ammo_count(ammo_count - 1);
+                }
+            }
+        }
+
+        if self.combat.is_active() && target != self.world.borrow().objects().dude() {
+            self.combat.join(target).target = Some(attacker);
+        }
+
+        if let Some(damage) = damage {
+            self.damage(target, attacker, damage, ui);
+        }
+    }
+
+    fn damage(&mut self, target: object::Handle, attacker: object::Handle, damage: i32,
+        ui: &mut Ui)
+    {
+        let dead = {
+            let world = self.world.borrow();
+            let mut targeto = world.objects().get_mut(target);
+            if targeto.proto().unwrap().sub.as_critter().unwrap().flags
+                .contains(CritterFlag::Invulnerable)
+            {
+                return;
+            }
+            let critter = targeto.sub.as_critter_mut().unwrap();
+            critter.hit_points -= damage;
+            debug!("{:?} takes {} damage, {} HP left", target, damage, critter.hit_points);
+            if critter.hit_points <= 0 {
+                critter.combat.damage_flags.insert(DamageFlag::Dead);
+                true
+            } else {
+                false
+            }
+        };
+
+        self.execute_obj_proc(target, PredefinedProc::Damage, 0, Some(attacker), ui);
+
+        let seq = Chain::new();
+        if dead {
+            self.execute_obj_proc(target, PredefinedProc::Destroy, 0, Some(attacker), ui);
+            self.combat.remove(target);
+
+            let world = self.world.borrow();
+            if attacker == world.objects().dude() {
+                let kill_kind = world.objects().get(target).proto().unwrap()
+                    .sub.as_critter().unwrap().kill_kind;
+                self.kill_counts[kill_kind as usize] += 1;
+            }
+
+            seq.control().cancellable(FrameAnim::new(target, FrameAnimOptions {
+                anim: Some(CritterAnim::FallBack),
+                ..Default::default()
+            }));
+        } else {
+            seq.control()
+                .cancellable(FrameAnim::new(target, FrameAnimOptions {
+                    anim: Some(CritterAnim::HitFromFront),
+                    ..Default::default()
+                }))
+                .finalizing(Stand::new(target));
+        }
+        self.obj_sequencer.replace(target, seq);
+    }
+
+    fn update_combat(&mut self, ui: &mut Ui) {
+        for request in self.combat.take_requests() {
+            match request {
+                combat::Request::Attack { attacker, target } => {
+                    self.begin_combat(attacker, Some(target), ui);
+                }
+                combat::Request::StopAttacking { obj } => {
+                    if let Some(c) = self.combat.get_mut(obj) {
+                        c.target = None;
+                    }
+                }
+                combat::Request::Terminate => if self.combat.is_active() {
+                    self.end_combat(ui);
+                }
+            }
+        }
+
+        let current = if let Some(c) = self.combat.current() {
+            c.obj
+        } else {
+            return;
+        };
+        if self.obj_sequencer.is_running(current) {
+            return;
+        }
+        if current == self.world.borrow().objects().dude() {
+            if self.combat.current().unwrap().action_points <= 0 {
+                self.end_turn(ui);
+            }
+        } else {
+            self.npc_turn(current, ui);
+        }
+    }
+
+    // combat_ai()
+    fn npc_turn(&mut self, obj: object::Handle, ui: &mut Ui) {
+        let decision = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let team_id = |h| objs.get(h).sub.as_critter().map(|c| c.combat.team_id);
+            let attackers: Vec<_> = self.combat.combatants().iter()
+                .filter(|c| c.target == Some(obj))
+                .map(|c| c.obj)
+                .collect();
+            let enemies: Vec<_> = self.combat.combatants().iter()
+                .filter(|c| team_id(c.obj) != team_id(obj)
+                    && c.target.map(team_id) == Some(team_id(obj)))
+                .map(|c| c.obj)
+                .collect();
+            let combatant = self.combat.current().unwrap();
+            let target = self.ai.choose_target(obj, combatant.target, &attackers, &enemies,
+                &self.rpg, objs);
+            let decision = self.ai.decide(obj, target, combatant.action_points, &self.rpg, objs);
+            self.combat.current_mut().unwrap().target = target;
+            decision
+        };
+        debug!("{:?} decided to {:?}", obj, decision);
+        match decision {
+            Decision::Attack(target) => if self.action_attack(obj, target) {
+                return;
+            }
+            Decision::Move { to, steps } => {
+                self.combat.current_mut().unwrap().action_points -= steps;
+                let seq = Chain::new();
+                seq.control()
+                    .cancellable(Move::new(obj, PathTo::Point {
+                        point: to,
+                        neighbor_if_blocked: true,
+                    }, CritterAnim::Running))
+                    .finalizing(Stand::new(obj));
+                self.obj_sequencer.replace(obj, seq);
+                return;
+            }
+            Decision::Wield(weapon) => if self.wield(obj, weapon) {
+                return;
+            }
+            Decision::UseDrug(drug) => if self.use_drug(obj, drug) {
+                return;
+            }
+            Decision::EndTurn => {}
+        }
+        self.end_turn(ui);
+    }
+
+    /// Puts `weapon` from the inventory of `obj` into its right hand. During combat this costs
+    /// `combat::WIELD_AP_COST`. Returns `false` if `obj` doesn't have enough action points.
+    // inven_wield()
+    fn wield(&mut self, obj: object::Handle, weapon: object::Handle) -> bool {
+        if self.combat.is_active() {
+            let combatant = self.combat.join(obj);
+            if combatant.action_points < combat::WIELD_AP_COST {
+                return false;
+            }
+            combatant.action_points -= combat::WIELD_AP_COST;
+        }
+
+        let world = self.world.borrow();
+        let objs = world.objects();
+        if let Some(old) = objs.get(obj).equipment(EquipmentSlot::Hand(Hand::Right), objs) {
+            objs.get_mut(old).flags.remove(Flag::RightHand);
+        }
+        objs.get_mut(weapon).flags.insert(Flag::RightHand);
+
+        let kind = objs.get(weapon).proto().unwrap().sub.as_weapon().unwrap().kind;
+        let mut objo = objs.get_mut(obj);
+        if let Some(fid) = objo.fid.critter() {
+            let fid = fid.with_weapon(kind).with_anim(CritterAnim::Stand).into();
+            if self.frm_db.exists(fid) {
+                objo.fid = fid;
+            }
+        }
+        true
+    }
+
+    /// Uses `drug` from the inventory of `obj`. During combat this costs
+    /// `combat::USE_DRUG_AP_COST`. Returns `false` if `obj` doesn't have enough action points.
+    /// Only the immediate hit point effects are applied.
+    // item_d_take_drug()
+    fn use_drug(&mut self, obj: object::Handle, drug: object::Handle) -> bool {
+        if self.combat.is_active() {
+            let combatant = self.combat.join(obj);
+            if combatant.action_points < combat::USE_DRUG_AP_COST {
+                return false;
+            }
+            combatant.action_points -= combat::USE_DRUG_AP_COST;
+        }
+
+        let mut world = self.world.borrow_mut();
+        let objs = world.objects_mut();
+        let heal: i32 = objs.get(drug).proto().unwrap()
+            .sub.as_item().unwrap()
+            .sub.as_drug().unwrap()
+            .effects.iter()
+            .filter(|e| e.delay == 0 && e.stat == Stat::CurrentHitPoints)
+            .map(|e| match e.modifier {
+                DrugEffectModifier::Fixed(v) => v,
+                DrugEffectModifier::Random(min, max) => random(min, max),
+            })
+            .sum();
+        let max_hit_points = self.rpg.stat(Stat::HitPoints, &objs.get(obj), objs);
+        {
+            let mut objo = objs.get_mut(obj);
+            let critter = objo.sub.as_critter_mut().unwrap();
+            critter.hit_points = cmp::min(critter.hit_points + heal, max_hit_points);
+            debug!("{:?} uses {:?} and heals to {} HP", obj, drug, critter.hit_points);
+
+            let items = &mut objo.inventory.items;
+            let idx = items.iter().position(|i| i.object == drug).unwrap();
+            if items[idx].count > 1 {
+                items[idx].count -= 1;
+                return true;
+            }
+            items.remove(idx);
+        }
+        objs.remove(drug);
+        true
+    }
 }
 
 impl AppState for GameState {
@@ -1130,15 +2482,22 @@ impl AppState for GameState {
                         self.set_dude_pos(pos, direction, ctx.ui);
                     }
                     TargetMap::Map { map_id } => {
-                        if self.map_id.unwrap() != map_id {
-                            let map_def = self.map_db.get(map_id).unwrap();
-                            let name = map_def.name.clone();
-                            self.switch_map(&name, ctx.ui);
+                        if self.map_id != Some(map_id) {
+                            let name = if let Some(map_def) = self.map_db.get(map_id) {
+                                map_def.name.clone()
+                            } else {
+                                warn!("unknown map in map exit: {}", map_id);
+                                return;
+                            };
+                            if let Err(e) = self.switch_map(&name, ctx.ui) {
+                                error!("couldn't switch map to `{}`: {}", name, e);
+                                return;
+                            }
                         }
                         self.set_dude_pos(pos, direction, ctx.ui);
                     }
-                    TargetMap::WorldMap(k) => {
-                        warn!("map exit to {:?} is not implemented", k);
+                    TargetMap::WorldMap(_) => {
+                        self.show_worldmap(ctx.ui);
                     }
                 }
             }
@@ -1248,7 +2607,12 @@ impl AppState for GameState {
 
                         self.time.set_paused(true);
                     }
-                    ObjectPickKind::DefaultAction => if let Some(a) = default_action {
+                    ObjectPickKind::DefaultAction => if self.combat.is_active() && self.is_attackable(objh) {
+                        let dude_obj = self.world.borrow().objects().dude();
+                        if self.combat.is_turn_of(dude_obj) && !self.obj_sequencer.is_running(dude_obj) {
+                            self.action_attack(dude_obj, objh);
+                        }
+                    } else if let Some(a) = default_action {
                         ui.widget_mut::<WorldView>(self.world_view).default_action_icon = if self.object_action_menu.is_none() {
                             default_action
                         }  else {
@@ -1265,6 +2629,25 @@ impl AppState for GameState {
                 if action {
                     let dude_objh = self.world.borrow().objects().dude();
 
+                    if self.combat.is_active() {
+                        if !self.combat.is_turn_of(dude_objh) || self.obj_sequencer.is_running(dude_objh) {
+                            return;
+                        }
+                        let path_len = self.world.borrow()
+                            .objects().path(dude_objh, PathTo::Point {
+                                point: pos.point,
+                                neighbor_if_blocked: true,
+                            }, false)
+                            .map(|p| p.len() as i32);
+                        let combatant = self.combat.current_mut().unwrap();
+                        match path_len {
+                            Some(len) if len <= combatant.action_points => {
+                                combatant.action_points -= len;
+                            }
+                            _ => return,
+                        }
+                    }
+
                     let seq = Chain::new();
 
                     let anim = if self.shift_key_down {
@@ -1300,6 +2683,40 @@ impl AppState for GameState {
                 action_menu::hide(object_action.menu, ui);
                 self.time.set_paused(false);
             }
+            UiCommandData::Pick { id } if self.say_dialog.is_options(command.source) => {
+                let proc = self.say_dialog.pick(id);
+                if let (Some((sid, proc_id)), Some(map_id)) = (proc, self.map_id) {
+                    if self.scripts.get(sid).is_some() {
+                        let r = self.scripts.execute_proc(sid, proc_id,
+                            &mut script::Context {
+                                ui,
+                                world: &mut self.world.borrow_mut(),
+                                obj_sequencer: &mut self.obj_sequencer,
+                                dialog: &mut self.dialog,
+                                message_panel: self.message_panel,
+                                map_id,
+                                source_obj: None,
+                                target_obj: None,
+                                skill: None,
+                                fixed_param: 0,
+                                rpg: &mut self.rpg,
+                                combat: &mut self.combat,
+                                sound: &mut self.sound,
+                                movies: &mut self.movies,
+                                script_windows: &mut self.script_windows,
+                                say_dialog: &mut self.say_dialog,
+                            });
+                        if r.suspend.is_some() {
+                            // The next reply is shown when the procedure finishes.
+                            self.say_dialog.suspend_option();
+                            return;
+                        }
+                    }
+                }
+                if self.say_dialog.next_reply(ui) {
+                    self.resume_say(ui);
+                }
+            }
             UiCommandData::Pick { id } => {
                 let (sid, proc_id) = {
                     let dialog = self.dialog.as_mut().unwrap();
@@ -1325,7 +2742,13 @@ impl AppState for GameState {
                             source_obj,
                             target_obj,
                             skill: None,
+                            fixed_param: 0,
                             rpg: &mut self.rpg,
+                            combat: &mut self.combat,
+                            sound: &mut self.sound,
+                            movies: &mut self.movies,
+                            script_windows: &mut self.script_windows,
+                            say_dialog: &mut self.say_dialog,
                         }).assert_no_suspend();
                     // No dialog options means the dialog is finished.
                     self.dialog.as_ref().unwrap().is_empty()
@@ -1343,7 +2766,13 @@ impl AppState for GameState {
                         source_obj: None,
                         target_obj: None,
                         skill: None,
+                        fixed_param: 0,
                         rpg: &mut self.rpg,
+                        combat: &mut self.combat,
+                        sound: &mut self.sound,
+                        movies: &mut self.movies,
+                        script_windows: &mut self.script_windows,
+                        say_dialog: &mut self.say_dialog,
                     };
                     self.scripts.resume(ctx).assert_no_suspend();
                     assert!(!self.scripts.can_resume());
@@ -1353,6 +2782,25 @@ impl AppState for GameState {
                     self.scripts.execute_map_procs(PredefinedProc::MapUpdate, ctx);
                 }
             }
+            UiCommandData::Combat(cmd) => {
+                let dude_obj = self.world.borrow().objects().dude();
+                match cmd {
+                    CombatCommand::Begin => {
+                        self.obj_sequencer.cancel(dude_obj);
+                        self.begin_combat(dude_obj, None, ui);
+                    }
+                    CombatCommand::EndTurn => if self.combat.is_turn_of(dude_obj) {
+                        self.end_turn(ui);
+                    }
+                    CombatCommand::End => if self.combat.is_turn_of(dude_obj) {
+                        if self.can_end_combat() {
+                            self.end_combat(ui);
+                        } else {
+                            debug!("can't end combat with hostile critters around");
+                        }
+                    }
+                }
+            }
             UiCommandData::Scroll => {
                 let (dir, widg) = self.scroll_areas
                     .iter()
@@ -1394,6 +2842,72 @@ impl AppState for GameState {
                 _ => {}
             }
             UiCommandData::MoveWindow(_) => {}
+            UiCommandData::WorldMap(cmd) => match cmd {
+                WorldMapCommand::Travel { pos } => {
+                    self.worldmap.borrow_mut().travel_to(pos);
+                }
+                WorldMapCommand::Area { area } => {
+                    let arrived = {
+                        let mut worldmap = self.worldmap.borrow_mut();
+                        let arrived = !worldmap.is_traveling()
+                            && worldmap.area_at(worldmap.pos()) == Some(area);
+                        if !arrived {
+                            let pos = worldmap.def().areas[area].pos;
+                            worldmap.travel_to(pos);
+                        }
+                        arrived
+                    };
+                    if arrived {
+                        self.enter_area(area, ui);
+                    }
+                }
+                WorldMapCommand::Encounter { accept } => {
+                    if let Some((win, table)) = self.encounter_prompt.take() {
+                        ui.remove(win);
+                        if accept {
+                            self.pick_encounter(table, ui);
+                        } else {
+                            debug!("random encounter avoided");
+                        }
+                    }
+                }
+            }
+            UiCommandData::Say(cmd) => if self.say_dialog.handle(cmd, ui) {
+                self.resume_say(ui);
+            }
+            UiCommandData::ScriptWindow(cmd) => {
+                let handler = self.script_windows.handler(command.source, cmd);
+                if let (Some((sid, proc_id)), Some(map_id)) = (handler, self.map_id) {
+                    // The script could have been removed since the handler was set.
+                    if self.scripts.get(sid).is_none() {
+                        return;
+                    }
+                    let r = self.scripts.execute_proc(sid, proc_id,
+                        &mut script::Context {
+                            ui,
+                            world: &mut self.world.borrow_mut(),
+                            obj_sequencer: &mut self.obj_sequencer,
+                            dialog: &mut self.dialog,
+                            message_panel: self.message_panel,
+                            map_id,
+                            source_obj: None,
+                            target_obj: None,
+                            skill: None,
+                            fixed_param: 0,
+                            rpg: &mut self.rpg,
+                            combat: &mut self.combat,
+                            sound: &mut self.sound,
+                            movies: &mut self.movies,
+                            script_windows: &mut self.script_windows,
+                            say_dialog: &mut self.say_dialog,
+                        });
+                    match r.suspend {
+                        // Resumed when the dialog or message closes.
+                        None | Some(Suspend::GsayEnd) | Some(Suspend::SayEnd)
+                        | Some(Suspend::SayMessage) => {}
+                    }
+                }
+            }
         }
     }
 
@@ -1402,7 +2916,25 @@ impl AppState for GameState {
             self.user_paused ||
             self.scripts.can_resume() ||
             self.skilldex.is_visible() ||
-            self.inventory.is_visible());
+            self.inventory.is_visible() ||
+            self.worldmap_window.is_some() ||
+            self.movies.is_playing() ||
+            self.say_dialog.is_running());
+
+        self.movies.update(ctx.ui);
+        if self.say_dialog.update(ctx.time, ctx.ui) {
+            self.resume_say(ctx.ui);
+        }
+
+        if self.worldmap_window.is_some() && self.encounter_prompt.is_none() {
+            let game_time = self.world.borrow().game_time;
+            let update = self.worldmap.borrow_mut().update(ctx.delta, game_time);
+            self.world.borrow_mut().game_time =
+                GameTime::from_decis(game_time.as_decis() + update.game_time);
+            if update.encounter {
+                self.random_encounter(ctx.ui);
+            }
+        }
 
         self.time.update(ctx.delta);
 
@@ -1412,6 +2944,8 @@ impl AppState for GameState {
                 world.update(self.time.time());
             }
 
+            self.sound.update(self.time.time());
+
             const MAX_ITERS: u32 = 1000;
             for i in 0..MAX_ITERS {
                 assert!(i < MAX_ITERS - 1, "infinite loop in sequencer updating - event handling");
@@ -1431,6 +2965,8 @@ impl AppState for GameState {
                 self.handle_seq_events(&mut ctx);
             }
 
+            self.update_combat(ctx.ui);
+
             self.fidget.update(
                 self.time.time(),
                 &mut self.world.borrow_mut(),
@@ -1495,4 +3031,80 @@ impl PausableTime {
 struct ObjectActionMenu {
     menu: ui::Handle,
     obj: object::Handle,
-}
\ No newline at end of file
+}
+
+/// Name of the map state file in the map states or save slot directory.
+fn map_state_file_name(map_name: &str) -> String {
+    format!("{}.SAV", map_name.to_uppercase())
+}
+
+/// Path of the save slot directory relative to the `data` dir.
+fn slot_dir_path(slot: u32) -> String {
+    format!("savegame/{}", save::slot_dir_name(slot))
+}
+
+fn is_map_state_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.eq_ignore_ascii_case("sav"))
+        .unwrap_or(false)
+}
+
+fn remove_map_states(dir: &Path) -> io::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && is_map_state_file(&path) {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves all map state files from `src` to `dst`.
+fn move_map_states(src: &Path, dst: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(src)? {
+        let path = entry?.path();
+        if path.is_file() && is_map_state_file(&path) {
+            fs::rename(&path, dst.join(path.file_name().unwrap()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the map state files in `dir` with the ones from `staging`. The replaced files are
+/// moved to `backup` while in progress and moved back on failure.
+fn replace_map_states(dir: &Path, staging: &Path, backup: &Path) -> io::Result<()> {
+    remove_map_states(backup)?;
+    let r = move_map_states(dir, backup)
+        .and_then(|()| move_map_states(staging, dir));
+    match r {
+        Ok(()) => remove_map_states(backup),
+        Err(e) => {
+            if let Err(e) = remove_map_states(dir).and_then(|()| move_map_states(backup, dir)) {
+                error!("couldn't restore map states from {}: {}", backup.display(), e);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Copies all map state files from `src` to `dst`. Returns names of the copied files.
+fn copy_map_states(src: &Path, dst: &Path) -> io::Result<Vec<BString>> {
+    let mut r = Vec::new();
+    if !src.is_dir() {
+        return Ok(r);
+    }
+    for entry in fs::read_dir(src)? {
+        let path = entry?.path();
+        if path.is_file() && is_map_state_file(&path) {
+            let name = path.file_name().unwrap();
+            fs::copy(&path, dst.join(name))?;
+            r.push(name.to_string_lossy().into_owned().into());
+        }
+    }
+    r.sort();
+    Ok(r)
+}
@@ -1,6 +1,10 @@
 pub mod action_menu;
 pub mod hud;
 pub mod inventory_list;
+pub mod message_box;
+pub mod movie;
 pub mod move_window;
+pub mod script_window;
 pub mod scroll_area;
 pub mod world;
+pub mod worldmap;
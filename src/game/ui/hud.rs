@@ -5,7 +5,7 @@ use crate::graphics::font::FontKey;
 use crate::graphics::sprite::Sprite;
 use crate::ui::*;
 use crate::ui::button::Button;
-use crate::ui::command::{inventory, SkilldexCommand, UiCommandData};
+use crate::ui::command::{inventory, CombatCommand, SkilldexCommand, UiCommandData};
 use crate::ui::message_panel::{MessagePanel, Anchor};
 
 pub fn create(ui: &mut Ui) -> Handle {
@@ -52,7 +52,18 @@ pub fn create(ui: &mut Ui) -> Handle {
     // Attack button.
     // FIXME this should be a custom button with overlay text images.
     ui.new_widget(main_hud, Rect::with_size(267, 26, 188, 67), None, None,
-        Button::new(FrameId::SINGLE_ATTACK_BUTTON_UP, FrameId::SINGLE_ATTACK_BUTTON_DOWN, None));
+        Button::new(FrameId::SINGLE_ATTACK_BUTTON_UP, FrameId::SINGLE_ATTACK_BUTTON_DOWN,
+            Some(UiCommandData::Combat(CombatCommand::Begin))));
+
+    // End turn button.
+    ui.new_widget(main_hud, Rect::with_size(590, 43, 38, 22), None, None,
+        Button::new(FrameId::ENDTURNU, FrameId::ENDTURND,
+            Some(UiCommandData::Combat(CombatCommand::EndTurn))));
+
+    // End combat button.
+    ui.new_widget(main_hud, Rect::with_size(590, 65, 38, 22), None, None,
+        Button::new(FrameId::ENDCMBTU, FrameId::ENDCMBTD,
+            Some(UiCommandData::Combat(CombatCommand::End))));
 
     message_panel
 }
\ No newline at end of file
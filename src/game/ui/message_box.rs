@@ -0,0 +1,67 @@
+use bstring::BString;
+
+use crate::asset::frame::FrameId;
+use crate::asset::message::Messages;
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::Rgb15;
+use crate::graphics::font::{DrawOptions, FontKey, HorzAlign, VertAlign};
+use crate::graphics::sprite::Sprite;
+use crate::ui::*;
+use crate::ui::button::{self, Button};
+use crate::ui::command::UiCommandData;
+use crate::ui::panel::{self, Panel};
+
+const TEXT_FONT: FontKey = FontKey::antialiased(3);
+const TEXT_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x7e48) };
+const LINE_HEIGHT: i32 = 13;
+
+/// Shows modal message box at `pos` with `title`, `body` lines and Yes/No buttons.
+/// The buttons emit `yes` and `no` commands, the caller must remove the returned window.
+/// `msgs` is `game/dbox.msg`.
+// dialog_out()
+pub fn show_yes_no(
+    pos: Point,
+    title: &BString,
+    body: &[&BString],
+    msgs: &Messages,
+    yes: UiCommandData,
+    no: UiCommandData,
+    ui: &mut Ui,
+) -> Handle {
+    let win_size = ui.frm_db().get(FrameId::MEDIALOG).unwrap().first().size();
+    let window = ui.new_window(Rect::with_size(pos.x, pos.y, win_size.x, win_size.y),
+        Some(Sprite::new(FrameId::MEDIALOG)));
+    ui.widget_base_mut(window).set_modal(true);
+
+    let lines = Some(title).into_iter().chain(body.iter().copied());
+    for (i, line) in lines.enumerate() {
+        let mut text = Panel::new();
+        text.set_text(Some(panel::Text {
+            text: line.clone(),
+            font: TEXT_FONT,
+            color: TEXT_COLOR,
+            options: DrawOptions {
+                horz_align: HorzAlign::Center,
+                ..Default::default()
+            },
+        }));
+        let y = 16 + LINE_HEIGHT * i as i32 + if i > 0 { 3 } else { 0 };
+        ui.new_widget(window, Rect::with_size(0, y, win_size.x, LINE_HEIGHT), None, None, text);
+    }
+
+    let btn_size = ui.frm_db().get(FrameId::SMALL_RED_BUTTON_UP).unwrap().first().size();
+    let buttons = [(101, yes, win_size.x / 2 - 70), (102, no, win_size.x / 2 + 20)];
+    for &(msg_id, command, x) in &buttons {
+        let mut btn = Button::new(FrameId::SMALL_RED_BUTTON_UP, FrameId::SMALL_RED_BUTTON_DOWN,
+            Some(command));
+        let mut text = button::Text::new(msgs.get(msg_id).unwrap().text.clone(), TEXT_FONT);
+        text.pos = Point::new(btn_size.x + 8, 1);
+        text.color = TEXT_COLOR;
+        text.options.vert_align = VertAlign::Middle;
+        btn.set_text(Some(text));
+        ui.new_widget(window, Rect::with_size(x, win_size.y - 37, 50, btn_size.y), None, None,
+            btn);
+    }
+
+    window
+}
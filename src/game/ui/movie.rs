@@ -0,0 +1,160 @@
+use log::*;
+use std::io::BufRead;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::asset::mve::{Frame, MveReader, Subtitles};
+use crate::graphics::Point;
+use crate::graphics::color::{self, Rgb15};
+use crate::graphics::color::palette::Palette;
+use crate::graphics::font::*;
+use crate::graphics::render::TextureHandle;
+use crate::sound::mixer::{Channel, Mixer, SoundId};
+use crate::ui::*;
+
+const SUBTITLE_FONT: FontKey = FontKey::antialiased(1);
+const SUBTITLE_COLOR: Rgb15 = color::WHITE;
+
+/// Margin between the subtitles and the movie edges.
+const SUBTITLE_MARGIN: i32 = 20;
+
+/// Plays MVE movie scaled to the widget rect. The movie is skipped on key press or mouse click.
+pub struct MovieView {
+    reader: MveReader<Box<dyn BufRead + Send>>,
+    subtitles: Option<Subtitles>,
+    palette: Rc<Palette>,
+    mixer: Option<Arc<Mutex<Mixer>>>,
+    audio_stream: Option<SoundId>,
+    start: Option<Instant>,
+    frame: Option<Frame>,
+    texture: Option<TextureHandle>,
+    finished: bool,
+}
+
+impl MovieView {
+    pub fn new(
+        reader: MveReader<Box<dyn BufRead + Send>>,
+        subtitles: Option<Subtitles>,
+        palette: Rc<Palette>,
+        mixer: Option<Arc<Mutex<Mixer>>>,
+    ) -> Self {
+        Self {
+            reader,
+            subtitles,
+            palette,
+            mixer,
+            audio_stream: None,
+            start: None,
+            frame: None,
+            texture: None,
+            finished: false,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+        if let (Some(mixer), Some(id)) = (&self.mixer, self.audio_stream) {
+            mixer.lock().unwrap().end_stream(id);
+        }
+    }
+
+    fn decode_next(&mut self) -> bool {
+        let frame = match self.reader.next_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return false,
+            Err(e) => {
+                warn!("error decoding movie: {}", e);
+                return false;
+            }
+        };
+
+        let audio = self.reader.take_audio();
+        if let (Some(mixer), Some(format)) = (&self.mixer, self.reader.audio_format()) {
+            if !audio.is_empty() {
+                let mut mixer = mixer.lock().unwrap();
+                let id = *self.audio_stream.get_or_insert_with(||
+                    mixer.play_stream(Channel::Speech, format.channels, format.sample_rate));
+                mixer.queue_stream(id, &audio);
+            }
+        }
+
+        self.frame = Some(frame);
+        self.texture = None;
+        true
+    }
+
+    fn update(&mut self, now: Instant) {
+        let start = *self.start.get_or_insert(now);
+        let frame_duration = self.reader.frame_duration().as_micros().max(1);
+        let target = ((now - start).as_micros() / frame_duration) as usize;
+        while self.frame.as_ref().map(|f| f.index < target) != Some(false) {
+            if !self.decode_next() {
+                self.finish();
+                break;
+            }
+        }
+    }
+}
+
+impl Widget for MovieView {
+    fn handle_event(&mut self, ctx: HandleEvent) {
+        match ctx.event {
+            Event::Tick if !self.finished => self.update(ctx.now),
+            Event::KeyDown { .. } | Event::MouseUp { .. } => self.finish(),
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, ctx: Render) {
+        let frame = if let Some(f) = &self.frame {
+            f
+        } else {
+            return;
+        };
+        let rect = ctx.base.unwrap().rect();
+
+        if self.texture.is_none() {
+            // Map the movie palette to the game palette.
+            let mut color_idx = [0; 256];
+            for (dst, &rgb) in color_idx.iter_mut().zip(frame.palette.iter()) {
+                *dst = self.palette.color_idx(rgb);
+            }
+            let pixels: Vec<_> = frame.pixels.iter().map(|&c| color_idx[c as usize]).collect();
+            self.texture = Some(ctx.frm_db.texture_factory()
+                .new_texture(frame.width, frame.height, pixels.into()));
+        }
+        ctx.canvas.draw_scaled(self.texture.as_ref().unwrap(), rect);
+
+        if let Some(text) = self.subtitles.as_ref().and_then(|s| s.get(frame.index)) {
+            ctx.canvas.draw_text(text,
+                Point::new(rect.center().x, rect.bottom - SUBTITLE_MARGIN),
+                SUBTITLE_FONT, SUBTITLE_COLOR,
+                &DrawOptions {
+                    horz_align: HorzAlign::Center,
+                    vert_align: VertAlign::Bottom,
+                    horz_overflow: Some(Overflow {
+                        size: rect.width() - SUBTITLE_MARGIN * 2,
+                        boundary: OverflowBoundary::Word,
+                        action: OverflowAction::Wrap,
+                    }),
+                    .. Default::default()
+                });
+        }
+    }
+}
+
+impl Drop for MovieView {
+    fn drop(&mut self) {
+        if let (Some(mixer), Some(id)) = (&self.mixer, self.audio_stream) {
+            mixer.lock().unwrap().stop(id);
+        }
+    }
+}
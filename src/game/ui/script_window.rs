@@ -0,0 +1,357 @@
+use bstring::BString;
+use std::cmp;
+
+use crate::asset::frame::FrameId;
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::Rgb15;
+use crate::graphics::font::{DrawOptions, FontKey};
+use crate::graphics::sprite::Sprite;
+use crate::ui::*;
+use crate::ui::button::Button;
+use crate::ui::command::{ScriptWindowCommand, UiCommandData};
+
+enum Draw {
+    Fill {
+        rect: Rect,
+        color: Rgb15,
+    },
+    Image {
+        fid: FrameId,
+        rect: Rect,
+    },
+    Text {
+        text: BString,
+        pos: Point,
+        font: FontKey,
+        color: Rgb15,
+        options: DrawOptions,
+    },
+}
+
+/// Contents of the script window drawn by `fillwin`, `display`, `print` etc. The drawing is kept
+/// as a list of operations in window coordinates that are replayed on every render.
+pub struct ScriptCanvas {
+    draws: Vec<Draw>,
+}
+
+impl ScriptCanvas {
+    pub fn new() -> Self {
+        Self {
+            draws: Vec::new(),
+        }
+    }
+
+    /// Fills the whole canvas with `color` discarding everything drawn before.
+    pub fn fill_all(&mut self, size: Point, color: Rgb15) {
+        self.draws.clear();
+        self.fill(Rect::with_size(0, 0, size.x, size.y), color);
+    }
+
+    pub fn fill(&mut self, rect: Rect, color: Rgb15) {
+        self.draws.push(Draw::Fill { rect, color });
+    }
+
+    /// Draws the first frame of `fid` scaled to `rect`.
+    pub fn image(&mut self, fid: FrameId, rect: Rect) {
+        self.draws.push(Draw::Image { fid, rect });
+    }
+
+    pub fn text(&mut self, text: BString, pos: Point, font: FontKey, color: Rgb15,
+        options: DrawOptions)
+    {
+        self.draws.push(Draw::Text { text, pos, font, color, options });
+    }
+
+    /// Scales everything drawn so far from `from` size to `to` size.
+    pub fn scale(&mut self, from: Point, to: Point) {
+        for draw in &mut self.draws {
+            match draw {
+                Draw::Fill { rect, .. } | Draw::Image { rect, .. } =>
+                    *rect = scale_rect(*rect, from, to),
+                Draw::Text { pos, .. } => *pos = scale_point(*pos, from, to),
+            }
+        }
+    }
+}
+
+impl Widget for ScriptCanvas {
+    fn render(&mut self, ctx: Render) {
+        let base_rect = ctx.base.unwrap().rect();
+        let origin = base_rect.top_left();
+        ctx.canvas.set_clip_rect(base_rect);
+        for draw in &self.draws {
+            match draw {
+                &Draw::Fill { rect, color } => ctx.canvas.fill(rect.translate(origin), color),
+                &Draw::Image { fid, rect } => {
+                    if rect.is_empty() {
+                        continue;
+                    }
+                    if let Ok(frm) = ctx.frm_db.get(fid) {
+                        ctx.canvas.draw_scaled(&frm.first().texture, rect.translate(origin));
+                    }
+                }
+                Draw::Text { text, pos, font, color, options } => {
+                    ctx.canvas.draw_text(text, origin + *pos, *font, *color, options);
+                }
+            }
+        }
+        ctx.canvas.reset_clip_rect();
+    }
+}
+
+/// Tracks mouse hover and translates mouse events into `ScriptWindowCommand`s.
+#[derive(Default)]
+struct MouseTracker {
+    hovered: bool,
+}
+
+impl MouseTracker {
+    /// `inside` tells whether the `pos` of the event is within the control.
+    fn handle(&mut self, event: &Event, inside: impl Fn(Point) -> bool)
+        -> Option<ScriptWindowCommand>
+    {
+        use ScriptWindowCommand::*;
+        match *event {
+            Event::MouseMove { pos } => {
+                let hovered = inside(pos);
+                if hovered != self.hovered {
+                    self.hovered = hovered;
+                    return Some(if hovered { Enter } else { Exit });
+                }
+            }
+            Event::MouseLeave if self.hovered => {
+                self.hovered = false;
+                return Some(Exit);
+            }
+            Event::MouseDown { pos, button } if inside(pos) => match button {
+                MouseButton::Left => return Some(Press),
+                MouseButton::Right => return Some(RightPress),
+                _ => {}
+            }
+            Event::MouseUp { pos, button } if inside(pos) => match button {
+                MouseButton::Left => return Some(Release),
+                MouseButton::Right => return Some(RightRelease),
+                _ => {}
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Button created by `addbutton`. Emits `UiCommandData::ScriptWindow` commands on mouse events.
+/// Disabled button doesn't emit any commands.
+pub struct ScriptButton {
+    button: Button,
+    mouse: MouseTracker,
+    up: Option<Sprite>,
+    hover: Option<Sprite>,
+}
+
+impl ScriptButton {
+    pub fn new() -> Self {
+        let mut button = Button::new(FrameId::BLANK, FrameId::BLANK,
+            Some(UiCommandData::ScriptWindow(ScriptWindowCommand::Release)));
+        button.config_mut(button::State::Up).background = None;
+        button.config_mut(button::State::Down).background = None;
+        Self {
+            button,
+            mouse: MouseTracker::default(),
+            up: None,
+            hover: None,
+        }
+    }
+
+    pub fn button(&self) -> &Button {
+        &self.button
+    }
+
+    pub fn button_mut(&mut self) -> &mut Button {
+        &mut self.button
+    }
+
+    /// Sets images shown when the button is up, pressed and hovered by the mouse.
+    /// The up image is used in place of the missing ones.
+    pub fn set_images(&mut self, up: Option<Sprite>, down: Option<Sprite>, hover: Option<Sprite>) {
+        self.up = up;
+        self.hover = hover;
+        self.button.config_mut(button::State::Down).background = down.or(up);
+        self.button.config_mut(button::State::Disabled).background = up;
+        self.sync_up_image();
+    }
+
+    fn sync_up_image(&mut self) {
+        self.button.config_mut(button::State::Up).background = if self.mouse.hovered {
+            self.hover.or(self.up)
+        } else {
+            self.up
+        };
+    }
+}
+
+impl Widget for ScriptButton {
+    fn handle_event(&mut self, mut ctx: HandleEvent) {
+        if !self.button.is_enabled() {
+            return;
+        }
+        let rect = ctx.base.rect();
+        match self.mouse.handle(&ctx.event, |p| rect.contains(p)) {
+            // Release is emitted by the button itself.
+            Some(ScriptWindowCommand::Release) | None => {}
+            Some(cmd) => {
+                if cmd == ScriptWindowCommand::Enter || cmd == ScriptWindowCommand::Exit {
+                    self.sync_up_image();
+                }
+                ctx.out(UiCommandData::ScriptWindow(cmd));
+            }
+        }
+        self.button.handle_event(ctx);
+    }
+
+    fn render(&mut self, ctx: Render) {
+        self.button.render(ctx);
+    }
+}
+
+/// Polygonal region created by `addregion`. Emits `UiCommandData::ScriptWindow` commands on mouse
+/// events within the polygon.
+pub struct ScriptRegion {
+    /// Polygon vertices in screen coordinates.
+    points: Vec<Point>,
+    active: bool,
+    mouse: MouseTracker,
+}
+
+impl ScriptRegion {
+    pub fn new(points: Vec<Point>) -> Self {
+        Self {
+            points,
+            active: true,
+            mouse: MouseTracker::default(),
+        }
+    }
+
+    /// Returns bounding rect of the `points`.
+    pub fn bounds(points: &[Point]) -> Rect {
+        if points.is_empty() {
+            return Rect::empty();
+        }
+        let mut r = Rect::with_size(points[0].x, points[0].y, 1, 1);
+        for p in &points[1..] {
+            r.left = cmp::min(r.left, p.x);
+            r.top = cmp::min(r.top, p.y);
+            r.right = cmp::max(r.right, p.x + 1);
+            r.bottom = cmp::max(r.bottom, p.y + 1);
+        }
+        r
+    }
+
+    pub fn translate(&mut self, offset: Point) {
+        for p in &mut self.points {
+            *p += offset;
+        }
+    }
+
+    /// Replaces every polygon vertex `p` with `f(p)`.
+    pub fn map_points(&mut self, f: impl Fn(Point) -> Point) {
+        for p in &mut self.points {
+            *p = f(*p);
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+        if !active {
+            self.mouse.hovered = false;
+        }
+    }
+
+    pub fn contains(&self, p: Point) -> bool {
+        polygon_contains(&self.points, p)
+    }
+}
+
+/// Scales `p` from `from` size to `to` size. Returns `p` unchanged if `from` is empty.
+pub fn scale_point(p: Point, from: Point, to: Point) -> Point {
+    if from.x <= 0 || from.y <= 0 {
+        return p;
+    }
+    Point::new(p.x * to.x / from.x, p.y * to.y / from.y)
+}
+
+/// Scales `r` from `from` size to `to` size. Returns `r` unchanged if `from` is empty.
+pub fn scale_rect(r: Rect, from: Point, to: Point) -> Rect {
+    Rect::with_points(scale_point(r.top_left(), from, to),
+        scale_point(Point::new(r.right, r.bottom), from, to))
+}
+
+/// Even-odd rule point in polygon test.
+fn polygon_contains(points: &[Point], p: Point) -> bool {
+    let mut r = false;
+    let mut j = points.len().wrapping_sub(1);
+    for (i, &a) in points.iter().enumerate() {
+        let b = points[j];
+        // Whether the edge crosses the horizontal line at p.y and the crossing point is to the
+        // right of p.
+        if (a.y > p.y) != (b.y > p.y)
+            && ((p.x - a.x) * (b.y - a.y) < (b.x - a.x) * (p.y - a.y)) == (b.y > a.y)
+        {
+            r = !r;
+        }
+        j = i;
+    }
+    r
+}
+
+impl Widget for ScriptRegion {
+    fn handle_event(&mut self, mut ctx: HandleEvent) {
+        if !self.active {
+            return;
+        }
+        let points = &self.points;
+        if let Some(cmd) = self.mouse.handle(&ctx.event, |p| polygon_contains(points, p)) {
+            ctx.out(UiCommandData::ScriptWindow(cmd));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn region_contains() {
+        let r = ScriptRegion::new(vec![
+            Point::new(0, 0), Point::new(10, 0), Point::new(10, 10), Point::new(5, 5),
+            Point::new(0, 10)]);
+        assert_eq!(ScriptRegion::bounds(&r.points), Rect::with_size(0, 0, 11, 11));
+        assert!(r.contains(Point::new(1, 1)));
+        assert!(r.contains(Point::new(2, 6)));
+        assert!(r.contains(Point::new(9, 9)));
+        assert!(!r.contains(Point::new(5, 8)));
+        assert!(!r.contains(Point::new(11, 5)));
+        assert!(!r.contains(Point::new(-1, 5)));
+    }
+
+    #[test]
+    fn button_hover_image() {
+        let fid = |b: &ScriptButton, state| b.button().config(state).background.map(|s| s.fid);
+        let mut b = ScriptButton::new();
+        b.set_images(Some(Sprite::new(FrameId::BLANK)), None,
+            Some(Sprite::new(FrameId::MOUSE_HEX)));
+        assert_eq!(fid(&b, button::State::Up), Some(FrameId::BLANK));
+        assert_eq!(fid(&b, button::State::Down), Some(FrameId::BLANK));
+
+        b.mouse.hovered = true;
+        b.sync_up_image();
+        assert_eq!(fid(&b, button::State::Up), Some(FrameId::MOUSE_HEX));
+
+        b.set_images(Some(Sprite::new(FrameId::BLANK)), Some(Sprite::new(FrameId::EGG)), None);
+        assert_eq!(fid(&b, button::State::Up), Some(FrameId::BLANK));
+        assert_eq!(fid(&b, button::State::Down), Some(FrameId::EGG));
+    }
+}
@@ -0,0 +1,185 @@
+use std::cmp;
+
+use crate::asset::frame::FrameId;
+use crate::game::worldmap::{Visibility, WorldMapRef};
+use crate::game::worldmap::def::*;
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::{self, Rgb15};
+use crate::graphics::font::*;
+use crate::graphics::render::TextureHandle;
+use crate::graphics::sprite::{Anchor, Sprite};
+use crate::ui::*;
+use crate::ui::command::{UiCommandData, WorldMapCommand};
+
+const AREA_FONT: FontKey = FontKey::antialiased(1);
+const AREA_COLOR: Rgb15 = color::GREEN;
+
+/// Height of a row in the area list.
+const AREA_ROW_HEIGHT: i32 = 27;
+
+/// Creates world map window.
+pub fn create(worldmap: WorldMapRef, ui: &mut Ui) -> Handle {
+    let window = ui.new_window(Rect::with_size(0, 0, 640, 480),
+        Some(Sprite::new(FrameId::WMAPBOX)));
+    ui.widget_base_mut(window).set_modal(true);
+
+    ui.new_widget(window, Rect::with_size(22, 21, 450, 443), None, None,
+        WorldMapView::new(worldmap.clone()));
+
+    ui.new_widget(window, Rect::with_size(501, 135, 125, 7 * AREA_ROW_HEIGHT), None, None,
+        AreaList::new(worldmap));
+
+    window
+}
+
+/// Scrollable view of the world map with fog of war, known areas and the party marker.
+pub struct WorldMapView {
+    worldmap: WorldMapRef,
+    /// Masks for `draw_masked_color()` to darken subtiles: unknown and known but not visited.
+    fog_masks: Option<(TextureHandle, TextureHandle)>,
+}
+
+impl WorldMapView {
+    pub fn new(worldmap: WorldMapRef) -> Self {
+        Self {
+            worldmap,
+            fog_masks: None,
+        }
+    }
+
+    /// Returns world map position that is displayed at the top-left corner of `rect`.
+    fn origin(&self, rect: Rect) -> Point {
+        let worldmap = self.worldmap.borrow();
+        let size = worldmap.def().size();
+        let p = worldmap.pos() - Point::new(rect.width(), rect.height()) / 2;
+        Point::new(
+            cmp::max(cmp::min(p.x, size.x - rect.width()), 0),
+            cmp::max(cmp::min(p.y, size.y - rect.height()), 0))
+    }
+}
+
+impl Widget for WorldMapView {
+    fn handle_event(&mut self, mut ctx: HandleEvent) {
+        if let Event::MouseUp { pos, button: MouseButton::Left } = ctx.event {
+            let rect = ctx.base.rect();
+            let pos = pos - rect.top_left() + self.origin(rect);
+            let area = {
+                let worldmap = self.worldmap.borrow();
+                worldmap.area_at(pos).filter(|&a| worldmap.is_area_known(a))
+            };
+            ctx.out(UiCommandData::WorldMap(if let Some(area) = area {
+                WorldMapCommand::Area { area }
+            } else {
+                WorldMapCommand::Travel { pos }
+            }));
+        }
+    }
+
+    fn render(&mut self, ctx: Render) {
+        let rect = ctx.base.unwrap().rect();
+        let origin = self.origin(rect);
+        let offset = rect.top_left() - origin;
+        let worldmap = self.worldmap.borrow();
+        let def = worldmap.def();
+
+        let (unknown_mask, known_mask) = self.fog_masks.get_or_insert_with(|| {
+            let mask = |v| ctx.frm_db.texture_factory().new_texture(SUBTILE_SIZE, SUBTILE_SIZE,
+                vec![v; (SUBTILE_SIZE * SUBTILE_SIZE) as usize].into_boxed_slice());
+            (mask(7), mask(4))
+        });
+
+        ctx.canvas.set_clip_rect(rect);
+
+        for (i, tile) in def.tiles.iter().enumerate() {
+            let i = i as i32;
+            let pos = Point::new(i % def.tile_cols * TILE_WIDTH, i / def.tile_cols * TILE_HEIGHT);
+            if Rect::with_size(pos.x, pos.y, TILE_WIDTH, TILE_HEIGHT)
+                .intersects(Rect::with_size(origin.x, origin.y, rect.width(), rect.height()))
+            {
+                Sprite::new_with_pos(tile.fid, pos + offset).render(ctx.canvas, ctx.frm_db);
+            }
+        }
+
+        let first = origin / SUBTILE_SIZE;
+        let last = (origin + Point::new(rect.width(), rect.height())) / SUBTILE_SIZE;
+        for y in first.y..=last.y {
+            for x in first.x..=last.x {
+                let subtile = Point::new(x, y);
+                let mask = match worldmap.visibility(subtile) {
+                    Visibility::Unknown => &*unknown_mask,
+                    Visibility::Known => &*known_mask,
+                    Visibility::Visited => continue,
+                };
+                ctx.canvas.draw_masked_color(color::BLACK, None,
+                    subtile * SUBTILE_SIZE + offset, mask);
+            }
+        }
+
+        for (i, area) in def.areas.iter().enumerate() {
+            if worldmap.is_area_known(i) {
+                ctx.canvas.draw_text(area.name.as_bytes().into(),
+                    area.pos + Point::new(0, area.size.radius()) + offset,
+                    AREA_FONT, AREA_COLOR, &DrawOptions {
+                        horz_align: HorzAlign::Center,
+                        ..Default::default()
+                    });
+            }
+        }
+
+        if let Some(dest) = worldmap.destination() {
+            let mut sprite = Sprite::new_with_pos(FrameId::WMAPTARG, dest + offset);
+            sprite.anchor = Anchor::Center;
+            sprite.render(ctx.canvas, ctx.frm_db);
+        }
+
+        let mut sprite = Sprite::new_with_pos(FrameId::WMAPLOC, worldmap.pos() + offset);
+        sprite.anchor = Anchor::Center;
+        sprite.render(ctx.canvas, ctx.frm_db);
+
+        ctx.canvas.reset_clip_rect();
+    }
+}
+
+/// List of the known areas. Clicking an area travels to it.
+pub struct AreaList {
+    worldmap: WorldMapRef,
+}
+
+impl AreaList {
+    pub fn new(worldmap: WorldMapRef) -> Self {
+        Self {
+            worldmap,
+        }
+    }
+
+    fn areas(&self, rect: Rect) -> Vec<usize> {
+        let worldmap = self.worldmap.borrow();
+        (0..worldmap.def().areas.len())
+            .filter(|&i| worldmap.is_area_known(i))
+            .take((rect.height() / AREA_ROW_HEIGHT) as usize)
+            .collect()
+    }
+}
+
+impl Widget for AreaList {
+    fn handle_event(&mut self, mut ctx: HandleEvent) {
+        if let Event::MouseUp { pos, button: MouseButton::Left } = ctx.event {
+            let rect = ctx.base.rect();
+            let row = (pos.y - rect.top) / AREA_ROW_HEIGHT;
+            if let Some(&area) = self.areas(rect).get(row as usize) {
+                ctx.out(UiCommandData::WorldMap(WorldMapCommand::Area { area }));
+            }
+        }
+    }
+
+    fn render(&mut self, ctx: Render) {
+        let rect = ctx.base.unwrap().rect();
+        let worldmap = self.worldmap.borrow();
+        for (row, area) in self.areas(rect).into_iter().enumerate() {
+            let pos = rect.top_left() + Point::new(0, row as i32 * AREA_ROW_HEIGHT);
+            Sprite::new_with_pos(FrameId::HOTSPOT1, pos).render(ctx.canvas, ctx.frm_db);
+            ctx.canvas.draw_text(worldmap.def().areas[area].name.as_bytes().into(),
+                pos + Point::new(20, 2), AREA_FONT, AREA_COLOR, &Default::default());
+        }
+    }
+}
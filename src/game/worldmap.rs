@@ -0,0 +1,580 @@
+pub mod def;
+pub mod encounter;
+
+use log::*;
+use std::cell::RefCell;
+use std::cmp;
+use std::io::{self, Error, ErrorKind};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::asset::save::{AreaState, EntranceState, TILE_SUBTILE_COUNT, WorldMapState};
+use crate::game::GameTime;
+use crate::graphics::Point;
+use crate::util::random::random;
+
+use def::*;
+
+/// Real time it takes to make one travel step.
+const STEP_DURATION: Duration = Duration::from_millis(40);
+
+/// Game time in deciseconds it takes to make one travel step.
+/// Moving by one pixel takes `Terrain::difficulty` steps.
+const STEP_GAME_TIME: u32 = 3000;
+
+/// Game time in deciseconds of travel between random encounter rolls.
+const ENCOUNTER_CHECK_INTERVAL: u32 = 36000;
+
+pub type WorldMapRef = Rc<RefCell<WorldMap>>;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Visibility {
+    Unknown,
+    Known,
+    Visited,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Travel {
+    start: Point,
+    dest: Point,
+    step: i32,
+    step_count: i32,
+}
+
+/// Result of `WorldMap::update()`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Update {
+    /// Game time passed in deciseconds.
+    pub game_time: u32,
+    /// Whether the party has reached the destination.
+    pub arrived: bool,
+    /// Whether the random encounter roll succeeded. The travel is paused at this point.
+    pub encounter: bool,
+}
+
+pub struct WorldMap {
+    def: WorldMapDef,
+    /// Per subtile in row-major order.
+    fog: Vec<Visibility>,
+    known_areas: Vec<bool>,
+    pos: Point,
+    travel: Option<Travel>,
+    /// Steps made on the current pixel.
+    terrain_steps: i32,
+    elapsed: Duration,
+    /// Game time traveled since the last random encounter roll.
+    encounter_check_time: u32,
+    /// Per encounter table entry: how many times it can occur yet. `None` if unlimited.
+    encounter_counters: Vec<Vec<Option<i32>>>,
+}
+
+impl WorldMap {
+    pub fn new(def: WorldMapDef) -> Self {
+        let size = def.subtile_size();
+        let known_areas = def.areas.iter().map(|a| a.known).collect();
+        let encounter_counters = def.encounter_tables.iter()
+            .map(|t| t.entries.iter().map(|e| e.counter).collect())
+            .collect();
+        let mut r = Self {
+            fog: vec![Visibility::Unknown; (size.x * size.y) as usize],
+            known_areas,
+            pos: Point::new(0, 0),
+            travel: None,
+            terrain_steps: 0,
+            elapsed: Duration::from_secs(0),
+            encounter_check_time: 0,
+            encounter_counters,
+            def,
+        };
+        for i in 0..r.def.areas.len() {
+            if r.known_areas[i] {
+                r.reveal(r.def.areas[i].pos);
+            }
+        }
+        r
+    }
+
+    pub fn def(&self) -> &WorldMapDef {
+        &self.def
+    }
+
+    /// Current party position in pixels.
+    pub fn pos(&self) -> Point {
+        self.pos
+    }
+
+    pub fn set_pos(&mut self, pos: Point) {
+        self.pos = self.clamp(pos);
+        self.travel = None;
+        self.reveal(self.pos);
+    }
+
+    pub fn destination(&self) -> Option<Point> {
+        self.travel.map(|t| t.dest)
+    }
+
+    pub fn is_traveling(&self) -> bool {
+        self.travel.is_some()
+    }
+
+    pub fn travel_to(&mut self, dest: Point) {
+        let dest = self.clamp(dest);
+        let d = (dest - self.pos).abs();
+        let step_count = cmp::max(d.x, d.y);
+        self.travel = if step_count > 0 {
+            Some(Travel {
+                start: self.pos,
+                dest,
+                step: 0,
+                step_count,
+            })
+        } else {
+            None
+        };
+        self.terrain_steps = 0;
+    }
+
+    pub fn stop(&mut self) {
+        self.travel = None;
+    }
+
+    /// Returns visibility of subtile at the specified subtile coordinates.
+    pub fn visibility(&self, subtile: Point) -> Visibility {
+        self.fog_idx(subtile).map(|i| self.fog[i]).unwrap_or(Visibility::Unknown)
+    }
+
+    pub fn is_area_known(&self, area: usize) -> bool {
+        self.known_areas[area]
+    }
+
+    pub fn set_area_known(&mut self, area: usize, known: bool) {
+        self.known_areas[area] = known;
+        if known {
+            self.reveal(self.def.areas[area].pos);
+        }
+    }
+
+    /// Returns the area whose circle contains the point `p`.
+    pub fn area_at(&self, p: Point) -> Option<usize> {
+        self.def.areas.iter().position(|a| {
+            let d = p - a.pos;
+            let r = a.size.radius();
+            d.x * d.x + d.y * d.y <= r * r
+        })
+    }
+
+    /// Returns terrain at the current party position.
+    pub fn terrain(&self) -> &Terrain {
+        let subtile = self.def.subtile(self.pos / SUBTILE_SIZE).unwrap();
+        &self.def.terrains[subtile.terrain]
+    }
+
+    /// Returns random encounter frequency at the current party position at the specified hour.
+    pub fn frequency(&self, hour: u8) -> Frequency {
+        self.def.subtile(self.pos / SUBTILE_SIZE).unwrap().frequency[DayPart::from_hour(hour)]
+    }
+
+    /// Returns encounter table at the current party position.
+    pub fn encounter_table(&self) -> Option<usize> {
+        self.def.subtile(self.pos / SUBTILE_SIZE).unwrap().encounter_table
+    }
+
+    /// Picks random entry from the encounter `table` among the entries that haven't exhausted
+    /// their counters and for which `filter` returns `true`. Entries with greater chance are
+    /// picked more often.
+    pub fn pick_encounter(&mut self, table: usize,
+        filter: impl Fn(&EncounterTableEntry) -> bool,
+    ) -> Option<usize> {
+        let entries = &self.def.encounter_tables[table].entries;
+        let counters = &self.encounter_counters[table];
+        let candidates: Vec<_> = (0..entries.len())
+            .filter(|&i| counters[i] != Some(0) && entries[i].chance > 0 && filter(&entries[i]))
+            .collect();
+        let total: i32 = candidates.iter().map(|&i| entries[i].chance).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut roll = random(1, total);
+        let entry = candidates.into_iter()
+            .find(|&i| {
+                roll -= entries[i].chance;
+                roll <= 0
+            })
+            .unwrap();
+        self.use_encounter(table, entry);
+        Some(entry)
+    }
+
+    /// Counts the occurrence of the encounter table entry.
+    pub fn use_encounter(&mut self, table: usize, entry: usize) {
+        if let Some(c) = self.encounter_counters[table][entry].as_mut() {
+            *c = cmp::max(*c - 1, 0);
+        }
+    }
+
+    /// Advances travel by the `elapsed` real time. `game_time` is the game time before the update.
+    // wmPartyWalkingStep()
+    pub fn update(&mut self, elapsed: Duration, game_time: GameTime) -> Update {
+        let mut r = Update::default();
+        if self.travel.is_none() {
+            self.elapsed = Duration::from_secs(0);
+            return r;
+        }
+        self.elapsed += elapsed;
+        while self.elapsed >= STEP_DURATION {
+            self.elapsed -= STEP_DURATION;
+            r.game_time += STEP_GAME_TIME;
+            if self.step() {
+                r.arrived = true;
+                break;
+            }
+            self.encounter_check_time += STEP_GAME_TIME;
+            if self.encounter_check_time >= ENCOUNTER_CHECK_INTERVAL {
+                self.encounter_check_time = 0;
+                let hour = GameTime::from_decis(game_time.as_decis() + r.game_time).hour();
+                if self.roll_encounter(hour) {
+                    r.encounter = true;
+                    break;
+                }
+            }
+        }
+        r
+    }
+
+    // wmRndEncounterOccurred()
+    fn roll_encounter(&self, hour: u8) -> bool {
+        if self.area_at(self.pos).is_some() || self.encounter_table().is_none() {
+            return false;
+        }
+        random(1, 100) <= self.def.frequencies[self.frequency(hour)]
+    }
+
+    /// Makes a single travel step. Returns `true` if the destination is reached.
+    fn step(&mut self) -> bool {
+        self.terrain_steps += 1;
+        if self.terrain_steps < self.terrain().difficulty {
+            return false;
+        }
+        self.terrain_steps = 0;
+
+        let travel = self.travel.as_mut().unwrap();
+        travel.step += 1;
+        let pos = travel.start + (travel.dest - travel.start) * travel.step / travel.step_count;
+        let arrived = travel.step >= travel.step_count;
+        if arrived {
+            self.travel = None;
+        }
+        self.pos = pos;
+        self.reveal(pos);
+        if let Some(area) = self.area_at(pos) {
+            if !self.known_areas[area] {
+                debug!("discovered area `{}`", self.def.areas[area].name);
+                self.known_areas[area] = true;
+            }
+        }
+        arrived
+    }
+
+    /// Stores the party position, known areas, fog and encounter counters in the `state`.
+    /// The rest of the `state` is left as is.
+    // wmWorldMap_save()
+    pub fn save_state(&self, state: &mut WorldMapState) {
+        state.area = self.area_at(self.pos).map(|a| a as i32).unwrap_or(-1);
+        state.pos = self.pos;
+
+        if state.areas.len() != self.def.areas.len() {
+            state.areas = self.def.areas.iter()
+                .map(|a| AreaState {
+                    pos: a.pos,
+                    state: 0,
+                    visited: 0,
+                    entrances: a.entrances.iter()
+                        .map(|e| EntranceState { known: e.known, pos: e.pos })
+                        .collect(),
+                })
+                .collect();
+        }
+        for (area, &known) in state.areas.iter_mut().zip(&self.known_areas) {
+            area.state = if known { cmp::max(area.state, 1) } else { 0 };
+        }
+
+        state.tile_cols = self.def.tile_cols;
+        state.tiles = (0..self.def.tiles.len() as i32)
+            .map(|i| {
+                let origin = Point::new(i % self.def.tile_cols * SUBTILE_COLS,
+                    i / self.def.tile_cols * SUBTILE_ROWS);
+                let mut tile = [0; TILE_SUBTILE_COUNT];
+                for y in 0..SUBTILE_ROWS {
+                    for x in 0..SUBTILE_COLS {
+                        tile[(y * SUBTILE_COLS + x) as usize] =
+                            match self.visibility(origin + Point::new(x, y)) {
+                                Visibility::Unknown => 0,
+                                Visibility::Known => 1,
+                                Visibility::Visited => 2,
+                            };
+                    }
+                }
+                tile
+            })
+            .collect();
+
+        state.encounter_counters.clear();
+        for (table, counters) in self.encounter_counters.iter().enumerate() {
+            for (entry, &counter) in counters.iter().enumerate() {
+                if let Some(counter) = counter {
+                    state.encounter_counters.push((table as i32, entry as i32, counter));
+                }
+            }
+        }
+    }
+
+    /// Restores the state saved by `save_state()`. Nothing is modified if the `state` doesn't
+    /// match the world map definition.
+    // wmWorldMap_load()
+    pub fn load_state(&mut self, state: &WorldMapState) -> io::Result<()> {
+        let err = |s: &str| Error::new(ErrorKind::InvalidData,
+            format!("world map state doesn't match the world map definition: {}", s));
+        if state.tile_cols != self.def.tile_cols || state.tiles.len() != self.def.tiles.len() {
+            return Err(err("tiles"));
+        }
+        if state.areas.len() != self.def.areas.len() {
+            return Err(err("areas"));
+        }
+        let mut fog = vec![Visibility::Unknown; self.fog.len()];
+        for (i, tile) in state.tiles.iter().enumerate() {
+            let i = i as i32;
+            let origin = Point::new(i % self.def.tile_cols * SUBTILE_COLS,
+                i / self.def.tile_cols * SUBTILE_ROWS);
+            for y in 0..SUBTILE_ROWS {
+                for x in 0..SUBTILE_COLS {
+                    let v = match tile[(y * SUBTILE_COLS + x) as usize] {
+                        0 => Visibility::Unknown,
+                        1 => Visibility::Known,
+                        2 => Visibility::Visited,
+                        _ => return Err(err("subtile visibility")),
+                    };
+                    fog[self.fog_idx(origin + Point::new(x, y)).unwrap()] = v;
+                }
+            }
+        }
+        let mut encounter_counters: Vec<Vec<_>> = self.def.encounter_tables.iter()
+            .map(|t| t.entries.iter().map(|e| e.counter).collect())
+            .collect();
+        for &(table, entry, counter) in &state.encounter_counters {
+            *encounter_counters.get_mut(table as usize)
+                .and_then(|t| t.get_mut(entry as usize))
+                .ok_or_else(|| err("encounter counters"))? = Some(counter);
+        }
+
+        self.fog = fog;
+        self.known_areas = state.areas.iter().map(|a| a.state > 0).collect();
+        self.encounter_counters = encounter_counters;
+        self.pos = self.clamp(state.pos);
+        self.travel = None;
+        self.terrain_steps = 0;
+        self.encounter_check_time = 0;
+        Ok(())
+    }
+
+    /// Marks subtile at `pos` as visited and its neighbors as known.
+    fn reveal(&mut self, pos: Point) {
+        let center = pos / SUBTILE_SIZE;
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let p = center + Point::new(x, y);
+                if let Some(i) = self.fog_idx(p) {
+                    if self.fog[i] == Visibility::Unknown {
+                        self.fog[i] = Visibility::Known;
+                        let fill = self.def.subtile(p).unwrap().fill;
+                        if let Some(fi) = fill.offset().and_then(|o| self.fog_idx(p + o)) {
+                            if self.fog[fi] == Visibility::Unknown {
+                                self.fog[fi] = Visibility::Known;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(i) = self.fog_idx(center) {
+            self.fog[i] = Visibility::Visited;
+        }
+    }
+
+    fn fog_idx(&self, subtile: Point) -> Option<usize> {
+        let size = self.def.subtile_size();
+        if subtile.x >= 0 && subtile.y >= 0 && subtile.x < size.x && subtile.y < size.y {
+            Some((subtile.y * size.x + subtile.x) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn clamp(&self, p: Point) -> Point {
+        let size = self.def.size();
+        Point::new(
+            cmp::min(cmp::max(p.x, 0), size.x - 1),
+            cmp::min(cmp::max(p.y, 0), size.y - 1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use enum_map::enum_map;
+    use super::*;
+    use crate::asset::frame::FrameId;
+
+    fn entry(chance: i32, counter: Option<i32>) -> EncounterTableEntry {
+        EncounterTableEntry {
+            chance,
+            counter,
+            groups: vec![],
+            map: None,
+            special: false,
+            conditions: vec![],
+        }
+    }
+
+    fn worldmap() -> WorldMap {
+        let subtiles = (0..SUBTILE_COLS * SUBTILE_ROWS)
+            .map(|i| Subtile {
+                // Mountains in the rightmost column.
+                terrain: (i % SUBTILE_COLS == SUBTILE_COLS - 1) as usize,
+                fill: if i == 1 { Fill::East } else { Fill::None },
+                // Forced encounters in the bottom row.
+                frequency: enum_map! { _ => if i / SUBTILE_COLS == SUBTILE_ROWS - 1 {
+                    Frequency::Forced
+                } else {
+                    Frequency::None
+                }},
+                encounter_table: Some(0),
+            })
+            .collect();
+        WorldMap::new(WorldMapDef {
+            terrains: vec![
+                Terrain { name: "Desert".into(), difficulty: 1 },
+                Terrain { name: "Mountain".into(), difficulty: 2 },
+            ],
+            frequencies: enum_map! {
+                Frequency::Forced => 100,
+                _ => 0,
+            },
+            tile_cols: 1,
+            tiles: vec![Tile {
+                fid: FrameId::WRLDMP00,
+                encounter_difficulty: 0,
+                subtiles,
+            }],
+            encounter_tables: vec![EncounterTable {
+                name: "Table".into(),
+                maps: vec![],
+                entries: vec![entry(50, Some(1)), entry(0, None), entry(50, None)],
+            }],
+            encounter_groups: vec![],
+            areas: vec![Area {
+                name: "Town".into(),
+                pos: Point::new(305, 20),
+                known: false,
+                locked: false,
+                size: AreaSize::Small,
+                townmap_fid: None,
+                label_fid: None,
+                entrances: vec![],
+            }],
+        })
+    }
+
+    #[test]
+    fn fog() {
+        let mut wm = worldmap();
+        assert_eq!(wm.visibility(Point::new(0, 0)), Visibility::Unknown);
+        wm.set_pos(Point::new(10, 60));
+        assert_eq!(wm.visibility(Point::new(0, 1)), Visibility::Visited);
+        assert_eq!(wm.visibility(Point::new(0, 0)), Visibility::Known);
+        assert_eq!(wm.visibility(Point::new(1, 2)), Visibility::Known);
+        // Fill to the east from (1, 0).
+        assert_eq!(wm.visibility(Point::new(2, 0)), Visibility::Known);
+        assert_eq!(wm.visibility(Point::new(2, 1)), Visibility::Unknown);
+        assert_eq!(wm.visibility(Point::new(0, 3)), Visibility::Unknown);
+        assert_eq!(wm.visibility(Point::new(-1, 0)), Visibility::Unknown);
+    }
+
+    #[test]
+    fn travel() {
+        let mut wm = worldmap();
+        wm.set_pos(Point::new(280, 20));
+        assert_eq!(wm.update(STEP_DURATION * 5, GameTime::from_decis(0)), Update::default());
+
+        wm.travel_to(Point::new(310, 20));
+        assert_eq!(wm.destination(), Some(Point::new(310, 20)));
+        let u = wm.update(STEP_DURATION * 10, GameTime::from_decis(0));
+        assert_eq!(u, Update { game_time: 10 * STEP_GAME_TIME, arrived: false, encounter: false });
+        assert_eq!(wm.pos(), Point::new(290, 20));
+        assert!(!wm.is_area_known(0));
+
+        let u = wm.update(STEP_DURATION * 100, GameTime::from_decis(0));
+        assert!(u.arrived);
+        assert!(wm.is_area_known(0));
+        assert_eq!(wm.pos(), Point::new(310, 20));
+        assert!(!wm.is_traveling());
+        // 10 pixels of desert and 10 pixels of mountains.
+        assert_eq!(u.game_time, (10 + 2 * 10) * STEP_GAME_TIME);
+        assert_eq!(wm.visibility(Point::new(6, 0)), Visibility::Visited);
+        assert_eq!(wm.visibility(Point::new(6, 1)), Visibility::Known);
+    }
+
+    #[test]
+    fn encounter() {
+        let mut wm = worldmap();
+        wm.set_pos(Point::new(10, SUBTILE_SIZE * (SUBTILE_ROWS - 1) + 10));
+        wm.travel_to(Point::new(100, SUBTILE_SIZE * (SUBTILE_ROWS - 1) + 10));
+        let check_steps = ENCOUNTER_CHECK_INTERVAL / STEP_GAME_TIME;
+        let u = wm.update(STEP_DURATION * 100, GameTime::from_decis(0));
+        assert_eq!(u, Update {
+            game_time: check_steps * STEP_GAME_TIME,
+            arrived: false,
+            encounter: true,
+        });
+        assert!(wm.is_traveling());
+        assert_eq!(wm.frequency(12), Frequency::Forced);
+        assert_eq!(wm.encounter_table(), Some(0));
+
+        let limited = |e: &EncounterTableEntry| e.counter.is_some();
+        assert_eq!(wm.pick_encounter(0, limited), Some(0));
+        // The counter is exhausted.
+        assert_eq!(wm.pick_encounter(0, limited), None);
+        // Zero chance entries are never picked.
+        assert_eq!(wm.pick_encounter(0, |e| e.chance == 0), None);
+        for _ in 0..10 {
+            assert_eq!(wm.pick_encounter(0, |_| true), Some(2));
+        }
+    }
+
+    #[test]
+    fn state_roundtrip() {
+        let mut wm = worldmap();
+        wm.set_pos(Point::new(310, 20));
+        wm.set_area_known(0, true);
+        wm.use_encounter(0, 0);
+        let mut state = WorldMapState::default();
+        wm.save_state(&mut state);
+        assert_eq!(state.area, 0);
+        assert_eq!(state.areas[0].state, 1);
+        assert_eq!(state.tiles[0][6], 2);
+        assert_eq!(state.tiles[0][0], 0);
+        assert_eq!(state.encounter_counters, vec![(0, 0, 0)]);
+
+        let mut wm2 = worldmap();
+        wm2.load_state(&state).unwrap();
+        assert_eq!(wm2.pos(), Point::new(310, 20));
+        assert!(wm2.is_area_known(0));
+        assert_eq!(wm2.visibility(Point::new(6, 0)), Visibility::Visited);
+        assert_eq!(wm2.visibility(Point::new(5, 1)), Visibility::Known);
+        assert_eq!(wm2.visibility(Point::new(0, 0)), Visibility::Unknown);
+        assert_eq!(wm2.pick_encounter(0, |e| e.counter.is_some()), None);
+
+        state.tiles.clear();
+        assert!(wm2.load_state(&state).is_err());
+        assert!(wm2.is_area_known(0));
+    }
+}
@@ -0,0 +1,1190 @@
+use enum_map::{enum_map, EnumMap};
+use enum_map_derive::Enum;
+use enum_primitive_derive::Primitive;
+use log::*;
+use num_traits::FromPrimitive;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Error, ErrorKind};
+use std::str::FromStr;
+
+use crate::asset::EntityKind;
+use crate::asset::frame::{FrameId, Idx};
+use crate::asset::proto::ProtoId;
+use crate::asset::script::ProgramId;
+use crate::fs::FileSystem;
+use crate::graphics::{EPoint, Point};
+use crate::graphics::geometry::hex::{Direction, TileGrid};
+use crate::util::RangeInclusive;
+
+/// Size of a world map tile in pixels.
+pub const TILE_WIDTH: i32 = 350;
+pub const TILE_HEIGHT: i32 = 300;
+
+/// Size of a subtile in pixels. Subtile is the unit of terrain, fog of war and encounter chances.
+pub const SUBTILE_SIZE: i32 = 50;
+pub const SUBTILE_COLS: i32 = TILE_WIDTH / SUBTILE_SIZE;
+pub const SUBTILE_ROWS: i32 = TILE_HEIGHT / SUBTILE_SIZE;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Terrain {
+    pub name: String,
+    /// Travel time multiplier.
+    pub difficulty: i32,
+}
+
+/// Random encounter frequency.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq, Primitive)]
+pub enum Frequency {
+    None = 0,
+    Rare = 1,
+    Uncommon = 2,
+    Common = 3,
+    Frequent = 4,
+    Forced = 5,
+}
+
+impl Frequency {
+    fn from_name(s: &str) -> Option<Self> {
+        use Frequency::*;
+        Some(match s {
+            "none" => None,
+            "rare" => Rare,
+            "uncommon" => Uncommon,
+            "common" => Common,
+            "frequent" => Frequent,
+            "forced" => Forced,
+            _ => return Option::None,
+        })
+    }
+
+    fn default_chance(self) -> i32 {
+        use Frequency::*;
+        match self {
+            None => 0,
+            Rare => 4,
+            Uncommon => 12,
+            Common => 22,
+            Frequent => 38,
+            Forced => 100,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum DayPart {
+    Morning,
+    Afternoon,
+    Night,
+}
+
+impl DayPart {
+    pub fn from_hour(hour: u8) -> Self {
+        match hour {
+            6..=11 => DayPart::Morning,
+            12..=17 => DayPart::Afternoon,
+            _ => DayPart::Night,
+        }
+    }
+}
+
+/// Neighbor subtile that gets revealed along with this one.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Fill {
+    None,
+    North,
+    South,
+    East,
+    West,
+    NorthWest,
+    NorthEast,
+    SouthWest,
+    SouthEast,
+}
+
+impl Fill {
+    fn from_name(s: &str) -> Option<Self> {
+        use Fill::*;
+        Some(match s {
+            "no_fill" => None,
+            "fill_n" => North,
+            "fill_s" => South,
+            "fill_e" => East,
+            "fill_w" => West,
+            "fill_nw" => NorthWest,
+            "fill_ne" => NorthEast,
+            "fill_sw" => SouthWest,
+            "fill_se" => SouthEast,
+            _ => return Option::None,
+        })
+    }
+
+    /// Offset in subtiles to the neighbor.
+    pub fn offset(self) -> Option<Point> {
+        use Fill::*;
+        Some(match self {
+            None => return Option::None,
+            North => (0, -1),
+            South => (0, 1),
+            East => (1, 0),
+            West => (-1, 0),
+            NorthWest => (-1, -1),
+            NorthEast => (1, -1),
+            SouthWest => (-1, 1),
+            SouthEast => (1, 1),
+        }.into())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Subtile {
+    /// Index into `WorldMapDef::terrains`.
+    pub terrain: usize,
+    pub fill: Fill,
+    pub frequency: EnumMap<DayPart, Frequency>,
+    /// Index into `WorldMapDef::encounter_tables`.
+    pub encounter_table: Option<usize>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Tile {
+    pub fid: FrameId,
+    pub encounter_difficulty: i32,
+    /// `SUBTILE_COLS` x `SUBTILE_ROWS` subtiles in row-major order.
+    pub subtiles: Vec<Subtile>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    pub fn eval(self, lhs: i32, rhs: i32) -> bool {
+        use CompareOp::*;
+        match self {
+            Eq => lhs == rhs,
+            Ne => lhs != rhs,
+            Lt => lhs < rhs,
+            Le => lhs <= rhs,
+            Gt => lhs > rhs,
+            Ge => lhs >= rhs,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConditionValue {
+    PlayerLevel,
+    Global(usize),
+}
+
+/// Condition of an encounter table entry or an encounter critter: `If(Rand(20%))`,
+/// `If(Player(Level) > 6 And Global(452) == 0)`. All conditions in the list must be satisfied.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Condition {
+    /// Percent chance.
+    Rand(i32),
+    Compare {
+        value: ConditionValue,
+        op: CompareOp,
+        rhs: i32,
+    },
+}
+
+/// How an encounter group behaves when the party arrives.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Attitude {
+    Neutral,
+    /// Attacks the party: `AMBUSH Player`.
+    Ambush,
+    /// Fights the previous group of the entry: `FIGHTING`.
+    Fighting,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EncounterTableGroup {
+    pub count: RangeInclusive<i32>,
+    /// Index into `WorldMapDef::encounter_groups`.
+    pub group: usize,
+    pub attitude: Attitude,
+}
+
+// Chance:30%, Counter:2, Enc:(2-4) ARRO_Geckos AMBUSH Player, If(Rand(50%))
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EncounterTableEntry {
+    /// Percent chance of this entry to be picked.
+    pub chance: i32,
+    /// How many times this entry can occur. `None` if unlimited.
+    pub counter: Option<i32>,
+    pub groups: Vec<EncounterTableGroup>,
+    /// Lookup name of the map that overrides the table maps.
+    pub map: Option<String>,
+    /// Special encounters are scripted and don't use encounter groups.
+    pub special: bool,
+    pub conditions: Vec<Condition>,
+}
+
+/// Arrangement of the encounter group critters.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Formation {
+    Surrounding,
+    StraightLine,
+    DoubleLine,
+    Wedge,
+    Cone,
+    Huddle,
+}
+
+impl Formation {
+    fn from_name(s: &str) -> Option<Self> {
+        use Formation::*;
+        Some(match s {
+            "surrounding" => Surrounding,
+            "straight_line" => StraightLine,
+            "double_line" => DoubleLine,
+            "wedge" => Wedge,
+            "cone" => Cone,
+            "huddle" => Huddle,
+            _ => return None,
+        })
+    }
+}
+
+// Item:(0-10)41, Item:4(wielded)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EncounterItem {
+    pub count: RangeInclusive<i32>,
+    pub pid: ProtoId,
+    pub wielded: bool,
+}
+
+// type_00=Ratio:60%, pid:16777224, Item:(0-10)41, Script:501, If(Rand(50%))
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EncounterCritter {
+    /// Percent of the group critters of this type. If `None` the critter takes its share of what
+    /// is left from the other types.
+    pub ratio: Option<i32>,
+    pub pid: ProtoId,
+    pub items: Vec<EncounterItem>,
+    /// Overrides the proto script.
+    pub script: Option<ProgramId>,
+    pub dead: bool,
+    pub conditions: Vec<Condition>,
+}
+
+/// Encounter group from `[Encounter: NAME]` section.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EncounterGroup {
+    pub name: String,
+    pub formation: Formation,
+    /// Distance in hexes between critters in formation.
+    pub spacing: u32,
+    /// Distance in hexes from the party. If `None` a random distance is used.
+    pub distance: Option<u32>,
+    pub critters: Vec<EncounterCritter>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EncounterTable {
+    pub name: String,
+    /// Lookup names of the maps (see `MapDef::lookup_name`) to pick encounter map from.
+    pub maps: Vec<String>,
+    pub entries: Vec<EncounterTableEntry>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AreaSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl AreaSize {
+    fn from_name(s: &str) -> Option<Self> {
+        Some(match s {
+            "small" => AreaSize::Small,
+            "medium" => AreaSize::Medium,
+            "large" => AreaSize::Large,
+            _ => return None,
+        })
+    }
+
+    /// Radius of the area circle in pixels.
+    pub fn radius(self) -> i32 {
+        match self {
+            AreaSize::Small => 5,
+            AreaSize::Medium => 10,
+            AreaSize::Large => 20,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entrance {
+    pub known: bool,
+    /// Position on the town map.
+    pub pos: Point,
+    /// Lookup name of the map (see `MapDef::lookup_name`).
+    pub map: String,
+    /// Dude position in the map. If `None` the map's default entrance is used.
+    pub dude_pos: Option<EPoint>,
+    pub direction: Direction,
+}
+
+/// City area from `data/city.txt`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Area {
+    pub name: String,
+    /// Position on the world map in pixels.
+    pub pos: Point,
+    /// Whether the area is initially known.
+    pub known: bool,
+    pub locked: bool,
+    pub size: AreaSize,
+    pub townmap_fid: Option<FrameId>,
+    pub label_fid: Option<FrameId>,
+    pub entrances: Vec<Entrance>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WorldMapDef {
+    pub terrains: Vec<Terrain>,
+    /// Chance in percent of random encounter per frequency.
+    pub frequencies: EnumMap<Frequency, i32>,
+    /// Width of the world map in tiles.
+    pub tile_cols: i32,
+    pub tiles: Vec<Tile>,
+    pub encounter_tables: Vec<EncounterTable>,
+    /// Sorted by name.
+    pub encounter_groups: Vec<EncounterGroup>,
+    pub areas: Vec<Area>,
+}
+
+fn err(section: &str, key: &str, v: &str) -> Error {
+    Error::new(ErrorKind::InvalidData,
+        format!("invalid value of `{}` in `{}`: {}", key, section, v))
+}
+
+fn missing(section: &str, key: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("missing `{}` in `{}`", key, section))
+}
+
+fn parse<T: FromStr>(section: &str, key: &str, v: &str) -> io::Result<T> {
+    v.trim().parse().map_err(|_| err(section, key, v))
+}
+
+fn parse_enum<T>(section: &str, key: &str, v: &str, f: impl Fn(&str) -> Option<T>)
+    -> io::Result<T>
+{
+    f(&v.trim().to_ascii_lowercase()).ok_or_else(|| err(section, key, v))
+}
+
+fn parse_on_off(section: &str, key: &str, v: &str) -> io::Result<bool> {
+    parse_enum(section, key, v, |s| match s {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    })
+}
+
+fn get<'a>(ini: &'a HashMap<String, String>, section: &str, key: &str) -> io::Result<&'a str> {
+    ini.get(key).map(|v| v.as_str()).ok_or_else(|| missing(section, key))
+}
+
+fn strip_prefix_ci<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    if s.len() >= prefix.len() && s.is_char_boundary(prefix.len())
+        && s[..prefix.len()].eq_ignore_ascii_case(prefix)
+    {
+        Some(&s[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+// (2-4), (3)
+fn parse_count(section: &str, key: &str, v: &str) -> io::Result<RangeInclusive<i32>> {
+    let s = v.strip_prefix('(')
+        .and_then(|s| s.strip_suffix(')'))
+        .ok_or_else(|| err(section, key, v))?;
+    let mut parts = s.splitn(2, '-');
+    let start = parse(section, key, parts.next().unwrap())?;
+    let end = parts.next()
+        .map(|s| parse(section, key, s))
+        .transpose()?
+        .unwrap_or(start);
+    Ok(RangeInclusive { start, end })
+}
+
+fn parse_pid(section: &str, key: &str, v: &str) -> io::Result<ProtoId> {
+    ProtoId::from_packed(parse(section, key, v)?).ok_or_else(|| err(section, key, v))
+}
+
+// If(Player(Level) > 6 And Rand(50%))
+fn parse_conditions(section: &str, key: &str, v: &str) -> io::Result<Vec<Condition>> {
+    let s = v.to_ascii_lowercase();
+    let s = s.trim()
+        .strip_prefix("if")
+        .map(|s| s.trim())
+        .and_then(|s| s.strip_prefix('('))
+        .and_then(|s| s.strip_suffix(')'))
+        .ok_or_else(|| err(section, key, v))?;
+    let mut r = Vec::new();
+    for cond in s.split(" and ") {
+        if let Some(cond) = parse_condition(section, key, cond)? {
+            r.push(cond);
+        } else {
+            warn!("ignoring unsupported condition `{}` in `{}` {}", cond.trim(), section, key);
+        }
+    }
+    Ok(r)
+}
+
+fn parse_condition(section: &str, key: &str, v: &str) -> io::Result<Option<Condition>> {
+    let s: String = v.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut s = s.trim_start_matches('(');
+    while s.ends_with(')') && s.matches(')').count() > s.matches('(').count() {
+        s = &s[..s.len() - 1];
+    }
+
+    if let Some(chance) = s.strip_prefix("rand(") {
+        let chance = chance.strip_suffix("%)").ok_or_else(|| err(section, key, v))?;
+        return Ok(Some(Condition::Rand(parse(section, key, chance)?)));
+    }
+
+    let (value, s) = if let Some(s) = s.strip_prefix("player(level)") {
+        (ConditionValue::PlayerLevel, s)
+    } else if let Some(s) = s.strip_prefix("global(") {
+        let end = s.find(')').ok_or_else(|| err(section, key, v))?;
+        (ConditionValue::Global(parse(section, key, &s[..end])?), &s[end + 1..])
+    } else {
+        return Ok(None);
+    };
+    use CompareOp::*;
+    for &(op_str, op) in &[("==", Eq), ("!=", Ne), ("<=", Le), (">=", Ge),
+        ("<", Lt), (">", Gt), ("=", Eq)]
+    {
+        if let Some(rhs) = s.strip_prefix(op_str) {
+            return Ok(Some(Condition::Compare {
+                value,
+                op,
+                rhs: parse(section, key, rhs)?,
+            }));
+        }
+    }
+    Err(err(section, key, v))
+}
+
+fn art_fid(section: &str, key: &str, v: &str) -> io::Result<Option<FrameId>> {
+    let idx: i32 = parse(section, key, v)?;
+    if idx < 0 {
+        Ok(None)
+    } else {
+        FrameId::new_generic(EntityKind::Interface, idx as Idx)
+            .map(Some)
+            .ok_or_else(|| err(section, key, v))
+    }
+}
+
+impl WorldMapDef {
+    pub fn new(fs: &FileSystem) -> io::Result<Self> {
+        Self::read(&mut fs.reader("data/worldmap.txt")?, &mut fs.reader("data/city.txt")?)
+    }
+
+    fn read(worldmap: &mut impl BufRead, city: &mut impl BufRead) -> io::Result<Self> {
+        let ini = crate::asset::read_ini(worldmap)?;
+
+        let data = ini.get("Data").ok_or_else(|| missing("worldmap.txt", "Data"))?;
+        let terrains = get(data, "Data", "terrain_types")?
+            .split(',')
+            .map(|s| {
+                let mut parts = s.splitn(2, ':');
+                let name = parts.next().unwrap().trim();
+                let difficulty = parts.next().ok_or_else(|| err("Data", "terrain_types", s))?;
+                Ok(Terrain {
+                    name: name.into(),
+                    difficulty: parse("Data", "terrain_types", difficulty)?,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let tile_cols = parse("Data", "num_horizontal_tiles",
+            get(data, "Data", "num_horizontal_tiles")?)?;
+        let mut frequencies = EnumMap::new();
+        for i in 0..6 {
+            let freq = Frequency::from_u32(i).unwrap();
+            let key = format!("{:?}", freq);
+            frequencies[freq] = if let Some(v) = data.get(&key) {
+                parse("Data", &key, v.trim_end_matches('%'))?
+            } else {
+                freq.default_chance()
+            };
+        }
+
+        let encounter_groups = Self::read_encounter_groups(&ini)?;
+
+        let mut encounter_tables = Vec::new();
+        for i in 0.. {
+            let name = format!("Encounter Table {}", i);
+            let section = if let Some(v) = ini.get(&name) {
+                v
+            } else {
+                break;
+            };
+            let maps = section.get("maps")
+                .map(|v| v.split(',')
+                    .map(|s| s.trim().to_owned())
+                    .filter(|s| !s.is_empty())
+                    .collect())
+                .unwrap_or_default();
+            let mut entries = Vec::new();
+            for i in 0.. {
+                let key = format!("enc_{:02}", i);
+                let v = if let Some(v) = section.get(&key) {
+                    v
+                } else {
+                    break;
+                };
+                entries.push(Self::read_encounter_entry(&name, &key, v, &encounter_groups)?);
+            }
+            encounter_tables.push(EncounterTable {
+                name: get(section, &name, "lookup_name")?.into(),
+                maps,
+                entries,
+            });
+        }
+
+        let mut tiles = Vec::new();
+        for i in 0.. {
+            let name = format!("Tile {}", i);
+            let section = if let Some(v) = ini.get(&name) {
+                v
+            } else {
+                break;
+            };
+            let fid = art_fid(&name, "art_idx", get(section, &name, "art_idx")?)?
+                .ok_or_else(|| err(&name, "art_idx", "-1"))?;
+            let encounter_difficulty = section.get("encounter_difficulty")
+                .map(|v| parse(&name, "encounter_difficulty", v))
+                .transpose()?
+                .unwrap_or(0);
+            let mut subtiles = Vec::with_capacity((SUBTILE_COLS * SUBTILE_ROWS) as usize);
+            for y in 0..SUBTILE_ROWS {
+                for x in 0..SUBTILE_COLS {
+                    let key = format!("{}_{}", x, y);
+                    subtiles.push(Self::read_subtile(&name, &key, get(section, &name, &key)?,
+                        &terrains, &encounter_tables)?);
+                }
+            }
+            tiles.push(Tile {
+                fid,
+                encounter_difficulty,
+                subtiles,
+            });
+        }
+        if tile_cols <= 0 || tiles.is_empty() || tiles.len() % tile_cols as usize != 0 {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("number of world map tiles ({}) doesn't match num_horizontal_tiles ({})",
+                    tiles.len(), tile_cols)));
+        }
+
+        let areas = Self::read_areas(city)?;
+
+        Ok(Self {
+            terrains,
+            frequencies,
+            tile_cols,
+            tiles,
+            encounter_tables,
+            encounter_groups,
+            areas,
+        })
+    }
+
+    // Chance:30%, Counter:2, Enc:(2-4) ARRO_Geckos AMBUSH Player, If(Rand(50%))
+    fn read_encounter_entry(section: &str, key: &str, v: &str,
+        encounter_groups: &[EncounterGroup],
+    ) -> io::Result<EncounterTableEntry> {
+        let mut chance = None;
+        let mut counter = None;
+        let mut groups = Vec::new();
+        let mut map = None;
+        let mut special = false;
+        let mut conditions = Vec::new();
+        for part in v.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            if let Some(s) = strip_prefix_ci(part, "chance:") {
+                chance = Some(parse(section, key, s.trim().trim_end_matches('%'))?);
+            } else if let Some(s) = strip_prefix_ci(part, "counter:") {
+                let v: i32 = parse(section, key, s)?;
+                counter = if v >= 0 { Some(v) } else { None };
+            } else if let Some(s) = strip_prefix_ci(part, "enc:") {
+                groups = Self::read_encounter_groups_spec(section, key, s, encounter_groups)?;
+            } else if let Some(s) = strip_prefix_ci(part, "map:") {
+                map = Some(s.trim().into());
+            } else if part.eq_ignore_ascii_case("special") {
+                special = true;
+            } else if strip_prefix_ci(part, "if").is_some() {
+                conditions = parse_conditions(section, key, part)?;
+            } else {
+                warn!("ignoring unknown `{}` in `{}` {}", part, section, key);
+            }
+        }
+        Ok(EncounterTableEntry {
+            chance: chance.ok_or_else(|| err(section, key, v))?,
+            counter,
+            groups,
+            map,
+            special,
+            conditions,
+        })
+    }
+
+    // (2-4) ARRO_Geckos AMBUSH Player
+    // (1-2) Raiders FIGHTING (2-3) Caravan AND Brahmin
+    fn read_encounter_groups_spec(section: &str, key: &str, v: &str,
+        encounter_groups: &[EncounterGroup],
+    ) -> io::Result<Vec<EncounterTableGroup>> {
+        let mut r = Vec::new();
+        let mut tokens = v.split_whitespace().peekable();
+        let mut next_attitude = Attitude::Neutral;
+        loop {
+            let count = if tokens.peek().map(|s| s.starts_with('(')) == Some(true) {
+                parse_count(section, key, tokens.next().unwrap())?
+            } else {
+                RangeInclusive { start: 1, end: 1 }
+            };
+            let name = tokens.next().ok_or_else(|| err(section, key, v))?;
+            let mut attitude = next_attitude;
+            let mut token = tokens.next();
+            if token.map(|s| s.eq_ignore_ascii_case("ambush")) == Some(true) {
+                if tokens.next().map(|s| s.eq_ignore_ascii_case("player")) != Some(true) {
+                    return Err(err(section, key, v));
+                }
+                attitude = Attitude::Ambush;
+                token = tokens.next();
+            }
+            if let Some(group) = encounter_groups.iter()
+                .position(|g| g.name.eq_ignore_ascii_case(name))
+            {
+                r.push(EncounterTableGroup {
+                    count,
+                    group,
+                    attitude,
+                });
+            } else {
+                warn!("unknown encounter group `{}` in `{}` {}", name, section, key);
+            }
+            next_attitude = match token.map(|s| s.to_ascii_lowercase()).as_deref() {
+                None => break,
+                Some("and") => Attitude::Neutral,
+                Some("fighting") => Attitude::Fighting,
+                Some(_) => return Err(err(section, key, v)),
+            };
+        }
+        Ok(r)
+    }
+
+    fn read_encounter_groups(ini: &HashMap<String, HashMap<String, String>>)
+        -> io::Result<Vec<EncounterGroup>>
+    {
+        let mut r = Vec::new();
+        for (section_name, section) in ini {
+            let name = if let Some(v) = strip_prefix_ci(section_name, "encounter:") {
+                v.trim()
+            } else {
+                continue;
+            };
+
+            // position=wedge, spacing:2, distance:8
+            let mut formation = Formation::Surrounding;
+            let mut spacing = 1;
+            let mut distance = None;
+            if let Some(v) = section.get("position") {
+                for (i, part) in v.split(',').map(|s| s.trim()).enumerate() {
+                    if i == 0 {
+                        formation = parse_enum(section_name, "position", part,
+                            Formation::from_name)?;
+                    } else if let Some(s) = strip_prefix_ci(part, "spacing:") {
+                        spacing = parse(section_name, "position", s)?;
+                    } else if let Some(s) = strip_prefix_ci(part, "distance:") {
+                        distance = Some(parse(section_name, "position", s)?);
+                    } else {
+                        warn!("ignoring unknown `{}` in `{}` position", part, section_name);
+                    }
+                }
+            }
+
+            let mut critters = Vec::new();
+            for i in 0.. {
+                let key = format!("type_{:02}", i);
+                let v = if let Some(v) = section.get(&key) {
+                    v
+                } else {
+                    break;
+                };
+                critters.push(Self::read_encounter_critter(section_name, &key, v)?);
+            }
+
+            r.push(EncounterGroup {
+                name: name.into(),
+                formation,
+                spacing,
+                distance,
+                critters,
+            });
+        }
+        r.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(r)
+    }
+
+    // Ratio:60%, pid:16777224, Item:(0-10)41, Item:4(wielded), Script:501, Dead, If(Rand(50%))
+    fn read_encounter_critter(section: &str, key: &str, v: &str) -> io::Result<EncounterCritter> {
+        let mut ratio = None;
+        let mut pid = None;
+        let mut items = Vec::new();
+        let mut script = None;
+        let mut dead = false;
+        let mut conditions = Vec::new();
+        for part in v.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            if let Some(s) = strip_prefix_ci(part, "ratio:") {
+                ratio = Some(parse(section, key, s.trim().trim_end_matches('%'))?);
+            } else if let Some(s) = strip_prefix_ci(part, "pid:") {
+                pid = Some(parse_pid(section, key, s)?);
+            } else if let Some(s) = strip_prefix_ci(part, "item:") {
+                let mut s = s.trim();
+                let count = if s.starts_with('(') {
+                    let end = s.find(')').ok_or_else(|| err(section, key, part))? + 1;
+                    let count = parse_count(section, key, &s[..end])?;
+                    s = s[end..].trim();
+                    count
+                } else {
+                    RangeInclusive { start: 1, end: 1 }
+                };
+                let wielded = s.len() > 9 && s[s.len() - 9..].eq_ignore_ascii_case("(wielded)");
+                if wielded {
+                    s = s[..s.len() - 9].trim();
+                }
+                items.push(EncounterItem {
+                    count,
+                    pid: parse_pid(section, key, s)?,
+                    wielded,
+                });
+            } else if let Some(s) = strip_prefix_ci(part, "script:") {
+                let v: i32 = parse(section, key, s)?;
+                script = if v > 0 { ProgramId::new(v as u32) } else { None };
+            } else if part.eq_ignore_ascii_case("dead") {
+                dead = true;
+            } else if strip_prefix_ci(part, "if").is_some() {
+                conditions = parse_conditions(section, key, part)?;
+            } else {
+                warn!("ignoring unknown `{}` in `{}` {}", part, section, key);
+            }
+        }
+        Ok(EncounterCritter {
+            ratio,
+            pid: pid.ok_or_else(|| missing(section, &format!("{} pid", key)))?,
+            items,
+            script,
+            dead,
+            conditions,
+        })
+    }
+
+    // Mountain,Fill_W,Uncommon,Common,Rare,Arroyo_Mountain
+    fn read_subtile(section: &str, key: &str, v: &str,
+        terrains: &[Terrain],
+        encounter_tables: &[EncounterTable],
+    ) -> io::Result<Subtile> {
+        let parts: Vec<_> = v.split(',').map(|s| s.trim()).collect();
+        if parts.len() != 6 {
+            return Err(err(section, key, v));
+        }
+        let terrain = terrains.iter()
+            .position(|t| t.name.eq_ignore_ascii_case(parts[0]))
+            .ok_or_else(|| err(section, key, v))?;
+        let fill = parse_enum(section, key, parts[1], Fill::from_name)?;
+        let morning = parse_enum(section, key, parts[2], Frequency::from_name)?;
+        let afternoon = parse_enum(section, key, parts[3], Frequency::from_name)?;
+        let night = parse_enum(section, key, parts[4], Frequency::from_name)?;
+        let frequency = enum_map! {
+            DayPart::Morning => morning,
+            DayPart::Afternoon => afternoon,
+            DayPart::Night => night,
+        };
+        let encounter_table = encounter_tables.iter()
+            .position(|t| t.name.eq_ignore_ascii_case(parts[5]));
+        if encounter_table.is_none() {
+            warn!("unknown encounter table `{}` in `{}` subtile {}", parts[5], section, key);
+        }
+        Ok(Subtile {
+            terrain,
+            fill,
+            frequency,
+            encounter_table,
+        })
+    }
+
+    fn read_areas(rd: &mut impl BufRead) -> io::Result<Vec<Area>> {
+        let ini = crate::asset::read_ini(rd)?;
+        let mut areas = Vec::new();
+        for i in 0.. {
+            let name = format!("Area {:02}", i);
+            let section = if let Some(v) = ini.get(&name) {
+                v
+            } else {
+                break;
+            };
+
+            let pos = get(section, &name, "world_pos")?;
+            let pos = {
+                let mut parts = pos.splitn(2, ',');
+                let x = parse(&name, "world_pos", parts.next().unwrap())?;
+                let y = parse(&name, "world_pos", parts.next().ok_or_else(||
+                    err(&name, "world_pos", pos))?)?;
+                Point::new(x, y)
+            };
+
+            let mut entrances = Vec::new();
+            for i in 0.. {
+                let key = format!("entrance_{}", i);
+                let v = if let Some(v) = section.get(&key) {
+                    v
+                } else {
+                    break;
+                };
+                entrances.push(Self::read_entrance(&name, &key, v)?);
+            }
+
+            areas.push(Area {
+                name: get(section, &name, "area_name")?.into(),
+                pos,
+                known: parse_on_off(&name, "start_state", get(section, &name, "start_state")?)?,
+                locked: section.get("lock_state")
+                    .map(|v| parse_on_off(&name, "lock_state", v))
+                    .transpose()?
+                    .unwrap_or(false),
+                size: parse_enum(&name, "size", get(section, &name, "size")?,
+                    AreaSize::from_name)?,
+                townmap_fid: section.get("townmap_art_idx")
+                    .map(|v| art_fid(&name, "townmap_art_idx", v))
+                    .transpose()?
+                    .flatten(),
+                label_fid: section.get("townmap_label_art_idx")
+                    .map(|v| art_fid(&name, "townmap_label_art_idx", v))
+                    .transpose()?
+                    .flatten(),
+                entrances,
+            });
+        }
+        Ok(areas)
+    }
+
+    // On,333,160,Arroyo Bridge,-1,-1,0
+    fn read_entrance(section: &str, key: &str, v: &str) -> io::Result<Entrance> {
+        let parts: Vec<_> = v.split(',').map(|s| s.trim()).collect();
+        if parts.len() != 7 {
+            return Err(err(section, key, v));
+        }
+        let elevation: i32 = parse(section, key, parts[4])?;
+        let tile_num: i32 = parse(section, key, parts[5])?;
+        let dude_pos = if elevation >= 0 && tile_num >= 0 {
+            Some(EPoint::new(elevation as u32,
+                TileGrid::default().from_linear_inv(tile_num as u32)))
+        } else {
+            None
+        };
+        Ok(Entrance {
+            known: parse_on_off(section, key, parts[0])?,
+            pos: Point::new(parse(section, key, parts[1])?, parse(section, key, parts[2])?),
+            map: parts[3].into(),
+            dude_pos,
+            direction: Direction::from_u32(parse(section, key, parts[6])?)
+                .ok_or_else(|| err(section, key, v))?,
+        })
+    }
+
+    pub fn tile_rows(&self) -> i32 {
+        self.tiles.len() as i32 / self.tile_cols
+    }
+
+    /// World map size in pixels.
+    pub fn size(&self) -> Point {
+        Point::new(self.tile_cols * TILE_WIDTH, self.tile_rows() * TILE_HEIGHT)
+    }
+
+    /// Size of the world map in subtiles.
+    pub fn subtile_size(&self) -> Point {
+        Point::new(self.tile_cols * SUBTILE_COLS, self.tile_rows() * SUBTILE_ROWS)
+    }
+
+    /// Returns subtile at the specified subtile coordinates.
+    pub fn subtile(&self, p: Point) -> Option<&Subtile> {
+        let size = self.subtile_size();
+        if p.x < 0 || p.y < 0 || p.x >= size.x || p.y >= size.y {
+            return None;
+        }
+        let tile = &self.tiles[(p.y / SUBTILE_ROWS * self.tile_cols + p.x / SUBTILE_COLS) as usize];
+        Some(&tile.subtiles[(p.y % SUBTILE_ROWS * SUBTILE_COLS + p.x % SUBTILE_COLS) as usize])
+    }
+
+    /// Finds area that has an entrance to the map with `lookup_name`.
+    pub fn area_by_map(&self, lookup_name: &str) -> Option<usize> {
+        self.areas.iter()
+            .position(|a| a.entrances.iter().any(|e| e.map.eq_ignore_ascii_case(lookup_name)))
+    }
+
+    pub fn encounter_table_by_name(&self, name: &str) -> Option<usize> {
+        self.encounter_tables.iter().position(|t| t.name.eq_ignore_ascii_case(name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::*;
+    use super::*;
+
+    fn subtiles(n: usize) -> String {
+        let mut r = String::new();
+        for y in 0..SUBTILE_ROWS {
+            for x in 0..SUBTILE_COLS {
+                let terrain = if x == 0 { "Mountain" } else { "Desert" };
+                r += &format!("{}_{}={}, Fill_W, Uncommon,Common ,Rare,Table_{}\n",
+                    x, y, terrain, n);
+            }
+        }
+        r
+    }
+
+    #[test]
+    fn read() {
+        let worldmap = format!("
+[Data]
+terrain_types=Desert:1, Mountain:2
+num_horizontal_tiles=2
+Rare=5%  ; percent
+
+[Encounter Table 0]
+lookup_name=Table_0
+maps=Desert Encounter 1, Desert Encounter 2
+enc_00=Chance:30%, Counter:2, Enc:(2-4) ARRO_Geckos AMBUSH Player
+enc_01=chance:5%,Enc:Rats
+enc_02=Chance:10%, Enc:(1-2) Raiders FIGHTING (3) arro_geckos AND Rats, Map:Desert Encounter 3, If((Player(Level) >= 4) And Rand(50%))
+enc_03=Chance:1%, Special, Enc:Raiders, If(Global(12) != 1)
+
+[Encounter: ARRO_Geckos]
+position=wedge, spacing:2, distance:6
+type_00=Ratio:60%, pid:16777224, Item:(0-10)41, Item:4(wielded), Script:501, If(Rand(50%))
+type_01=pid:16777225, Dead
+
+[Encounter: Raiders]
+type_00=pid:16777230
+
+[Tile 0]
+art_idx=339
+encounter_difficulty=-2
+{}
+[Tile 1]
+art_idx=340
+{}",
+            subtiles(0), subtiles(1));
+        let city = "
+[Area 00]
+area_name=Arroyo
+world_pos=173,122
+start_state=On
+size=Large
+townmap_art_idx=156
+townmap_label_art_idx=-1
+entrance_0=On,333,160,Arroyo Bridge,-1,-1,0
+entrance_1=Off,100,90,Arroyo Caves,1,19086,3
+
+[Area 01]
+area_name=Den
+world_pos=400,100
+start_state=Off
+lock_state=On
+size=Small
+";
+        let def = WorldMapDef::read(
+            &mut BufReader::new(Cursor::new(worldmap)),
+            &mut BufReader::new(Cursor::new(city))).unwrap();
+
+        assert_eq!(def.terrains, vec![
+            Terrain { name: "Desert".into(), difficulty: 1 },
+            Terrain { name: "Mountain".into(), difficulty: 2 },
+        ]);
+        assert_eq!(def.frequencies[Frequency::Rare], 5);
+        assert_eq!(def.frequencies[Frequency::Common], 22);
+        assert_eq!(def.size(), Point::new(700, 300));
+        assert_eq!(def.subtile_size(), Point::new(14, 6));
+
+        assert_eq!(def.encounter_tables, vec![EncounterTable {
+            name: "Table_0".into(),
+            maps: vec!["Desert Encounter 1".into(), "Desert Encounter 2".into()],
+            entries: vec![
+                EncounterTableEntry {
+                    chance: 30,
+                    counter: Some(2),
+                    groups: vec![EncounterTableGroup {
+                        count: RangeInclusive { start: 2, end: 4 },
+                        group: 0,
+                        attitude: Attitude::Ambush,
+                    }],
+                    map: None,
+                    special: false,
+                    conditions: vec![],
+                },
+                EncounterTableEntry {
+                    chance: 5,
+                    counter: None,
+                    // Unknown group is skipped.
+                    groups: vec![],
+                    map: None,
+                    special: false,
+                    conditions: vec![],
+                },
+                EncounterTableEntry {
+                    chance: 10,
+                    counter: None,
+                    groups: vec![
+                        EncounterTableGroup {
+                            count: RangeInclusive { start: 1, end: 2 },
+                            group: 1,
+                            attitude: Attitude::Neutral,
+                        },
+                        EncounterTableGroup {
+                            count: RangeInclusive { start: 3, end: 3 },
+                            group: 0,
+                            attitude: Attitude::Fighting,
+                        },
+                    ],
+                    map: Some("Desert Encounter 3".into()),
+                    special: false,
+                    conditions: vec![
+                        Condition::Compare {
+                            value: ConditionValue::PlayerLevel,
+                            op: CompareOp::Ge,
+                            rhs: 4,
+                        },
+                        Condition::Rand(50),
+                    ],
+                },
+                EncounterTableEntry {
+                    chance: 1,
+                    counter: None,
+                    groups: vec![EncounterTableGroup {
+                        count: RangeInclusive { start: 1, end: 1 },
+                        group: 1,
+                        attitude: Attitude::Neutral,
+                    }],
+                    map: None,
+                    special: true,
+                    conditions: vec![Condition::Compare {
+                        value: ConditionValue::Global(12),
+                        op: CompareOp::Ne,
+                        rhs: 1,
+                    }],
+                },
+            ],
+        }]);
+
+        assert_eq!(def.encounter_groups, vec![
+            EncounterGroup {
+                name: "ARRO_Geckos".into(),
+                formation: Formation::Wedge,
+                spacing: 2,
+                distance: Some(6),
+                critters: vec![
+                    EncounterCritter {
+                        ratio: Some(60),
+                        pid: ProtoId::from_packed(16777224).unwrap(),
+                        items: vec![
+                            EncounterItem {
+                                count: RangeInclusive { start: 0, end: 10 },
+                                pid: ProtoId::from_packed(41).unwrap(),
+                                wielded: false,
+                            },
+                            EncounterItem {
+                                count: RangeInclusive { start: 1, end: 1 },
+                                pid: ProtoId::from_packed(4).unwrap(),
+                                wielded: true,
+                            },
+                        ],
+                        script: ProgramId::new(501),
+                        dead: false,
+                        conditions: vec![Condition::Rand(50)],
+                    },
+                    EncounterCritter {
+                        ratio: None,
+                        pid: ProtoId::from_packed(16777225).unwrap(),
+                        items: vec![],
+                        script: None,
+                        dead: true,
+                        conditions: vec![],
+                    },
+                ],
+            },
+            EncounterGroup {
+                name: "Raiders".into(),
+                formation: Formation::Surrounding,
+                spacing: 1,
+                distance: None,
+                critters: vec![EncounterCritter {
+                    ratio: None,
+                    pid: ProtoId::from_packed(16777230).unwrap(),
+                    items: vec![],
+                    script: None,
+                    dead: false,
+                    conditions: vec![],
+                }],
+            },
+        ]);
+        assert_eq!(def.encounter_table_by_name("table_0"), Some(0));
+
+        assert_eq!(def.tiles[0].fid, FrameId::WRLDMP00);
+        assert_eq!(def.tiles[0].encounter_difficulty, -2);
+        assert_eq!(def.tiles[1].encounter_difficulty, 0);
+
+        let st = def.subtile(Point::new(7, 5)).unwrap();
+        assert_eq!(st.terrain, 1);
+        assert_eq!(st.fill, Fill::West);
+        assert_eq!(st.frequency[DayPart::Afternoon], Frequency::Common);
+        assert_eq!(st.frequency[DayPart::Night], Frequency::Rare);
+        assert_eq!(st.encounter_table, None);
+        assert_eq!(def.subtile(Point::new(1, 0)).unwrap().terrain, 0);
+        assert_eq!(def.subtile(Point::new(1, 0)).unwrap().encounter_table, Some(0));
+        assert!(def.subtile(Point::new(14, 0)).is_none());
+
+        assert_eq!(def.areas.len(), 2);
+        let a = &def.areas[0];
+        assert_eq!(a.name, "Arroyo");
+        assert_eq!(a.pos, Point::new(173, 122));
+        assert!(a.known);
+        assert!(!a.locked);
+        assert_eq!(a.size, AreaSize::Large);
+        assert_eq!(a.label_fid, None);
+        assert_eq!(a.entrances, vec![
+            Entrance {
+                known: true,
+                pos: Point::new(333, 160),
+                map: "Arroyo Bridge".into(),
+                dude_pos: None,
+                direction: Direction::NE,
+            },
+            Entrance {
+                known: false,
+                pos: Point::new(100, 90),
+                map: "Arroyo Caves".into(),
+                dude_pos: Some(EPoint::new(1, Point::new(113, 95))),
+                direction: Direction::SW,
+            },
+        ]);
+        assert!(def.areas[1].locked);
+        assert!(def.areas[1].entrances.is_empty());
+        assert_eq!(def.area_by_map("arroyo caves"), Some(0));
+        assert_eq!(def.area_by_map("Klamath"), None);
+    }
+}
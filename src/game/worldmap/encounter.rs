@@ -0,0 +1,211 @@
+use std::cmp;
+
+use crate::graphics::Point;
+use crate::graphics::geometry::hex::{self, Direction};
+use crate::util::EnumExt;
+use crate::util::random::random;
+
+use super::def::*;
+
+/// Max distance from the desired position to look for a free hex when placing critters.
+pub const PLACEMENT_DISTANCE: u32 = 5;
+
+/// Whether all `conditions` are satisfied.
+pub fn check_conditions(conditions: &[Condition], player_level: i32, global_vars: &[i32]) -> bool {
+    conditions.iter().all(|c| match *c {
+        Condition::Rand(chance) => random(1, 100) <= chance,
+        Condition::Compare { value, op, rhs } => {
+            let lhs = match value {
+                ConditionValue::PlayerLevel => player_level,
+                ConditionValue::Global(i) => global_vars.get(i).copied().unwrap_or(0),
+            };
+            op.eval(lhs, rhs)
+        }
+    })
+}
+
+/// Distributes `count` critters of the group between the critter types `critters`.
+/// Each type gets `count * ratio / 100` critters, the rest is evenly spread between types without
+/// ratio (or between all types if all have ratio). Returns indices into `critters`.
+pub fn critter_types(critters: &[&EncounterCritter], count: usize) -> Vec<usize> {
+    if critters.is_empty() {
+        return Vec::new();
+    }
+    let mut r = Vec::with_capacity(count);
+    for (i, c) in critters.iter().enumerate() {
+        if let Some(ratio) = c.ratio {
+            let n = count * cmp::max(ratio, 0) as usize / 100;
+            r.extend((0..n).map(|_| i));
+        }
+    }
+    r.truncate(count);
+    let rest: Vec<_> = (0..critters.len()).filter(|&i| critters[i].ratio.is_none()).collect();
+    let rest = if rest.is_empty() {
+        (0..critters.len()).collect()
+    } else {
+        rest
+    };
+    for i in 0..count - r.len() {
+        r.push(rest[i % rest.len()]);
+    }
+    r
+}
+
+fn rotate(direction: Direction, n: usize) -> Direction {
+    Direction::from_ordinal((direction.ordinal() + n) % Direction::len())
+}
+
+/// Returns positions for `count` critters of the group arranged in `formation`. The group is
+/// placed `distance` hexes away from `center` in `direction`, facing `center`.
+// wmSetupCritterObjs()
+pub fn formation(
+    formation: Formation,
+    center: Point,
+    direction: Direction,
+    distance: u32,
+    spacing: u32,
+    count: usize,
+) -> Vec<Point> {
+    let spacing = cmp::max(spacing, 1);
+    let origin = hex::go(center, direction, distance);
+    let towards = rotate(direction, 3);
+    // Alternates between two directions going further on every second critter.
+    let alternate = |from: Point, dir1: Direction, dir2: Direction, i: usize| {
+        let d = spacing * i.div_ceil(2) as u32;
+        hex::go(from, if i % 2 == 1 { dir1 } else { dir2 }, d)
+    };
+    (0..count)
+        .map(|i| match formation {
+            Formation::Surrounding => {
+                let ring = (i / Direction::len()) as u32;
+                hex::go(center, rotate(direction, i), distance + ring * spacing)
+            }
+            Formation::StraightLine =>
+                alternate(origin, rotate(direction, 2), rotate(direction, 4), i),
+            Formation::DoubleLine => {
+                let row = if i % 2 == 0 { origin } else { hex::go(origin, direction, spacing) };
+                alternate(row, rotate(direction, 2), rotate(direction, 4), i / 2)
+            }
+            Formation::Wedge =>
+                alternate(origin, direction.rotate_cw(), direction.rotate_ccw(), i),
+            Formation::Cone =>
+                alternate(origin, towards.rotate_cw(), towards.rotate_ccw(), i),
+            Formation::Huddle => if i == 0 {
+                origin
+            } else {
+                let ring = ((i - 1) / Direction::len()) as u32;
+                hex::go(origin, rotate(direction, i - 1), spacing * (ring + 1))
+            }
+        })
+        .collect()
+}
+
+/// Finds the hex nearest to `pos` for which `is_free` returns `true`, looking at most
+/// `max_distance` hexes away.
+// critter_attempt_placement()
+pub fn attempt_placement(pos: Point, max_distance: u32, is_free: impl Fn(Point) -> bool)
+    -> Option<Point>
+{
+    if is_free(pos) {
+        return Some(pos);
+    }
+    for distance in 1..=max_distance {
+        for dir in Direction::iter() {
+            let corner = hex::go(pos, dir, distance);
+            for i in 0..distance {
+                let p = hex::go(corner, rotate(dir, 2), i);
+                if is_free(p) {
+                    return Some(p);
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asset::proto::ProtoId;
+
+    fn critter(ratio: Option<i32>) -> EncounterCritter {
+        EncounterCritter {
+            ratio,
+            pid: ProtoId::DUDE,
+            items: Vec::new(),
+            script: None,
+            dead: false,
+            conditions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn critter_types_() {
+        let a = critter(Some(50));
+        let b = critter(None);
+        let c = critter(Some(25));
+        assert_eq!(critter_types(&[&a, &b, &c], 4), vec![0, 0, 2, 1]);
+        assert_eq!(critter_types(&[&a, &b, &c], 1), vec![1]);
+        assert_eq!(critter_types(&[&a, &c], 3), vec![0, 0, 1]);
+        assert_eq!(critter_types(&[&b], 2), vec![0, 0]);
+        assert_eq!(critter_types(&[], 2), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn formation_() {
+        use Direction::*;
+        let center = Point::new(100, 100);
+        let f = |form, count| formation(form, center, E, 4, 1, count);
+
+        let surrounding = f(Formation::Surrounding, 7);
+        assert_eq!(&surrounding[..2], &[hex::go(center, E, 4), hex::go(center, SE, 4)]);
+        assert_eq!(surrounding[6], hex::go(center, E, 5));
+        assert!(surrounding[..6].iter().all(|&p| hex::distance(center, p) == 4));
+
+        let origin = hex::go(center, E, 4);
+        assert_eq!(f(Formation::StraightLine, 3),
+            vec![origin, hex::go(origin, SW, 1), hex::go(origin, NW, 1)]);
+        assert_eq!(f(Formation::Wedge, 3),
+            vec![origin, hex::go(origin, SE, 1), hex::go(origin, NE, 1)]);
+        assert_eq!(f(Formation::Cone, 3),
+            vec![origin, hex::go(origin, NW, 1), hex::go(origin, SW, 1)]);
+        assert_eq!(f(Formation::DoubleLine, 3),
+            vec![origin, hex::go(origin, E, 1), hex::go(origin, SW, 1)]);
+
+        let huddle = f(Formation::Huddle, 8);
+        assert_eq!(huddle[0], origin);
+        assert!(huddle[1..7].iter().all(|&p| hex::distance(origin, p) == 1));
+        assert_eq!(huddle[7], hex::go(origin, E, 2));
+
+        for &form in &[Formation::Surrounding, Formation::StraightLine, Formation::DoubleLine,
+            Formation::Wedge, Formation::Cone, Formation::Huddle]
+        {
+            let ps = f(form, 12);
+            for (i, p) in ps.iter().enumerate() {
+                assert!(!ps[..i].contains(p), "{:?}", form);
+            }
+        }
+    }
+
+    #[test]
+    fn attempt_placement_() {
+        let pos = Point::new(50, 50);
+        assert_eq!(attempt_placement(pos, 3, |_| true), Some(pos));
+        assert_eq!(attempt_placement(pos, 3, |_| false), None);
+        let p = attempt_placement(pos, 3, |p| hex::distance(pos, p) >= 2).unwrap();
+        assert_eq!(hex::distance(pos, p), 2);
+    }
+
+    #[test]
+    fn check_conditions_() {
+        use CompareOp::*;
+        use ConditionValue::*;
+        let cmp = |value, op, rhs| Condition::Compare { value, op, rhs };
+        assert!(check_conditions(&[], 1, &[]));
+        assert!(check_conditions(&[Condition::Rand(100)], 1, &[]));
+        assert!(!check_conditions(&[Condition::Rand(0)], 1, &[]));
+        assert!(check_conditions(&[cmp(PlayerLevel, Gt, 5), cmp(Global(1), Eq, 2)], 6, &[0, 2]));
+        assert!(!check_conditions(&[cmp(PlayerLevel, Gt, 5), cmp(Global(1), Eq, 2)], 5, &[0, 2]));
+        assert!(!check_conditions(&[cmp(Global(1), Ne, 2)], 5, &[0, 2]));
+    }
+}
@@ -6,6 +6,7 @@ use std::ops::MulAssign;
 pub mod color;
 pub mod font;
 pub mod geometry;
+pub mod image;
 pub mod lighting;
 pub mod map;
 pub mod render;
@@ -310,6 +310,10 @@ impl Fonts {
         assert!(existing.is_none());
     }
 
+    pub fn contains(&self, key: FontKey) -> bool {
+        self.fonts.contains_key(&key)
+    }
+
     pub fn get(&self, key: FontKey) -> &Font {
         &self.fonts[&key]
     }
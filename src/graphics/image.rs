@@ -0,0 +1,437 @@
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use std::io::{self, BufWriter, Error, ErrorKind, prelude::*};
+use std::path::PathBuf;
+
+use crate::fs::std::StdFileSystem;
+use crate::graphics::render::Canvas;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ImageFormat {
+    Bmp,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Png => "png",
+        }
+    }
+
+    /// Writes 24-bit RGB image with `rgb` pixels in row-major order.
+    pub fn write(self, wr: &mut impl Write, width: i32, height: i32, rgb: &[u8])
+        -> io::Result<()>
+    {
+        assert_eq!(rgb.len(), (width * height * 3) as usize);
+        match self {
+            ImageFormat::Bmp => write_bmp(wr, width, height, rgb),
+            ImageFormat::Png => write_png(wr, width, height, rgb),
+        }
+    }
+}
+
+fn write_bmp(wr: &mut impl Write, width: i32, height: i32, rgb: &[u8]) -> io::Result<()> {
+    const HEADER_LEN: u32 = 14 + 40;
+
+    let stride = (width as u32 * 3 + 3) & !3;
+    let image_len = stride * height as u32;
+
+    wr.write_all(b"BM")?;
+    wr.write_u32::<LittleEndian>(HEADER_LEN + image_len)?;
+    wr.write_u32::<LittleEndian>(0)?;
+    wr.write_u32::<LittleEndian>(HEADER_LEN)?;
+
+    wr.write_u32::<LittleEndian>(40)?;
+    wr.write_i32::<LittleEndian>(width)?;
+    wr.write_i32::<LittleEndian>(height)?;
+    wr.write_u16::<LittleEndian>(1)?;
+    wr.write_u16::<LittleEndian>(24)?;
+    wr.write_u32::<LittleEndian>(0)?;
+    wr.write_u32::<LittleEndian>(image_len)?;
+    wr.write_i32::<LittleEndian>(2835)?;
+    wr.write_i32::<LittleEndian>(2835)?;
+    wr.write_u32::<LittleEndian>(0)?;
+    wr.write_u32::<LittleEndian>(0)?;
+
+    // Rows are stored bottom-up in BGR order.
+    let mut row = vec![0; stride as usize];
+    for src in rgb.chunks(width as usize * 3).rev() {
+        for (dst, src) in row.chunks_exact_mut(3).zip(src.chunks_exact(3)) {
+            dst[0] = src[2];
+            dst[1] = src[1];
+            dst[2] = src[0];
+        }
+        wr.write_all(&row)?;
+    }
+    Ok(())
+}
+
+fn write_png(wr: &mut impl Write, width: i32, height: i32, rgb: &[u8]) -> io::Result<()> {
+    write_png_header(wr, width, height, COLOR_TYPE_RGB)?;
+    write_png_data(wr, rgb, width as usize * 3)
+}
+
+/// Writes 8-bit palette-indexed PNG. `palette` contains RGB triples. Colors with indices listed in
+/// `transparent` are fully transparent. `text` is written as `tEXt` chunks.
+pub fn write_indexed_png(wr: &mut impl Write, width: i32, height: i32, pixels: &[u8],
+    palette: &[u8], transparent: &[u8], text: &[(&str, &str)]) -> io::Result<()>
+{
+    assert_eq!(pixels.len(), (width * height) as usize);
+    assert!(palette.len().is_multiple_of(3) && palette.len() <= 256 * 3);
+
+    write_png_header(wr, width, height, COLOR_TYPE_INDEXED)?;
+    write_png_chunk(wr, b"PLTE", palette)?;
+    if !transparent.is_empty() {
+        let mut trns = vec![0xff; transparent.iter().max().map(|&v| v as usize + 1).unwrap_or(0)];
+        for &i in transparent {
+            trns[i as usize] = 0;
+        }
+        write_png_chunk(wr, b"tRNS", &trns)?;
+    }
+    for &(key, value) in text {
+        let mut data = Vec::with_capacity(key.len() + 1 + value.len());
+        data.extend_from_slice(key.as_bytes());
+        data.push(0);
+        data.extend_from_slice(value.as_bytes());
+        write_png_chunk(wr, b"tEXt", &data)?;
+    }
+    write_png_data(wr, pixels, width as usize)
+}
+
+const COLOR_TYPE_GRAY: u8 = 0;
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_INDEXED: u8 = 3;
+const COLOR_TYPE_GRAY_ALPHA: u8 = 4;
+const COLOR_TYPE_RGBA: u8 = 6;
+
+fn write_png_chunk(wr: &mut impl Write, kind: &[u8], data: &[u8]) -> io::Result<()> {
+    let mut crc = flate2::Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    wr.write_u32::<BigEndian>(data.len() as u32)?;
+    wr.write_all(kind)?;
+    wr.write_all(data)?;
+    wr.write_u32::<BigEndian>(crc.sum())
+}
+
+fn write_png_header(wr: &mut impl Write, width: i32, height: i32, color_type: u8)
+    -> io::Result<()>
+{
+    wr.write_all(PNG_SIGNATURE)?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.write_u32::<BigEndian>(width as u32)?;
+    ihdr.write_u32::<BigEndian>(height as u32)?;
+    // Bit depth, color type, compression, filter and interlace methods.
+    ihdr.extend_from_slice(&[8, color_type, 0, 0, 0]);
+    write_png_chunk(wr, b"IHDR", &ihdr)
+}
+
+fn write_png_data(wr: &mut impl Write, data: &[u8], stride: usize) -> io::Result<()> {
+    let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in data.chunks(stride) {
+        // No filtering.
+        enc.write_all(&[0])?;
+        enc.write_all(row)?;
+    }
+    write_png_chunk(wr, b"IDAT", &enc.finish()?)?;
+
+    write_png_chunk(wr, b"IEND", &[])
+}
+
+/// Image decoded from PNG file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PngImage {
+    pub width: i32,
+    pub height: i32,
+    /// 8-bit RGBA pixels in row-major order.
+    pub rgba: Box<[u8]>,
+    /// Original palette indices if the image is palette-indexed.
+    pub indices: Option<Box<[u8]>>,
+    /// Contents of the `tEXt` chunks.
+    pub text: Vec<(String, String)>,
+}
+
+/// Reads non-interlaced PNG image with 8-bit samples.
+pub fn read_png(rd: &mut impl Read) -> io::Result<PngImage> {
+    fn bad(msg: impl Into<String>) -> Error {
+        Error::new(ErrorKind::InvalidData, msg.into())
+    }
+
+    let mut signature = [0; 8];
+    rd.read_exact(&mut signature)?;
+    if signature != PNG_SIGNATURE {
+        return Err(bad("not a PNG file"));
+    }
+
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut trns = Vec::new();
+    let mut idat = Vec::new();
+    let mut text = Vec::new();
+    loop {
+        let len = rd.read_u32::<BigEndian>()? as usize;
+        let mut kind = [0; 4];
+        rd.read_exact(&mut kind)?;
+        let mut data = Vec::new();
+        rd.take(len as u64).read_to_end(&mut data)?;
+        if data.len() != len {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated PNG chunk"));
+        }
+        let mut crc = flate2::Crc::new();
+        crc.update(&kind);
+        crc.update(&data);
+        if rd.read_u32::<BigEndian>()? != crc.sum() {
+            return Err(bad(format!("CRC mismatch in {} chunk", String::from_utf8_lossy(&kind))));
+        }
+
+        match &kind {
+            b"IHDR" => {
+                if data.len() != 13 {
+                    return Err(bad("invalid IHDR chunk"));
+                }
+                let rd = &mut &data[..];
+                let width = rd.read_u32::<BigEndian>()?;
+                let height = rd.read_u32::<BigEndian>()?;
+                let [bit_depth, color_type, compression, filter, interlace] =
+                    [data[8], data[9], data[10], data[11], data[12]];
+                if width == 0 || height == 0 || width > i16::MAX as u32 || height > i16::MAX as u32 {
+                    return Err(bad(format!("unsupported PNG size: {}x{}", width, height)));
+                }
+                if bit_depth != 8 {
+                    return Err(bad(format!("unsupported PNG bit depth: {}", bit_depth)));
+                }
+                if compression != 0 || filter != 0 || interlace != 0 {
+                    return Err(bad("unsupported PNG compression, filter or interlace method"));
+                }
+                header = Some((width as i32, height as i32, color_type));
+            }
+            b"PLTE" => palette = data,
+            b"tRNS" => trns = data,
+            b"IDAT" => idat.extend_from_slice(&data),
+            b"tEXt" => {
+                if let Some(i) = data.iter().position(|&c| c == 0) {
+                    text.push((String::from_utf8_lossy(&data[..i]).into_owned(),
+                        String::from_utf8_lossy(&data[i + 1..]).into_owned()));
+                }
+            }
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+
+    let (width, height, color_type) = header.ok_or_else(|| bad("missing IHDR chunk"))?;
+    let channels = match color_type {
+        COLOR_TYPE_GRAY | COLOR_TYPE_INDEXED => 1,
+        COLOR_TYPE_GRAY_ALPHA => 2,
+        COLOR_TYPE_RGB => 3,
+        COLOR_TYPE_RGBA => 4,
+        _ => return Err(bad(format!("unsupported PNG color type: {}", color_type))),
+    };
+
+    let stride = width as usize * channels;
+    let mut data = Vec::with_capacity((stride + 1) * height as usize);
+    flate2::read::ZlibDecoder::new(&idat[..]).read_to_end(&mut data)?;
+    if data.len() < (stride + 1) * height as usize {
+        return Err(bad("truncated PNG image data"));
+    }
+
+    let mut samples = vec![0; stride * height as usize];
+    for y in 0..height as usize {
+        let filter = data[y * (stride + 1)];
+        let src = &data[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (prev, cur) = samples.split_at_mut(y * stride);
+        let prev = if y > 0 { &prev[(y - 1) * stride..] } else { &[][..] };
+        let cur = &mut cur[..stride];
+        for x in 0..stride {
+            let a = if x >= channels { cur[x - channels] } else { 0 };
+            let b = prev.get(x).cloned().unwrap_or(0);
+            let c = if x >= channels { prev.get(x - channels).cloned().unwrap_or(0) } else { 0 };
+            let pred = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u32 + b as u32) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(bad(format!("invalid PNG filter: {}", filter))),
+            };
+            cur[x] = src[x].wrapping_add(pred);
+        }
+    }
+
+    let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+    for px in samples.chunks_exact(channels) {
+        let c = match color_type {
+            COLOR_TYPE_GRAY => [px[0], px[0], px[0], 0xff],
+            COLOR_TYPE_GRAY_ALPHA => [px[0], px[0], px[0], px[1]],
+            COLOR_TYPE_RGB => [px[0], px[1], px[2], 0xff],
+            COLOR_TYPE_RGBA => [px[0], px[1], px[2], px[3]],
+            _ => {
+                let i = px[0] as usize;
+                let rgb = palette.get(i * 3..i * 3 + 3)
+                    .ok_or_else(|| bad(format!("PNG palette index out of range: {}", i)))?;
+                [rgb[0], rgb[1], rgb[2], trns.get(i).cloned().unwrap_or(0xff)]
+            }
+        };
+        rgba.extend_from_slice(&c);
+    }
+
+    let indices = if color_type == COLOR_TYPE_INDEXED {
+        Some(samples.into())
+    } else {
+        None
+    };
+
+    Ok(PngImage {
+        width,
+        height,
+        rgba: rgba.into(),
+        indices,
+        text,
+    })
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i32 + b as i32 - c as i32;
+    let pa = (p - a as i32).abs();
+    let pb = (p - b as i32).abs();
+    let pc = (p - c as i32).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// dump_screen()
+/// Saves the back buffer of the `canvas` into the first free `scrNNNNN.<ext>` file in the root
+/// of `fs`. Returns host path of the written file.
+pub fn save_screenshot(canvas: &dyn Canvas, fs: &StdFileSystem, format: ImageFormat)
+    -> io::Result<PathBuf>
+{
+    let name = (0..100000)
+        .map(|i| format!("scr{:05}.{}", i, format.extension()))
+        .find(|p| !fs.exists(p))
+        .ok_or_else(|| Error::new(ErrorKind::AlreadyExists, "too many screenshots"))?;
+    let (width, height) = canvas.size();
+    let mut wr = BufWriter::new(fs.create(&name)?);
+    format.write(&mut wr, width, height, &canvas.back_buffer_rgb())?;
+    wr.flush()?;
+    Ok(fs.resolve(&name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+
+    const RGB: [u8; 12] = [
+        1, 2, 3,    4, 5, 6,
+        7, 8, 9,    10, 11, 12,
+    ];
+
+    #[test]
+    fn bmp() {
+        let mut buf = Vec::new();
+        ImageFormat::Bmp.write(&mut buf, 2, 2, &RGB).unwrap();
+        assert_eq!(buf.len(), 54 + 16);
+        assert_eq!(&buf[..2], b"BM");
+        assert_eq!((&buf[2..]).read_u32::<LittleEndian>().unwrap(), 70);
+        assert_eq!((&buf[18..]).read_i32::<LittleEndian>().unwrap(), 2);
+        assert_eq!((&buf[22..]).read_i32::<LittleEndian>().unwrap(), 2);
+        assert_eq!((&buf[28..]).read_u16::<LittleEndian>().unwrap(), 24);
+        assert_eq!(&buf[54..], &[
+            9, 8, 7,    12, 11, 10,     0, 0,
+            3, 2, 1,    6, 5, 4,        0, 0,
+        ]);
+    }
+
+    #[test]
+    fn png() {
+        let mut buf = Vec::new();
+        ImageFormat::Png.write(&mut buf, 2, 2, &RGB).unwrap();
+        assert_eq!(&buf[..8], PNG_SIGNATURE);
+
+        let rd = &mut &buf[8..];
+        let mut chunks = Vec::new();
+        while !rd.is_empty() {
+            let len = rd.read_u32::<BigEndian>().unwrap() as usize;
+            let kind = rd[..4].to_vec();
+            let data = rd[4..4 + len].to_vec();
+            let crc = (&rd[4 + len..]).read_u32::<BigEndian>().unwrap();
+            let mut exp_crc = flate2::Crc::new();
+            exp_crc.update(&rd[..4 + len]);
+            assert_eq!(crc, exp_crc.sum());
+            *rd = &rd[8 + len..];
+            chunks.push((kind, data));
+        }
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(&chunks[0].0, b"IHDR");
+        assert_eq!(&chunks[0].1, &[0, 0, 0, 2, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        assert_eq!(&chunks[1].0, b"IDAT");
+        let mut data = Vec::new();
+        ZlibDecoder::new(&chunks[1].1[..]).read_to_end(&mut data).unwrap();
+        assert_eq!(data, &[0, 1, 2, 3, 4, 5, 6, 0, 7, 8, 9, 10, 11, 12]);
+        assert_eq!(&chunks[2].0, b"IEND");
+        assert_eq!(&buf[buf.len() - 4..], &[0xae, 0x42, 0x60, 0x82]);
+    }
+
+    #[test]
+    fn read_png_() {
+        let mut buf = Vec::new();
+        ImageFormat::Png.write(&mut buf, 2, 2, &RGB).unwrap();
+        let image = read_png(&mut &buf[..]).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(&image.rgba[..], &[
+            1, 2, 3, 255,   4, 5, 6, 255,
+            7, 8, 9, 255,   10, 11, 12, 255,
+        ]);
+        assert!(image.indices.is_none());
+
+        let mut buf = Vec::new();
+        write_indexed_png(&mut buf, 3, 1, &[0, 2, 1], &[1, 2, 3, 4, 5, 6, 7, 8, 9], &[0],
+            &[("key", "a=1\nb=2")]).unwrap();
+        let image = read_png(&mut &buf[..]).unwrap();
+        assert_eq!(&image.rgba[..], &[1, 2, 3, 0,   7, 8, 9, 255,   4, 5, 6, 255]);
+        assert_eq!(image.indices.as_deref(), Some(&[0, 2, 1][..]));
+        assert_eq!(image.text, vec![("key".to_string(), "a=1\nb=2".to_string())]);
+    }
+
+    #[test]
+    fn read_png_filters() {
+        let filtered: &[u8] = &[
+            1, 10, 20, 30,      30, 30, 30,
+            2, 5, 5, 5,         10, 20, 30,
+            4, 5, 5, 5,         10, 10, 10,
+            3, 246, 241, 236,   225, 215, 205,
+        ];
+        let mut buf = Vec::new();
+        write_png_header(&mut buf, 2, 4, COLOR_TYPE_RGB).unwrap();
+        let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(filtered).unwrap();
+        write_png_chunk(&mut buf, b"IDAT", &enc.finish().unwrap()).unwrap();
+        write_png_chunk(&mut buf, b"IEND", &[]).unwrap();
+
+        let image = read_png(&mut &buf[..]).unwrap();
+        let rgb: Vec<_> = image.rgba.chunks(4).flat_map(|c| c[..3].to_vec()).collect();
+        assert_eq!(rgb, &[
+            10, 20, 30,     40, 50, 60,
+            15, 25, 35,     50, 70, 90,
+            20, 30, 40,     60, 80, 100,
+            0, 0, 0,        255, 255, 255,
+        ]);
+
+        // Corrupt IDAT CRC.
+        let i = buf.len() - 13;
+        buf[i] ^= 1;
+        assert_eq!(read_png(&mut &buf[..]).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -68,11 +68,23 @@ pub trait Canvas {
 
     fn fonts(&self) -> &Rc<Fonts>;
 
+    /// Returns width and height of the back buffer.
+    fn size(&self) -> (i32, i32);
+
+    /// Returns color indices of the back buffer in row-major order.
+    fn back_buffer(&self) -> &[u8];
+
+    /// Returns the back buffer converted to 24-bit RGB with palette overlay applied.
+    fn back_buffer_rgb(&self) -> Box<[u8]>;
+
     fn set_clip_rect(&mut self, rect: Rect);
     fn reset_clip_rect(&mut self);
 
     fn clear(&mut self, color: Rgb15);
 
+    /// Fills `rect` with `color`. Respects the clip rect.
+    fn fill(&mut self, rect: Rect, color: Rgb15);
+
     fn draw(&mut self, tex: &TextureHandle, pos: Point, light: u32);
     fn draw_multi_light(&mut self, tex: &TextureHandle, pos: Point, lights: &[u32]);
 
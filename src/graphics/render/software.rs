@@ -13,8 +13,13 @@ use crate::graphics::font::{self, FontKey, Fonts};
 use crate::graphics::lighting::light_map::{self, LightMap};
 use crate::graphics::{Point, Rect};
 
+enum Target {
+    Window(WindowCanvas),
+    Memory { width: i32, height: i32 },
+}
+
 pub struct Backend {
-    canvas: WindowCanvas,
+    target: Target,
     palette: Box<Palette>,
     palette_overlay: PaletteOverlay,
     textures: Textures,
@@ -24,7 +29,21 @@ impl Backend {
     pub fn new(canvas: WindowCanvas, palette: Box<Palette>,
             palette_overlay: PaletteOverlay) -> Self {
         Self {
-            canvas,
+            target: Target::Window(canvas),
+            palette,
+            palette_overlay,
+            textures: Textures::new(),
+        }
+    }
+
+    /// Creates backend that renders into in-memory back buffer of the specified size without
+    /// presenting it anywhere. The result can be read with `Canvas::back_buffer()` and
+    /// `Canvas::back_buffer_rgb()`.
+    pub fn new_headless(width: i32, height: i32, palette: Box<Palette>,
+            palette_overlay: PaletteOverlay) -> Self {
+        assert!(width > 0 && height > 0);
+        Self {
+            target: Target::Memory { width, height },
             palette,
             palette_overlay,
             textures: Textures::new(),
@@ -85,6 +104,8 @@ impl TexturesInner {
     }
 
     fn new_texture(&mut self, width: i32, height: i32, data: Box<[u8]>) -> TextureHandle {
+        // Headless backend may never present so free the dropped textures here as well.
+        self.cleanup();
         let key = self.handles.insert(());
         self.textures.insert(key, Texture::new(width, height, data));
         TextureHandle(Rc::new(TextureHandleInner {
@@ -126,38 +147,65 @@ impl Textures {
     }
 }
 
-struct CanvasImpl {
+struct Window {
     canvas: WindowCanvas,
+    texture: SdlTexture,
+}
+
+struct CanvasImpl {
+    window: Option<Window>,
     palette: Box<Palette>,
     palette_overlay: PaletteOverlay,
     textures: Textures,
     light_map: LightMap,
     back_buf: Texture,
-    canvas_texture: SdlTexture,
     clip_rect: Rect,
     fonts: Rc<Fonts>,
 }
 
 impl CanvasImpl {
     fn new(backend: Backend, fonts: Rc<Fonts>) -> Self {
-        let (w, h) = backend.canvas.window().size();
-        let canvas_texture = backend.canvas
-            .texture_creator()
-            .create_texture_streaming(PixelFormatEnum::RGB24, w, h)
-            .unwrap();
+        let (window, w, h) = match backend.target {
+            Target::Window(canvas) => {
+                let (w, h) = canvas.window().size();
+                let texture = canvas
+                    .texture_creator()
+                    .create_texture_streaming(PixelFormatEnum::RGB24, w, h)
+                    .unwrap();
+                (Some(Window { canvas, texture }), w as i32, h as i32)
+            }
+            Target::Memory { width, height } => (None, width, height),
+        };
         Self {
-            canvas: backend.canvas,
+            window,
             palette: backend.palette,
             palette_overlay: backend.palette_overlay,
             textures: backend.textures,
             light_map: LightMap::new(),
-            back_buf: Texture::new_empty(w as i32, h as i32, 0),
-            canvas_texture,
-            clip_rect: Rect::with_size(0, 0, w as i32, h as i32),
+            back_buf: Texture::new_empty(w, h, 0),
+            clip_rect: Rect::with_size(0, 0, w, h),
             fonts,
         }
     }
 
+    /// Writes back buffer as 24-bit RGB into `dst` with rows `stride` bytes apart.
+    fn write_rgb(&self, dst: &mut [u8], stride: usize) {
+        let pal = &self.palette;
+        let pal_overlay = &self.palette_overlay;
+        let src = &self.back_buf.data;
+        let src_width = self.back_buf.width;
+        for (src_row, dst_row) in src.chunks(src_width as usize).zip(dst.chunks_mut(stride)) {
+            for (&src_pixel, dst_pixel) in src_row.iter().zip(dst_row.chunks_mut(3)) {
+                let rgb = pal_overlay.get(src_pixel)
+                    .unwrap_or_else(|| pal.rgb18(src_pixel))
+                    .scale::<Color8>();
+                dst_pixel[0] = rgb.r();
+                dst_pixel[1] = rgb.g();
+                dst_pixel[2] = rgb.b();
+            }
+        }
+    }
+
     fn make_translucent(src: u8, dst: u8, trans_color_idx: u8, palette: &Palette,
             grayscale_func: impl Fn(Rgb15) -> u8) -> u8 {
         let alpha = grayscale_func(palette.rgb15(src)) / 4;
@@ -225,24 +273,26 @@ impl Canvas for CanvasImpl {
     }
 
     fn present(&mut self) {
-        let pal = &self.palette;
-        let pal_overlay = &self.palette_overlay;
-        let src = &self.back_buf.data;
-        let src_width = self.back_buf.width;
-        self.canvas_texture.with_lock(None, |dst, stride| {
-            for (src_row, dst_row) in src.chunks(src_width as usize).zip(dst.chunks_mut(stride)) {
-                for (&src_pixel, dst_pixel) in src_row.iter().zip(dst_row.chunks_mut(3)) {
-                    let rgb = pal_overlay.get(src_pixel)
-                        .unwrap_or_else(|| pal.rgb18(src_pixel))
-                        .scale::<Color8>();
-                    dst_pixel[0] = rgb.r();
-                    dst_pixel[1] = rgb.g();
-                    dst_pixel[2] = rgb.b();
-                }
-            }
-        }).unwrap();
-        self.canvas.copy(&self.canvas_texture, None, None).unwrap();
-        self.canvas.present();
+        if let Some(mut window) = self.window.take() {
+            window.texture.with_lock(None, |dst, stride| self.write_rgb(dst, stride)).unwrap();
+            window.canvas.copy(&window.texture, None, None).unwrap();
+            window.canvas.present();
+            self.window = Some(window);
+        }
+    }
+
+    fn size(&self) -> (i32, i32) {
+        (self.back_buf.width, self.back_buf.height)
+    }
+
+    fn back_buffer(&self) -> &[u8] {
+        &self.back_buf.data
+    }
+
+    fn back_buffer_rgb(&self) -> Box<[u8]> {
+        let mut r = vec![0; self.back_buf.len() * 3];
+        self.write_rgb(&mut r, self.back_buf.width as usize * 3);
+        r.into()
     }
 
     fn update(&mut self, time: Instant) {
@@ -259,8 +309,7 @@ impl Canvas for CanvasImpl {
     }
 
     fn reset_clip_rect(&mut self) {
-        let (w, h) = self.canvas.window().size();
-        self.clip_rect = Rect::with_size(0, 0, w as i32, h as i32);
+        self.clip_rect = Rect::with_size(0, 0, self.back_buf.width, self.back_buf.height);
     }
 
     fn clear(&mut self, color: Rgb15) {
@@ -270,6 +319,20 @@ impl Canvas for CanvasImpl {
         }
     }
 
+    fn fill(&mut self, rect: Rect, color: Rgb15) {
+        let v = self.palette.color_idx(color);
+        let rect = rect.intersect(self.clip_rect);
+        if rect.is_empty() {
+            return;
+        }
+        for y in rect.top..rect.bottom {
+            let start = (y * self.back_buf.width) as usize;
+            for b in &mut self.back_buf.data[start + rect.left as usize..start + rect.right as usize] {
+                *b = v;
+            }
+        }
+    }
+
     fn draw(&mut self, tex: &TextureHandle, pos: Point, light: u32) {
         let pal = &self.palette;
         let tex = self.textures.get(tex);
@@ -533,4 +596,86 @@ impl Canvas for CanvasImpl {
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graphics::color::{BLACK, Rgb, WHITE};
+    use crate::util::test::ungz;
+
+    fn palette() -> Palette {
+        let data = ungz(include_bytes!("../color/color.pal.gz"));
+        crate::asset::palette::read_palette(&mut std::io::Cursor::new(&data[..])).unwrap()
+    }
+
+    fn canvas(width: i32, height: i32) -> (Box<dyn Canvas>, TextureFactory) {
+        let backend = Backend::new_headless(width, height, Box::new(palette()),
+            PaletteOverlay::standard());
+        let textures = backend.new_texture_factory();
+        (backend.into_canvas(Rc::new(Fonts::new())), textures)
+    }
+
+    #[test]
+    fn draw_and_clip() {
+        let pal = palette();
+        let (mut c, textures) = canvas(4, 3);
+        assert_eq!(c.size(), (4, 3));
+        c.clear(BLACK);
+        let black = pal.color_idx(BLACK);
+        let tex = textures.new_texture(2, 2, vec![0, 10, 11, 12].into());
+
+        c.draw(&tex, Point::new(-1, 1), 0x10000);
+        c.set_clip_rect(Rect::with_size(2, 0, 1, 3));
+        c.draw(&tex, Point::new(2, 0), 0x10000);
+        c.reset_clip_rect();
+        c.draw(&tex, Point::new(3, 2), 0);
+
+        let (c10, c11, c12) = (pal.darken(10, 128), pal.darken(11, 128), pal.darken(12, 128));
+        assert_eq!(c.back_buffer(), &[
+            black, black, black, black,
+            c10, black, c11, black,
+            c12, black, black, black,
+        ]);
+    }
+
+    #[test]
+    fn fill() {
+        let pal = palette();
+        let (mut c, _) = canvas(4, 3);
+        c.clear(BLACK);
+        c.set_clip_rect(Rect::with_size(0, 0, 3, 3));
+        c.fill(Rect::with_size(1, 1, 5, 1), WHITE);
+        let black = pal.color_idx(BLACK);
+        let white = pal.color_idx(WHITE);
+        assert_eq!(c.back_buffer(), &[
+            black, black, black, black,
+            black, white, white, black,
+            black, black, black, black,
+        ]);
+    }
+
+    #[test]
+    fn draw_scaled() {
+        let (mut c, textures) = canvas(5, 2);
+        c.clear(BLACK);
+        let black = palette().color_idx(BLACK);
+        let tex = textures.new_texture(2, 1, vec![5, 6].into());
+        c.draw_scaled(&tex, Rect::with_size(1, 0, 4, 2));
+        assert_eq!(c.back_buffer(), &[
+            black, 5, 5, 6, 6,
+            black, 5, 5, 6, 6,
+        ]);
+    }
+
+    #[test]
+    fn back_buffer_rgb() {
+        let pal = palette();
+        let (mut c, textures) = canvas(2, 1);
+        let tex = textures.new_texture(2, 1, vec![10, 229].into());
+        c.draw_scaled(&tex, Rect::with_size(0, 0, 2, 1));
+        let rgb = c.back_buffer_rgb();
+        let c0 = pal.rgb18(10).scale::<Color8>();
+        let c1: Rgb<Color8> = PaletteOverlay::standard().get(229).unwrap().scale();
+        assert_eq!(&rgb[..], &[c0.r(), c0.g(), c0.b(), c1.r(), c1.g(), c1.b()]);
+    }
+}
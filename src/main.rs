@@ -11,10 +11,12 @@
 #[macro_use] mod macros;
 
 mod asset;
+mod check;
 mod fs;
 mod game;
 mod graphics;
 mod sequence;
+mod sound;
 mod state;
 mod ui;
 mod util;
@@ -23,7 +25,8 @@ mod vm;
 use log::*;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use std::path::{Path, PathBuf};
+use std::cell::{Cell, RefCell};
+use std::path::Path;
 use std::rc::Rc;
 use std::time::{Instant, Duration};
 
@@ -33,6 +36,7 @@ use crate::asset::frame::{FrameDb, FrameId};
 use crate::asset::message::Messages;
 use crate::asset::palette::read_palette;
 use crate::asset::proto::ProtoDb;
+use crate::fs::std::StdFileSystem;
 use crate::game::state::GameState;
 use crate::game::ui::world::WorldView;
 use crate::graphics::{EPoint, Point};
@@ -41,6 +45,8 @@ use crate::graphics::color::palette::overlay::PaletteOverlay;
 use crate::graphics::font::{self, FontKey};
 use crate::graphics::geometry::TileGridView;
 use crate::graphics::geometry::sqr;
+use crate::graphics::image::{self, ImageFormat};
+use crate::graphics::render::Canvas;
 use crate::graphics::render::software::Backend;
 use crate::state::{AppState, Update, HandleAppEvent};
 use crate::ui::Ui;
@@ -68,57 +74,341 @@ fn args() -> clap::App<'static, 'static> {
 
     App::new(format!("Vault 13 {} ({})", VERSION, GIT_DATE))
         .arg(Arg::with_name("RESOURCE_DIR")
-            .help("One or more resource directories where master.dat, critter.dat and patchXXX.dat \
-                   can be found")
+            .help("Resource directory where fallout2.cfg, master.dat, critter.dat and \
+                   patchXXX.dat can be found")
             .required_unless("version"))
         .arg(Arg::with_name("MAP")
             .help("Map name to load. For example: artemple")
-            .required_unless("version"))
+            .required_unless_one(&["version", "load", "movie-hashes"]))
+        .arg(Arg::with_name("load")
+            .long("load")
+            .value_name("SLOT")
+            .help("Loads saved game from the specified slot (1-10) instead of starting new game")
+            .takes_value(true))
+        .arg(Arg::with_name("encounter")
+            .long("encounter")
+            .value_name("TABLE[:ENTRY]")
+            .help("Starts random encounter from the specified encounter table of worldmap.txt. \
+                   ENTRY is the entry index or key, for example: enc_03")
+            .takes_value(true))
+        .arg(Arg::with_name("movie-hashes")
+            .long("movie-hashes")
+            .value_name("MOVIE")
+            .help("Decodes the movie without playing and prints hash of every frame. \
+                   MOVIE is the movie name or path, for example: intro")
+            .takes_value(true))
+        .arg(Arg::with_name("mods")
+            .long("mods")
+            .value_name("FILE")
+            .help("Mods list in ddraw.ini format: directories and DAT files listed as \
+                   PatchFileN=path entries of the [ExtraPatches] section, PatchFile0 has the highest \
+                   priority. By default ddraw.ini from the resources dir is used if present")
+            .takes_value(true))
+        .arg(Arg::with_name("log-fs")
+            .long("log-fs")
+            .help("Logs the resource directory or DAT file that served each requested file"))
+        .arg(Arg::with_name("screenshot-format")
+            .long("screenshot-format")
+            .value_name("FORMAT")
+            .help("Image format of screenshots taken with F12")
+            .possible_values(&["bmp", "png"])
+            .default_value("bmp"))
+        .arg(Arg::with_name("debug-scripts")
+            .long("debug-scripts")
+            .help("Enables script debugger. Commands are read from stdin, type `help` for the list. \
+                   F9 toggles the debugger overlay, F10 stops at the next script instruction"))
+        .arg(Arg::with_name("break")
+            .long("break")
+            .value_name("BREAKPOINT")
+            .help("Adds script debugger breakpoint and enables the debugger. BREAKPOINT is \
+                   PROC, PROGRAM:PROC or PROGRAM@OFFSET, for example: artemple:talk_p_proc")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("profile-scripts")
+            .long("profile-scripts")
+            .value_name("FILE")
+            .help("Profiles scripts and writes report with instruction counts and timings per script, \
+                   procedure and opcode, and hits of unimplemented opcodes to FILE on exit or F11")
+            .takes_value(true))
+        .arg(Arg::with_name("script-failures")
+            .long("script-failures")
+            .value_name("POLICY")
+            .help("What to do when a script instruction is unimplemented or fails with a bad value: \
+                   `strict` fails the script, `lenient` skips the instruction using 0 as its result")
+            .possible_values(&["strict", "lenient"])
+            .default_value("strict"))
+        .arg(Arg::with_name("script-failure-override")
+            .long("script-failure-override")
+            .value_name("OPCODE=POLICY")
+            .help("Overrides --script-failures for the opcode, for example: anim_busy=lenient")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
         .arg(Arg::with_name("version")
             .short("v")
             .long("version")
             .help("Prints version information"))
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(SubCommand::with_name("check")
+            .about("Loads all protos, frame sets, maps, scripts and messages and reports errors")
+            .arg(Arg::with_name("RESOURCE_DIR").required(true))
+            .arg(Arg::with_name("mods")
+                .long("mods")
+                .value_name("FILE")
+                .help("Mods list in ddraw.ini format")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("dat")
+            .about("Works with DAT2 archives")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("pack")
+                .about("Packs all files in the directory into a new DAT file")
+                .arg(Arg::with_name("DIR").required(true))
+                .arg(Arg::with_name("DAT").required(true)))
+            .subcommand(SubCommand::with_name("unpack")
+                .about("Extracts all files of the DAT file into the directory")
+                .arg(Arg::with_name("DAT").required(true))
+                .arg(Arg::with_name("DIR").required(true)))
+            .subcommand(SubCommand::with_name("list")
+                .about("Lists files in the DAT file")
+                .arg(Arg::with_name("DAT").required(true))))
+        .subcommand(SubCommand::with_name("frm")
+            .about("Converts FRM and FR0-FR5 files to PNG sprite sheets and back")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("export")
+                .about("Writes all directions and frames of the FRM file into PNG sprite sheet. \
+                    Any of the FR0-FR5 files exports all six of them")
+                .arg(Arg::with_name("FRM").required(true))
+                .arg(Arg::with_name("PNG").required(true))
+                .arg(palette_arg()))
+            .subcommand(SubCommand::with_name("import")
+                .about("Creates FRM file from PNG sprite sheet quantizing colors to the palette. \
+                    Any of the FR0-FR5 file names writes all six of them")
+                .arg(Arg::with_name("PNG").required(true))
+                .arg(Arg::with_name("FRM").required(true))
+                .arg(palette_arg())))
+        .subcommand(SubCommand::with_name("script")
+            .about("Inspects compiled .int scripts")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("disasm")
+                .about("Prints disassembly of the script with procedures and jump targets labelled")
+                .arg(Arg::with_name("RESOURCE_DIR").required(true))
+                .arg(script_name_arg())
+                .arg(Arg::with_name("mods")
+                    .long("mods")
+                    .value_name("FILE")
+                    .help("Mods list in ddraw.ini format")
+                    .takes_value(true)))
+            .subcommand(SubCommand::with_name("decompile")
+                .about("Prints SSL source reconstructed from the script")
+                .arg(Arg::with_name("RESOURCE_DIR").required(true))
+                .arg(script_name_arg())
+                .arg(Arg::with_name("mods")
+                    .long("mods")
+                    .value_name("FILE")
+                    .help("Mods list in ddraw.ini format")
+                    .takes_value(true))))
         .after_help(
             "EXAMPLE:\n\
-          \x20   vault13 /path/to/fallout2 artemple")
+          \x20   vault13 /path/to/fallout2 artemple\n\
+          \x20   vault13 /path/to/fallout2 --load 1\n\
+          \x20   vault13 check /path/to/fallout2\n\
+          \x20   vault13 dat pack patch_dir patch000.dat\n\
+          \x20   vault13 frm export hmjmpsaa.frm hmjmpsaa.png --palette color.pal\n\
+          \x20   vault13 script disasm /path/to/fallout2 artemple\n\
+          \x20   vault13 script decompile /path/to/fallout2 artemple")
 }
 
-fn setup_file_system(fs: &mut fs::FileSystem, args: &clap::ArgMatches) {
-    let res_dir = Path::new(args.value_of("RESOURCE_DIR").unwrap());
-    info!("Using resources dir: {}", res_dir.display());
+fn script_name_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("NAME")
+        .help("Script name as in scripts.lst, for example artemple, or path to .int file")
+        .required(true)
+}
 
-    let mut dat_files = Vec::new();
+fn palette_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("palette")
+        .long("palette")
+        .value_name("FILE")
+        .help("Palette file, for example color.pal extracted from master.dat")
+        .takes_value(true)
+        .required(true)
+}
 
-    // Add patchXXX.dat files.
-    for i in 0..999 {
-        let file = format!("patch{:03}.dat", i);
-        let path: PathBuf = [res_dir, Path::new(&file)].iter().collect();
-        if path.is_file() {
-            info!("Found {}", file);
-            dat_files.push(path)
-        } else {
-            break;
+fn run_frm_command(args: &clap::ArgMatches) -> std::io::Result<()> {
+    use asset::frame::{Frm, sheet};
+    use enum_map::EnumMap;
+    use graphics::geometry::hex::Direction;
+    use std::fs::File;
+    use std::io::{BufReader, BufWriter, Write};
+    use std::path::PathBuf;
+    use util::EnumExt;
+
+    let read_pal = |args: &clap::ArgMatches| -> std::io::Result<_> {
+        read_palette(&mut BufReader::new(File::open(args.value_of("palette").unwrap())?))
+    };
+    let read_frm = |path: &Path| -> std::io::Result<_> {
+        Frm::read(&mut BufReader::new(File::open(path)?))
+    };
+    let write_frm = |path: &Path, frm: &Frm| -> std::io::Result<_> {
+        let mut wr = BufWriter::new(File::create(path)?);
+        frm.write(&mut wr)?;
+        wr.flush()
+    };
+    // For any of the .fr0-.fr5 files returns paths of all of them.
+    let direction_paths = |path: &Path| -> Option<EnumMap<Direction, PathBuf>> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        let is_direction_ext = ext.len() == 3 && ext.starts_with("fr")
+            && (b'0'..=b'5').contains(&ext.as_bytes()[2]);
+        if !is_direction_ext {
+            return None;
+        }
+        Some(EnumMap::from(|dir: Direction| path.with_extension(format!("fr{}", dir.ordinal()))))
+    };
+    match args.subcommand() {
+        ("export", Some(args)) => {
+            let pal = read_pal(args)?;
+            let path = Path::new(args.value_of("FRM").unwrap());
+            let frm = if let Some(paths) = direction_paths(path) {
+                let frms = Direction::iter()
+                    .map(|dir| read_frm(&paths[dir]))
+                    .collect::<std::io::Result<Vec<_>>>()?;
+                sheet::combine_directions(&frms)?
+            } else {
+                read_frm(path)?
+            };
+            let mut wr = BufWriter::new(File::create(args.value_of("PNG").unwrap())?);
+            sheet::export_png(&frm, &pal, &mut wr)?;
+            wr.flush()?;
         }
+        ("import", Some(args)) => {
+            let pal = read_pal(args)?;
+            let frm = sheet::import_png(
+                &mut BufReader::new(File::open(args.value_of("PNG").unwrap())?), &pal)?;
+            let path = Path::new(args.value_of("FRM").unwrap());
+            if let Some(paths) = direction_paths(path) {
+                let frms = sheet::split_directions(&frm);
+                for dir in Direction::iter() {
+                    write_frm(&paths[dir], &frms[dir])?;
+                }
+            } else {
+                write_frm(path, &frm)?;
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn run_dat_command(args: &clap::ArgMatches) -> std::io::Result<()> {
+    use fs::dat::v2;
+
+    match args.subcommand() {
+        ("pack", Some(args)) => {
+            let count = v2::pack(args.value_of("DIR").unwrap(), args.value_of("DAT").unwrap())?;
+            println!("Packed {} file(s)", count);
+        }
+        ("unpack", Some(args)) => {
+            let count = v2::unpack(args.value_of("DAT").unwrap(), args.value_of("DIR").unwrap())?;
+            println!("Extracted {} file(s)", count);
+        }
+        ("list", Some(args)) => {
+            for entry in v2::list(args.value_of("DAT").unwrap())? {
+                let compressed_size = entry.compressed_size
+                    .map(|v| v.to_string())
+                    .unwrap_or_else(|| "-".into());
+                println!("{:>10} {:>10} {}", entry.size, compressed_size, entry.path);
+            }
+        }
+        _ => unreachable!(),
     }
-    dat_files.reverse();
+    Ok(())
+}
+
+fn run_script_command(args: &clap::ArgMatches) -> std::io::Result<()> {
+    use std::io::{BufWriter, Error, ErrorKind, Read, Write};
 
-    for file in &["master.dat", "critter.dat"] {
-        let path: PathBuf = [res_dir, Path::new(file)].iter().collect();
-        if path.is_file() {
-            info!("Found {}", file);
-            dat_files.push(path);
+    let load = |args: &clap::ArgMatches| -> std::io::Result<_> {
+        let name = args.value_of("NAME").unwrap();
+        let mut code = Vec::new();
+        let name = if Path::new(name).is_file() {
+            std::fs::File::open(name)?.read_to_end(&mut code)?;
+            Path::new(name).file_stem().unwrap().to_string_lossy().into_owned()
+        } else {
+            let mut fs = fs::FileSystem::new();
+            setup_file_system(&mut fs, args);
+            let name = name.strip_suffix(".int").unwrap_or(name);
+            fs.reader(&format!("scripts/{}.int", name))?.read_to_end(&mut code)?;
+            name.to_owned()
+        };
+        vm::Vm::default().load(name, code.into())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{:?}", e)))
+    };
+    match args.subcommand() {
+        ("disasm", Some(args)) => {
+            let program = load(args)?;
+            let stdout = std::io::stdout();
+            let mut wr = BufWriter::new(stdout.lock());
+            vm::disasm::disassemble(&program, &mut wr)?;
+            wr.flush()?;
+        }
+        ("decompile", Some(args)) => {
+            let program = load(args)?;
+            let stdout = std::io::stdout();
+            let mut wr = BufWriter::new(stdout.lock());
+            vm::decompile::decompile(&program, &mut wr)?;
+            wr.flush()?;
         }
+        _ => unreachable!(),
     }
+    Ok(())
+}
 
-    let data_dir: PathBuf = [res_dir, Path::new("data")].iter().collect();
-    if data_dir.is_dir() {
-        info!("Found `data` dir");
-        fs.register_provider(fs::std::new_provider(data_dir).unwrap());
+fn run_check_command(args: &clap::ArgMatches) -> std::io::Result<bool> {
+    let mut fs = fs::FileSystem::new();
+    setup_file_system(&mut fs, args);
+    let report = check::check(Rc::new(fs), "english")?;
+    for issue in &report.issues {
+        println!("{}", issue);
     }
+    let checked: Vec<_> = report.checked.iter()
+        .map(|(kind, count)| format!("{} {}(s)", count, kind))
+        .collect();
+    println!("Checked {}: {} issue(s)", checked.join(", "), report.issues.len());
+    Ok(report.issues.is_empty())
+}
 
-    for dat_file in dat_files.iter().rev() {
-        fs.register_provider(fs::dat::v2::new_provider(dat_file).unwrap());
+fn setup_file_system(fs: &mut fs::FileSystem, args: &clap::ArgMatches) {
+    let res_dir = Path::new(args.value_of("RESOURCE_DIR").unwrap());
+    info!("Using resources dir: {}", res_dir.display());
+
+    if let Err(e) = fs::mount::mount(fs, res_dir, args.value_of("mods").map(Path::new)) {
+        eprintln!("Error setting up resources: {}", e);
+        std::process::exit(1);
     }
+    fs.set_log_access(args.is_present("log-fs"));
+}
+
+fn write_script_profile(profiler: &vm::profile::Profiler, path: &Path) -> std::io::Result<()> {
+    use std::io::{BufWriter, Write};
+
+    let mut wr = BufWriter::new(std::fs::File::create(path)?);
+    profiler.write_report(&mut wr)?;
+    wr.flush()
+}
+
+fn print_movie_hashes(fs: &fs::FileSystem, name: &str) -> std::io::Result<()> {
+    let path = game::movie::movie_path(name);
+    let mut mve = asset::mve::MveReader::new(fs.reader(&path)?)?;
+    let mut audio_len = 0;
+    while let Some(frame) = mve.next_frame()? {
+        println!("{} {:016x}", frame.index, frame.hash());
+        audio_len += mve.take_audio().len();
+    }
+    if let Some(format) = mve.audio_format() {
+        println!("audio: {} channel(s), {} Hz, {} samples",
+            format.channels, format.sample_rate, audio_len);
+    }
+    Ok(())
 }
 
 struct Timer {
@@ -142,13 +432,24 @@ impl Timer {
         self.time - self.last
     }
 
-    pub fn tick(&mut self, time: Instant) {
+    /// Advances the timer to `time`. `excluded` is the time that isn't counted in `delta()`.
+    pub fn tick(&mut self, time: Instant, excluded: Duration) {
         assert!(time >= self.time);
-        self.last = self.time;
+        self.last = std::cmp::min(self.time + excluded, time);
         self.time = time;
     }
 }
 
+fn draw_script_debugger_status(canvas: &mut dyn Canvas, debugger: &vm::debug::Debugger) {
+    let msg = debugger.status();
+    canvas.draw_text(msg.as_bytes().into(), Point::new(2, 80), FontKey::antialiased(1), GREEN,
+        &font::DrawOptions {
+            dst_color: Some(BLACK),
+            outline: Some(graphics::render::Outline::Fixed { color: BLACK, trans_color: None }),
+            .. Default::default()
+        });
+}
+
 fn log_sdl_info() {
     info!("SDL version: {}", sdl2::version::version());
     info!("Video drivers:");
@@ -189,7 +490,15 @@ fn main() {
 
     let mut fs = fs::FileSystem::new();
 
-    let map_name: String;
+    let map_name: Option<String>;
+    let load_slot: Option<u32>;
+    let encounter: Option<(String, Option<usize>)>;
+    let screenshot_format: ImageFormat;
+    let script_breakpoints: Option<Vec<vm::debug::Breakpoint>>;
+    let script_profile_path: Option<String>;
+    let mut vm_config = vm::VmConfig::default();
+    let res_fs: StdFileSystem;
+    let data_fs: StdFileSystem;
     {
         let args = &args().get_matches();
 
@@ -198,14 +507,119 @@ fn main() {
             return;
         }
 
+        if let ("check", Some(args)) = args.subcommand() {
+            match run_check_command(args) {
+                Ok(true) => return,
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        if let ("frm", Some(args)) = args.subcommand() {
+            if let Err(e) = run_frm_command(args) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+
+        if let ("script", Some(args)) = args.subcommand() {
+            if let Err(e) = run_script_command(args) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+
+        if let ("dat", Some(args)) = args.subcommand() {
+            if let Err(e) = run_dat_command(args) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+
         setup_file_system(&mut fs, args);
 
-        let s = args.value_of("MAP").unwrap().to_lowercase();
-        map_name = if s.ends_with(".map") {
-            s[..s.len() - 4].into()
+        if let Some(movie) = args.value_of("movie-hashes") {
+            if let Err(e) = print_movie_hashes(&fs, movie) {
+                eprintln!("Error decoding movie {}: {}", movie, e);
+                std::process::exit(1);
+            }
+            return;
+        }
+
+        res_fs = StdFileSystem::new_writable(args.value_of("RESOURCE_DIR").unwrap());
+        data_fs = StdFileSystem::new_writable(res_fs.resolve("data"));
+
+        map_name = args.value_of("MAP").map(|s| {
+            let s = s.to_lowercase();
+            if s.ends_with(".map") {
+                s[..s.len() - 4].into()
+            } else {
+                s
+            }
+        });
+
+        load_slot = args.value_of("load").map(|s| {
+            match s.parse() {
+                Ok(v) if (1..=asset::save::SLOT_COUNT).contains(&v) => v,
+                _ => {
+                    eprintln!("Invalid save slot: {}", s);
+                    std::process::exit(1);
+                }
+            }
+        });
+
+        encounter = args.value_of("encounter").map(|s| {
+            let mut parts = s.splitn(2, ':');
+            let table = parts.next().unwrap().to_owned();
+            let entry = parts.next().map(|e| {
+                match e.trim_start_matches("enc_").parse() {
+                    Ok(v) => v,
+                    Err(_) => {
+                        eprintln!("Invalid encounter entry: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            });
+            (table, entry)
+        });
+
+        screenshot_format = match args.value_of("screenshot-format") {
+            Some("png") => ImageFormat::Png,
+            _ => ImageFormat::Bmp,
+        };
+
+        script_breakpoints = if args.is_present("debug-scripts") || args.is_present("break") {
+            Some(args.values_of("break").into_iter().flatten()
+                .map(|s| s.parse().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }))
+                .collect())
         } else {
-            s
+            None
         };
+
+        script_profile_path = args.value_of("profile-scripts").map(|s| s.into());
+
+        vm_config.set_failure_policy(args.value_of("script-failures").unwrap().parse().unwrap());
+        for s in args.values_of("script-failure-override").into_iter().flatten() {
+            let mut parts = s.splitn(2, '=');
+            let opcode = parts.next().unwrap();
+            let ok = parts.next()
+                .and_then(|p| p.parse().ok())
+                .map(|p| vm_config.set_opcode_failure_policy(opcode, p))
+                .unwrap_or(false);
+            if !ok {
+                eprintln!("invalid script failure override: {}", s);
+                std::process::exit(1);
+            }
+        }
     }
 
     let language = "english";
@@ -219,7 +633,8 @@ fn main() {
     log_sdl_info();
 
     let sdl = sdl2::init().unwrap();
-    let mut event_pump = sdl.event_pump().unwrap();
+    // Shared with the script debugger which pumps the events while a script is stopped.
+    let event_pump = Rc::new(RefCell::new(sdl.event_pump().unwrap()));
     let video = sdl.video().unwrap();
     info!("Using video driver: {}", video.current_video_driver());
 
@@ -229,6 +644,8 @@ fn main() {
         .build()
         .unwrap();
 
+    let audio = sound::Audio::new(fs.clone(), &sdl);
+
     let mouse = sdl.mouse();
     mouse.set_relative_mouse_mode(true);
 
@@ -256,8 +673,8 @@ fn main() {
 
     let fonts = Rc::new(load_fonts(&fs, &texture_factory));
 
-    let mut canvas = gfx_backend.into_canvas(fonts.clone());
-    let canvas = canvas.as_mut();
+    // Shared with the script debugger which draws its overlay while a script is stopped.
+    let canvas = Rc::new(RefCell::new(gfx_backend.into_canvas(fonts.clone())));
 
     let start = Instant::now();
     let mut timer = Timer::new(start);
@@ -269,17 +686,63 @@ fn main() {
     let misc_msgs = Rc::new(Messages::read_file(&fs, language, "game/misc.msg").unwrap());
     let mut state = GameState::new(
         fs,
+        data_fs,
         language,
         proto_db,
         frm_db,
         fonts,
         misc_msgs,
+        audio,
+        vm_config,
         start,
         ui,
-    );
+    ).unwrap_or_else(|e| {
+        eprintln!("Error initializing game: {}", e);
+        std::process::exit(1);
+    });
+
+    let draw_script_debugger = Rc::new(Cell::new(true));
+
+    if let Some(breakpoints) = script_breakpoints {
+        let mut debugger = vm::debug::Debugger::new(Box::new(vm::debug::Console::spawn()));
+        for bp in breakpoints {
+            debugger.add_breakpoint(bp);
+        }
+        // Keep the window responsive while a script is stopped. The events stay queued and are
+        // handled once the script is resumed. The overlay is drawn over the last rendered frame.
+        let event_pump = event_pump.clone();
+        let canvas = canvas.clone();
+        let draw_script_debugger = draw_script_debugger.clone();
+        debugger.set_idle(Box::new(move |debugger| {
+            event_pump.borrow_mut().pump_events();
+            if draw_script_debugger.get() {
+                if let Ok(mut canvas) = canvas.try_borrow_mut() {
+                    draw_script_debugger_status(canvas.as_mut(), debugger);
+                    canvas.present();
+                }
+            }
+        }));
+        state.set_script_debugger(Some(Rc::new(RefCell::new(debugger))));
+    }
+    if script_profile_path.is_some() {
+        state.set_script_profiler(Some(Default::default()));
+    }
 
     state.new_game();
-    state.switch_map(&map_name, ui);
+    let r = if let Some(slot) = load_slot {
+        state.load_game(slot, ui)
+    } else {
+        state.switch_map(map_name.as_ref().unwrap(), ui)
+    };
+    if let Err(e) = r {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    if let Some((table, entry)) = encounter {
+        if let Err(e) = state.force_encounter(&table, entry, ui) {
+            error!("couldn't start encounter: {}", e);
+        }
+    }
 
     let mut draw_debug = true;
 
@@ -287,6 +750,10 @@ fn main() {
     let app_events = &mut Vec::new();
 
     'running: loop {
+        if let Some(debugger) = state.script_debugger() {
+            debugger.borrow_mut().poll();
+        }
+
         // Handle app events.
 
         for event in app_events.drain(..) {
@@ -298,7 +765,9 @@ fn main() {
 
         // Handle input.
 
-        for event in event_pump.poll_iter() {
+        // Collect first since handling the events may run scripts and stop in the debugger.
+        let events: Vec<_> = event_pump.borrow_mut().poll_iter().collect();
+        for event in events {
             let mut handled = ui.handle_input(ui::HandleInput {
                 now: timer.time(),
                 event: &event,
@@ -312,6 +781,39 @@ fn main() {
                     Event::KeyDown { keycode: Some(Keycode::Backquote), .. } => {
                         draw_debug = !draw_debug;
                     }
+                    Event::KeyDown { keycode: Some(Keycode::F6), .. } => {
+                        let canvas = canvas.borrow();
+                        if let Err(e) = state.save_game(1, "Quick save".into(), &**canvas, ui) {
+                            error!("couldn't save game: {}", e);
+                        }
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::F7), .. } => {
+                        if let Err(e) = state.load_game(1, ui) {
+                            error!("couldn't load game: {}", e);
+                        }
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::F9), .. } => {
+                        draw_script_debugger.set(!draw_script_debugger.get());
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::F10), .. } => {
+                        if let Some(debugger) = state.script_debugger() {
+                            debugger.borrow_mut().break_next();
+                        }
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::F11), .. } => {
+                        if let (Some(profiler), Some(path)) = (state.script_profiler(), &script_profile_path) {
+                            match write_script_profile(&profiler.borrow(), path.as_ref()) {
+                                Ok(()) => info!("saved script profile to {}", path),
+                                Err(e) => error!("couldn't save script profile: {}", e),
+                            }
+                        }
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::F12), .. } => {
+                        match image::save_screenshot(&**canvas.borrow(), &res_fs, screenshot_format) {
+                            Ok(path) => info!("saved screenshot to {}", path.display()),
+                            Err(e) => error!("couldn't save screenshot: {}", e),
+                        }
+                    }
                     Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                         break 'running
                     },
@@ -337,6 +839,9 @@ fn main() {
 
         ui.sync();
 
+        let mut canvas = canvas.borrow_mut();
+        let canvas = canvas.as_mut();
+
         canvas.update(timer.time());
 
         // Render
@@ -384,11 +889,24 @@ fn main() {
                 });
         }
 
+        if let Some(debugger) = state.script_debugger().filter(|_| draw_script_debugger.get()) {
+            draw_script_debugger_status(canvas, &debugger.borrow());
+        }
+
         canvas.present();
         canvas.cleanup();
 
         std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
 
-        timer.tick(Instant::now());
+        let stopped_time = state.script_debugger()
+            .and_then(|d| d.borrow_mut().take_stopped_time())
+            .unwrap_or_default();
+        timer.tick(Instant::now(), stopped_time);
+    }
+
+    if let (Some(profiler), Some(path)) = (state.script_profiler(), &script_profile_path) {
+        if let Err(e) = write_script_profile(&profiler.borrow(), path.as_ref()) {
+            error!("couldn't save script profile: {}", e);
+        }
     }
 }
\ No newline at end of file
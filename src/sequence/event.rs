@@ -26,7 +26,11 @@ pub enum Event {
         skill: crate::asset::Skill,
         user: object::Handle,
         target: object::Handle,
-    }
+    },
+    Attack {
+        attacker: object::Handle,
+        target: object::Handle,
+    },
 }
 
 pub struct PushEvent {
@@ -0,0 +1,126 @@
+pub mod mixer;
+
+use log::*;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::asset::acm::AcmReader;
+use crate::fs::FileSystem;
+use mixer::*;
+
+pub const SAMPLE_RATE: u32 = 22050;
+
+struct Output(Arc<Mutex<Mixer>>);
+
+impl AudioCallback for Output {
+    type Channel = i16;
+
+    fn callback(&mut self, out: &mut [i16]) {
+        self.0.lock().unwrap().mix(out);
+    }
+}
+
+/// Plays ACM sounds from the file system through the SDL audio device. If the device can't be
+/// opened the audio is silent but otherwise works as usual.
+pub struct Audio {
+    fs: Rc<FileSystem>,
+    mixer: Arc<Mutex<Mixer>>,
+    device: Option<AudioDevice<Output>>,
+    /// Decoded sounds by path. `None` if the sound couldn't be loaded.
+    cache: RefCell<HashMap<String, Option<Samples>>>,
+}
+
+impl Audio {
+    pub fn new(fs: Rc<FileSystem>, sdl: &sdl2::Sdl) -> Self {
+        let mixer = Arc::new(Mutex::new(Mixer::new(SAMPLE_RATE)));
+        let device = sdl.audio()
+            .and_then(|audio| {
+                info!("Using audio driver: {}", audio.current_audio_driver());
+                let spec = AudioSpecDesired {
+                    freq: Some(SAMPLE_RATE as i32),
+                    channels: Some(OUTPUT_CHANNELS as u8),
+                    samples: None,
+                };
+                audio.open_playback(None, &spec, |spec| {
+                    mixer.lock().unwrap().set_sample_rate(spec.freq as u32);
+                    Output(mixer.clone())
+                })
+            });
+        let device = match device {
+            Ok(device) => {
+                device.resume();
+                Some(device)
+            }
+            Err(e) => {
+                warn!("couldn't open audio device, sound is disabled: {}", e);
+                None
+            }
+        };
+        Self {
+            fs,
+            mixer,
+            device,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Creates audio without output device.
+    pub fn silent(fs: Rc<FileSystem>) -> Self {
+        Self {
+            fs,
+            mixer: Arc::new(Mutex::new(Mixer::new(SAMPLE_RATE))),
+            device: None,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.device.is_none()
+    }
+
+    /// Loads and decodes ACM file at `path`.
+    pub fn load(&self, path: &str) -> io::Result<Samples> {
+        let mut acm = AcmReader::new(self.fs.reader(path)?)?;
+        let data = acm.read_to_end()?;
+        Ok(Samples {
+            channels: acm.channels(),
+            sample_rate: acm.sample_rate(),
+            data: data.into(),
+        })
+    }
+
+    /// Plays ACM file at `path` in the `channel`. Music is not cached since it's usually big and
+    /// played once. Returns `None` if the audio is silent or the file couldn't be loaded.
+    pub fn play(&self, path: &str, channel: Channel, options: PlayOptions) -> Option<SoundId> {
+        if self.is_silent() {
+            return None;
+        }
+        let path = path.to_ascii_lowercase();
+        let samples = if channel == Channel::Music {
+            self.load(&path)
+                .map_err(|e| warn!("couldn't load sound {}: {}", path, e))
+                .ok()
+        } else {
+            self.cache.borrow_mut().entry(path.clone())
+                .or_insert_with(|| self.load(&path)
+                    .map_err(|e| warn!("couldn't load sound {}: {}", path, e))
+                    .ok())
+                .clone()
+        }?;
+        Some(self.mixer().play(channel, samples, options))
+    }
+
+    pub fn mixer(&self) -> MutexGuard<'_, Mixer> {
+        self.mixer.lock().unwrap()
+    }
+
+    /// Returns mixer handle for feeding streamed sound from other places. `None` if the audio is
+    /// silent.
+    pub fn shared_mixer(&self) -> Option<Arc<Mutex<Mixer>>> {
+        self.device.as_ref().map(|_| self.mixer.clone())
+    }
+}
@@ -0,0 +1,364 @@
+use enum_map::{enum_map, Enum, EnumMap};
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Number of output channels. The output is always stereo.
+pub const OUTPUT_CHANNELS: usize = 2;
+
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum Channel {
+    Music,
+    Ambient,
+    Sfx,
+    Speech,
+}
+
+/// Decoded sound data with channels interleaved.
+#[derive(Clone)]
+pub struct Samples {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub data: Arc<[i16]>,
+}
+
+impl Samples {
+    pub fn frame_count(&self) -> usize {
+        self.data.len() / cmp::max(self.channels as usize, 1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PlayOptions {
+    /// Restart the sound when it ends.
+    pub looping: bool,
+    /// Time to wait before the sound starts.
+    pub delay: Duration,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SoundId(u32);
+
+impl SoundId {
+    pub fn from_raw(v: u32) -> Self {
+        Self(v)
+    }
+
+    pub fn raw(self) -> u32 {
+        self.0
+    }
+}
+
+struct Voice {
+    id: SoundId,
+    channel: Channel,
+    samples: Samples,
+    /// Position in source frames, 16.16 fixed point.
+    pos: u64,
+    /// Increment of `pos` per output frame.
+    step: u64,
+    looping: bool,
+    /// Output frames left until the voice starts.
+    delay: usize,
+    paused: bool,
+    /// Samples to play after the current ones.
+    queue: VecDeque<Samples>,
+    /// Whether more samples can be queued. Streaming voice plays silence while the queue is
+    /// empty.
+    streaming: bool,
+}
+
+impl Voice {
+    /// Returns next stereo frame or `None` if the voice has ended.
+    fn next(&mut self) -> Option<(i32, i32)> {
+        let frame = loop {
+            let frame_count = self.samples.frame_count() as u64;
+            let frame = self.pos >> 16;
+            if frame < frame_count {
+                break frame;
+            }
+            if self.looping && frame_count > 0 {
+                self.pos %= frame_count << 16;
+                break self.pos >> 16;
+            }
+            if let Some(samples) = self.queue.pop_front() {
+                self.pos -= frame_count << 16;
+                self.samples = samples;
+            } else if self.streaming {
+                return Some((0, 0));
+            } else {
+                return None;
+            }
+        };
+        self.pos += self.step;
+
+        let channels = self.samples.channels as usize;
+        let i = frame as usize * channels;
+        let left = self.samples.data[i] as i32;
+        let right = if channels > 1 {
+            self.samples.data[i + 1] as i32
+        } else {
+            left
+        };
+        Some((left, right))
+    }
+}
+
+/// Mixes sounds playing in several channels into a stereo stream. Each channel has its own volume.
+/// Sounds with sample rate different from the output rate are resampled.
+pub struct Mixer {
+    sample_rate: u32,
+    volumes: EnumMap<Channel, f32>,
+    voices: Vec<Voice>,
+    next_id: u32,
+    buf: Vec<i32>,
+}
+
+impl Mixer {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            volumes: enum_map! { _ => 1.0 },
+            voices: Vec::new(),
+            next_id: 1,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Sets the output sample rate. Affects sounds played after the call.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn volume(&self, channel: Channel) -> f32 {
+        self.volumes[channel]
+    }
+
+    /// Sets volume of the `channel` in range `0.0..=1.0`.
+    pub fn set_volume(&mut self, channel: Channel, volume: f32) {
+        self.volumes[channel] = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn play(&mut self, channel: Channel, samples: Samples, options: PlayOptions) -> SoundId {
+        self.add_voice(channel, samples, options, false)
+    }
+
+    /// Starts a stream of sound data in the specified format. The data is added with
+    /// `queue_stream()`. The stream plays until `end_stream()` is called and all queued data
+    /// is played.
+    pub fn play_stream(&mut self, channel: Channel, channels: u16, sample_rate: u32) -> SoundId {
+        let samples = Samples {
+            channels,
+            sample_rate,
+            data: Vec::new().into(),
+        };
+        self.add_voice(channel, samples, Default::default(), true)
+    }
+
+    /// Appends interleaved samples to the stream started with `play_stream()`.
+    pub fn queue_stream(&mut self, id: SoundId, data: &[i16]) {
+        if let Some(v) = self.voices.iter_mut().find(|v| v.id == id) {
+            let samples = Samples {
+                data: data.into(),
+                .. v.samples.clone()
+            };
+            v.queue.push_back(samples);
+        }
+    }
+
+    /// Marks the stream as finished. It will stop once the queued data is played.
+    pub fn end_stream(&mut self, id: SoundId) {
+        if let Some(v) = self.voices.iter_mut().find(|v| v.id == id) {
+            v.streaming = false;
+        }
+    }
+
+    fn add_voice(&mut self, channel: Channel, samples: Samples, options: PlayOptions,
+        streaming: bool) -> SoundId
+    {
+        let id = SoundId(self.next_id);
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        let step = ((samples.sample_rate as u64) << 16) / cmp::max(self.sample_rate as u64, 1);
+        let delay = (options.delay.as_millis() as u64 * self.sample_rate as u64 / 1000) as usize;
+        self.voices.push(Voice {
+            id,
+            channel,
+            samples,
+            pos: 0,
+            step,
+            looping: options.looping,
+            delay,
+            paused: false,
+            queue: VecDeque::new(),
+            streaming,
+        });
+        id
+    }
+
+    pub fn is_playing(&self, id: SoundId) -> bool {
+        self.voices.iter().any(|v| v.id == id)
+    }
+
+    pub fn is_channel_playing(&self, channel: Channel) -> bool {
+        self.voices.iter().any(|v| v.channel == channel)
+    }
+
+    pub fn stop(&mut self, id: SoundId) {
+        self.voices.retain(|v| v.id != id);
+    }
+
+    pub fn stop_channel(&mut self, channel: Channel) {
+        self.voices.retain(|v| v.channel != channel);
+    }
+
+    pub fn stop_all(&mut self) {
+        self.voices.clear();
+    }
+
+    pub fn set_paused(&mut self, id: SoundId, paused: bool) {
+        if let Some(v) = self.voices.iter_mut().find(|v| v.id == id) {
+            v.paused = paused;
+        }
+    }
+
+    /// Restarts the sound from the beginning.
+    pub fn rewind(&mut self, id: SoundId) {
+        if let Some(v) = self.voices.iter_mut().find(|v| v.id == id) {
+            v.pos = 0;
+        }
+    }
+
+    /// Fills `out` with interleaved stereo samples. Finished sounds are removed.
+    pub fn mix(&mut self, out: &mut [i16]) {
+        let frames = out.len() / OUTPUT_CHANNELS;
+        self.buf.clear();
+        self.buf.resize(frames * OUTPUT_CHANNELS, 0);
+
+        let volumes = &self.volumes;
+        let buf = &mut self.buf;
+        self.voices.retain_mut(|voice| {
+            if voice.paused {
+                return true;
+            }
+            let volume = (volumes[voice.channel] * 256.0) as i32;
+            let skip = cmp::min(voice.delay, frames);
+            voice.delay -= skip;
+            for frame in buf.chunks_exact_mut(OUTPUT_CHANNELS).skip(skip) {
+                if let Some((left, right)) = voice.next() {
+                    frame[0] += (left * volume) >> 8;
+                    frame[1] += (right * volume) >> 8;
+                } else {
+                    return false;
+                }
+            }
+            true
+        });
+
+        for (dst, &src) in out.iter_mut().zip(self.buf.iter()) {
+            *dst = src.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+        for dst in &mut out[frames * OUTPUT_CHANNELS..] {
+            *dst = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn samples(channels: u16, sample_rate: u32, data: &[i16]) -> Samples {
+        Samples {
+            channels,
+            sample_rate,
+            data: data.into(),
+        }
+    }
+
+    #[test]
+    fn mix() {
+        let mut m = Mixer::new(100);
+        let mut out = [1; 8];
+        m.mix(&mut out);
+        assert_eq!(out, [0; 8]);
+
+        let sfx = m.play(Channel::Sfx, samples(1, 100, &[100, 200]), Default::default());
+        m.play(Channel::Music, samples(2, 100, &[1, 2, 3, 4, 5, 6]), Default::default());
+        m.set_volume(Channel::Sfx, 0.5);
+        m.mix(&mut out);
+        assert_eq!(out, [51, 52, 103, 104, 5, 6, 0, 0]);
+        assert!(!m.is_playing(sfx));
+        assert!(!m.is_channel_playing(Channel::Music));
+    }
+
+    #[test]
+    fn clamp() {
+        let mut m = Mixer::new(100);
+        m.play(Channel::Sfx, samples(1, 100, &[30000, -30000]), Default::default());
+        m.play(Channel::Speech, samples(1, 100, &[30000, -30000]), Default::default());
+        let mut out = [0; 4];
+        m.mix(&mut out);
+        assert_eq!(out, [32767, 32767, -32768, -32768]);
+    }
+
+    #[test]
+    fn looping_and_delay() {
+        let mut m = Mixer::new(1000);
+        let id = m.play(Channel::Ambient, samples(1, 1000, &[1, 2]), PlayOptions {
+            looping: true,
+            delay: Duration::from_millis(2),
+        });
+        let mut out = [0; 12];
+        m.mix(&mut out);
+        assert_eq!(out, [0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2]);
+
+        m.set_paused(id, true);
+        m.mix(&mut out);
+        assert_eq!(out, [0; 12]);
+
+        m.set_paused(id, false);
+        m.mix(&mut out[..4]);
+        assert_eq!(&out[..4], &[1, 1, 2, 2]);
+
+        m.stop(id);
+        assert!(!m.is_playing(id));
+    }
+
+    #[test]
+    fn resample() {
+        let mut m = Mixer::new(200);
+        m.play(Channel::Sfx, samples(1, 100, &[1, 2]), Default::default());
+        let mut out = [0; 10];
+        m.mix(&mut out);
+        assert_eq!(out, [1, 1, 1, 1, 2, 2, 2, 2, 0, 0]);
+
+        let mut m = Mixer::new(100);
+        m.play(Channel::Sfx, samples(1, 200, &[1, 2, 3, 4]), Default::default());
+        let mut out = [0; 6];
+        m.mix(&mut out);
+        assert_eq!(out, [1, 1, 3, 3, 0, 0]);
+    }
+
+    #[test]
+    fn stream() {
+        let mut m = Mixer::new(100);
+        let id = m.play_stream(Channel::Speech, 2, 100);
+        m.queue_stream(id, &[1, 2, 3, 4]);
+        m.queue_stream(id, &[5, 6]);
+        let mut out = [9; 8];
+        m.mix(&mut out);
+        assert_eq!(out, [1, 2, 3, 4, 5, 6, 0, 0]);
+        assert!(m.is_playing(id));
+
+        m.queue_stream(id, &[7, 8]);
+        m.end_stream(id);
+        m.mix(&mut out);
+        assert_eq!(out, [7, 8, 0, 0, 0, 0, 0, 0]);
+        assert!(!m.is_playing(id));
+    }
+}
@@ -671,6 +671,11 @@ impl Base {
         self.rect
     }
 
+    /// Moves and resizes the widget. Note the child widgets of a window are not moved.
+    pub fn set_rect(&mut self, rect: Rect) {
+        self.rect = rect;
+    }
+
     pub fn cursor(&self) -> Option<Cursor> {
         self.cursor
     }
@@ -81,6 +81,10 @@ impl Button {
         self.configs[State::Up].text = text;
     }
 
+    pub fn is_enabled(&self) -> bool {
+        self.state != State::Disabled
+    }
+
     pub fn set_enabled(&mut self, enabled: bool) {
         self.state = if enabled {
             State::Up
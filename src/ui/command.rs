@@ -28,8 +28,12 @@ pub enum UiCommandData {
     },
     Scroll,
     Skilldex(SkilldexCommand),
+    Combat(CombatCommand),
     Inventory(inventory::Command),
     MoveWindow(move_window::Command),
+    WorldMap(WorldMapCommand),
+    ScriptWindow(ScriptWindowCommand),
+    Say(SayCommand),
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -50,6 +54,49 @@ pub enum SkilldexCommand {
     },
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CombatCommand {
+    Begin,
+    EndTurn,
+    End,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WorldMapCommand {
+    /// Travel to the world map position.
+    Travel {
+        pos: Point,
+    },
+    /// Travel to the area or enter it if the party is already there.
+    Area {
+        area: usize,
+    },
+    /// Answer to the prompt whether to enter the random encounter detected by the Outdoorsman
+    /// skill.
+    Encounter {
+        accept: bool,
+    },
+}
+
+/// Mouse event on a button or region created by a script.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum ScriptWindowCommand {
+    Enter,
+    Exit,
+    Press,
+    Release,
+    RightPress,
+    RightRelease,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SayCommand {
+    /// Scroll the options of the `say*` dialog.
+    Scroll(crate::ui::message_panel::Scroll),
+    /// Close the message shown by `saymessage`.
+    CloseMessage,
+}
+
 pub mod inventory {
     use super::*;
     use crate::game::ui::action_menu::Action;
@@ -99,6 +99,7 @@ pub struct MessagePanel {
     skew: i32,
     anchor: Anchor,
     message_spacing: i32,
+    horz_align: font::HorzAlign,
     needs_update_highlight: bool,
 }
 
@@ -120,6 +121,7 @@ impl MessagePanel {
             skew: 0,
             anchor: Anchor::Top,
             message_spacing: 0,
+            horz_align: font::HorzAlign::Left,
             needs_update_highlight: false,
         }
     }
@@ -191,6 +193,11 @@ impl MessagePanel {
         self.message_spacing = message_spacing;
     }
 
+    /// Horizontal alignment of the lines within the panel width.
+    pub fn set_horz_align(&mut self, horz_align: font::HorzAlign) {
+        self.horz_align = horz_align;
+    }
+
     fn layout(&self) -> Layout {
         self.layout.expect("Widget::init() wasn't called")
     }
@@ -201,7 +208,8 @@ impl MessagePanel {
         self.lines.len() as i32 - self.layout().visible_line_count
     }
 
-    fn scroll(&mut self, scroll: Scroll) {
+    /// Scrolls by one line.
+    pub fn scroll(&mut self, scroll: Scroll) {
         self.scroll_pos += match scroll {
             Scroll::Up => -1,
             Scroll::Down => 1,
@@ -319,8 +327,8 @@ struct Message {
     line_count: usize,
 }
 
-#[derive(Clone, Copy, Debug)]
-enum Scroll {
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Scroll {
     Up,
     Down,
 }
@@ -399,6 +407,16 @@ impl Widget for MessagePanel {
             Anchor::Bottom => self.lines.len() as i32,
         };
 
+        let options = font::DrawOptions {
+            horz_align: self.horz_align,
+            ..Default::default()
+        };
+        let align_x = match self.horz_align {
+            font::HorzAlign::Left => 0,
+            font::HorzAlign::Center => layout.width / 2,
+            font::HorzAlign::Right => layout.width,
+        };
+
         let mut last_message = None;
         for i in end_i - layout.visible_line_count..end_i {
             if i >= self.lines.len() as i32 {
@@ -418,8 +436,7 @@ impl Widget for MessagePanel {
                 }
                 last_message = Some(line.message);
 
-                ctx.canvas.draw_text(s, Point::new(x, y), self.font, color,
-                    &font::DrawOptions::default());
+                ctx.canvas.draw_text(s, Point::new(x + align_x, y), self.font, color, &options);
             }
             x += self.skew;
             y += vert_advance;
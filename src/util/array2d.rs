@@ -2,6 +2,7 @@ use std::ops::{Deref, DerefMut};
 
 use crate::util::VecExt;
 
+#[derive(Clone)]
 pub struct Array2d<T> {
     arr: Box<[T]>,
     width: usize,
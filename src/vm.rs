@@ -85,9 +85,15 @@
 //!
 //! Stored in `save.dat`. Defined in `vault13.gam`.
 
+pub mod debug;
+pub mod decompile;
+pub mod disasm;
 mod error;
 mod instruction;
+pub mod profile;
 mod stack;
+#[cfg(test)]
+mod test;
 pub mod value;
 
 use bstring::{bstr, BString};
@@ -97,15 +103,17 @@ use enumflags2_derive::EnumFlags;
 use log::*;
 use matches::matches;
 use slotmap::{SecondaryMap, SlotMap};
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::io::{self, Cursor};
 use std::rc::Rc;
 use std::str;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::game::object;
 use crate::game::script::{NewScripts, ScriptKind};
+use crate::util::EnumExt;
 
 use instruction::{Instruction, instruction_map, Opcode};
 use stack::{Stack, StackId};
@@ -144,9 +152,9 @@ impl PredefinedProc {
     pub fn name(self) -> &'static str {
         use PredefinedProc::*;
         match self {
-            Combat => "combat_is_over_p_proc",
-            CombatIsOver => "combat_is_starting_p_proc",
-            CombatIsStarting => "combat_p_proc",
+            Combat => "combat_p_proc",
+            CombatIsOver => "combat_is_over_p_proc",
+            CombatIsStarting => "combat_is_starting_p_proc",
             Create => "create_p_proc",
             Critter => "critter_p_proc",
             Damage => "damage_p_proc",
@@ -180,6 +188,8 @@ impl fmt::Display for PredefinedProc {
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Suspend {
     GsayEnd,
+    SayEnd,
+    SayMessage,
 }
 
 /// Result of program invocation.
@@ -221,6 +231,9 @@ pub struct Context<'a> {
     pub source_obj: Option<object::Handle>,
     pub target_obj: Option<object::Handle>,
     pub skill: Option<crate::asset::Skill>,
+    pub fixed_param: i32,
+    /// Script that runs the program.
+    pub sid: crate::game::script::ScriptIid,
     pub ui: &'a mut crate::ui::Ui,
     pub world: &'a mut crate::game::world::World,
     pub obj_sequencer: &'a mut crate::game::sequence::ObjSequencer,
@@ -231,11 +244,64 @@ pub struct Context<'a> {
     pub proto_db: &'a crate::asset::proto::ProtoDb,
     pub map_id: crate::asset::map::MapId,
     pub rpg: &'a mut crate::game::rpg::Rpg,
+    pub combat: &'a mut crate::game::combat::Combat,
+    pub sound: &'a mut crate::game::sound::Sound,
+    pub movies: &'a mut crate::game::movie::Movies,
+    pub script_windows: &'a mut crate::game::script_window::ScriptWindows,
+    pub say_dialog: &'a mut crate::game::say::SayDialog,
+}
+
+/// What to do when an instruction fails with `Error::UnimplementedOpcode` or `Error::BadValue`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FailurePolicy {
+    /// Fail the invocation with the error.
+    Strict,
+
+    /// Skip the instruction: pop its arguments and push `0` if it returns a value. Applies only
+    /// to the opcodes with known `Opcode::signature()`, the rest fail as with `Strict`.
+    /// Every failed instruction is logged once.
+    Lenient,
+}
+
+impl str::FromStr for FailurePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(FailurePolicy::Strict),
+            "lenient" => Ok(FailurePolicy::Lenient),
+            _ => Err(format!("invalid failure policy: {}", s)),
+        }
+    }
 }
 
 pub struct VmConfig {
     instructions: HashMap<u16, Instruction>,
     max_stack_len: usize,
+    failure_policy: FailurePolicy,
+    opcode_failure_policies: HashMap<Opcode, FailurePolicy>,
+}
+
+impl VmConfig {
+    pub fn failure_policy(&self, opcode: Opcode) -> FailurePolicy {
+        self.opcode_failure_policies.get(&opcode).cloned().unwrap_or(self.failure_policy)
+    }
+
+    /// Sets failure policy for all opcodes that don't have it overridden.
+    pub fn set_failure_policy(&mut self, policy: FailurePolicy) {
+        self.failure_policy = policy;
+    }
+
+    /// Overrides failure policy for the opcode with `snake_case` name `opcode_name`.
+    /// Returns `false` if there's no such opcode.
+    pub fn set_opcode_failure_policy(&mut self, opcode_name: &str, policy: FailurePolicy) -> bool {
+        if let Some(opcode) = Opcode::iter().find(|&op| disasm::mnemonic(op) == opcode_name) {
+            self.opcode_failure_policies.insert(opcode, policy);
+            true
+        } else {
+            false
+        }
+    }
 }
 
 impl Default for VmConfig {
@@ -243,6 +309,8 @@ impl Default for VmConfig {
         Self {
             instructions: instruction_map(),
             max_stack_len: 2000,
+            failure_policy: FailurePolicy::Strict,
+            opcode_failure_policies: HashMap::new(),
         }
     }
 }
@@ -295,6 +363,9 @@ impl Procedure {
     }
 }
 
+/// Offset of the procedure table. Code before it is the program startup code.
+const PROC_TABLE_START: usize = 42;
+
 struct Procs {
     by_id: Vec<Procedure>,
     by_name: HashMap<Rc<BString>, ProcedureId>,
@@ -304,33 +375,39 @@ pub struct Program {
     name: String,
     config: Rc<VmConfig>,
     code: Box<[u8]>,
+    /// Offset where procedure code starts after the procedure, name and string tables.
+    code_start: usize,
     names: StringMap,
     strings: StringMap,
     procs: Procs,
+    /// Code positions of the instructions that failed and were skipped due to
+    /// `FailurePolicy::Lenient`. Used to log every such instruction once.
+    skipped_failures: RefCell<HashSet<usize>>,
 }
 
 impl Program {
     fn new(name: String, code: Box<[u8]>, config: Rc<VmConfig>) -> Result<Self> {
-        const PROC_TABLE_START: usize = 42;
         const PROC_TABLE_HEADER_LEN: usize = 4;
         const PROC_ENTRY_LEN: usize = 24;
 
-        if code.len() < PROC_ENTRY_LEN + PROC_TABLE_HEADER_LEN {
+        if code.len() < PROC_TABLE_START + PROC_TABLE_HEADER_LEN {
             return Err(Error::BadMetadata("missing procedure table".into()));
         }
 
         let proc_count = BigEndian::read_i32(&code[PROC_TABLE_START..]) as usize;
 
-        let name_table_start = PROC_TABLE_START + PROC_TABLE_HEADER_LEN +
-            proc_count * PROC_ENTRY_LEN;
+        let name_table_start = proc_count.checked_mul(PROC_ENTRY_LEN)
+            .and_then(|v| v.checked_add(PROC_TABLE_START + PROC_TABLE_HEADER_LEN))
+            .ok_or(Error::UnexpectedEof)?;
         debug!("reading name table at 0x{:04x}", name_table_start);
         let (names, name_table_len_bytes) =
-            Self::read_string_table(&code[name_table_start..])?;
+            Self::read_string_table(code.get(name_table_start..).ok_or(Error::UnexpectedEof)?)?;
 
         let string_table_start = name_table_start + name_table_len_bytes;
         debug!("reading string table at 0x{:04x}", string_table_start);
-        let (strings, _) =
-            Self::read_string_table(&code[string_table_start..])?;
+        let (strings, string_table_len_bytes) =
+            Self::read_string_table(code.get(string_table_start..).ok_or(Error::UnexpectedEof)?)?;
+        let code_start = string_table_start + string_table_len_bytes;
 
         debug!("reading procedure table at 0x{:04x}", PROC_TABLE_START);
         let procs = Self::read_proc_table(&code[PROC_TABLE_START..], &names)?;
@@ -339,9 +416,11 @@ impl Program {
             name,
             config,
             code,
+            code_start,
             names,
             strings,
             procs,
+            skipped_failures: RefCell::new(HashSet::new()),
         })
     }
 
@@ -469,9 +548,13 @@ pub struct ProgramState {
     base:  Option<usize>,
     /// Base offset in `data_stack` of program global variables.
     global_base: Option<usize>,
+    /// Number of program global variables. Known after the initialization code has finished.
+    global_count: Option<usize>,
     instr_state: instruction::State,
     /// Stack of code positions where suspend requested.
     suspend_stack: Vec<usize>,
+    debugger: Option<Rc<RefCell<debug::Debugger>>>,
+    profiler: Option<Rc<RefCell<profile::Profiler>>>,
 }
 
 impl ProgramState {
@@ -487,8 +570,11 @@ impl ProgramState {
             return_stack,
             base: None,
             global_base: None,
+            global_count: None,
             instr_state: instruction::State::new(),
             suspend_stack: Vec::new(),
+            debugger: None,
+            profiler: None,
         }
     }
 
@@ -515,7 +601,16 @@ impl ProgramState {
                         break Some(s);
                     }
                 }
-                Err(ref e) if matches!(e, Error::Halted) => break None,
+                Err(ref e) if matches!(e, Error::Halted) => {
+                    if self.global_count.is_none() {
+                        self.global_count = self.global_base
+                            .map(|base| self.data_stack.len().saturating_sub(base));
+                    }
+                    if let Some(debugger) = &self.debugger {
+                        debugger.borrow_mut().on_halt(self);
+                    }
+                    break None;
+                }
                 Err(e) => return Err(e),
             }
         };
@@ -560,14 +655,62 @@ impl ProgramState {
 
     fn step(&mut self, ctx: &mut Context) -> Result<Option<Suspend>> {
         trace!("code_pos: 0x{:04x}", self.code_pos);
+        if let Some(debugger) = &self.debugger {
+            debugger.borrow_mut().before_step(self, &debug::Vars {
+                local_vars: ctx.local_vars,
+                map_vars: ctx.map_vars,
+                global_vars: ctx.global_vars,
+                external_vars: ctx.external_vars,
+            });
+        }
         let opcode_pos = self.code_pos;
         let instr = self.next_instruction()?;
         self.opcode = Some((instr.opcode(), opcode_pos));
-        let suspend = instr.execute(instruction::Context {
+        let data_len = self.data_stack.len();
+        let start = self.profiler.as_ref().map(|_| Instant::now());
+        let r = instr.execute(instruction::Context {
             prg: self,
             ext: ctx,
-        })?;
-        Ok(suspend)
+        });
+        if let (Some(profiler), Some(start)) = (&self.profiler, start) {
+            let mut profiler = profiler.borrow_mut();
+            profiler.record(&self.program, opcode_pos, instr.opcode(), start.elapsed());
+            if let Err(Error::UnimplementedOpcode(opcode)) = r {
+                profiler.record_unimplemented(&self.program, opcode_pos, opcode);
+            }
+        }
+        r.or_else(|e| self.recover(e, instr.opcode(), opcode_pos, data_len))
+    }
+
+    /// Applies `FailurePolicy` to the instruction that failed with `e`. `data_len` is the data stack
+    /// length before the instruction was executed.
+    fn recover(&mut self, e: Error, opcode: Opcode, opcode_pos: usize, data_len: usize)
+        -> Result<Option<Suspend>>
+    {
+        if !matches!(e, Error::UnimplementedOpcode(_) | Error::BadValue(_))
+            || self.program.config.failure_policy(opcode) != FailurePolicy::Lenient
+        {
+            return Err(e);
+        }
+        let sig = if let Some(sig) = opcode.signature() {
+            sig
+        } else {
+            return Err(e);
+        };
+        // The instruction could have popped some of its arguments before failing.
+        let len = match data_len.checked_sub(sig.arg_count) {
+            Some(len) if len <= self.data_stack.len() => len,
+            _ => return Err(e),
+        };
+        self.data_stack.truncate(len)?;
+        if sig.returns_value {
+            self.data_stack.push(Value::Int(0))?;
+        }
+        if self.program.skipped_failures.borrow_mut().insert(opcode_pos) {
+            warn!("{}: skipping {:?} at 0x{:04x} that failed with {:?}",
+                self.program.name(), opcode, opcode_pos, e);
+        }
+        Ok(None)
     }
 
     fn next_instruction(&mut self) -> Result<Instruction> {
@@ -691,6 +834,8 @@ pub struct Vm {
     config: Rc<VmConfig>,
     program_handles: SlotMap<Handle, ()>,
     program_states: SecondaryMap<Handle, ProgramState>,
+    debugger: Option<Rc<RefCell<debug::Debugger>>>,
+    profiler: Option<Rc<RefCell<profile::Profiler>>>,
 }
 
 impl Vm {
@@ -699,6 +844,8 @@ impl Vm {
             config,
             program_handles: SlotMap::with_key(),
             program_states: SecondaryMap::new(),
+            debugger: None,
+            profiler: None,
         }
     }
 
@@ -707,12 +854,30 @@ impl Vm {
     }
 
     pub fn insert(&mut self, program: Rc<Program>) -> Handle {
-        let program_state = ProgramState::new(program);
+        let mut program_state = ProgramState::new(program);
+        program_state.debugger = self.debugger.clone();
+        program_state.profiler = self.profiler.clone();
         let h = self.program_handles.insert(());
         self.program_states.insert(h, program_state);
         h
     }
 
+    /// Attaches `debugger` to all existing and future programs.
+    pub fn set_debugger(&mut self, debugger: Option<Rc<RefCell<debug::Debugger>>>) {
+        for (_, state) in &mut self.program_states {
+            state.debugger = debugger.clone();
+        }
+        self.debugger = debugger;
+    }
+
+    /// Attaches `profiler` to all existing and future programs.
+    pub fn set_profiler(&mut self, profiler: Option<Rc<RefCell<profile::Profiler>>>) {
+        for (_, state) in &mut self.program_states {
+            state.profiler = profiler.clone();
+        }
+        self.profiler = profiler;
+    }
+
     pub fn run(&mut self, program: Handle, ctx: &mut Context) -> Result<InvocationResult> {
         self.program_state_mut(program).run(ctx)
     }
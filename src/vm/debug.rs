@@ -0,0 +1,821 @@
+//! Script debugger.
+//!
+//! The debugger is consulted before every instruction. When a breakpoint is hit or a step is
+//! finished the program is stopped in place and the debugger polls its `Frontend` for commands
+//! until the program is resumed. Nothing else runs while the program is stopped so the game world
+//! is frozen. Between the polls the idle function (see `Debugger::set_idle()`) is called which
+//! lets the game keep pumping window events and draw the debugger overlay. The time spent stopped
+//! is reported by `Debugger::take_stopped_time()` so the game can exclude it from the game time.
+
+use bstring::BString;
+use std::cmp;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io::{self, prelude::*};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Procedure, ProcedureFlag, ProcedureId, Program, ProgramState};
+use super::disasm;
+use super::value::{StringValue, Value};
+
+/// How long to sleep between polls of the frontend while a program is stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+const HELP: &str = "\
+c, continue           resume execution
+s, step               execute single instruction
+n, next               step over procedure calls
+o, out                run until the current procedure returns
+b, break BREAKPOINT   add breakpoint: PROC, PROGRAM:PROC or PROGRAM@OFFSET
+d, delete N           delete breakpoint N
+bl, breakpoints       list breakpoints
+w, where              print current location
+stack                 print data stack
+rstack                print return stack
+globals               print program global variables
+lvar                  print local variables (LVAR) of the program
+mvar                  print map variables (MVAR)
+gvar [N]              print game global variable (GVAR) N or all non-zero ones
+ext                   print external variables
+h, help               print this help
+";
+
+/// Place where the debugger stops the program.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Breakpoint {
+    /// Entry point of the procedure. `program` of `None` matches any program.
+    Proc {
+        program: Option<String>,
+        proc: String,
+    },
+    /// Code offset in the program.
+    Pos {
+        program: String,
+        pos: usize,
+    },
+}
+
+impl Breakpoint {
+    fn matches(&self, program: &Program, pos: usize) -> bool {
+        match self {
+            Breakpoint::Proc { program: prg, proc } => {
+                prg.as_ref().map(|p| p.eq_ignore_ascii_case(program.name())).unwrap_or(true)
+                    && program.procs.by_id.iter().any(|p| p.body_pos == pos
+                        && !p.flags.contains(ProcedureFlag::Import)
+                        && p.name.as_bytes().eq_ignore_ascii_case(proc.as_bytes()))
+            }
+            Breakpoint::Pos { program: prg, pos: p } => {
+                *p == pos && prg.eq_ignore_ascii_case(program.name())
+            }
+        }
+    }
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let bad = || format!("invalid breakpoint: {}", s);
+        Ok(if let Some(i) = s.find('@') {
+            let pos = &s[i + 1..];
+            let pos = if let Some(hex) = pos.strip_prefix("0x") {
+                usize::from_str_radix(hex, 16)
+            } else {
+                pos.parse()
+            }.map_err(|_| bad())?;
+            Breakpoint::Pos {
+                program: s[..i].into(),
+                pos,
+            }
+        } else if let Some(i) = s.find(':') {
+            Breakpoint::Proc {
+                program: Some(s[..i].into()),
+                proc: s[i + 1..].into(),
+            }
+        } else {
+            Breakpoint::Proc {
+                program: None,
+                proc: s.into(),
+            }
+        }).and_then(|bp| match &bp {
+            | Breakpoint::Proc { program: Some(p), .. }
+            | Breakpoint::Pos { program: p, .. }
+            if p.is_empty() => Err(bad()),
+            Breakpoint::Proc { proc, .. } if proc.is_empty() => Err(bad()),
+            _ => Ok(bp),
+        })
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Proc { program: Some(program), proc } => write!(f, "{}:{}", program, proc),
+            Breakpoint::Proc { program: None, proc } => write!(f, "{}", proc),
+            Breakpoint::Pos { program, pos } => write!(f, "{}@0x{:04x}", program, pos),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Continue,
+    Step,
+    Next,
+    Out,
+    Break(Breakpoint),
+    Delete(usize),
+    Breakpoints,
+    Where,
+    Stack,
+    ReturnStack,
+    Globals,
+    LocalVars,
+    MapVars,
+    GlobalVars(Option<usize>),
+    Externals,
+    Help,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        use Command::*;
+
+        let mut parts = s.split_whitespace();
+        let cmd = parts.next().unwrap_or("");
+        let arg = parts.next();
+        if parts.next().is_some() {
+            return Err(format!("too many arguments: {}", s));
+        }
+        let index = |arg: &str| arg.parse().map_err(|_| format!("invalid number: {}", arg));
+        let r = match cmd {
+            "c" | "continue" => Continue,
+            "s" | "step" => Step,
+            "n" | "next" => Next,
+            "o" | "out" => Out,
+            "b" | "break" => {
+                return Ok(Break(arg.ok_or_else(|| "missing breakpoint".to_owned())?.parse()?));
+            }
+            "d" | "delete" => {
+                return Ok(Delete(index(arg.ok_or_else(|| "missing breakpoint number".to_owned())?)?));
+            }
+            "bl" | "breakpoints" => Breakpoints,
+            "w" | "where" => Where,
+            "stack" => Stack,
+            "rstack" => ReturnStack,
+            "globals" => Globals,
+            "lvar" => LocalVars,
+            "mvar" => MapVars,
+            "gvar" => return Ok(GlobalVars(arg.map(index).transpose()?)),
+            "ext" => Externals,
+            "h" | "help" => Help,
+            _ => return Err(format!("unknown command: {} (type `help` for the list of commands)", cmd)),
+        };
+        if arg.is_some() {
+            return Err(format!("unexpected argument: {}", s));
+        }
+        Ok(r)
+    }
+}
+
+/// Source of the debugger commands and sink of its output.
+pub trait Frontend {
+    /// Returns the next command line. If `wait` is `true` blocks until the line is available and
+    /// returns `None` only if the input is closed.
+    fn read_line(&mut self, wait: bool) -> Option<String>;
+
+    /// Whether the input is closed and no more lines will be available.
+    fn is_closed(&self) -> bool;
+
+    fn write(&mut self, s: &str);
+}
+
+/// Frontend that reads commands from stdin and writes to stdout. Stdin is read in a background
+/// thread so commands can be queued while the game is running.
+pub struct Console {
+    lines: mpsc::Receiver<String>,
+    closed: bool,
+}
+
+impl Console {
+    pub fn spawn() -> Self {
+        let (tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                if line.ok().and_then(|l| tx.send(l).ok()).is_none() {
+                    break;
+                }
+            }
+        });
+        Self {
+            lines,
+            closed: false,
+        }
+    }
+}
+
+impl Frontend for Console {
+    fn read_line(&mut self, wait: bool) -> Option<String> {
+        let r = if wait {
+            self.lines.recv().map_err(|_| mpsc::TryRecvError::Disconnected)
+        } else {
+            self.lines.try_recv()
+        };
+        match r {
+            Ok(line) => Some(line),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.closed = true;
+                None
+            }
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn write(&mut self, s: &str) {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        let _ = stdout.write_all(s.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+/// Variables outside of the program state that can be inspected.
+pub struct Vars<'a> {
+    pub local_vars: &'a [i32],
+    pub map_vars: &'a [i32],
+    pub global_vars: &'a [i32],
+    pub external_vars: &'a HashMap<Rc<BString>, Option<Value>>,
+}
+
+#[derive(Clone)]
+enum Step {
+    Into,
+    /// Stops at the next instruction of the same procedure or after it returns.
+    Over {
+        program: Rc<Program>,
+        proc: Option<ProcedureId>,
+        depth: usize,
+    },
+    /// Stops when return stack becomes shorter than `depth`.
+    Out {
+        program: Rc<Program>,
+        depth: usize,
+    },
+}
+
+/// Function called while a program is stopped, see `Debugger::set_idle()`.
+pub type Idle = dyn FnMut(&Debugger);
+
+pub struct Debugger {
+    frontend: Box<dyn Frontend>,
+    idle: Option<Box<Idle>>,
+    breakpoints: Vec<Breakpoint>,
+    step: Option<Step>,
+    /// Location and instruction where the program was stopped last time.
+    last_stop: Option<String>,
+    /// Whether a program is stopped right now.
+    is_stopped: bool,
+    /// Time spent stopped since the last `take_stopped_time()`.
+    stopped_time: Option<Duration>,
+}
+
+impl Debugger {
+    pub fn new(frontend: Box<dyn Frontend>) -> Self {
+        Self {
+            frontend,
+            idle: None,
+            breakpoints: Vec::new(),
+            step: None,
+            last_stop: None,
+            is_stopped: false,
+            stopped_time: None,
+        }
+    }
+
+    /// Sets function that is called repeatedly while a program is stopped and waits for a command.
+    pub fn set_idle(&mut self, idle: Box<Idle>) {
+        self.idle = Some(idle);
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    /// Requests stop at the next executed instruction of any program.
+    pub fn break_next(&mut self) {
+        self.step = Some(Step::Into);
+    }
+
+    pub fn last_stop(&self) -> Option<&str> {
+        self.last_stop.as_deref()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.is_stopped
+    }
+
+    /// Returns the time spent stopped since the last call or `None` if no program has been
+    /// stopped. The game subtracts it from the frame time so the game time doesn't advance while
+    /// a program is stopped.
+    pub fn take_stopped_time(&mut self) -> Option<Duration> {
+        self.stopped_time.take()
+    }
+
+    /// Short summary of the debugger state for the in-game overlay.
+    pub fn status(&self) -> String {
+        let mut r = format!("script debugger: {} breakpoint(s)\n", self.breakpoints.len());
+        for (i, bp) in self.breakpoints.iter().enumerate() {
+            r += &format!("  {}: {}\n", i + 1, bp);
+        }
+        if self.step.is_some() {
+            r += "stepping\n";
+        }
+        if let Some(s) = &self.last_stop {
+            if self.is_stopped {
+                r += &format!("stopped at {}\n", s);
+            } else {
+                r += &format!("last stop: {}\n", s);
+            }
+        }
+        r
+    }
+
+    /// Handles commands that arrived while no program is stopped.
+    pub fn poll(&mut self) {
+        while let Some(line) = self.frontend.read_line(false) {
+            match line.trim().parse() {
+                Ok(Command::Step) => {
+                    self.break_next();
+                    self.frontend.write("will stop at the next instruction\n");
+                }
+                Ok(cmd) => self.execute(cmd, None),
+                Err(_) if line.trim().is_empty() => {}
+                Err(e) => self.frontend.write(&format!("{}\n", e)),
+            }
+        }
+    }
+
+    pub(super) fn before_step(&mut self, prg: &ProgramState, vars: &Vars) {
+        let pos = prg.code_pos;
+        let depth = prg.return_stack.len();
+        let stepped = match &self.step {
+            Some(Step::Into) => true,
+            Some(Step::Over { program, proc, depth: d }) => Rc::ptr_eq(program, &prg.program)
+                && (depth < *d
+                    || depth == *d && proc_at(program, pos).map(|(id, _)| id) == *proc),
+            Some(Step::Out { program, depth: d }) => Rc::ptr_eq(program, &prg.program) && depth < *d,
+            None => false,
+        };
+        let reason = if stepped {
+            "stopped".into()
+        } else if let Some(i) = self.breakpoints.iter().position(|b| b.matches(&prg.program, pos)) {
+            format!("breakpoint {}", i + 1)
+        } else {
+            return;
+        };
+        self.stop(prg, vars, &reason);
+    }
+
+    /// Called when the program returns control to the engine.
+    pub(super) fn on_halt(&mut self, prg: &ProgramState) {
+        let program = match &self.step {
+            Some(Step::Over { program, .. }) | Some(Step::Out { program, .. }) => program,
+            Some(Step::Into) | None => return,
+        };
+        if Rc::ptr_eq(program, &prg.program) {
+            // Stepped out of the procedure invoked by the engine.
+            self.step = Some(Step::Into);
+        }
+    }
+
+    fn stop(&mut self, prg: &ProgramState, vars: &Vars, reason: &str) {
+        let start = Instant::now();
+        self.step = None;
+        self.is_stopped = true;
+        let location = location(prg);
+        self.frontend.write(&format!("{} at {}\n", reason, location));
+        self.last_stop = Some(location);
+        self.wait_resume(prg, vars);
+        self.is_stopped = false;
+        *self.stopped_time.get_or_insert(Duration::from_secs(0)) += start.elapsed();
+    }
+
+    /// Executes commands until the one that resumes the program.
+    fn wait_resume(&mut self, prg: &ProgramState, vars: &Vars) {
+        loop {
+            self.frontend.write("(debug) ");
+            let line = if let Some(v) = self.wait_line() {
+                v
+            } else {
+                break;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let cmd = match line.trim().parse() {
+                Ok(v) => v,
+                Err(e) => {
+                    self.frontend.write(&format!("{}\n", e));
+                    continue;
+                }
+            };
+            let depth = prg.return_stack.len();
+            match cmd {
+                Command::Continue => break,
+                Command::Step => {
+                    self.step = Some(Step::Into);
+                    break;
+                }
+                Command::Next => {
+                    self.step = Some(Step::Over {
+                        program: prg.program.clone(),
+                        proc: proc_at(&prg.program, prg.code_pos).map(|(id, _)| id),
+                        depth,
+                    });
+                    break;
+                }
+                Command::Out => {
+                    self.step = Some(Step::Out {
+                        program: prg.program.clone(),
+                        depth,
+                    });
+                    break;
+                }
+                cmd => self.execute(cmd, Some((prg, vars))),
+            }
+        }
+    }
+
+    /// Polls the frontend until the next line is available calling the idle function in between.
+    /// Returns `None` if the input is closed.
+    fn wait_line(&mut self) -> Option<String> {
+        loop {
+            if let Some(line) = self.frontend.read_line(false) {
+                return Some(line);
+            }
+            if self.frontend.is_closed() {
+                return None;
+            }
+            if let Some(mut idle) = self.idle.take() {
+                idle(self);
+                self.idle = Some(idle);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn execute(&mut self, cmd: Command, stopped: Option<(&ProgramState, &Vars)>) {
+        use Command::*;
+        let mut out = match cmd {
+            Break(bp) => {
+                let r = format!("breakpoint {}: {}", self.breakpoints.len() + 1, bp);
+                self.breakpoints.push(bp);
+                r
+            }
+            Delete(i) => if i >= 1 && i <= self.breakpoints.len() {
+                self.breakpoints.remove(i - 1);
+                format!("deleted breakpoint {}", i)
+            } else {
+                format!("no breakpoint {}", i)
+            }
+            Breakpoints => if self.breakpoints.is_empty() {
+                "no breakpoints".into()
+            } else {
+                let l: Vec<_> = self.breakpoints.iter().enumerate()
+                    .map(|(i, bp)| format!("{}: {}", i + 1, bp))
+                    .collect();
+                l.join("\n")
+            }
+            Help => HELP.trim_end().into(),
+            Continue | Next | Out => "no program is stopped".into(),
+            cmd => if let Some((prg, vars)) = stopped {
+                inspect(cmd, prg, vars)
+            } else {
+                "no program is stopped".into()
+            }
+        };
+        out.push('\n');
+        self.frontend.write(&out);
+    }
+}
+
+fn inspect(cmd: Command, prg: &ProgramState, vars: &Vars) -> String {
+    let program = &prg.program;
+    let ints = |vals: &[i32]| -> String {
+        if vals.is_empty() {
+            return "none".into();
+        }
+        let l: Vec<_> = vals.iter().enumerate().map(|(i, v)| format!("{}: {}", i, v)).collect();
+        l.join("\n")
+    };
+    let values = |vals: &[Value], start: usize, marks: &[(Option<usize>, &str)]| -> String {
+        if vals.is_empty() {
+            return "empty".into();
+        }
+        let l: Vec<_> = vals.iter().enumerate()
+            .map(|(i, v)| {
+                let mut s = format!("{}: {}", start + i, value(program, v));
+                for &(mark, name) in marks {
+                    if mark == Some(start + i) {
+                        s += "  <- ";
+                        s += name;
+                    }
+                }
+                s
+            })
+            .collect();
+        l.join("\n")
+    };
+    match cmd {
+        Command::Where => format!("{}\n{}", location(prg), instr(program, prg.code_pos)),
+        Command::Stack => values(prg.data_stack.as_slice(), 0,
+            &[(prg.global_base, "global_base"), (prg.base, "base")]),
+        Command::ReturnStack => values(prg.return_stack.as_slice(), 0, &[]),
+        Command::Globals => {
+            let stack = prg.data_stack.as_slice();
+            match (prg.global_base, prg.global_count) {
+                (Some(base), Some(count)) if base + count <= stack.len() =>
+                    if count == 0 {
+                        "none".into()
+                    } else {
+                        values(&stack[base..base + count], 0, &[])
+                    }
+                _ => "program globals aren't initialized".into(),
+            }
+        }
+        Command::LocalVars => ints(vars.local_vars),
+        Command::MapVars => ints(vars.map_vars),
+        Command::GlobalVars(Some(i)) => vars.global_vars.get(i)
+            .map(|v| format!("{}: {}", i, v))
+            .unwrap_or_else(|| format!("no global variable {}", i)),
+        Command::GlobalVars(None) => {
+            let l: Vec<_> = vars.global_vars.iter().enumerate()
+                .filter(|&(_, &v)| v != 0)
+                .map(|(i, v)| format!("{}: {}", i, v))
+                .collect();
+            if l.is_empty() {
+                "all zero".into()
+            } else {
+                l.join("\n")
+            }
+        }
+        Command::Externals => {
+            let mut l: Vec<_> = vars.external_vars.iter()
+                .map(|(k, v)| format!("{}: {}", k.display(),
+                    v.as_ref().map(|v| value(program, v)).unwrap_or_else(|| "undefined".into())))
+                .collect();
+            l.sort();
+            if l.is_empty() {
+                "none".into()
+            } else {
+                l.join("\n")
+            }
+        }
+        | Command::Break(_)
+        | Command::Breakpoints
+        | Command::Continue
+        | Command::Delete(_)
+        | Command::Help
+        | Command::Next
+        | Command::Out
+        | Command::Step
+        => unreachable!(),
+    }
+}
+
+/// Returns procedure which body contains `pos`.
+fn proc_at(program: &Program, pos: usize) -> Option<(ProcedureId, &Procedure)> {
+    program.procs.by_id.iter()
+        .enumerate()
+        .filter(|(_, p)| !p.flags.contains(ProcedureFlag::Import) && p.body_pos <= pos)
+        .max_by_key(|(_, p)| p.body_pos)
+        .map(|(id, p)| (id as ProcedureId, p))
+}
+
+fn location(prg: &ProgramState) -> String {
+    let program = &prg.program;
+    let pos = prg.code_pos;
+    if let Some((_, proc)) = proc_at(program, pos) {
+        format!("{}:{}+0x{:x} (0x{:04x})", program.name(), proc.name.display(), pos - proc.body_pos,
+            pos)
+    } else {
+        format!("{}@0x{:04x}", program.name(), pos)
+    }
+}
+
+fn instr(program: &Program, pos: usize) -> String {
+    // Enough for the instruction with operand and the next opcode.
+    let end = cmp::min(pos + 8, program.code.len());
+    let region = disasm::decode(&program.code, pos, end, Vec::new());
+    let mut r = Vec::new();
+    if let Some(instr) = region.instrs.first() {
+        let next = region.instrs.get(1).and_then(|i| i.opcode);
+        disasm::write_instr(program, instr, next, &BTreeSet::new(), &mut r).unwrap();
+    }
+    String::from_utf8_lossy(&r).trim().into()
+}
+
+fn value(program: &Program, v: &Value) -> String {
+    match v {
+        Value::Int(v) => v.to_string(),
+        Value::Float(v) => format!("{:?}", v),
+        Value::String(s) => match s.clone().resolve(&program.strings) {
+            Ok(s) => format!("{:?}", String::from_utf8_lossy(s.as_bytes())),
+            Err(_) => match s {
+                StringValue::Indirect(i) => format!("<invalid string {}>", i),
+                StringValue::Direct(_) => unreachable!(),
+            }
+        }
+        Value::Object(Some(h)) => format!("{:?}", h),
+        Value::Object(None) => "null object".into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use crate::vm::instruction::Opcode;
+    use crate::vm::test::{Asm::*, Proc, program};
+
+    struct Scripted {
+        input: Rc<RefCell<VecDeque<&'static str>>>,
+        output: Rc<RefCell<String>>,
+        /// Whether the input is closed once exhausted.
+        closing: bool,
+    }
+
+    impl Frontend for Scripted {
+        fn read_line(&mut self, _wait: bool) -> Option<String> {
+            self.input.borrow_mut().pop_front().map(|s| s.into())
+        }
+
+        fn is_closed(&self) -> bool {
+            self.closing && self.input.borrow().is_empty()
+        }
+
+        fn write(&mut self, s: &str) {
+            self.output.borrow_mut().push_str(s);
+        }
+    }
+
+    #[test]
+    fn parse_command() {
+        assert_eq!("c".parse(), Ok(Command::Continue));
+        assert_eq!("break foo".parse(), Ok(Command::Break(Breakpoint::Proc {
+            program: None,
+            proc: "foo".into(),
+        })));
+        assert_eq!("b artemple:talk_p_proc".parse(), Ok(Command::Break(Breakpoint::Proc {
+            program: Some("artemple".into()),
+            proc: "talk_p_proc".into(),
+        })));
+        assert_eq!("b artemple@0x1a".parse(), Ok(Command::Break(Breakpoint::Pos {
+            program: "artemple".into(),
+            pos: 0x1a,
+        })));
+        assert_eq!("gvar".parse(), Ok(Command::GlobalVars(None)));
+        assert_eq!("gvar 12".parse(), Ok(Command::GlobalVars(Some(12))));
+        assert!("b @12".parse::<Command>().is_err());
+        assert!("step 1".parse::<Command>().is_err());
+        assert!("gvar x".parse::<Command>().is_err());
+        assert!("foo".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn stop() {
+        use Opcode::*;
+
+        let (program, labels) = program(&["start", "foo"], &["hi"], &[
+            Proc { name: 0, flags: 0, arg_count: 0, body: "start" },
+            Proc { name: 1, flags: 0, arg_count: 0, body: "foo" },
+        ], &[
+            Label("start"), Int(1), Str(0), Op(Add),
+            Label("foo"), Op(Noop8000), Op(Noop8000),
+        ]);
+        let mut prg = ProgramState::new(Rc::new(program));
+        let external_vars = HashMap::new();
+        let vars = Vars {
+            local_vars: &[4, 5],
+            map_vars: &[],
+            global_vars: &[0, 0, 7],
+            external_vars: &external_vars,
+        };
+
+        let input = Rc::new(RefCell::new(VecDeque::new()));
+        let out = Rc::new(RefCell::new(String::new()));
+        let mut d = Debugger::new(Box::new(Scripted {
+            input: input.clone(),
+            output: out.clone(),
+            closing: true,
+        }));
+        let feed = |lines: &[&'static str]| input.borrow_mut().extend(lines);
+
+        feed(&["b FOO", "bl", "where"]);
+        d.poll();
+        assert_eq!(d.breakpoints().len(), 1);
+
+        prg.code_pos = labels["start"];
+        d.before_step(&prg, &vars);
+        assert!(d.take_stopped_time().is_none());
+
+        prg.data_stack.push(Value::Int(1)).unwrap();
+        prg.data_stack.push(Value::String(StringValue::Indirect(6))).unwrap();
+        prg.base = Some(1);
+        prg.code_pos = labels["foo"];
+        feed(&["stack", "gvar", "s"]);
+        d.before_step(&prg, &vars);
+        assert!(d.take_stopped_time().is_some());
+        assert_eq!(d.last_stop(), Some("test:foo+0x0 (0x008f)"));
+
+        // Step into.
+        prg.code_pos += 2;
+        feed(&["where", "n"]);
+        d.before_step(&prg, &vars);
+        assert!(d.take_stopped_time().is_some());
+
+        // Step over doesn't stop in another procedure on the same depth.
+        prg.code_pos = labels["start"];
+        d.before_step(&prg, &vars);
+        assert!(d.take_stopped_time().is_none());
+        prg.code_pos = labels["foo"] + 2;
+        feed(&["c"]);
+        d.before_step(&prg, &vars);
+        assert!(d.take_stopped_time().is_some());
+
+        assert_eq!(&*out.borrow(), "\
+breakpoint 1: FOO
+1: FOO
+no program is stopped
+breakpoint 1 at test:foo+0x0 (0x008f)
+(debug) 0: 1
+1: \"hi\"  <- base
+(debug) 2: 7
+(debug) stopped at test:foo+0x2 (0x0091)
+(debug) test:foo+0x2 (0x0091)
+0x0091  noop8000
+(debug) stopped at test:foo+0x2 (0x0091)
+(debug) ");
+    }
+
+    #[test]
+    fn idle_while_stopped() {
+        let (program, labels) = program(&["start"], &[], &[
+            Proc { name: 0, flags: 0, arg_count: 0, body: "start" },
+        ], &[
+            Label("start"), Op(Opcode::Noop8000),
+        ]);
+        let mut prg = ProgramState::new(Rc::new(program));
+        prg.code_pos = labels["start"];
+        let external_vars = HashMap::new();
+        let vars = Vars {
+            local_vars: &[],
+            map_vars: &[],
+            global_vars: &[],
+            external_vars: &external_vars,
+        };
+
+        let input = Rc::new(RefCell::new(VecDeque::new()));
+        let out = Rc::new(RefCell::new(String::new()));
+        let mut d = Debugger::new(Box::new(Scripted {
+            input: input.clone(),
+            output: out.clone(),
+            closing: false,
+        }));
+        let idle_count = Rc::new(RefCell::new(0));
+        d.set_idle(Box::new({
+            let idle_count = idle_count.clone();
+            move |d| {
+                assert!(d.is_stopped());
+                assert!(d.status().contains("stopped at test:start+0x0"));
+                let mut idle_count = idle_count.borrow_mut();
+                *idle_count += 1;
+                if *idle_count == 3 {
+                    input.borrow_mut().push_back("c");
+                }
+            }
+        }));
+
+        d.break_next();
+        d.before_step(&prg, &vars);
+        assert!(d.take_stopped_time().is_some());
+        assert_eq!(*idle_count.borrow(), 3);
+        assert_eq!(&*out.borrow(), "stopped at test:start+0x0 (0x005e)\n(debug) ");
+    }
+}
@@ -0,0 +1,1097 @@
+//! Decompiler of the compiled `.int` programs into SSL source.
+//!
+//! Procedures are executed symbolically: the data and return stacks hold expressions instead of
+//! values and instructions that consume values without producing new ones become statements.
+//! `if` and `while` statements are recovered from the conditional jumps and the
+//! `const_long addr; jmp` pairs that end the branches and loop bodies.
+//!
+//! The calling convention isn't hardcoded. The stack effect of a procedure call is taken from what
+//! the return sequence of the callee does with the values below its frame.
+
+use matches::matches;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{self, prelude::*};
+
+use super::*;
+use super::disasm::{self, Entry, Instr, Operand, Region};
+
+const INDENT: &str = "    ";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum StackKind {
+    Data,
+    Return,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    And,
+    Or,
+    Bwand,
+    Bwor,
+    Bwxor,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl BinaryOp {
+    fn from_opcode(opcode: Opcode) -> Option<Self> {
+        use BinaryOp::*;
+        Some(match opcode {
+            Opcode::Add => Add,
+            Opcode::Sub => Sub,
+            Opcode::Mul => Mul,
+            Opcode::Div => Div,
+            Opcode::Mod => Mod,
+            Opcode::And => And,
+            Opcode::Or => Or,
+            Opcode::Bwand => Bwand,
+            Opcode::Bwor => Bwor,
+            Opcode::Bwxor => Bwxor,
+            Opcode::Equal => Equal,
+            Opcode::NotEqual => NotEqual,
+            Opcode::Less => Less,
+            Opcode::LessEqual => LessEqual,
+            Opcode::Greater => Greater,
+            Opcode::GreaterEqual => GreaterEqual,
+            _ => return None,
+        })
+    }
+
+    fn symbol(self) -> &'static str {
+        use BinaryOp::*;
+        match self {
+            Add => "+",
+            Sub => "-",
+            Mul => "*",
+            Div => "/",
+            Mod => "%",
+            And => "and",
+            Or => "or",
+            Bwand => "bwand",
+            Bwor => "bwor",
+            Bwxor => "bwxor",
+            Equal => "==",
+            NotEqual => "!=",
+            Less => "<",
+            LessEqual => "<=",
+            Greater => ">",
+            GreaterEqual => ">=",
+        }
+    }
+
+    /// Bitwise operators get the lowest precedence so they're always parenthesized when nested.
+    fn precedence(self) -> u32 {
+        use BinaryOp::*;
+        match self {
+            Bwand | Bwor | Bwxor => 0,
+            Or => 1,
+            And => 2,
+            Equal | NotEqual | Less | LessEqual | Greater | GreaterEqual => 3,
+            Add | Sub => 4,
+            Mul | Div | Mod => 5,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum UnaryOp {
+    Not,
+    Negate,
+    Bwnot,
+}
+
+impl UnaryOp {
+    fn from_opcode(opcode: Opcode) -> Option<Self> {
+        Some(match opcode {
+            Opcode::Not => UnaryOp::Not,
+            Opcode::Negate => UnaryOp::Negate,
+            Opcode::Bwnot => UnaryOp::Bwnot,
+            _ => return None,
+        })
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Not => "not ",
+            UnaryOp::Negate => "-",
+            UnaryOp::Bwnot => "bwnot ",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Callee {
+    Opcode,
+    Proc,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Int(i32),
+    Float(f32),
+    /// Offset in the string table (or in the name table depending on the consumer).
+    String(i32),
+    Var(String),
+    /// Procedure argument that was pushed by the caller.
+    Arg(usize),
+    Call(Callee, String, Vec<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// Value that was on the stack below the procedure frame.
+    Below(StackKind, usize),
+    /// Base pointer saved by `push_base`.
+    Base(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Stmt {
+    Expr(Expr),
+    Assign(String, Expr),
+    Return(Option<Expr>),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Break,
+    Continue,
+    Comment(String),
+}
+
+impl Stmt {
+    fn is_jump(&self) -> bool {
+        matches!(self, Stmt::Return(_) | Stmt::Break | Stmt::Continue)
+    }
+}
+
+/// Stack value along with the code position where its computation starts.
+#[derive(Clone, Debug)]
+struct Item {
+    start: usize,
+    expr: Expr,
+}
+
+#[derive(Clone)]
+struct SymStack {
+    kind: StackKind,
+    items: Vec<Item>,
+    /// Number of values popped from below the initial stack bottom.
+    below: usize,
+}
+
+impl SymStack {
+    fn new(kind: StackKind) -> Self {
+        Self {
+            kind,
+            items: Vec::new(),
+            below: 0,
+        }
+    }
+
+    fn push(&mut self, item: Item) {
+        self.items.push(item);
+    }
+
+    fn pop(&mut self, pos: usize) -> Item {
+        if let Some(v) = self.items.pop() {
+            v
+        } else {
+            self.below += 1;
+            Item {
+                start: pos,
+                expr: Expr::Below(self.kind, self.below - 1),
+            }
+        }
+    }
+}
+
+/// Net effect of a procedure call on the caller stacks after the procedure id, argument count
+/// and arguments are popped.
+#[derive(Clone, Debug)]
+struct CallEffect {
+    data_pops: usize,
+    ret_pops: usize,
+    /// Values left on the stacks. `Expr::Below` and `Expr::Arg` refer to the caller values,
+    /// anything else is the procedure return value.
+    data: Vec<Expr>,
+    ret: Vec<Expr>,
+}
+
+#[derive(Clone, Copy)]
+struct Loop {
+    start: usize,
+    end: usize,
+}
+
+#[derive(Clone)]
+struct StackState {
+    data: SymStack,
+    ret: SymStack,
+    base: Option<usize>,
+}
+
+#[derive(Default)]
+struct Body {
+    stmts: Vec<Stmt>,
+    /// Initial values of the procedure local variables.
+    locals: BTreeMap<usize, Option<Expr>>,
+    /// Initial values of the program global variables if the body has set the global base.
+    global_inits: Option<Vec<Expr>>,
+    globals: BTreeSet<usize>,
+    exports: BTreeSet<String>,
+    effect: Option<CallEffect>,
+}
+
+/// Writes SSL source reconstructed from the `program`. Constructs that can't be represented in SSL
+/// are written as comments.
+pub fn decompile(program: &Program, wr: &mut impl Write) -> io::Result<()> {
+    let dc = Decompiler {
+        program,
+        regions: disasm::regions(program),
+        effects: RefCell::new(HashMap::new()),
+    };
+
+    let mut globals = BTreeSet::new();
+    let mut exports = BTreeSet::new();
+    let mut global_inits = Vec::new();
+    for region in dc.regions.iter().filter(|r| r.entries.is_empty() || r.entries == [Entry::Startup]) {
+        let body = dc.body(&region.instrs, region.end, None);
+        globals.extend(body.globals);
+        exports.extend(body.exports);
+        if let Some(v) = body.global_inits {
+            global_inits = v;
+        }
+    }
+
+    let bodies: Vec<_> = (0..program.procs.by_id.len() as ProcedureId)
+        .map(|id| {
+            let body = dc.proc_body(id);
+            if let Some(body) = &body {
+                globals.extend(&body.globals);
+                exports.extend(body.exports.iter().cloned());
+            }
+            body
+        })
+        .collect();
+    globals.extend(0..global_inits.len());
+
+    writeln!(wr, "/* Decompiled from {} */", program.name())?;
+
+    writeln!(wr)?;
+    for proc in &program.procs.by_id {
+        writeln!(wr, "{};", proc_header(proc))?;
+    }
+
+    if !globals.is_empty() {
+        writeln!(wr)?;
+        for &id in &globals {
+            let mut s = format!("variable {}", global_name(id));
+            if let Some(v) = global_inits.get(id).filter(|v| **v != Expr::Int(0)) {
+                s += " := ";
+                dc.write_expr(v, &mut s);
+            }
+            writeln!(wr, "{};", s)?;
+        }
+    }
+
+    if !exports.is_empty() {
+        writeln!(wr)?;
+        for name in &exports {
+            writeln!(wr, "export variable {};", name)?;
+        }
+    }
+
+    for (proc, body) in program.procs.by_id.iter().zip(&bodies) {
+        if proc.flags.contains(ProcedureFlag::Import) {
+            continue;
+        }
+        writeln!(wr)?;
+        if proc.flags.contains(ProcedureFlag::Timed) {
+            writeln!(wr, "/* timed: delay {}ms */", proc.delay.as_millis())?;
+        }
+        if proc.flags.contains(ProcedureFlag::Conditional) {
+            writeln!(wr, "/* conditional: condition at 0x{:04x} */", proc.condition_pos)?;
+        }
+        let body = if let Some(v) = body {
+            v
+        } else {
+            writeln!(wr, "/* {}: invalid body position 0x{:04x} */",
+                proc.name.display(), proc.body_pos)?;
+            continue;
+        };
+        let mut s = format!("{} begin\n", proc_header(proc));
+        for (&i, init) in &body.locals {
+            s += INDENT;
+            s += &format!("variable {}", local_name(i));
+            if let Some(v) = init.as_ref().filter(|v| **v != Expr::Int(0)) {
+                s += " := ";
+                dc.write_expr(v, &mut s);
+            }
+            s += ";\n";
+        }
+        if !body.locals.is_empty() && !body.stmts.is_empty() {
+            s += "\n";
+        }
+        dc.write_block(&body.stmts, 1, &mut s);
+        s += "end\n";
+        wr.write_all(s.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+struct Decompiler<'a> {
+    program: &'a Program,
+    regions: Vec<Region>,
+    /// Memoized call effects. `None` while the procedure is being analyzed or if it has no
+    /// return instruction.
+    effects: RefCell<HashMap<ProcedureId, Option<CallEffect>>>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn proc_body(&self, id: ProcedureId) -> Option<Body> {
+        let proc = self.program.proc(id)?;
+        if proc.flags.contains(ProcedureFlag::Import) {
+            return None;
+        }
+        let region = self.regions.iter().find(|r| r.entries.contains(&Entry::Body(id)))?;
+        let mut body = self.body(&region.instrs, region.end, Some(proc.arg_count));
+        if matches!(body.stmts.last(), Some(Stmt::Return(None)) | Some(Stmt::Return(Some(Expr::Int(0))))) {
+            // Implicit return at the end of procedure.
+            body.stmts.pop();
+        }
+        Some(body)
+    }
+
+    /// `arg_count` is `None` for the code that isn't a procedure body.
+    fn body(&self, instrs: &[Instr], end: usize, arg_count: Option<usize>) -> Body {
+        let mut m = Machine {
+            dc: self,
+            instrs,
+            next: 0,
+            arg_count: arg_count.unwrap_or(0),
+            data: SymStack::new(StackKind::Data),
+            ret: SymStack::new(StackKind::Return),
+            base: None,
+            saved_bases: Vec::new(),
+            global_base: None,
+            body: Body::default(),
+        };
+        if let Some(arg_count) = arg_count {
+            for i in 0..arg_count {
+                m.data.push(Item { start: 0, expr: Expr::Arg(i) });
+            }
+            m.data.push(Item { start: 0, expr: Expr::Int(arg_count as i32) });
+        }
+        m.body.stmts = m.block(end, None);
+        if let Some(global_base) = m.global_base {
+            m.body.global_inits = Some(m.data.items.get(global_base..).unwrap_or(&[]).iter()
+                .map(|i| i.expr.clone())
+                .collect());
+        }
+        m.body
+    }
+
+    fn effect(&self, id: ProcedureId) -> Option<CallEffect> {
+        if let Some(v) = self.effects.borrow().get(&id) {
+            return v.clone();
+        }
+        self.effects.borrow_mut().insert(id, None);
+        let r = self.proc_body(id).and_then(|b| b.effect);
+        self.effects.borrow_mut().insert(id, r.clone());
+        r
+    }
+
+    fn string(&self, id: i32, name: bool) -> Option<String> {
+        let (primary, secondary) = if name {
+            (&self.program.names, &self.program.strings)
+        } else {
+            (&self.program.strings, &self.program.names)
+        };
+        let id = id as usize;
+        primary.get(id).or_else(|| secondary.get(id))
+            .map(|s| String::from_utf8_lossy(s.as_bytes()).into_owned())
+    }
+
+    fn write_block(&self, stmts: &[Stmt], indent: usize, out: &mut String) {
+        for stmt in stmts {
+            self.write_indent(indent, out);
+            self.write_stmt(stmt, indent, out);
+        }
+    }
+
+    fn write_stmt(&self, stmt: &Stmt, indent: usize, out: &mut String) {
+        match stmt {
+            Stmt::Expr(e) => {
+                if let Expr::Call(Callee::Proc, ..) = e {
+                    *out += "call ";
+                }
+                self.write_expr(e, out);
+                *out += ";\n";
+            }
+            Stmt::Assign(name, e) => {
+                *out += name;
+                *out += " := ";
+                self.write_expr(e, out);
+                *out += ";\n";
+            }
+            Stmt::Return(e) => {
+                *out += "return";
+                if let Some(e) = e {
+                    *out += " ";
+                    self.write_expr(e, out);
+                }
+                *out += ";\n";
+            }
+            Stmt::If(cond, then, else_) => {
+                *out += "if (";
+                self.write_expr(cond, out);
+                *out += ") then begin\n";
+                self.write_block(then, indent + 1, out);
+                self.write_end(indent, out);
+                if let [stmt @ Stmt::If(..)] = &else_[..] {
+                    self.write_indent(indent, out);
+                    *out += "else ";
+                    self.write_stmt(stmt, indent, out);
+                } else if !else_.is_empty() {
+                    self.write_indent(indent, out);
+                    *out += "else begin\n";
+                    self.write_block(else_, indent + 1, out);
+                    self.write_end(indent, out);
+                }
+            }
+            Stmt::While(cond, body) => {
+                *out += "while (";
+                self.write_expr(cond, out);
+                *out += ") do begin\n";
+                self.write_block(body, indent + 1, out);
+                self.write_end(indent, out);
+            }
+            Stmt::Break => *out += "break;\n",
+            Stmt::Continue => *out += "continue;\n",
+            Stmt::Comment(s) => *out += &format!("/* {} */\n", s),
+        }
+    }
+
+    fn write_indent(&self, indent: usize, out: &mut String) {
+        for _ in 0..indent {
+            *out += INDENT;
+        }
+    }
+
+    fn write_end(&self, indent: usize, out: &mut String) {
+        self.write_indent(indent, out);
+        *out += "end\n";
+    }
+
+    fn write_expr(&self, expr: &Expr, out: &mut String) {
+        fn precedence(e: &Expr) -> u32 {
+            match e {
+                Expr::Binary(op, ..) => op.precedence(),
+                Expr::Unary(..) => 6,
+                _ => 7,
+            }
+        }
+
+        match expr {
+            Expr::Int(v) => *out += &v.to_string(),
+            Expr::Float(v) => *out += &format!("{:?}", v),
+            Expr::String(v) => {
+                if let Some(s) = self.string(*v, false) {
+                    *out += &format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+                } else {
+                    *out += &format!("/* invalid string {} */ \"\"", v);
+                }
+            }
+            Expr::Var(name) => *out += name,
+            Expr::Arg(i) => *out += &arg_name(*i),
+            Expr::Call(_, name, args) => {
+                *out += name;
+                if !args.is_empty() {
+                    *out += "(";
+                    for (i, arg) in args.iter().enumerate() {
+                        if i > 0 {
+                            *out += ", ";
+                        }
+                        self.write_expr(arg, out);
+                    }
+                    *out += ")";
+                }
+            }
+            Expr::Unary(op, e) => {
+                *out += op.symbol();
+                self.write_operand(e, precedence(e) < 7, out);
+            }
+            Expr::Binary(op, l, r) => {
+                let p = op.precedence();
+                let nested_bitwise = |e: &Expr| p == 0 && matches!(e, Expr::Binary(..));
+                self.write_operand(l, precedence(l) < p || nested_bitwise(l), out);
+                *out += " ";
+                *out += op.symbol();
+                *out += " ";
+                self.write_operand(r, precedence(r) <= p || nested_bitwise(r), out);
+            }
+            Expr::Below(..) | Expr::Base(_) => *out += "/*?*/",
+        }
+    }
+
+    fn write_operand(&self, e: &Expr, parens: bool, out: &mut String) {
+        if parens {
+            *out += "(";
+        }
+        self.write_expr(e, out);
+        if parens {
+            *out += ")";
+        }
+    }
+}
+
+/// Symbolic execution state of a single code region.
+struct Machine<'a> {
+    dc: &'a Decompiler<'a>,
+    instrs: &'a [Instr],
+    /// Index of the next instruction in `instrs`.
+    next: usize,
+    arg_count: usize,
+    data: SymStack,
+    ret: SymStack,
+    /// Index in `data` where the procedure variables start.
+    base: Option<usize>,
+    saved_bases: Vec<Option<usize>>,
+    global_base: Option<usize>,
+    body: Body,
+}
+
+impl<'a> Machine<'a> {
+    fn block(&mut self, end: usize, loop_: Option<Loop>) -> Vec<Stmt> {
+        let instrs = self.instrs;
+        let mut r = Vec::new();
+        while let Some(instr) = instrs.get(self.next).filter(|i| i.pos < end) {
+            self.next += 1;
+            self.instr(instr, end, loop_, &mut r);
+        }
+        r
+    }
+
+    fn instr(&mut self, instr: &Instr, end: usize, loop_: Option<Loop>, out: &mut Vec<Stmt>) {
+        use Opcode::*;
+
+        let pos = instr.pos;
+        let opcode = if let Some(v) = instr.opcode {
+            v
+        } else {
+            out.push(Stmt::Comment(format!("0x{:04x}: unknown opcode 0x{:04x}",
+                pos, instr.raw_opcode)));
+            return;
+        };
+        match opcode {
+            ConstLong | ConstFloat | ConstString => {
+                let expr = match instr.operand.unwrap() {
+                    Operand::Int(v) => Expr::Int(v),
+                    Operand::Float(v) => Expr::Float(v),
+                    Operand::String(v) => Expr::String(v),
+                };
+                self.data.push(Item { start: pos, expr });
+            }
+            CriticalDone | CriticalDone804b | CriticalStart | CriticalStart804a
+                | Noop8000 | Noop80d1 => {}
+            Jmp => {
+                let target = self.data.pop(pos);
+                let stmt = match (&target.expr, loop_) {
+                    (&Expr::Int(v), Some(l)) if v as usize == l.start => Stmt::Continue,
+                    (&Expr::Int(v), Some(l)) if v as usize == l.end => Stmt::Break,
+                    (e, _) => {
+                        let mut s = format!("0x{:04x}: goto ", pos);
+                        self.dc.write_expr(e, &mut s);
+                        Stmt::Comment(s)
+                    }
+                };
+                out.push(stmt);
+            }
+            If | While => self.if_(pos, end, loop_, out),
+            Call => self.call(pos, out),
+            Fetch => {
+                let id = self.data.pop(pos);
+                let expr = Expr::Var(self.proc_var(&id.expr));
+                self.data.push(Item { start: id.start, expr });
+            }
+            Store => {
+                let id = self.data.pop(pos);
+                let value = self.data.pop(pos);
+                out.push(Stmt::Assign(self.proc_var(&id.expr), value.expr));
+            }
+            FetchGlobal => {
+                let id = self.data.pop(pos);
+                let expr = Expr::Var(self.global_var(&id.expr));
+                self.data.push(Item { start: id.start, expr });
+            }
+            StoreGlobal => {
+                let id = self.data.pop(pos);
+                let value = self.data.pop(pos);
+                out.push(Stmt::Assign(self.global_var(&id.expr), value.expr));
+            }
+            FetchExternal => {
+                let name = self.data.pop(pos);
+                let expr = Expr::Var(self.external_var(&name.expr));
+                self.data.push(Item { start: name.start, expr });
+            }
+            StoreExternal => {
+                let name = self.data.pop(pos);
+                let value = self.data.pop(pos);
+                out.push(Stmt::Assign(self.external_var(&name.expr), value.expr));
+            }
+            ExportVar => {
+                let name = self.data.pop(pos);
+                let name = self.external_var(&name.expr);
+                self.body.exports.insert(name);
+            }
+            ExportProc => {
+                self.data.pop(pos);
+            }
+            Dup => {
+                let v = self.data.pop(pos);
+                self.data.push(v.clone());
+                self.data.push(v);
+            }
+            Pop => {
+                let v = self.data.pop(pos);
+                if let Expr::Call(..) = v.expr {
+                    out.push(Stmt::Expr(v.expr));
+                }
+            }
+            Swap => {
+                let v1 = self.data.pop(pos);
+                let v2 = self.data.pop(pos);
+                self.data.push(v1);
+                self.data.push(v2);
+            }
+            Swapa => {
+                let v1 = self.ret.pop(pos);
+                let v2 = self.ret.pop(pos);
+                self.ret.push(v1);
+                self.ret.push(v2);
+            }
+            AToD => {
+                let v = self.ret.pop(pos);
+                self.data.push(v);
+            }
+            DToA => {
+                let v = self.data.pop(pos);
+                self.ret.push(v);
+            }
+            PushBase => {
+                let arg_count = self.data.pop(pos);
+                self.ret.push(Item { start: pos, expr: Expr::Base(self.saved_bases.len()) });
+                self.saved_bases.push(self.base);
+                self.base = match arg_count.expr {
+                    Expr::Int(v) if v >= 0 => self.data.items.len().checked_sub(v as usize),
+                    _ => None,
+                };
+            }
+            PopBase => {
+                self.base = match self.ret.pop(pos).expr {
+                    Expr::Base(i) => self.saved_bases[i],
+                    _ => None,
+                };
+            }
+            PopToBase => {
+                if let Some(base) = self.base {
+                    self.data.items.truncate(base);
+                }
+            }
+            SetGlobal => self.global_base = Some(self.data.items.len()),
+            PopFlags => {
+                for _ in 0..3 {
+                    self.data.pop(pos);
+                }
+            }
+            | PopExit
+            | PopFlagsExit
+            | PopFlagsExitExtern
+            | PopFlagsReturn
+            | PopFlagsReturnExtern
+            | PopFlagsReturnValExit
+            | PopFlagsReturnValExitExtern
+            | PopFlagsReturnValExtern
+            | PopReturn
+            => self.return_(pos, opcode, out),
+            _ => self.function(pos, opcode, out),
+        }
+    }
+
+    fn function(&mut self, pos: usize, opcode: Opcode, out: &mut Vec<Stmt>) {
+        let sig = if let Some(v) = opcode.signature() {
+            v
+        } else {
+            out.push(Stmt::Comment(format!("0x{:04x}: {}", pos, disasm::mnemonic(opcode))));
+            return;
+        };
+        let mut args = self.pop_n(pos, sig.arg_count);
+        let start = args.iter().map(|a| a.start).min().unwrap_or(pos);
+        let expr = if let Some(op) = BinaryOp::from_opcode(opcode) {
+            let r = args.pop().unwrap().expr;
+            let l = args.pop().unwrap().expr;
+            Expr::Binary(op, Box::new(l), Box::new(r))
+        } else if let Some(op) = UnaryOp::from_opcode(opcode) {
+            Expr::Unary(op, Box::new(args.pop().unwrap().expr))
+        } else {
+            Expr::Call(Callee::Opcode, ssl_name(opcode), args.into_iter().map(|a| a.expr).collect())
+        };
+        if sig.returns_value {
+            self.data.push(Item { start, expr });
+        } else {
+            out.push(Stmt::Expr(expr));
+        }
+    }
+
+    fn call(&mut self, pos: usize, out: &mut Vec<Stmt>) {
+        let id_item = self.data.pop(pos);
+        let proc = match id_item.expr {
+            Expr::Int(v) if v >= 0 => self.dc.program.proc(v as ProcedureId)
+                .map(|p| (v as ProcedureId, p)),
+            _ => None,
+        };
+        let (id, proc) = if let Some(v) = proc {
+            v
+        } else {
+            let mut s = format!("0x{:04x}: call of unknown procedure ", pos);
+            self.dc.write_expr(&id_item.expr, &mut s);
+            out.push(Stmt::Comment(s));
+            return;
+        };
+
+        let arg_count = self.data.pop(pos);
+        let n = match arg_count.expr {
+            Expr::Int(v) if v >= 0 => v as usize,
+            _ => proc.arg_count,
+        };
+        let args = self.pop_n(pos, n);
+        let mut start = args.iter()
+            .map(|a| a.start)
+            .fold(cmp::min(id_item.start, arg_count.start), cmp::min);
+        let call = Expr::Call(Callee::Proc, String::from_utf8_lossy(proc.name.as_bytes()).into_owned(),
+            args.iter().map(|a| a.expr.clone()).collect());
+
+        let effect = if proc.flags.contains(ProcedureFlag::Import) {
+            None
+        } else {
+            self.dc.effect(id)
+        };
+        let effect = if let Some(v) = effect {
+            v
+        } else {
+            self.data.push(Item { start, expr: call });
+            return;
+        };
+
+        let data: Vec<_> = (0..effect.data_pops).map(|_| self.data.pop(pos)).collect();
+        let ret: Vec<_> = (0..effect.ret_pops).map(|_| self.ret.pop(pos)).collect();
+        start = data.iter().chain(&ret).map(|i| i.start).fold(start, cmp::min);
+        let mut returned = false;
+        let mut map = |e: &Expr| match *e {
+            Expr::Below(StackKind::Data, i) => data[i].clone(),
+            Expr::Below(StackKind::Return, i) => ret[i].clone(),
+            Expr::Arg(i) if i < args.len() => args[i].clone(),
+            _ => {
+                returned = true;
+                Item { start, expr: call.clone() }
+            }
+        };
+        let data_pushes: Vec<_> = effect.data.iter().map(&mut map).collect();
+        let ret_pushes: Vec<_> = effect.ret.iter().map(&mut map).collect();
+        self.data.items.extend(data_pushes);
+        self.ret.items.extend(ret_pushes);
+        if !returned {
+            out.push(Stmt::Expr(call));
+        }
+    }
+
+    fn return_(&mut self, pos: usize, opcode: Opcode, out: &mut Vec<Stmt>) {
+        use Opcode::*;
+        if !matches!(opcode, PopExit | PopReturn) {
+            for _ in 0..3 {
+                self.data.pop(pos);
+            }
+        }
+        self.ret.pop(pos);
+        let value = self.ret.items.iter().rev()
+            .chain(self.data.items.iter().rev())
+            .map(|i| &i.expr)
+            .find(|e| !matches!(e, Expr::Below(..) | Expr::Base(_) | Expr::Arg(_)))
+            .cloned();
+        if self.body.effect.is_none() {
+            self.body.effect = Some(CallEffect {
+                data_pops: self.data.below,
+                ret_pops: self.ret.below,
+                data: self.data.items.iter().map(|i| i.expr.clone()).collect(),
+                ret: self.ret.items.iter().map(|i| i.expr.clone()).collect(),
+            });
+        }
+        out.push(Stmt::Return(value));
+    }
+
+    fn if_(&mut self, pos: usize, end: usize, loop_: Option<Loop>, out: &mut Vec<Stmt>) {
+        let cond = self.data.pop(pos);
+        let target = self.data.pop(pos);
+        let start = cmp::min(cond.start, target.start);
+        let target_idx = match target.expr {
+            Expr::Int(v) if v as usize > pos && v as usize <= end => self.index(v as usize),
+            _ => None,
+        };
+        let target_idx = if let Some(v) = target_idx {
+            v
+        } else {
+            let mut s = format!("0x{:04x}: unstructured jump to ", pos);
+            self.dc.write_expr(&target.expr, &mut s);
+            s += " unless ";
+            self.dc.write_expr(&cond.expr, &mut s);
+            out.push(Stmt::Comment(s));
+            return;
+        };
+        let target = self.instrs.get(target_idx).map(|i| i.pos).unwrap_or(end);
+
+        // Branch ending with `const_long addr; jmp`.
+        let jump = if target_idx >= self.next + 2 {
+            let (c, j) = (&self.instrs[target_idx - 2], &self.instrs[target_idx - 1]);
+            match (c.opcode, c.operand, j.opcode) {
+                (Some(Opcode::ConstLong), Some(Operand::Int(v)), Some(Opcode::Jmp)) if v >= 0 =>
+                    Some((c.pos, v as usize)),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        let saved = self.stack_state();
+        match jump {
+            Some((jump_pos, to)) if to == start => {
+                let body = self.block(jump_pos, Some(Loop { start, end: target }));
+                self.next = target_idx;
+                self.set_stack_state(saved);
+                out.push(Stmt::While(cond.expr, body));
+            }
+            Some((jump_pos, to)) if to >= target && to <= end && self.index(to).is_some() => {
+                let then = self.block(jump_pos, loop_);
+                let after_then = self.stack_state();
+                self.next = target_idx;
+                self.set_stack_state(saved);
+                let else_ = self.block(to, loop_);
+                self.next = self.index(to).unwrap();
+                let then_jumps = then.last().map(|s| s.is_jump()).unwrap_or(false);
+                let else_jumps = else_.last().map(|s| s.is_jump()).unwrap_or(false);
+                if else_jumps && !then_jumps {
+                    self.set_stack_state(after_then);
+                }
+                out.push(Stmt::If(cond.expr, then, else_));
+            }
+            _ => {
+                let then = self.block(target, loop_);
+                self.next = target_idx;
+                if then.last().map(|s| s.is_jump()).unwrap_or(false) {
+                    self.set_stack_state(saved);
+                }
+                out.push(Stmt::If(cond.expr, then, Vec::new()));
+            }
+        }
+    }
+
+    /// Returns index of the instruction at `pos` or the index past the last instruction if `pos`
+    /// is right after it.
+    fn index(&self, pos: usize) -> Option<usize> {
+        match self.instrs.binary_search_by_key(&pos, |i| i.pos) {
+            Ok(i) => Some(i),
+            Err(i) if i == self.instrs.len() => Some(i),
+            Err(_) => None,
+        }
+    }
+
+    fn pop_n(&mut self, pos: usize, n: usize) -> Vec<Item> {
+        let mut r: Vec<_> = (0..n).map(|_| self.data.pop(pos)).collect();
+        r.reverse();
+        r
+    }
+
+    fn stack_state(&self) -> StackState {
+        StackState {
+            data: self.data.clone(),
+            ret: self.ret.clone(),
+            base: self.base,
+        }
+    }
+
+    fn set_stack_state(&mut self, state: StackState) {
+        self.data = state.data;
+        self.ret = state.ret;
+        self.base = state.base;
+    }
+
+    fn proc_var(&mut self, id: &Expr) -> String {
+        let id = match *id {
+            Expr::Int(v) if v >= 0 => v as usize,
+            _ => return self.dynamic_var("var", id),
+        };
+        if id < self.arg_count {
+            return arg_name(id);
+        }
+        let i = id - self.arg_count;
+        if !self.body.locals.contains_key(&i) {
+            let init = self.base
+                .and_then(|base| self.data.items.get(base + id))
+                .map(|v| v.expr.clone());
+            self.body.locals.insert(i, init);
+        }
+        local_name(i)
+    }
+
+    fn global_var(&mut self, id: &Expr) -> String {
+        match *id {
+            Expr::Int(v) if v >= 0 => {
+                self.body.globals.insert(v as usize);
+                global_name(v as usize)
+            }
+            _ => self.dynamic_var("global", id),
+        }
+    }
+
+    fn external_var(&mut self, name: &Expr) -> String {
+        if let Expr::String(v) = *name {
+            if let Some(s) = self.dc.string(v, true) {
+                return s;
+            }
+        }
+        self.dynamic_var("external", name)
+    }
+
+    fn dynamic_var(&self, kind: &str, id: &Expr) -> String {
+        let mut r = format!("/* {} */ ", kind);
+        self.dc.write_expr(id, &mut r);
+        r
+    }
+}
+
+fn arg_name(i: usize) -> String {
+    format!("arg{}", i)
+}
+
+fn local_name(i: usize) -> String {
+    format!("var{}", i)
+}
+
+fn global_name(i: usize) -> String {
+    format!("global{}", i)
+}
+
+fn proc_header(proc: &Procedure) -> String {
+    let mut r = String::new();
+    if proc.flags.contains(ProcedureFlag::Import) {
+        r += "import ";
+    }
+    if proc.flags.contains(ProcedureFlag::Export) {
+        r += "export ";
+    }
+    if proc.flags.contains(ProcedureFlag::Critical) {
+        r += "critical ";
+    }
+    r += &format!("procedure {}", proc.name.display());
+    if proc.arg_count > 0 {
+        let args: Vec<_> = (0..proc.arg_count).map(|i| format!("variable {}", arg_name(i))).collect();
+        r += &format!("({})", args.join(", "));
+    }
+    r
+}
+
+/// Returns name of the opcode function as used in the SSL headers.
+fn ssl_name(opcode: Opcode) -> String {
+    match opcode {
+        Opcode::Attack => "attack_complex".into(),
+        Opcode::Fillwin3X3 => "fillwin3x3".into(),
+        Opcode::GdialogBarter => "gdialog_mod_barter".into(),
+        _ => disasm::mnemonic(opcode),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vm::test::{Asm::{self, *}, Proc, program};
+
+    #[test]
+    fn decompile_() {
+        use Opcode::*;
+
+        let ret = |v: Asm| vec![v, Op(DToA), Op(PopToBase), Op(Swapa), Op(PopBase), Op(Swapa),
+            Op(PopFlagsReturn)];
+        let mut code = vec![
+            // Program init.
+            Op(SetGlobal), Int(7), Name(2), Op(ExportVar), Op(ExitProg),
+
+            Label("start"), Op(PushBase), Int(5),
+            // while (var0 < 10)
+            Label("loop"), Addr("loop_end"), Int(0), Op(Fetch), Int(10), Op(Less), Op(While),
+            Int(0), Op(Fetch), Int(1), Op(Add), Int(0), Op(Store),
+            Addr("ret"), Op(DToA), Int(0), Int(0), Int(0), Int(0), Op(Fetch), Int(1), Int(1),
+                Op(Call),
+            Label("ret"), Op(AToD), Op(Pop),
+            Addr("loop"), Op(Jmp),
+            Label("loop_end"),
+            // if (global_var(3) == 1)
+            Addr("else"), Int(3), Op(GlobalVar), Int(1), Op(Equal), Op(If),
+            Str(0), Op(DisplayMsg),
+            Addr("end_if"), Op(Jmp),
+            Label("else"), Int(2), Int(3), Op(SetGlobalVar),
+            Label("end_if"),
+            Int(0), Op(FetchGlobal), Name(2), Op(FetchExternal), Op(Add), Int(0), Op(StoreGlobal),
+        ];
+        code.extend(ret(Int(0)));
+        code.extend(vec![Label("foo"), Op(PushBase), Int(0), Op(Fetch), Int(2), Op(Mul)]);
+        code.extend(ret(Int(0)).into_iter().skip(1));
+        code.extend(ret(Int(0)));
+        let (program, _) = program(&["start", "foo", "ext"], &["hello"], &[
+            Proc { name: 0, flags: 0, arg_count: 0, body: "start" },
+            Proc { name: 1, flags: 0x8, arg_count: 1, body: "foo" },
+        ], &code);
+
+        let mut out = Vec::new();
+        decompile(&program, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "\
+/* Decompiled from test */
+
+procedure start;
+export procedure foo(variable arg0);
+
+variable global0 := 7;
+
+export variable ext;
+
+procedure start begin
+    variable var0 := 5;
+
+    while (var0 < 10) do begin
+        var0 := var0 + 1;
+        call foo(var0);
+    end
+    if (global_var(3) == 1) then begin
+        display_msg(\"hello\");
+    end
+    else begin
+        set_global_var(2, 3);
+    end
+    global0 := global0 + ext;
+end
+
+export procedure foo(variable arg0) begin
+    return arg0 * 2;
+end
+");
+    }
+}
@@ -0,0 +1,340 @@
+//! Disassembler of the compiled `.int` programs.
+//!
+//! The code is decoded linearly starting at the startup code and each procedure entry point.
+//! Jump targets aren't encoded explicitly: any `const_long` that points to an instruction of the
+//! same procedure (or is directly followed by `jmp`) is treated as a code address and printed as
+//! a label.
+
+use matches::matches;
+use num_traits::FromPrimitive;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{self, prelude::*};
+
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum Operand {
+    Int(i32),
+    Float(f32),
+    String(i32),
+}
+
+#[derive(Debug)]
+pub(super) struct Instr {
+    pub pos: usize,
+    pub raw_opcode: u16,
+    /// `None` if the opcode is unknown.
+    pub opcode: Option<Opcode>,
+    pub operand: Option<Operand>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(super) enum Entry {
+    Startup,
+    Condition(ProcedureId),
+    Body(ProcedureId),
+}
+
+pub(super) struct Region {
+    pub end: usize,
+    pub entries: Vec<Entry>,
+    pub instrs: Vec<Instr>,
+    /// Trailing bytes that don't form a complete instruction.
+    pub rest: usize,
+}
+
+/// Writes disassembly of the `program` in human readable form.
+pub fn disassemble(program: &Program, wr: &mut impl Write) -> io::Result<()> {
+    let code = &program.code[..];
+    let regions = regions(program);
+
+    let all_boundaries: HashSet<usize> = regions.iter()
+        .flat_map(|r| r.instrs.iter().map(|i| i.pos))
+        .collect();
+    let mut labels = BTreeSet::new();
+    for region in &regions {
+        let boundaries: HashSet<usize> = region.instrs.iter().map(|i| i.pos).collect();
+        for (i, instr) in region.instrs.iter().enumerate() {
+            if let Some(Operand::Int(v)) = instr.operand {
+                let next = region.instrs.get(i + 1).and_then(|i| i.opcode);
+                let target = v as usize;
+                if v >= 0 && (boundaries.contains(&target)
+                    || next == Some(Opcode::Jmp) && all_boundaries.contains(&target))
+                {
+                    labels.insert(target);
+                }
+            }
+        }
+    }
+
+    writeln!(wr, "; program: {}", program.name())?;
+    writeln!(wr, "; code size: {} bytes, code start: 0x{:04x}", code.len(), program.code_start)?;
+    writeln!(wr, "; procedures: {}", program.procs.by_id.len())?;
+    for (id, proc) in program.procs.by_id.iter().enumerate() {
+        writeln!(wr, ";   {:3} {}", id, proc_summary(proc))?;
+    }
+
+    for region in &regions {
+        writeln!(wr)?;
+        for &entry in &region.entries {
+            match entry {
+                Entry::Startup => writeln!(wr, "; startup code")?,
+                Entry::Condition(id) => {
+                    let proc = &program.procs.by_id[id as usize];
+                    writeln!(wr, "; condition of procedure #{} {}", id, proc.name.display())?;
+                    writeln!(wr, "{}.condition:", proc.name.display())?;
+                }
+                Entry::Body(id) => {
+                    let proc = &program.procs.by_id[id as usize];
+                    writeln!(wr, "; procedure #{} {}", id, proc_summary(proc))?;
+                    writeln!(wr, "{}:", proc.name.display())?;
+                }
+            }
+        }
+        for (i, instr) in region.instrs.iter().enumerate() {
+            if labels.contains(&instr.pos) {
+                writeln!(wr, "L_{:04x}:", instr.pos)?;
+            }
+            let next = region.instrs.get(i + 1).and_then(|i| i.opcode);
+            write_instr(program, instr, next, &labels, wr)?;
+        }
+        if region.rest > 0 {
+            let pos = region.end - region.rest;
+            writeln!(wr, "    0x{:04x}  .bytes {:02x?}", pos, &code[pos..region.end])?;
+        }
+    }
+
+    for (id, proc) in program.procs.by_id.iter().enumerate() {
+        if proc.flags.contains(ProcedureFlag::Import) {
+            continue;
+        }
+        let bad = |pos: usize| pos < program.code_start || pos >= code.len();
+        if bad(proc.body_pos) {
+            writeln!(wr, "; procedure #{} {} has invalid body position 0x{:04x}",
+                id, proc.name.display(), proc.body_pos)?;
+        }
+        if proc.flags.contains(ProcedureFlag::Conditional) && bad(proc.condition_pos) {
+            writeln!(wr, "; procedure #{} {} has invalid condition position 0x{:04x}",
+                id, proc.name.display(), proc.condition_pos)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits the code into the startup code and regions starting at the code start and each
+/// procedure entry point.
+pub(super) fn regions(program: &Program) -> Vec<Region> {
+    let code = &program.code[..];
+    let code_start = program.code_start;
+
+    let mut starts = BTreeMap::new();
+    starts.entry(code_start).or_insert_with(Vec::new);
+    for (id, proc) in program.procs.by_id.iter().enumerate() {
+        if proc.flags.contains(ProcedureFlag::Import) {
+            continue;
+        }
+        let mut add = |pos: usize, entry: Entry| {
+            if pos >= code_start && pos < code.len() {
+                starts.entry(pos).or_insert_with(Vec::new).push(entry);
+            }
+        };
+        add(proc.body_pos, Entry::Body(id as ProcedureId));
+        if proc.flags.contains(ProcedureFlag::Conditional) {
+            add(proc.condition_pos, Entry::Condition(id as ProcedureId));
+        }
+    }
+
+    let mut r = vec![decode(code, 0, PROC_TABLE_START.min(code.len()), vec![Entry::Startup])];
+    let mut starts = starts.into_iter().peekable();
+    while let Some((start, mut entries)) = starts.next() {
+        let end = starts.peek().map(|&(pos, _)| pos).unwrap_or(code.len());
+        entries.sort();
+        r.push(decode(code, start, end, entries));
+    }
+    r
+}
+
+pub(super) fn decode(code: &[u8], start: usize, end: usize, entries: Vec<Entry>) -> Region {
+    let mut instrs = Vec::new();
+    let mut pos = start;
+    while pos + Opcode::SIZE <= end {
+        let raw_opcode = BigEndian::read_u16(&code[pos..]);
+        let opcode = Opcode::from_u16(raw_opcode);
+        let operand_len = match opcode {
+            Some(Opcode::ConstLong) | Some(Opcode::ConstFloat) | Some(Opcode::ConstString) => 4,
+            _ => 0,
+        };
+        let len = Opcode::SIZE + operand_len;
+        if pos + len > end {
+            break;
+        }
+        let operand = if operand_len > 0 {
+            let v = &code[pos + Opcode::SIZE..];
+            Some(match opcode.unwrap() {
+                Opcode::ConstLong => Operand::Int(BigEndian::read_i32(v)),
+                Opcode::ConstFloat => Operand::Float(BigEndian::read_f32(v)),
+                _ => Operand::String(BigEndian::read_i32(v)),
+            })
+        } else {
+            None
+        };
+        instrs.push(Instr {
+            pos,
+            raw_opcode,
+            opcode,
+            operand,
+        });
+        pos += len;
+    }
+    Region {
+        end,
+        entries,
+        instrs,
+        rest: end - pos,
+    }
+}
+
+pub(super) fn write_instr(program: &Program, instr: &Instr, next: Option<Opcode>, labels: &BTreeSet<usize>,
+    wr: &mut impl Write) -> io::Result<()>
+{
+    let opcode = if let Some(v) = instr.opcode {
+        v
+    } else {
+        return writeln!(wr, "    0x{:04x}  .word 0x{:04x}  ; unknown opcode",
+            instr.pos, instr.raw_opcode);
+    };
+    let mnemonic = mnemonic(opcode);
+    match instr.operand {
+        None => writeln!(wr, "    0x{:04x}  {}", instr.pos, mnemonic),
+        Some(Operand::Int(v)) => {
+            let comment = if v >= 0 && labels.contains(&(v as usize)) {
+                format!("  ; L_{:04x}", v)
+            } else if next == Some(Opcode::Call) {
+                program.proc(v as ProcedureId)
+                    .map(|p| format!("  ; {}", p.name.display()))
+                    .unwrap_or_default()
+            } else {
+                String::new()
+            };
+            writeln!(wr, "    0x{:04x}  {} {}{}", instr.pos, mnemonic, v, comment)
+        }
+        Some(Operand::Float(v)) => writeln!(wr, "    0x{:04x}  {} {:?}", instr.pos, mnemonic, v),
+        Some(Operand::String(v)) => {
+            let uses_name = matches!(next,
+                Some(Opcode::ExportVar)
+                | Some(Opcode::ExportProc)
+                | Some(Opcode::FetchExternal)
+                | Some(Opcode::StoreExternal));
+            let (primary, secondary) = if uses_name {
+                (&program.names, &program.strings)
+            } else {
+                (&program.strings, &program.names)
+            };
+            let s = primary.get(v as usize).or_else(|| secondary.get(v as usize));
+            let comment = if let Some(s) = s {
+                format!("  ; {:?}", String::from_utf8_lossy(s.as_bytes()))
+            } else {
+                "  ; invalid string reference".into()
+            };
+            writeln!(wr, "    0x{:04x}  {} {}{}", instr.pos, mnemonic, v, comment)
+        }
+    }
+}
+
+fn proc_summary(proc: &Procedure) -> String {
+    let mut r = format!("{} args={}", proc.name.display(), proc.arg_count);
+    if !proc.flags.is_empty() {
+        let flags: Vec<_> = proc.flags.iter().map(|f| format!("{:?}", f)).collect();
+        r += &format!(" flags={}", flags.join("|"));
+    }
+    if proc.flags.contains(ProcedureFlag::Timed) {
+        r += &format!(" delay={}ms", proc.delay.as_millis());
+    }
+    r
+}
+
+/// Returns opcode name in `snake_case`.
+pub(super) fn mnemonic(opcode: Opcode) -> String {
+    let mut r = String::new();
+    for (i, c) in format!("{:?}", opcode).chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                r.push('_');
+            }
+            r.push(c.to_ascii_lowercase());
+        } else {
+            r.push(c);
+        }
+    }
+    r
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vm::test::{Asm::*, Proc, program};
+
+    #[test]
+    fn disassemble_() {
+        use Opcode::*;
+
+        let (program, _) = program(&["start", "foo"], &["hello"], &[
+            Proc { name: 0, flags: 0, arg_count: 0, body: "start" },
+            Proc { name: 1, flags: 0x8, arg_count: 0, body: "foo" },
+        ], &[
+            Label("start"), Addr("skip"), Op(Jmp), Str(0),
+            Label("skip"), Float(1.5), Op(ExitProg),
+            Label("foo"), Int(0), Op(Call), Bytes(&[0x12, 0x34, 0xff]),
+        ]);
+
+        let mut out = Vec::new();
+        disassemble(&program, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "\
+; program: test
+; code size: 165 bytes, code start: 0x0084
+; procedures: 2
+;     0 start args=0
+;     1 foo args=0 flags=Export
+
+; startup code
+    0x0000  critical_start
+    0x0002  const_long 132  ; L_0084
+    0x0008  jmp
+    0x000a  noop8000
+    0x000c  noop8000
+    0x000e  noop8000
+    0x0010  noop8000
+    0x0012  noop8000
+    0x0014  noop8000
+    0x0016  noop8000
+    0x0018  noop8000
+    0x001a  noop8000
+    0x001c  noop8000
+    0x001e  noop8000
+    0x0020  noop8000
+    0x0022  noop8000
+    0x0024  noop8000
+    0x0026  noop8000
+    0x0028  noop8000
+
+; procedure #0 start args=0
+start:
+L_0084:
+    0x0084  const_long 146  ; L_0092
+    0x008a  jmp
+    0x008c  const_string 6  ; \"hello\"
+L_0092:
+    0x0092  const_float 1.5
+    0x0098  exit_prog
+
+; procedure #1 foo args=0 flags=Export
+foo:
+    0x009a  const_long 0  ; start
+    0x00a0  call
+    0x00a2  .word 0x1234  ; unknown opcode
+    0x00a4  .bytes [ff]
+");
+    }
+}
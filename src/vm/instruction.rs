@@ -380,6 +380,404 @@ pub enum Opcode {
 
 impl Opcode {
     pub const SIZE: usize = 2;
+
+    /// Returns stack effect of the opcode if it only pops its arguments from the data stack and
+    /// optionally pushes the result. Returns `None` for opcodes that take operands from the code,
+    /// work with the return stack, base pointers or control flow, and for the ones with variable
+    /// number of arguments.
+    pub fn signature(self) -> Option<Signature> {
+        use Opcode::*;
+        let (arg_count, returns_value) = match self {
+            | Cancelall
+            | CriticalDone
+            | CriticalDone804b
+            | CriticalStart
+            | CriticalStart804a
+            | DialogueSystemEnter
+            | EndDialogue
+            | EndgameMovie
+            | EndgameSlideshow
+            | Exit
+            | ExitProg
+            | GameUiDisable
+            | GameUiEnable
+            | GsayEnd
+            | GsayStart
+            | Hidemouse
+            | Noop8000
+            | Noop80d1
+            | Sayend
+            | Sayquit
+            | Sayrestart
+            | Saystart
+            | ScriptOverrides
+            | Showmouse
+            | Showwin
+            | Stopmovie
+            | StopProg
+            | TerminateCombat
+            | WorldMap
+            => (0, false),
+
+            | ActionBeingUsed
+            | CombatDifficulty
+            | CombatIsInitialized
+            | CurMapIndex
+            | DaysSinceVisited
+            | DifficultyLevel
+            | DudeObj
+            | FixedParam
+            | GameTime
+            | GameTimeHour
+            | GameTimeInSeconds
+            | GameUiIsDisabled
+            | GetDay
+            | GetMonth
+            | ObjBeingUsedWith
+            | RunningBurningGuy
+            | Saygetlastpos
+            | ScriptAction
+            | SelfObj
+            | SourceObj
+            | TargetObj
+            => (0, true),
+
+            | AnimateStandObj
+            | AnimateStandReverseObj
+            | Callstart
+            | Cancel
+            | Clearnamed
+            | CritterStopAttacking
+            | DebugMsg
+            | Deletebutton
+            | Deletekey
+            | Deleteregion
+            | Deletewin
+            | DestroyObject
+            | DialogueReaction
+            | Display
+            | DisplayMsg
+            | Displayraw
+            | DropObj
+            | Dump
+            | Exec
+            | Fadein
+            | Fadeout
+            | Fillwin3X3
+            | Fork
+            | GameTimeAdvance
+            | GdialogBarter
+            | GdialogSetBarterMod
+            | GfadeIn
+            | GfadeOut
+            | GiveExpPoints
+            | InvenUnwield
+            | JamLock
+            | Loadpalettetable
+            | Movieflags
+            | ObjClose
+            | ObjLock
+            | ObjOpen
+            | ObjUnlock
+            | PartyAdd
+            | PartyRemove
+            | PickupObj
+            | PlayGmovie
+            | Playmovie
+            | PlaySfx
+            | Print
+            | Refreshmouse
+            | RmTimerEvent
+            | Saygotoreply
+            | Saymessagetimeout
+            | Sayoptionflags
+            | Sayreplyflags
+            | Sayreplytitle
+            | Saysetspacing
+            | Saystartpos
+            | ScrReturn
+            | Selectwin
+            | Setfont
+            | SetLightLevel
+            | Setoneoptpause
+            | Settextflags
+            | Signalnamed
+            | Sounddelete
+            | Soundpause
+            | Soundresume
+            | Soundrewind
+            | Soundstop
+            | Spawn
+            | UseObj
+            | Wait
+            => (1, false),
+
+            | AnimBusy
+            | ArtAnim
+            | Bwnot
+            | Checkregion
+            | CritterIsFleeing
+            | CritterState
+            | Elevation
+            | Floor
+            | GameTicks
+            | GetPcStat
+            | GetPoison
+            | GlobalVar
+            | HowMuch
+            | IsCritical
+            | IsSuccess
+            | ItemCapsTotal
+            | LocalVar
+            | MapVar
+            | Negate
+            | Not
+            | ObjArtFid
+            | ObjIsLocked
+            | ObjIsOpen
+            | ObjItemSubtype
+            | ObjName
+            | ObjOnScreen
+            | ObjPid
+            | ObjType
+            | PartyMemberObj
+            | SfxBuildAmbientName
+            | SfxBuildInterfaceName
+            | SfxBuildItemName
+            | TileIsVisible
+            | TileNum
+            => (1, true),
+
+            | Activateregion
+            | AddObjToInven
+            | Addbuttonflag
+            | Addbuttontext
+            | Addkey
+            | Addnamedevent
+            | Addnamedhandler
+            | Addregionflag
+            | AttackSetup
+            | CallAt
+            | CritterHeal
+            | CritterInjure
+            | CritterSetFleeState
+            | Gotoxy
+            | GsayReply
+            | KillCritter
+            | KillCritterType
+            | LoadMap
+            | MoveObjInvenToObj
+            | Poison
+            | RadiationDec
+            | RadiationInc
+            | RegAnimAnimateForever
+            | RegAnimFunc
+            | RmObjFromInven
+            | Sayborder
+            | Saymessage
+            | Sayoption
+            | Sayreply
+            | SetGlobalVar
+            | Setglobalmousefunc
+            | SetLocalVar
+            | SetMapMusic
+            | SetMapVar
+            | SetObjVisibility
+            | UseObjOnObj
+            | WieldObjCritter
+            => (2, false),
+
+            | Add
+            | And
+            | AnimActionFrame
+            | Bwand
+            | Bwor
+            | Bwxor
+            | CritterInvenObj
+            | DestroyMultObjs
+            | Div
+            | Equal
+            | GetCritterStat
+            | Greater
+            | GreaterEqual
+            | HasSkill
+            | ItemCapsAdjust
+            | Less
+            | LessEqual
+            | MessageStr
+            | Metarule
+            | Mod
+            | Mul
+            | NotEqual
+            | ObjCanHearObj
+            | ObjCanSeeObj
+            | ObjCarryingPidObj
+            | ObjIsCarryingObjPid
+            | Or
+            | ProtoData
+            | Random
+            | RollDice
+            | RotationToTile
+            | Selectfilelist
+            | SfxBuildOpenName
+            | Soundplay
+            | Sub
+            | TileDistance
+            | TileDistanceObjs
+            | UsingSkill
+            => (2, true),
+
+            | AddMultObjsToInven
+            | AddTimerEvent
+            | Addbuttonrightproc
+            | Addregionrightproc
+            | Anim
+            | AnimateMoveObjToTile
+            | CritterDamage
+            | Explosion
+            | Fillwin
+            | FloatMsg
+            | GsayMessage
+            | MarkAreaKnown
+            | Mouseshape
+            | ObjSetLightLevel
+            | Printrect
+            | RegAnimAnimate
+            | RegAnimAnimateReverse
+            | RegAnimObjMoveToObj
+            | RegAnimObjMoveToTile
+            | RegAnimObjRunToObj
+            | RegAnimObjRunToTile
+            | RegAnimPlaySfx
+            | Sayoptioncolor
+            | Sayreplycolor
+            | Sethighlightcolor
+            | Settextcolor
+            | WmAreaSetPos
+            => (3, false),
+
+            | CritterAttemptPlacement
+            | CritterModSkill
+            | DoCheck
+            | HasTrait
+            | InvenCmds
+            | MoveTo
+            | ReactionInfluence
+            | RmMultObjsFromInven
+            | RollVsSkill
+            | SetCritterStat
+            | SfxBuildCharName
+            | SfxBuildSceneryName
+            | SkillContest
+            | TileContainsObjPid
+            | TileContainsPidObj
+            | TileNumInDirection
+            | Tokenize
+            => (3, true),
+
+            | Addbuttongfx
+            | GsayOption
+            | OverrideMapStart
+            | SetMapStart
+            => (4, false),
+
+            | CreateObjectSid
+            | CritterAddTrait
+            | CritterRmTrait
+            | Metarule3
+            | SfxBuildWeaponName
+            => (4, true),
+
+            | Addbutton
+            | Addbuttonproc
+            | Addregionproc
+            | Createwin
+            | Displaygfx
+            | GiqOption
+            | Playmovierect
+            | Resizewin
+            | Sayoptionwindow
+            | Sayreplywindow
+            | Scalewin
+            | SetExitGrids
+            | StartGdialog
+            => (5, false),
+
+            | TileInTileRect
+            => (5, true),
+
+            | Format
+            | Sayscrolldown
+            | Sayscrollup
+            => (6, false),
+
+            | Fillrect
+            => (7, false),
+
+            | Attack
+            => (8, false),
+
+            | Addregion
+            | AToD
+            | Attack80dd
+            | Call
+            | CallCondition
+            | CheckArgCount
+            | ConstFloat
+            | ConstLong
+            | ConstShort
+            | ConstString
+            | Detach
+            | DToA
+            | Dup
+            | ExportProc
+            | ExportVar
+            | Fetch
+            | FetchExternal
+            | FetchGlobal
+            | FetchProcAddress
+            | If
+            | Jmp
+            | LookupStringProc
+            | Pop
+            | PopAddress
+            | PopBase
+            | PopExit
+            | PopFlags
+            | PopFlagsExit
+            | PopFlagsExitExtern
+            | PopFlagsReturn
+            | PopFlagsReturnExtern
+            | PopFlagsReturnValExit
+            | PopFlagsReturnValExitExtern
+            | PopFlagsReturnValExtern
+            | PopReturn
+            | PopToBase
+            | PushBase
+            | SetGlobal
+            | Store
+            | StoreExternal
+            | StoreGlobal
+            | Swap
+            | Swapa
+            | While
+            => return None,
+        };
+        Some(Signature {
+            arg_count,
+            returns_value,
+        })
+    }
+}
+
+/// Stack effect of an opcode. See `Opcode::signature()`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Signature {
+    /// Number of values popped from the data stack.
+    pub arg_count: usize,
+
+    /// Whether the result is pushed to the data stack.
+    pub returns_value: bool,
 }
 
 macro_rules! is {
@@ -404,23 +802,23 @@ pub(in super) mod instructions {
 
     pub static INSTRUCTIONS: [Instruction; enum_len!(Opcode)] = [
         i!(ActionBeingUsed,             action_being_used),
-        i!(Activateregion,              unimplemented),
+        i!(Activateregion,              activateregion),
         i!(Add,                         add),
-        i!(Addbutton,                   unimplemented),
-        i!(Addbuttonflag,               unimplemented),
-        i!(Addbuttongfx,                unimplemented),
-        i!(Addbuttonproc,               unimplemented),
-        i!(Addbuttonrightproc,          unimplemented),
-        i!(Addbuttontext,               unimplemented),
+        i!(Addbutton,                   addbutton),
+        i!(Addbuttonflag,               addbuttonflag),
+        i!(Addbuttongfx,                addbuttongfx),
+        i!(Addbuttonproc,               addbuttonproc),
+        i!(Addbuttonrightproc,          addbuttonrightproc),
+        i!(Addbuttontext,               addbuttontext),
         i!(Addkey,                      unimplemented),
         i!(AddMultObjsToInven,          add_mult_objs_to_inven),
         i!(Addnamedevent,               unimplemented),
         i!(Addnamedhandler,             unimplemented),
         i!(AddObjToInven,               add_obj_to_inven),
-        i!(Addregion,                   unimplemented),
-        i!(Addregionflag,               unimplemented),
-        i!(Addregionproc,               unimplemented),
-        i!(Addregionrightproc,          unimplemented),
+        i!(Addregion,                   addregion),
+        i!(Addregionflag,               addregionflag),
+        i!(Addregionproc,               addregionproc),
+        i!(Addregionrightproc,          addregionrightproc),
         i!(AddTimerEvent,               add_timer_event),
         i!(And,                         and),
         i!(Anim,                        anim),
@@ -431,9 +829,9 @@ pub(in super) mod instructions {
         i!(AnimBusy,                    unimplemented),
         i!(ArtAnim,                     unimplemented),
         i!(AToD,                        atod),
-        i!(Attack,                      unimplemented),
+        i!(Attack,                      attack_complex),
         i!(Attack80dd,                  unimplemented),
-        i!(AttackSetup,                 unimplemented),
+        i!(AttackSetup,                 attack_setup),
         i!(Bwand,                       bwand),
         i!(Bwnot,                       bwnot),
         i!(Bwor,                        bwor),
@@ -454,7 +852,7 @@ pub(in super) mod instructions {
         i!(ConstShort,                  const_int),
         i!(ConstString,                 const_string),
         i!(CreateObjectSid,             create_object_sid),
-        i!(Createwin,                   unimplemented),
+        i!(Createwin,                   createwin),
         i!(CriticalDone,                noop),
         i!(CriticalDone804b,            noop),
         i!(CriticalStart,               noop),
@@ -470,22 +868,22 @@ pub(in super) mod instructions {
         i!(CritterRmTrait,              unimplemented),
         i!(CritterSetFleeState,         unimplemented),
         i!(CritterState,                unimplemented),
-        i!(CritterStopAttacking,        unimplemented),
+        i!(CritterStopAttacking,        critter_stop_attacking),
         i!(CurMapIndex,                 cur_map_index),
         i!(DaysSinceVisited,            unimplemented),
         i!(DebugMsg,                    debug_msg),
-        i!(Deletebutton,                unimplemented),
+        i!(Deletebutton,                deletebutton),
         i!(Deletekey,                   unimplemented),
-        i!(Deleteregion,                unimplemented),
-        i!(Deletewin,                   unimplemented),
+        i!(Deleteregion,                deleteregion),
+        i!(Deletewin,                   deletewin),
         i!(DestroyMultObjs,             unimplemented),
         i!(DestroyObject,               destroy_object),
         i!(Detach,                      unimplemented),
         i!(DialogueReaction,            unimplemented),
         i!(DialogueSystemEnter,         unimplemented),
         i!(DifficultyLevel,             unimplemented),
-        i!(Display,                     unimplemented),
-        i!(Displaygfx,                  unimplemented),
+        i!(Display,                     display),
+        i!(Displaygfx,                  displaygfx),
         i!(DisplayMsg,                  display_msg),
         i!(Displayraw,                  unimplemented),
         i!(Div,                         div),
@@ -512,10 +910,10 @@ pub(in super) mod instructions {
         i!(FetchExternal,               fetch_external),
         i!(FetchGlobal,                 fetch_global),
         i!(FetchProcAddress,            unimplemented),
-        i!(Fillrect,                    unimplemented),
-        i!(Fillwin,                     unimplemented),
+        i!(Fillrect,                    fillrect),
+        i!(Fillwin,                     fillwin),
         i!(Fillwin3X3,                  unimplemented),
-        i!(FixedParam,                  unimplemented),
+        i!(FixedParam,                  fixed_param),
         i!(FloatMsg,                    float_msg),
         i!(Floor,                       unimplemented),
         i!(Fork,                        unimplemented),
@@ -540,7 +938,7 @@ pub(in super) mod instructions {
         i!(GiqOption,                   giq_option),
         i!(GiveExpPoints,               give_exp_points),
         i!(GlobalVar,                   global_var),
-        i!(Gotoxy,                      unimplemented),
+        i!(Gotoxy,                      gotoxy),
         i!(Greater,                     greater),
         i!(GreaterEqual,                greater_equal),
         is!(GsayEnd,                    gsay_end),
@@ -578,7 +976,7 @@ pub(in super) mod instructions {
         i!(Mouseshape,                  unimplemented),
         i!(MoveObjInvenToObj,           move_obj_inven_to_obj),
         i!(MoveTo,                      move_to),
-        i!(Movieflags,                  unimplemented),
+        i!(Movieflags,                  movieflags),
         i!(Mul,                         mul),
         i!(Negate,                      negate),
         i!(Noop80d1,                    noop),
@@ -609,10 +1007,10 @@ pub(in super) mod instructions {
         i!(PartyMemberObj,              party_member_obj),
         i!(PartyRemove,                 unimplemented),
         i!(PickupObj,                   unimplemented),
-        i!(PlayGmovie,                  unimplemented),
-        i!(Playmovie,                   unimplemented),
-        i!(Playmovierect,               unimplemented),
-        i!(PlaySfx,                     unimplemented),
+        i!(PlayGmovie,                  play_gmovie),
+        i!(Playmovie,                   playmovie),
+        i!(Playmovierect,               playmovierect),
+        i!(PlaySfx,                     play_sfx),
         i!(Poison,                      unimplemented),
         i!(Pop,                         pop),
         i!(PopAddress,                  unimplemented),
@@ -628,8 +1026,8 @@ pub(in super) mod instructions {
         i!(PopFlagsReturnValExtern,     unimplemented),
         i!(PopReturn,                   pop_return),
         i!(PopToBase,                   pop_to_base),
-        i!(Print,                       unimplemented),
-        i!(Printrect,                   unimplemented),
+        i!(Print,                       print),
+        i!(Printrect,                   printrect),
         i!(ProtoData,                   unimplemented),
         i!(PushBase,                    push_base),
         i!(RadiationDec,                unimplemented),
@@ -645,8 +1043,8 @@ pub(in super) mod instructions {
         i!(RegAnimObjMoveToTile,        unimplemented),
         i!(RegAnimObjRunToObj,          unimplemented),
         i!(RegAnimObjRunToTile,         unimplemented),
-        i!(RegAnimPlaySfx,              unimplemented),
-        i!(Resizewin,                   unimplemented),
+        i!(RegAnimPlaySfx,              reg_anim_play_sfx),
+        i!(Resizewin,                   resizewin),
         i!(RmMultObjsFromInven,         unimplemented),
         i!(RmObjFromInven,              unimplemented),
         i!(RmTimerEvent,                rm_timer_event),
@@ -654,72 +1052,72 @@ pub(in super) mod instructions {
         i!(RollVsSkill,                 roll_vs_skill),
         i!(RotationToTile,              rotation_to_tile),
         i!(RunningBurningGuy,           unimplemented),
-        i!(Sayborder,                   unimplemented),
-        i!(Sayend,                      unimplemented),
-        i!(Saygetlastpos,               unimplemented),
-        i!(Saygotoreply,                unimplemented),
-        i!(Saymessage,                  unimplemented),
-        i!(Saymessagetimeout,           unimplemented),
-        i!(Sayoption,                   unimplemented),
-        i!(Sayoptioncolor,              unimplemented),
-        i!(Sayoptionflags,              unimplemented),
-        i!(Sayoptionwindow,             unimplemented),
-        i!(Sayquit,                     unimplemented),
-        i!(Sayreply,                    unimplemented),
-        i!(Sayreplycolor,               unimplemented),
-        i!(Sayreplyflags,               unimplemented),
-        i!(Sayreplytitle,               unimplemented),
-        i!(Sayreplywindow,              unimplemented),
-        i!(Sayrestart,                  unimplemented),
-        i!(Sayscrolldown,               unimplemented),
-        i!(Sayscrollup,                 unimplemented),
-        i!(Saysetspacing,               unimplemented),
-        i!(Saystart,                    unimplemented),
-        i!(Saystartpos,                 unimplemented),
-        i!(Scalewin,                    unimplemented),
+        i!(Sayborder,                   sayborder),
+        is!(Sayend,                     sayend),
+        i!(Saygetlastpos,               saygetlastpos),
+        i!(Saygotoreply,                saygotoreply),
+        is!(Saymessage,                 saymessage),
+        i!(Saymessagetimeout,           saymessagetimeout),
+        i!(Sayoption,                   sayoption),
+        i!(Sayoptioncolor,              sayoptioncolor),
+        i!(Sayoptionflags,              sayoptionflags),
+        i!(Sayoptionwindow,             sayoptionwindow),
+        i!(Sayquit,                     sayquit),
+        i!(Sayreply,                    sayreply),
+        i!(Sayreplycolor,               sayreplycolor),
+        i!(Sayreplyflags,               sayreplyflags),
+        i!(Sayreplytitle,               sayreplytitle),
+        i!(Sayreplywindow,              sayreplywindow),
+        i!(Sayrestart,                  sayrestart),
+        i!(Sayscrolldown,               sayscrolldown),
+        i!(Sayscrollup,                 sayscrollup),
+        i!(Saysetspacing,               saysetspacing),
+        i!(Saystart,                    saystart),
+        i!(Saystartpos,                 saystartpos),
+        i!(Scalewin,                    scalewin),
         i!(ScriptAction,                unimplemented),
         i!(ScriptOverrides,             script_overrides),
         i!(ScrReturn,                   unimplemented),
         i!(Selectfilelist,              unimplemented),
-        i!(Selectwin,                   unimplemented),
+        i!(Selectwin,                   selectwin),
         i!(SelfObj,                     self_obj),
         i!(SetCritterStat,              unimplemented),
         i!(SetExitGrids,                unimplemented),
-        i!(Setfont,                     unimplemented),
+        i!(Setfont,                     setfont),
         i!(SetGlobal,                   set_global),
         i!(Setglobalmousefunc,          unimplemented),
         i!(SetGlobalVar,                set_global_var),
         i!(Sethighlightcolor,           unimplemented),
         i!(SetLightLevel,               set_light_level),
         i!(SetLocalVar,                 set_local_var),
-        i!(SetMapMusic,                 unimplemented),
+        i!(SetMapMusic,                 set_map_music),
         i!(SetMapStart,                 unimplemented),
         i!(SetMapVar,                   set_map_var),
         i!(SetObjVisibility,            set_obj_visibility),
         i!(Setoneoptpause,              unimplemented),
-        i!(Settextcolor,                unimplemented),
+        i!(Settextcolor,                settextcolor),
         i!(Settextflags,                unimplemented),
-        i!(SfxBuildAmbientName,         unimplemented),
-        i!(SfxBuildCharName,            unimplemented),
-        i!(SfxBuildInterfaceName,       unimplemented),
-        i!(SfxBuildItemName,            unimplemented),
-        i!(SfxBuildOpenName,            unimplemented),
-        i!(SfxBuildSceneryName,         unimplemented),
-        i!(SfxBuildWeaponName,          unimplemented),
+        i!(SfxBuildAmbientName,         sfx_build_ambient_name),
+        i!(SfxBuildCharName,            sfx_build_char_name),
+        i!(SfxBuildInterfaceName,       sfx_build_interface_name),
+        i!(SfxBuildItemName,            sfx_build_item_name),
+        i!(SfxBuildOpenName,            sfx_build_open_name),
+        i!(SfxBuildSceneryName,         sfx_build_scenery_name),
+        i!(SfxBuildWeaponName,          sfx_build_weapon_name),
         i!(Showmouse,                   unimplemented),
-        i!(Showwin,                     unimplemented),
+        i!(Showwin,                     showwin),
         i!(Signalnamed,                 unimplemented),
         i!(SkillContest,                unimplemented),
-        i!(Sounddelete,                 unimplemented),
-        i!(Soundpause,                  unimplemented),
-        i!(Soundplay,                   unimplemented),
-        i!(Soundresume,                 unimplemented),
-        i!(Soundrewind,                 unimplemented),
-        i!(Soundstop,                   unimplemented),
+        i!(Sounddelete,                 sounddelete),
+        i!(Soundpause,                  soundpause),
+        i!(Soundplay,                   soundplay),
+        i!(Soundresume,                 soundresume),
+        i!(Soundrewind,                 soundrewind),
+        i!(Soundstop,                   soundstop),
         i!(SourceObj,                   source_obj),
         i!(Spawn,                       unimplemented),
         i!(StartGdialog,                start_gdialog),
-        i!(Stopmovie,                   unimplemented),
+        i!(Stopmovie,                   stopmovie),
         i!(StopProg,                    unimplemented),
         i!(Store,                       store),
         i!(StoreExternal,               store_external),
@@ -728,7 +1126,7 @@ pub(in super) mod instructions {
         i!(Swap,                        swap),
         i!(Swapa,                       swapa),
         i!(TargetObj,                   target_obj),
-        i!(TerminateCombat,             unimplemented),
+        i!(TerminateCombat,             terminate_combat),
         i!(TileContainsObjPid,          tile_contains_pid_obj),
         i!(TileContainsPidObj,          tile_contains_pid_obj),
         i!(TileDistance,                tile_distance),
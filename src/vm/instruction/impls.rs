@@ -1,13 +1,21 @@
 #[macro_use] mod macros;
 mod core;
 mod game;
+mod say;
+mod window;
 
 pub use self::core::*;
 pub use self::game::*;
+pub use self::say::*;
+pub use self::window::*;
 
 use super::Context;
 use super::value::*;
 use super::super::*;
+use crate::asset::frame::FrameId;
+use crate::game::script_window::image_fid;
+use crate::graphics::Rect;
+use crate::graphics::color::Rgb15;
 
 fn binary_op(ctx: Context, f: impl FnOnce(Value, Value, &Context) -> Result<Value>) -> Result<()> {
     let right = ctx.prg.data_stack.pop()?;
@@ -28,3 +36,67 @@ fn unary_op(ctx: Context, f: impl FnOnce(Value, &Context) -> Result<Value>) -> R
     log_a1r1!(ctx.prg, v, ctx.prg.data_stack.top().unwrap());
     Ok(())
 }
+
+fn pop_string(ctx: &mut Context) -> Result<Rc<BString>> {
+    ctx.prg.data_stack.pop()?.coerce_into_string(ctx.prg.strings())
+}
+
+fn pop_rect(ctx: &mut Context) -> Result<Rect> {
+    let height = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let width = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let y = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let x = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    Ok(Rect::with_size(x, y, width, height))
+}
+
+/// Pops color specified as three floats in range `0..1`.
+fn pop_color(ctx: &mut Context) -> Result<Rgb15> {
+    fn c(v: f32) -> u8 {
+        (v.clamp(0.0, 1.0) * 31.0) as u8
+    }
+    let b = ctx.prg.data_stack.pop()?.coerce_into_float()?;
+    let g = ctx.prg.data_stack.pop()?.coerce_into_float()?;
+    let r = ctx.prg.data_stack.pop()?.coerce_into_float()?;
+    Ok(Rgb15::new(c(r), c(g), c(b)))
+}
+
+/// Pops procedure specified either by ID or by name. Zero means no procedure.
+fn pop_proc(ctx: &mut Context) -> Result<Option<ProcedureId>> {
+    let v = ctx.prg.data_stack.pop()?.resolved(ctx.prg.strings())?;
+    Ok(match v {
+        Value::Int(0) => None,
+        Value::Int(id) => {
+            let id = id as ProcedureId;
+            if ctx.prg.program().proc(id).is_none() {
+                return Err(Error::BadProcedureId(id));
+            }
+            Some(id)
+        }
+        Value::String(name) => {
+            let name = name.resolve(ctx.prg.strings())?;
+            Some(ctx.prg.program().proc_id(&name).ok_or(Error::BadProcedure(name))?)
+        }
+        _ => return Err(Error::BadValue(BadValue::Type)),
+    })
+}
+
+/// Pops image file name. Returns `None` if the file is not specified (`0` or empty string) or
+/// unsupported.
+fn pop_image(ctx: &mut Context) -> Result<Option<FrameId>> {
+    let v = ctx.prg.data_stack.pop()?.resolved(ctx.prg.strings())?;
+    Ok(match v {
+        Value::Int(0) => None,
+        v => {
+            let path = v.coerce_into_string(ctx.prg.strings())?;
+            if path.is_empty() {
+                None
+            } else {
+                let fid = image_fid(ctx.ext.ui.frm_db(), &path);
+                if fid.is_none() {
+                    log_error!(ctx.prg, format!("unsupported image: {}", path.display()));
+                }
+                fid
+            }
+        }
+    })
+}
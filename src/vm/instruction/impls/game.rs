@@ -8,17 +8,20 @@ use std::cmp;
 use std::convert::{TryFrom, TryInto};
 
 use super::*;
-use crate::asset::{ExactEntityKind, Flag, Perk, Skill, Stat, Trait};
+use crate::asset::{CritterAnim, ExactEntityKind, Flag, Perk, Skill, Stat, Trait};
 use crate::asset::proto::ProtoId;
 use crate::asset::script::ProgramId;
+use crate::game::combat;
 use crate::game::dialog::Dialog;
 use crate::game::script::ScriptPid;
+use crate::game::sound::{self, SceneryAction, SceneryActionKind, WeaponSfx};
 use crate::game::world::floating_text;
-use crate::graphics::{EPoint, Point};
+use crate::graphics::{EPoint, Point, Rect};
 use crate::graphics::color::*;
 use crate::graphics::font::FontKey;
 use crate::graphics::geometry::hex::Direction;
 use crate::sequence::chain::Chain;
+use crate::sound::mixer::SoundId;
 use crate::util::random::{random as rand, RollCheckResult};
 
 /// This is also known as "trait" by `has_trait()`, `critter_add_trait` etc instructions.
@@ -180,11 +183,47 @@ pub fn anim(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn attack_complex(ctx: Context) -> Result<()> {
+    let target_results = ctx.prg.data_stack.pop()?.into_int()?;
+    let attacker_results = ctx.prg.data_stack.pop()?.into_int()?;
+    let max_damage = ctx.prg.data_stack.pop()?.into_int()?;
+    let min_damage = ctx.prg.data_stack.pop()?.into_int()?;
+    let bonus = ctx.prg.data_stack.pop()?.into_int()?;
+    let num_attacks = ctx.prg.data_stack.pop()?.into_int()?;
+    let called_shot = ctx.prg.data_stack.pop()?.into_int()?;
+    let target = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    log_a5!(ctx.prg, target, called_shot, num_attacks, bonus, min_damage);
+    debug!("  max_damage={} attacker_results={} target_results={}",
+        max_damage, attacker_results, target_results);
+
+    // TODO support the extra attack parameters.
+    let attacker = ctx.ext.self_obj.ok_or(Error::BadValue(BadValue::Content))?;
+    ctx.ext.combat.push_request(combat::Request::Attack { attacker, target });
+
+    Ok(())
+}
+
+pub fn attack_setup(ctx: Context) -> Result<()> {
+    let target = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let attacker = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    log_a2!(ctx.prg, attacker, target);
+
+    if ctx.ext.world.objects().get(attacker).sub.as_critter().map(|c| c.is_dead()) == Some(false) {
+        ctx.ext.combat.push_request(combat::Request::Attack { attacker, target });
+    }
+
+    Ok(())
+}
+
 pub fn combat_is_initialized(ctx: Context) -> Result<()> {
-    let r = false;
+    let r = ctx.ext.combat.is_active();
     ctx.prg.data_stack.push(r.into())?;
     log_r1!(ctx.prg, r);
-    log_stub!(ctx.prg);
     Ok(())
 }
 
@@ -280,6 +319,14 @@ pub fn critter_inven_obj(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn critter_stop_attacking(ctx: Context) -> Result<()> {
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    log_a1!(ctx.prg, obj);
+    ctx.ext.combat.push_request(combat::Request::StopAttacking { obj });
+    Ok(())
+}
+
 pub fn cur_map_index(ctx: Context) -> Result<()> {
     let r = ctx.ext.map_id;
     ctx.prg.data_stack.push(r.try_into().unwrap())?;
@@ -378,6 +425,13 @@ impl FloatingTextStyle {
 
 const_assert!(FloatingTextStyle::SEQ_MIN <= FloatingTextStyle::SEQ_MAX);
 
+pub fn fixed_param(ctx: Context) -> Result<()> {
+    let r = ctx.ext.fixed_param;
+    ctx.prg.data_stack.push(Value::Int(r))?;
+    log_r1!(ctx.prg, r);
+    Ok(())
+}
+
 pub fn float_msg(ctx: Context) -> Result<()> {
     let style = FloatingTextStyle::from_i32(ctx.prg.data_stack.pop()?.into_int()?);
     let msg = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
@@ -873,6 +927,13 @@ pub fn move_to(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn movieflags(ctx: Context) -> Result<()> {
+    let flags = ctx.prg.data_stack.pop()?.into_int()?;
+    ctx.ext.movies.set_flags(flags as u32);
+    log_a1!(ctx.prg, flags);
+    Ok(())
+}
+
 pub fn obj_art_fid(ctx: Context) -> Result<()> {
     let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
         .ok_or(Error::BadValue(BadValue::Content))?;
@@ -1044,6 +1105,45 @@ pub fn party_member_obj(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn play_gmovie(ctx: Context) -> Result<()> {
+    let movie = ctx.prg.data_stack.pop()?.into_int()?;
+    log_a1!(ctx.prg, movie);
+    if let Err(e) = ctx.ext.movies.play_game_movie(movie as usize, ctx.ext.ui) {
+        log_error!(ctx.prg, format!("couldn't play movie {}: {}", movie, e));
+    }
+    Ok(())
+}
+
+pub fn play_sfx(ctx: Context) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    ctx.ext.sound.play_sfx(&name.to_string_lossy());
+    log_a1!(ctx.prg, name);
+    Ok(())
+}
+
+pub fn playmovie(ctx: Context) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    log_a1!(ctx.prg, name);
+    if let Err(e) = ctx.ext.movies.play(&name.to_string_lossy(), None, ctx.ext.ui) {
+        log_error!(ctx.prg, format!("couldn't play movie {}: {}", name.display(), e));
+    }
+    Ok(())
+}
+
+pub fn playmovierect(ctx: Context) -> Result<()> {
+    let height = ctx.prg.data_stack.pop()?.into_int()?;
+    let width = ctx.prg.data_stack.pop()?.into_int()?;
+    let y = ctx.prg.data_stack.pop()?.into_int()?;
+    let x = ctx.prg.data_stack.pop()?.into_int()?;
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    log_a5!(ctx.prg, name, x, y, width, height);
+    let rect = Rect::with_size(x, y, width, height);
+    if let Err(e) = ctx.ext.movies.play(&name.to_string_lossy(), Some(rect), ctx.ext.ui) {
+        log_error!(ctx.prg, format!("couldn't play movie {}: {}", name.display(), e));
+    }
+    Ok(())
+}
+
 pub fn random(ctx: Context) -> Result<()> {
     let to_incl = ctx.prg.data_stack.pop()?.into_int()?;
     let from_incl = ctx.prg.data_stack.pop()?.into_int()?;
@@ -1112,6 +1212,21 @@ pub fn reg_anim_func(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn reg_anim_play_sfx(ctx: Context) -> Result<()> {
+    use std::time::Duration;
+
+    // Delay is in animation ticks.
+    let delay = ctx.prg.data_stack.pop()?.into_int()?;
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let obj = ctx.prg.data_stack.pop()?.into_object()?;
+    if obj.is_some() {
+        let delay = Duration::from_millis(cmp::max(delay, 0) as u64 * 100);
+        ctx.ext.sound.play_sfx_delayed(&name.to_string_lossy(), delay);
+    }
+    log_a3!(ctx.prg, obj, name, delay);
+    Ok(())
+}
+
 pub fn rm_timer_event(ctx: Context) -> Result<()> {
     let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
         .ok_or(Error::BadValue(BadValue::Content))?;
@@ -1144,6 +1259,22 @@ pub fn set_light_level(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn set_map_music(ctx: Context) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let map_id = ctx.prg.data_stack.pop()?.into_int()?;
+    let name_str = name.to_string_lossy().into_owned();
+    if map_id >= 0 {
+        if map_id as u32 == ctx.ext.map_id {
+            ctx.ext.sound.play_music(&name_str);
+        }
+        ctx.ext.sound.set_map_music(map_id as u32, name_str);
+    } else {
+        log_error!(ctx.prg, "bad map id");
+    }
+    log_a2!(ctx.prg, map_id, name);
+    Ok(())
+}
+
 pub fn set_obj_visibility(ctx: Context) -> Result<()> {
     let visible = ctx.prg.data_stack.pop()?.into_bool()?;
     let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
@@ -1155,6 +1286,149 @@ pub fn set_obj_visibility(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+fn push_sfx_name(ctx: &mut Context, name: Option<String>) -> Result<Rc<BString>> {
+    let r = Rc::new(BString::from(name.unwrap_or_default()));
+    ctx.prg.data_stack.push(r.clone().into())?;
+    Ok(r)
+}
+
+pub fn sfx_build_ambient_name(mut ctx: Context) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let r = push_sfx_name(&mut ctx, Some(sound::ambient_sfx_name(&name.to_string_lossy())))?;
+    log_a1r1!(ctx.prg, name, r);
+    Ok(())
+}
+
+pub fn sfx_build_char_name(mut ctx: Context) -> Result<()> {
+    let extra = ctx.prg.data_stack.pop()?.into_int()?;
+    let anim = ctx.prg.data_stack.pop()?.into_int()?;
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let critter_anim = CritterAnim::from_i32(anim)
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let fid = ctx.ext.world.objects().get(obj).fid;
+    let name = sound::character_sfx_name(ctx.ext.world.frm_db(), fid, critter_anim, extra);
+    let r = push_sfx_name(&mut ctx, name)?;
+    log_a3r1!(ctx.prg, obj, critter_anim, extra, r);
+    Ok(())
+}
+
+pub fn sfx_build_interface_name(mut ctx: Context) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let r = push_sfx_name(&mut ctx, Some(sound::interface_sfx_name(&name.to_string_lossy())))?;
+    log_a1r1!(ctx.prg, name, r);
+    Ok(())
+}
+
+pub fn sfx_build_item_name(mut ctx: Context) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let r = push_sfx_name(&mut ctx, Some(sound::item_sfx_name(&name.to_string_lossy())))?;
+    log_a1r1!(ctx.prg, name, r);
+    Ok(())
+}
+
+pub fn sfx_build_open_name(mut ctx: Context) -> Result<()> {
+    let action = ctx.prg.data_stack.pop()?.into_int()?;
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let action = SceneryAction::from_i32(action)
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let name = sound::object_open_sfx_name(&ctx.ext.world.objects().get(obj), action);
+    let r = push_sfx_name(&mut ctx, name)?;
+    log_a2r1!(ctx.prg, obj, action, r);
+    Ok(())
+}
+
+pub fn sfx_build_scenery_name(mut ctx: Context) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let action = ctx.prg.data_stack.pop()?.into_int()?;
+    let kind = ctx.prg.data_stack.pop()?.into_int()?;
+    let action = SceneryAction::from_i32(action)
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let kind = SceneryActionKind::from_i32(kind)
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let r = push_sfx_name(&mut ctx,
+        Some(sound::scenery_sfx_name(kind, action, &name.to_string_lossy())))?;
+    log_a3r1!(ctx.prg, kind, action, name, r);
+    Ok(())
+}
+
+pub fn sfx_build_weapon_name(mut ctx: Context) -> Result<()> {
+    let target = ctx.prg.data_stack.pop()?.coerce_into_object()?;
+    let hit_mode = ctx.prg.data_stack.pop()?.into_int()?;
+    let weapon = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let sfx = ctx.prg.data_stack.pop()?.into_int()?;
+    let sfx = WeaponSfx::from_i32(sfx)
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    // Hit modes: left primary, left secondary, right primary, right secondary, punch, ...
+    let secondary = hit_mode != 0 && hit_mode != 2 && hit_mode != 4;
+    let name = {
+        let objs = ctx.ext.world.objects();
+        let target = target.map(|t| sound::object_material(&objs.get(t)));
+        let weapono = objs.get(weapon);
+        let proto = weapono.proto();
+        proto.as_ref()
+            .and_then(|p| p.sub.as_weapon())
+            .map(|w| sound::weapon_sfx_name(sfx, w, secondary, target))
+    };
+    if name.is_none() {
+        log_error!(ctx.prg, "object is not a weapon");
+    }
+    let r = push_sfx_name(&mut ctx, name)?;
+    log_a4r1!(ctx.prg, sfx, weapon, hit_mode, target, r);
+    Ok(())
+}
+
+pub fn sounddelete(ctx: Context) -> Result<()> {
+    let id = ctx.prg.data_stack.pop()?.into_int()?;
+    ctx.ext.sound.audio().mixer().stop(SoundId::from_raw(id as u32));
+    log_a1!(ctx.prg, id);
+    Ok(())
+}
+
+pub fn soundpause(ctx: Context) -> Result<()> {
+    let id = ctx.prg.data_stack.pop()?.into_int()?;
+    ctx.ext.sound.audio().mixer().set_paused(SoundId::from_raw(id as u32), true);
+    log_a1!(ctx.prg, id);
+    Ok(())
+}
+
+pub fn soundplay(ctx: Context) -> Result<()> {
+    const LOOP: i32 = 1;
+
+    let flags = ctx.prg.data_stack.pop()?.into_int()?;
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let r = ctx.ext.sound.play_sound(&name.to_string_lossy(), flags & LOOP != 0)
+        .map(|id| id.raw() as i32)
+        .unwrap_or(0);
+    ctx.prg.data_stack.push(r.into())?;
+    log_a2r1!(ctx.prg, name, flags, r);
+    Ok(())
+}
+
+pub fn soundresume(ctx: Context) -> Result<()> {
+    let id = ctx.prg.data_stack.pop()?.into_int()?;
+    ctx.ext.sound.audio().mixer().set_paused(SoundId::from_raw(id as u32), false);
+    log_a1!(ctx.prg, id);
+    Ok(())
+}
+
+pub fn soundrewind(ctx: Context) -> Result<()> {
+    let id = ctx.prg.data_stack.pop()?.into_int()?;
+    ctx.ext.sound.audio().mixer().rewind(SoundId::from_raw(id as u32));
+    log_a1!(ctx.prg, id);
+    Ok(())
+}
+
+pub fn soundstop(ctx: Context) -> Result<()> {
+    let id = ctx.prg.data_stack.pop()?.into_int()?;
+    ctx.ext.sound.audio().mixer().stop(SoundId::from_raw(id as u32));
+    log_a1!(ctx.prg, id);
+    Ok(())
+}
+
 pub fn start_gdialog(mut ctx: Context) -> Result<()> {
     let background = ctx.prg.data_stack.pop()?.into_int()?;
     let head_id = ctx.prg.data_stack.pop()?.into_int()?;
@@ -1174,6 +1448,12 @@ pub fn start_gdialog(mut ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn stopmovie(ctx: Context) -> Result<()> {
+    log_!(ctx.prg);
+    ctx.ext.movies.stop(ctx.ext.ui);
+    Ok(())
+}
+
 pub fn tile_contains_pid_obj(ctx: Context) -> Result<()> {
     let pid = ctx.prg.data_stack.pop()?.into_int()?;
     let pid = ProtoId::from_packed(pid as u32)
@@ -1297,6 +1577,12 @@ pub fn target_obj(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn terminate_combat(ctx: Context) -> Result<()> {
+    log_!(ctx.prg);
+    ctx.ext.combat.push_request(combat::Request::Terminate);
+    Ok(())
+}
+
 pub fn tile_in_tile_rect(ctx: Context) -> Result<()> {
     let tile_num = ctx.prg.data_stack.pop()?.into_int()?;
     let right = ctx.prg.data_stack.pop()?.into_int()?;
@@ -0,0 +1,299 @@
+//! Instructions of the `say*` dialog engine. See `game::say` for details.
+
+use std::cmp;
+use std::time::Duration;
+
+use super::*;
+use crate::game::say::{ScrollButton, WindowConfig};
+use crate::graphics::Point;
+use crate::graphics::font::HorzAlign;
+use crate::ui::message_panel::Scroll;
+
+fn check_dialog(ctx: &Context, ok: bool) {
+    if !ok {
+        log_error!(ctx.prg, "no dialog");
+    }
+}
+
+fn set_window(ctx: &mut Context, reply: bool) -> Result<()> {
+    let background = pop_image(ctx)?;
+    let rect = pop_rect(ctx)?;
+
+    log_a2!(ctx.prg, rect, background);
+
+    let config = WindowConfig { rect, background };
+    if reply {
+        ctx.ext.say_dialog.set_reply_window(config);
+    } else {
+        ctx.ext.say_dialog.set_option_window(config);
+    }
+
+    Ok(())
+}
+
+/// Only the text alignment flags are supported.
+fn set_flags(ctx: &mut Context, reply: bool) -> Result<()> {
+    let flags = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+
+    log_a1!(ctx.prg, flags);
+
+    let align = match flags {
+        0 => HorzAlign::Left,
+        1 => HorzAlign::Right,
+        2 => HorzAlign::Center,
+        _ => {
+            log_error!(ctx.prg, format!("unsupported flags: {}", flags));
+            return Ok(());
+        }
+    };
+    if reply {
+        ctx.ext.say_dialog.set_reply_align(align);
+    } else {
+        ctx.ext.say_dialog.set_option_align(align);
+    }
+
+    Ok(())
+}
+
+fn set_scroll_button(ctx: &mut Context, scroll: Scroll) -> Result<()> {
+    let disabled = pop_image(ctx)?;
+    let hover = pop_image(ctx)?;
+    let down = pop_image(ctx)?;
+    let up = pop_image(ctx)?;
+    let y = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let x = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+
+    log_a5!(ctx.prg, Point::new(x, y), up, down, hover, disabled);
+
+    let button = up.map(|up| ScrollButton {
+        pos: Point::new(x, y),
+        up,
+        down: down.unwrap_or(up),
+    });
+    ctx.ext.say_dialog.set_scroll_button(scroll, button);
+
+    Ok(())
+}
+
+// opSayBorder()
+pub fn sayborder(ctx: Context) -> Result<()> {
+    let y = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let x = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+
+    log_a2!(ctx.prg, x, y);
+
+    ctx.ext.say_dialog.set_border(Point::new(x, y));
+
+    Ok(())
+}
+
+// opSayEnd()
+pub fn sayend(ctx: Context) -> Result<Option<Suspend>> {
+    log_!(ctx.prg);
+
+    let ok = ctx.ext.say_dialog.end(ctx.ext.ui);
+    check_dialog(&ctx, ok);
+
+    // The dialog ends right away if it has no replies.
+    Ok(if ok && ctx.ext.say_dialog.is_running() {
+        Some(Suspend::SayEnd)
+    } else {
+        None
+    })
+}
+
+// opSayGetLastPos()
+pub fn saygetlastpos(ctx: Context) -> Result<()> {
+    let r = ctx.ext.say_dialog.last_pos() as i32;
+    ctx.prg.data_stack.push(r.into())?;
+
+    log_r1!(ctx.prg, r);
+
+    Ok(())
+}
+
+// opSayGotoReply()
+pub fn saygotoreply(mut ctx: Context) -> Result<()> {
+    let name = pop_string(&mut ctx)?;
+
+    log_a1!(ctx.prg, name);
+
+    if !ctx.ext.say_dialog.go_to_reply(&name) {
+        log_error!(ctx.prg, format!("reply `{}` not found", name.display()));
+    }
+
+    Ok(())
+}
+
+// opSayMessage()
+pub fn saymessage(mut ctx: Context) -> Result<Option<Suspend>> {
+    let text = pop_string(&mut ctx)?;
+    let title = pop_string(&mut ctx)?;
+
+    log_a2!(ctx.prg, title, text);
+
+    ctx.ext.say_dialog.show_message(&title, &text, ctx.ext.ui);
+
+    Ok(Some(Suspend::SayMessage))
+}
+
+// opSayMessageTimeout()
+pub fn saymessagetimeout(ctx: Context) -> Result<()> {
+    let millis = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+
+    log_a1!(ctx.prg, millis);
+
+    let timeout = if millis > 0 {
+        Some(Duration::from_millis(millis as u64))
+    } else {
+        None
+    };
+    ctx.ext.say_dialog.set_message_timeout(timeout);
+
+    Ok(())
+}
+
+// opSayOption()
+pub fn sayoption(mut ctx: Context) -> Result<()> {
+    let proc_id = pop_proc(&mut ctx)?;
+    let text = pop_string(&mut ctx)?;
+
+    log_a2!(ctx.prg, text, proc_id);
+
+    let ok = ctx.ext.say_dialog.add_option((*text).clone(), proc_id);
+    if !ok {
+        log_error!(ctx.prg, "no dialog or reply");
+    }
+
+    Ok(())
+}
+
+// opSayOptionColor()
+pub fn sayoptioncolor(mut ctx: Context) -> Result<()> {
+    let color = pop_color(&mut ctx)?;
+
+    log_a1!(ctx.prg, color);
+
+    ctx.ext.say_dialog.set_option_color(color);
+
+    Ok(())
+}
+
+// opSayOptionFlags()
+pub fn sayoptionflags(mut ctx: Context) -> Result<()> {
+    set_flags(&mut ctx, false)
+}
+
+// opSayOptionWindow()
+pub fn sayoptionwindow(mut ctx: Context) -> Result<()> {
+    set_window(&mut ctx, false)
+}
+
+// opSayQuit()
+pub fn sayquit(ctx: Context) -> Result<()> {
+    log_!(ctx.prg);
+
+    let ok = ctx.ext.say_dialog.quit();
+    check_dialog(&ctx, ok);
+
+    Ok(())
+}
+
+// opSayReply()
+pub fn sayreply(mut ctx: Context) -> Result<()> {
+    let text = pop_string(&mut ctx)?;
+    let name = pop_string(&mut ctx)?;
+
+    log_a2!(ctx.prg, name, text);
+
+    let ok = ctx.ext.say_dialog.add_reply((*name).clone(), (*text).clone());
+    check_dialog(&ctx, ok);
+
+    Ok(())
+}
+
+// opSayReplyColor()
+pub fn sayreplycolor(mut ctx: Context) -> Result<()> {
+    let color = pop_color(&mut ctx)?;
+
+    log_a1!(ctx.prg, color);
+
+    ctx.ext.say_dialog.set_reply_color(color);
+
+    Ok(())
+}
+
+// opSayReplyFlags()
+pub fn sayreplyflags(mut ctx: Context) -> Result<()> {
+    set_flags(&mut ctx, true)
+}
+
+// opSayReplyTitle()
+pub fn sayreplytitle(mut ctx: Context) -> Result<()> {
+    let title = pop_string(&mut ctx)?;
+
+    log_a1!(ctx.prg, title);
+
+    let ok = ctx.ext.say_dialog.set_title((*title).clone());
+    check_dialog(&ctx, ok);
+
+    Ok(())
+}
+
+// opSayReplyWindow()
+pub fn sayreplywindow(mut ctx: Context) -> Result<()> {
+    set_window(&mut ctx, true)
+}
+
+// opSayRestart()
+pub fn sayrestart(ctx: Context) -> Result<()> {
+    log_!(ctx.prg);
+
+    let ok = ctx.ext.say_dialog.restart();
+    check_dialog(&ctx, ok);
+
+    Ok(())
+}
+
+// opSayScrollDown()
+pub fn sayscrolldown(mut ctx: Context) -> Result<()> {
+    set_scroll_button(&mut ctx, Scroll::Down)
+}
+
+// opSayScrollUp()
+pub fn sayscrollup(mut ctx: Context) -> Result<()> {
+    set_scroll_button(&mut ctx, Scroll::Up)
+}
+
+// opSaySetSpacing()
+pub fn saysetspacing(ctx: Context) -> Result<()> {
+    let spacing = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+
+    log_a1!(ctx.prg, spacing);
+
+    ctx.ext.say_dialog.set_option_spacing(spacing);
+
+    Ok(())
+}
+
+// opSayStart()
+pub fn saystart(ctx: Context) -> Result<()> {
+    log_!(ctx.prg);
+
+    if !ctx.ext.say_dialog.start(ctx.ext.sid) {
+        log_error!(ctx.prg, "dialog is already started");
+    }
+
+    Ok(())
+}
+
+// opSayStartPos()
+pub fn saystartpos(ctx: Context) -> Result<()> {
+    let pos = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+
+    log_a1!(ctx.prg, pos);
+
+    ctx.ext.say_dialog.set_start_pos(cmp::max(pos, 0) as usize);
+
+    Ok(())
+}
@@ -0,0 +1,502 @@
+//! Instructions of the script windows: `createwin`, `addbutton`, `print` etc.
+
+use bstring::BString;
+
+use super::*;
+use crate::game::script_window::{BUTTON_FLAG_DISABLED, TextAlign, Window};
+use crate::graphics::Point;
+use crate::graphics::font::FontKey;
+use crate::ui::Ui;
+use crate::ui::command::ScriptWindowCommand;
+
+fn current_window<'a>(ctx: &'a mut Context) -> Option<(&'a mut Window, &'a mut Ui)> {
+    let r = ctx.ext.script_windows.current(ctx.ext.sid);
+    if r.is_none() {
+        log_error!(ctx.prg, "no current window");
+    }
+    Some((r?, &mut *ctx.ext.ui))
+}
+
+fn check_found(ctx: &Context, found: bool, kind: &str, name: &BString) {
+    if !found {
+        log_error!(ctx.prg, format!("{} `{}` not found", kind, name.display()));
+    }
+}
+
+// opActivateRegion()
+pub fn activateregion(mut ctx: Context) -> Result<()> {
+    let active = ctx.prg.data_stack.pop()?.coerce_into_int()? != 0;
+    let name = pop_string(&mut ctx)?;
+
+    log_a2!(ctx.prg, name, active);
+
+    let found = if let Some((win, ui)) = current_window(&mut ctx) {
+        win.set_region_active(&name, active, ui)
+    } else {
+        return Ok(());
+    };
+    check_found(&ctx, found, "region", &name);
+
+    Ok(())
+}
+
+// opAddButton()
+pub fn addbutton(mut ctx: Context) -> Result<()> {
+    let rect = pop_rect(&mut ctx)?;
+    let name = pop_string(&mut ctx)?;
+
+    log_a2!(ctx.prg, name, rect);
+
+    if let Some((win, ui)) = current_window(&mut ctx) {
+        win.add_button(&name, rect, ui);
+    }
+
+    Ok(())
+}
+
+// opAddButtonFlag()
+pub fn addbuttonflag(mut ctx: Context) -> Result<()> {
+    let flag = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let name = pop_string(&mut ctx)?;
+
+    log_a2!(ctx.prg, name, flag);
+
+    let found = if let Some((win, ui)) = current_window(&mut ctx) {
+        win.add_button_flags(&name, flag, ui)
+    } else {
+        return Ok(());
+    };
+    check_found(&ctx, found, "button", &name);
+    if found && flag & !BUTTON_FLAG_DISABLED != 0 {
+        log_error!(ctx.prg, format!("unsupported flags: 0x{:x}", flag & !BUTTON_FLAG_DISABLED));
+    }
+
+    Ok(())
+}
+
+// opAddButtonGfx()
+pub fn addbuttongfx(mut ctx: Context) -> Result<()> {
+    let hover = pop_image(&mut ctx)?;
+    let down = pop_image(&mut ctx)?;
+    let up = pop_image(&mut ctx)?;
+    let name = pop_string(&mut ctx)?;
+
+    log_a4!(ctx.prg, name, up, down, hover);
+
+    let found = if let Some((win, ui)) = current_window(&mut ctx) {
+        win.set_button_images(&name, up, down, hover, ui)
+    } else {
+        return Ok(());
+    };
+    check_found(&ctx, found, "button", &name);
+
+    Ok(())
+}
+
+// opAddButtonProc()
+pub fn addbuttonproc(mut ctx: Context) -> Result<()> {
+    let release = pop_proc(&mut ctx)?;
+    let press = pop_proc(&mut ctx)?;
+    let exit = pop_proc(&mut ctx)?;
+    let enter = pop_proc(&mut ctx)?;
+    let name = pop_string(&mut ctx)?;
+
+    log_a5!(ctx.prg, name, enter, exit, press, release);
+
+    use ScriptWindowCommand::*;
+    let sid = ctx.ext.sid;
+    let found = if let Some((win, _)) = current_window(&mut ctx) {
+        win.set_button_procs(&name, sid,
+            &[(Enter, enter), (Exit, exit), (Press, press), (Release, release)])
+    } else {
+        return Ok(());
+    };
+    check_found(&ctx, found, "button", &name);
+
+    Ok(())
+}
+
+// opAddButtonRightProc()
+pub fn addbuttonrightproc(mut ctx: Context) -> Result<()> {
+    let release = pop_proc(&mut ctx)?;
+    let press = pop_proc(&mut ctx)?;
+    let name = pop_string(&mut ctx)?;
+
+    log_a3!(ctx.prg, name, press, release);
+
+    use ScriptWindowCommand::*;
+    let sid = ctx.ext.sid;
+    let found = if let Some((win, _)) = current_window(&mut ctx) {
+        win.set_button_procs(&name, sid, &[(RightPress, press), (RightRelease, release)])
+    } else {
+        return Ok(());
+    };
+    check_found(&ctx, found, "button", &name);
+
+    Ok(())
+}
+
+// opAddButtonText()
+pub fn addbuttontext(mut ctx: Context) -> Result<()> {
+    let text = pop_string(&mut ctx)?;
+    let name = pop_string(&mut ctx)?;
+
+    log_a2!(ctx.prg, name, text);
+
+    let found = if let Some((win, ui)) = current_window(&mut ctx) {
+        win.set_button_text(&name, (*text).clone(), ui)
+    } else {
+        return Ok(());
+    };
+    check_found(&ctx, found, "button", &name);
+
+    Ok(())
+}
+
+// opAddRegion()
+pub fn addregion(mut ctx: Context) -> Result<()> {
+    // The argument count includes the region name.
+    let arg_count = ctx.prg.data_stack.pop()?.into_int()?;
+    if arg_count < 7 || arg_count % 2 == 0 {
+        log_error!(ctx.prg, format!("bad argument count: {}", arg_count));
+        return Err(Error::BadValue(BadValue::Content));
+    }
+    let mut points = Vec::with_capacity(arg_count as usize / 2);
+    for _ in 0..arg_count / 2 {
+        let y = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+        let x = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+        points.push(Point::new(x, y));
+    }
+    points.reverse();
+    let name = pop_string(&mut ctx)?;
+
+    log_a2!(ctx.prg, name, points);
+
+    if let Some((win, ui)) = current_window(&mut ctx) {
+        win.add_region(&name, points, ui);
+    }
+
+    Ok(())
+}
+
+// opAddRegionFlag()
+pub fn addregionflag(mut ctx: Context) -> Result<()> {
+    let flag = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let name = pop_string(&mut ctx)?;
+
+    log_a2!(ctx.prg, name, flag);
+
+    let found = if let Some((win, _)) = current_window(&mut ctx) {
+        win.add_region_flags(&name, flag)
+    } else {
+        return Ok(());
+    };
+    check_found(&ctx, found, "region", &name);
+    if found && flag != 0 {
+        log_error!(ctx.prg, format!("unsupported flags: 0x{:x}", flag));
+    }
+
+    Ok(())
+}
+
+// opAddRegionProc()
+pub fn addregionproc(mut ctx: Context) -> Result<()> {
+    let release = pop_proc(&mut ctx)?;
+    let press = pop_proc(&mut ctx)?;
+    let exit = pop_proc(&mut ctx)?;
+    let enter = pop_proc(&mut ctx)?;
+    let name = pop_string(&mut ctx)?;
+
+    log_a5!(ctx.prg, name, enter, exit, press, release);
+
+    use ScriptWindowCommand::*;
+    let sid = ctx.ext.sid;
+    let found = if let Some((win, _)) = current_window(&mut ctx) {
+        win.set_region_procs(&name, sid,
+            &[(Enter, enter), (Exit, exit), (Press, press), (Release, release)])
+    } else {
+        return Ok(());
+    };
+    check_found(&ctx, found, "region", &name);
+
+    Ok(())
+}
+
+// opAddRegionRightProc()
+pub fn addregionrightproc(mut ctx: Context) -> Result<()> {
+    let release = pop_proc(&mut ctx)?;
+    let press = pop_proc(&mut ctx)?;
+    let name = pop_string(&mut ctx)?;
+
+    log_a3!(ctx.prg, name, press, release);
+
+    use ScriptWindowCommand::*;
+    let sid = ctx.ext.sid;
+    let found = if let Some((win, _)) = current_window(&mut ctx) {
+        win.set_region_procs(&name, sid, &[(RightPress, press), (RightRelease, release)])
+    } else {
+        return Ok(());
+    };
+    check_found(&ctx, found, "region", &name);
+
+    Ok(())
+}
+
+// opCreateWin()
+pub fn createwin(mut ctx: Context) -> Result<()> {
+    let rect = pop_rect(&mut ctx)?;
+    let name = pop_string(&mut ctx)?;
+
+    log_a2!(ctx.prg, name, rect);
+
+    ctx.ext.script_windows.create(ctx.ext.sid, &name, rect, ctx.ext.ui);
+
+    Ok(())
+}
+
+// opDeleteButton()
+pub fn deletebutton(mut ctx: Context) -> Result<()> {
+    let v = ctx.prg.data_stack.pop()?.resolved(ctx.prg.strings())?;
+    // -1 deletes all buttons.
+    let name = match v {
+        Value::Int(-1) => None,
+        v => Some(v.coerce_into_string(ctx.prg.strings())?),
+    };
+
+    log_a1!(ctx.prg, name);
+
+    let found = if let Some((win, ui)) = current_window(&mut ctx) {
+        win.delete_button(name.as_ref().map(|n| &***n), ui)
+    } else {
+        return Ok(());
+    };
+    if let Some(name) = name {
+        check_found(&ctx, found, "button", &name);
+    }
+
+    Ok(())
+}
+
+// opDeleteRegion()
+pub fn deleteregion(mut ctx: Context) -> Result<()> {
+    let v = ctx.prg.data_stack.pop()?.resolved(ctx.prg.strings())?;
+    // -1 deletes all regions.
+    let name = match v {
+        Value::Int(-1) => None,
+        v => Some(v.coerce_into_string(ctx.prg.strings())?),
+    };
+
+    log_a1!(ctx.prg, name);
+
+    let found = if let Some((win, ui)) = current_window(&mut ctx) {
+        win.delete_region(name.as_ref().map(|n| &***n), ui)
+    } else {
+        return Ok(());
+    };
+    if let Some(name) = name {
+        check_found(&ctx, found, "region", &name);
+    }
+
+    Ok(())
+}
+
+// opDeleteWin()
+pub fn deletewin(mut ctx: Context) -> Result<()> {
+    let name = pop_string(&mut ctx)?;
+
+    log_a1!(ctx.prg, name);
+
+    let found = ctx.ext.script_windows.delete(&name, ctx.ext.ui);
+    check_found(&ctx, found, "window", &name);
+
+    Ok(())
+}
+
+// opDisplay()
+pub fn display(mut ctx: Context) -> Result<()> {
+    let fid = pop_image(&mut ctx)?;
+
+    log_a1!(ctx.prg, fid);
+
+    if let (Some(fid), Some((win, ui))) = (fid, current_window(&mut ctx)) {
+        win.display(fid, None, ui);
+    }
+
+    Ok(())
+}
+
+// opDisplayGfx()
+pub fn displaygfx(mut ctx: Context) -> Result<()> {
+    let rect = pop_rect(&mut ctx)?;
+    let fid = pop_image(&mut ctx)?;
+
+    log_a2!(ctx.prg, fid, rect);
+
+    if let (Some(fid), Some((win, ui))) = (fid, current_window(&mut ctx)) {
+        win.display(fid, Some(rect), ui);
+    }
+
+    Ok(())
+}
+
+// opFillRect()
+pub fn fillrect(mut ctx: Context) -> Result<()> {
+    let color = pop_color(&mut ctx)?;
+    let rect = pop_rect(&mut ctx)?;
+
+    log_a2!(ctx.prg, rect, color);
+
+    if let Some((win, ui)) = current_window(&mut ctx) {
+        win.fill(Some(rect), color, ui);
+    }
+
+    Ok(())
+}
+
+// opFillWin()
+pub fn fillwin(mut ctx: Context) -> Result<()> {
+    let color = pop_color(&mut ctx)?;
+
+    log_a1!(ctx.prg, color);
+
+    if let Some((win, ui)) = current_window(&mut ctx) {
+        win.fill(None, color, ui);
+    }
+
+    Ok(())
+}
+
+// opGotoXY()
+pub fn gotoxy(mut ctx: Context) -> Result<()> {
+    let y = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let x = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+
+    log_a2!(ctx.prg, x, y);
+
+    if let Some((win, _)) = current_window(&mut ctx) {
+        win.move_text_to(Point::new(x, y));
+    }
+
+    Ok(())
+}
+
+// opPrint()
+pub fn print(mut ctx: Context) -> Result<()> {
+    let text = pop_string(&mut ctx)?;
+
+    log_a1!(ctx.prg, text);
+
+    if let Some((win, ui)) = current_window(&mut ctx) {
+        win.print((*text).clone(), None, ui);
+    }
+
+    Ok(())
+}
+
+// opPrintRect()
+pub fn printrect(mut ctx: Context) -> Result<()> {
+    let align = match ctx.prg.data_stack.pop()?.coerce_into_int()? {
+        0 => TextAlign::Left,
+        1 => TextAlign::Right,
+        2 => TextAlign::Center,
+        v => {
+            log_error!(ctx.prg, format!("bad alignment: {}", v));
+            return Err(Error::BadValue(BadValue::Content));
+        }
+    };
+    let width = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let text = pop_string(&mut ctx)?;
+
+    log_a3!(ctx.prg, text, width, align);
+
+    if let Some((win, ui)) = current_window(&mut ctx) {
+        win.print((*text).clone(), Some((width, align)), ui);
+    }
+
+    Ok(())
+}
+
+// opResizeWin()
+pub fn resizewin(mut ctx: Context) -> Result<()> {
+    resize_window(&mut ctx, false)
+}
+
+// opScaleWin()
+pub fn scalewin(mut ctx: Context) -> Result<()> {
+    resize_window(&mut ctx, true)
+}
+
+fn resize_window(ctx: &mut Context, scale: bool) -> Result<()> {
+    let rect = pop_rect(ctx)?;
+    let name = pop_string(ctx)?;
+
+    log_a2!(ctx.prg, name, rect);
+
+    let sid = ctx.ext.sid;
+    let found = ctx.ext.script_windows.select(sid, &name);
+    check_found(ctx, found, "window", &name);
+    if let Some((win, ui)) = current_window(ctx) {
+        win.set_rect(rect, scale, ui);
+    }
+
+    Ok(())
+}
+
+// opSelectWin()
+pub fn selectwin(mut ctx: Context) -> Result<()> {
+    let name = pop_string(&mut ctx)?;
+
+    log_a1!(ctx.prg, name);
+
+    let found = ctx.ext.script_windows.select(ctx.ext.sid, &name);
+    check_found(&ctx, found, "window", &name);
+
+    Ok(())
+}
+
+// opSetFont()
+pub fn setfont(mut ctx: Context) -> Result<()> {
+    let id = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+
+    log_a1!(ctx.prg, id);
+
+    let font = if id >= 100 {
+        FontKey::antialiased(id as u32 - 100)
+    } else {
+        FontKey::non_antialiased(id as u32)
+    };
+    let found = if let Some((win, ui)) = current_window(&mut ctx) {
+        win.set_font(font, ui)
+    } else {
+        return Ok(());
+    };
+    if !found {
+        log_error!(ctx.prg, format!("font {} not found", id));
+    }
+
+    Ok(())
+}
+
+// opSetTextColor()
+pub fn settextcolor(mut ctx: Context) -> Result<()> {
+    let color = pop_color(&mut ctx)?;
+
+    log_a1!(ctx.prg, color);
+
+    if let Some((win, _)) = current_window(&mut ctx) {
+        win.set_text_color(color);
+    }
+
+    Ok(())
+}
+
+// opShowWin()
+pub fn showwin(mut ctx: Context) -> Result<()> {
+    log_!(ctx.prg);
+
+    if let Some((win, ui)) = current_window(&mut ctx) {
+        win.show(ui);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,267 @@
+//! Script profiler.
+//!
+//! Counts executed instructions and the time spent in them per program, procedure and opcode.
+//! The procedure time is self time: instructions of the called procedures are counted in the
+//! callee only. Every hit of an unimplemented opcode is recorded along with the script and procedure
+//! that reached it so it's known which of the opcodes the game content actually needs.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::{self, prelude::*};
+use std::time::Duration;
+
+use super::{ProcedureFlag, Program};
+use super::disasm::mnemonic;
+use super::instruction::Opcode;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Counter {
+    pub count: u64,
+    pub time: Duration,
+}
+
+impl Counter {
+    fn add(&mut self, time: Duration) {
+        self.count += 1;
+        self.time += time;
+    }
+}
+
+struct ProcStats {
+    body_pos: usize,
+    name: String,
+    counter: Counter,
+}
+
+struct ProgramStats {
+    counter: Counter,
+    /// Non-imported procedures sorted by `body_pos`.
+    procs: Vec<ProcStats>,
+}
+
+impl ProgramStats {
+    fn new(program: &Program) -> Self {
+        let mut procs: Vec<_> = program.procs.by_id.iter()
+            .filter(|p| !p.flags.contains(ProcedureFlag::Import))
+            .map(|p| ProcStats {
+                body_pos: p.body_pos,
+                name: p.name.display().to_string(),
+                counter: Counter::default(),
+            })
+            .collect();
+        procs.sort_by_key(|p| p.body_pos);
+        Self {
+            counter: Counter::default(),
+            procs,
+        }
+    }
+
+    /// Returns index of the procedure that contains code at `pos`.
+    fn proc_idx(&self, pos: usize) -> Option<usize> {
+        match self.procs.binary_search_by_key(&pos, |p| p.body_pos) {
+            Ok(i) => Some(i),
+            Err(0) => None,
+            Err(i) => Some(i - 1),
+        }
+    }
+
+    fn proc_name(&self, pos: usize) -> Option<&str> {
+        self.proc_idx(pos).map(|i| &self.procs[i].name[..])
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct UnimplementedHit {
+    opcode: Opcode,
+    program: String,
+    proc: Option<String>,
+}
+
+#[derive(Default)]
+pub struct Profiler {
+    programs: HashMap<String, ProgramStats>,
+    opcodes: HashMap<Opcode, Counter>,
+    unimplemented: HashMap<UnimplementedHit, u64>,
+}
+
+impl Profiler {
+    /// Counter of the `program` or `program:proc` if `proc` is specified.
+    pub fn counter(&self, program: &str, proc: Option<&str>) -> Counter {
+        self.programs.get(program)
+            .and_then(|p| if let Some(proc) = proc {
+                p.procs.iter().find(|p| p.name == proc).map(|p| p.counter)
+            } else {
+                Some(p.counter)
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn opcode_counter(&self, opcode: Opcode) -> Counter {
+        self.opcodes.get(&opcode).cloned().unwrap_or_default()
+    }
+
+    /// Returns number of hits of the unimplemented `opcode` in all scripts.
+    pub fn unimplemented_hits(&self, opcode: Opcode) -> u64 {
+        self.unimplemented.iter()
+            .filter(|(h, _)| h.opcode == opcode)
+            .map(|(_, &c)| c)
+            .sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.programs.clear();
+        self.opcodes.clear();
+        self.unimplemented.clear();
+    }
+
+    pub(super) fn record(&mut self, program: &Program, pos: usize, opcode: Opcode, time: Duration) {
+        let stats = self.program_stats(program);
+        stats.counter.add(time);
+        if let Some(i) = stats.proc_idx(pos) {
+            stats.procs[i].counter.add(time);
+        }
+        self.opcodes.entry(opcode).or_default().add(time);
+    }
+
+    pub(super) fn record_unimplemented(&mut self, program: &Program, pos: usize, opcode: Opcode) {
+        let proc = self.program_stats(program).proc_name(pos).map(|s| s.to_owned());
+        *self.unimplemented.entry(UnimplementedHit {
+            opcode,
+            program: program.name().to_owned(),
+            proc,
+        }).or_insert(0) += 1;
+    }
+
+    pub fn write_report(&self, wr: &mut impl Write) -> io::Result<()> {
+        fn write_counter(wr: &mut impl Write, c: Counter, name: &str) -> io::Result<()> {
+            writeln!(wr, "  {:>12} {:>12.3}  {}", c.count, c.time.as_secs_f64() * 1000.0, name)
+        }
+
+        writeln!(wr, "Programs:")?;
+        writeln!(wr, "  {:>12} {:>12}  program/procedure", "instructions", "time, ms")?;
+        let mut programs: Vec<_> = self.programs.iter().collect();
+        programs.sort_by_key(|&(name, p)| (Reverse(p.counter.time), name));
+        for (name, program) in programs {
+            write_counter(wr, program.counter, name)?;
+            let mut procs: Vec<_> = program.procs.iter()
+                .filter(|p| p.counter.count > 0)
+                .collect();
+            procs.sort_by_key(|p| (Reverse(p.counter.time), &p.name));
+            for proc in procs {
+                write_counter(wr, proc.counter, &format!("  {}:{}", name, proc.name))?;
+            }
+        }
+
+        writeln!(wr)?;
+        writeln!(wr, "Opcodes:")?;
+        writeln!(wr, "  {:>12} {:>12}  opcode", "instructions", "time, ms")?;
+        let mut opcodes: Vec<_> = self.opcodes.iter()
+            .map(|(&op, &c)| (mnemonic(op), c))
+            .collect();
+        opcodes.sort_by(|(an, a), (bn, b)| b.time.cmp(&a.time).then_with(|| an.cmp(bn)));
+        for (name, c) in opcodes {
+            write_counter(wr, c, &name)?;
+        }
+
+        writeln!(wr)?;
+        writeln!(wr, "Unimplemented opcodes:")?;
+        writeln!(wr, "  {:>12} {:>12}  opcode/location", "hits", "scripts")?;
+        let mut by_opcode: HashMap<Opcode, Vec<(&UnimplementedHit, u64)>> = HashMap::new();
+        for (hit, &count) in &self.unimplemented {
+            by_opcode.entry(hit.opcode).or_default().push((hit, count));
+        }
+        let mut by_opcode: Vec<_> = by_opcode.into_iter()
+            .map(|(op, mut hits)| {
+                hits.sort_by(|(a, ac), (b, bc)| bc.cmp(ac)
+                    .then_with(|| (&a.program, &a.proc).cmp(&(&b.program, &b.proc))));
+                (mnemonic(op), hits)
+            })
+            .collect();
+        by_opcode.sort_by_key(|(name, hits)| (Reverse(hits.iter().map(|&(_, c)| c).sum::<u64>()),
+            name.clone()));
+        for (name, hits) in by_opcode {
+            let mut scripts: Vec<_> = hits.iter().map(|(h, _)| &h.program).collect();
+            scripts.sort();
+            scripts.dedup();
+            writeln!(wr, "  {:>12} {:>12}  {}", hits.iter().map(|&(_, c)| c).sum::<u64>(),
+                scripts.len(), name)?;
+            for (hit, count) in hits {
+                let location = if let Some(proc) = &hit.proc {
+                    format!("{}:{}", hit.program, proc)
+                } else {
+                    hit.program.clone()
+                };
+                writeln!(wr, "  {:>12} {:>12}    {}", count, "", location)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn program_stats(&mut self, program: &Program) -> &mut ProgramStats {
+        if !self.programs.contains_key(program.name()) {
+            self.programs.insert(program.name().to_owned(), ProgramStats::new(program));
+        }
+        self.programs.get_mut(program.name()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vm::test::{Asm::*, Proc, program};
+
+    #[test]
+    fn report() {
+        let (program, labels) = program(&["start", "foo"], &[], &[
+            Proc { name: 0, flags: 0, arg_count: 0, body: "start" },
+            Proc { name: 1, flags: 0, arg_count: 0, body: "foo" },
+        ], &[
+            Label("start"),
+            Op(Opcode::Noop8000),
+            Op(Opcode::Exit),
+            Label("foo"),
+            Op(Opcode::AnimBusy),
+        ]);
+        let start = labels["start"];
+        let foo = labels["foo"];
+        let ms = Duration::from_millis(1);
+
+        let mut p = Profiler::default();
+        p.record(&program, 0, Opcode::CriticalStart, ms);
+        p.record(&program, start, Opcode::Noop8000, ms);
+        p.record(&program, start + 2, Opcode::Exit, ms * 2);
+        p.record(&program, foo, Opcode::AnimBusy, ms);
+        p.record(&program, foo, Opcode::AnimBusy, ms);
+        p.record_unimplemented(&program, foo, Opcode::AnimBusy);
+        p.record_unimplemented(&program, foo, Opcode::AnimBusy);
+
+        assert_eq!(p.counter("test", None), Counter { count: 5, time: ms * 6 });
+        assert_eq!(p.counter("test", Some("start")), Counter { count: 2, time: ms * 3 });
+        assert_eq!(p.counter("test", Some("foo")), Counter { count: 2, time: ms * 2 });
+        assert_eq!(p.opcode_counter(Opcode::AnimBusy), Counter { count: 2, time: ms * 2 });
+        assert_eq!(p.unimplemented_hits(Opcode::AnimBusy), 2);
+
+        let mut report = Vec::new();
+        p.write_report(&mut report).unwrap();
+        assert_eq!(String::from_utf8(report).unwrap(), "\
+Programs:
+  instructions     time, ms  program/procedure
+             5        6.000  test
+             2        3.000    test:start
+             2        2.000    test:foo
+
+Opcodes:
+  instructions     time, ms  opcode
+             2        2.000  anim_busy
+             1        2.000  exit
+             1        1.000  critical_start
+             1        1.000  noop8000
+
+Unimplemented opcodes:
+          hits      scripts  opcode/location
+             2            1  anim_busy
+             2                 test:foo
+");
+    }
+}
@@ -33,6 +33,10 @@ impl<Id: StackId> Stack<Id> {
         self.vec.len()
     }
 
+    pub fn as_slice(&self) -> &[Value] {
+        &self.vec
+    }
+
     pub fn top(&self) -> Option<&Value> {
         self.vec.last()
     }
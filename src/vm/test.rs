@@ -0,0 +1,154 @@
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::*;
+use super::instruction::Opcode;
+
+/// Item of the program code for `program()`.
+pub enum Asm {
+    Op(Opcode),
+    Int(i32),
+    Float(f32),
+    /// `const_string` referring to the string with the specified index.
+    Str(usize),
+    /// `const_string` referring to the name with the specified index.
+    Name(usize),
+    /// `const_long` with the position of the label.
+    Addr(&'static str),
+    Label(&'static str),
+    /// Raw bytes.
+    Bytes(&'static [u8]),
+}
+
+pub struct Proc {
+    /// Index in the name table.
+    pub name: usize,
+    pub flags: u32,
+    pub arg_count: u32,
+    /// Label of the body.
+    pub body: &'static str,
+}
+
+/// Returns the table and offsets of the strings in it.
+pub fn string_table(strings: &[&str]) -> (Vec<u8>, Vec<usize>) {
+    let mut r = vec![0; 4];
+    let mut offsets = Vec::new();
+    for s in strings {
+        r.write_u16::<BigEndian>(s.len() as u16 + 1).unwrap();
+        offsets.push(r.len());
+        r.extend_from_slice(s.as_bytes());
+        r.push(0);
+    }
+    r.write_u16::<BigEndian>(0xffff).unwrap();
+    r.write_u16::<BigEndian>(0).unwrap();
+    let len = r.len() as u32 - 8;
+    BigEndian::write_u32(&mut r[..4], len);
+    (r, offsets)
+}
+
+/// Builds program named `test`. The startup code jumps to the `code` that is placed right after
+/// the tables. Returns the program and positions of the labels.
+pub fn program(names: &[&str], strings: &[&str], procs: &[Proc], code: &[Asm])
+    -> (Program, HashMap<&'static str, usize>)
+{
+    let (names, name_offsets) = string_table(names);
+    let (strings, string_offsets) = string_table(strings);
+    let code_start = PROC_TABLE_START + 4 + procs.len() * 24 + names.len() + strings.len();
+
+    let mut labels = HashMap::new();
+    let mut pos = code_start;
+    for a in code {
+        match *a {
+            Asm::Op(_) => pos += 2,
+            Asm::Label(l) => {
+                labels.insert(l, pos);
+            }
+            Asm::Bytes(v) => pos += v.len(),
+            _ => pos += 6,
+        }
+    }
+
+    let mut r = Vec::new();
+    r.write_u16::<BigEndian>(Opcode::CriticalStart as u16).unwrap();
+    r.write_u16::<BigEndian>(Opcode::ConstLong as u16).unwrap();
+    r.write_i32::<BigEndian>(code_start as i32).unwrap();
+    r.write_u16::<BigEndian>(Opcode::Jmp as u16).unwrap();
+    while r.len() < PROC_TABLE_START {
+        r.write_u16::<BigEndian>(Opcode::Noop8000 as u16).unwrap();
+    }
+    r.write_u32::<BigEndian>(procs.len() as u32).unwrap();
+    for proc in procs {
+        r.write_u32::<BigEndian>(name_offsets[proc.name] as u32).unwrap();
+        r.write_u32::<BigEndian>(proc.flags).unwrap();
+        r.write_u32::<BigEndian>(0).unwrap();
+        r.write_u32::<BigEndian>(0).unwrap();
+        r.write_u32::<BigEndian>(labels[proc.body] as u32).unwrap();
+        r.write_u32::<BigEndian>(proc.arg_count).unwrap();
+    }
+    r.extend_from_slice(&names);
+    r.extend_from_slice(&strings);
+    assert_eq!(r.len(), code_start);
+
+    for a in code {
+        let opcode = match *a {
+            Asm::Op(op) => op,
+            Asm::Float(_) => Opcode::ConstFloat,
+            Asm::Str(_) | Asm::Name(_) => Opcode::ConstString,
+            Asm::Int(_) | Asm::Addr(_) => Opcode::ConstLong,
+            Asm::Label(_) => continue,
+            Asm::Bytes(v) => {
+                r.extend_from_slice(v);
+                continue;
+            }
+        };
+        r.write_u16::<BigEndian>(opcode as u16).unwrap();
+        match *a {
+            Asm::Int(v) => r.write_i32::<BigEndian>(v).unwrap(),
+            Asm::Float(v) => r.write_f32::<BigEndian>(v).unwrap(),
+            Asm::Str(i) => r.write_i32::<BigEndian>(string_offsets[i] as i32).unwrap(),
+            Asm::Name(i) => r.write_i32::<BigEndian>(name_offsets[i] as i32).unwrap(),
+            Asm::Addr(l) => r.write_i32::<BigEndian>(labels[l] as i32).unwrap(),
+            Asm::Op(_) | Asm::Label(_) | Asm::Bytes(_) => {}
+        }
+    }
+
+    (Vm::default().load("test".into(), r.into()).unwrap(), labels)
+}
+
+#[test]
+fn failure_policy() {
+    let mut config = VmConfig::default();
+    config.set_failure_policy(FailurePolicy::Lenient);
+    assert!(config.set_opcode_failure_policy("destroy_object", FailurePolicy::Strict));
+    assert!(!config.set_opcode_failure_policy("foo", FailurePolicy::Strict));
+    assert_eq!(config.failure_policy(Opcode::AnimBusy), FailurePolicy::Lenient);
+    assert_eq!(config.failure_policy(Opcode::DestroyObject), FailurePolicy::Strict);
+
+    let (mut program, _) = program(&[], &[], &[], &[]);
+    program.config = Rc::new(config);
+    let mut prg = ProgramState::new(Rc::new(program));
+    prg.data_stack.push(Value::Int(1)).unwrap();
+    prg.data_stack.push(Value::Int(2)).unwrap();
+
+    let e = Error::UnimplementedOpcode(Opcode::AnimBusy);
+    assert_eq!(prg.recover(e.clone(), Opcode::AnimBusy, 0x100, 2), Ok(None));
+    assert_eq!(prg.data_stack.as_slice(), &[Value::Int(1), Value::Int(0)]);
+
+    // Argument was popped before failing.
+    prg.data_stack.pop().unwrap();
+    let e = Error::BadValue(BadValue::Type);
+    assert_eq!(prg.recover(e, Opcode::DisplayMsg, 0x100, 2), Ok(None));
+    assert_eq!(prg.data_stack.as_slice(), &[Value::Int(1)]);
+
+    let e = Error::UnimplementedOpcode(Opcode::DestroyObject);
+    assert_eq!(prg.recover(e.clone(), Opcode::DestroyObject, 0x100, 1), Err(e));
+
+    // Unknown signature.
+    let e = Error::UnimplementedOpcode(Opcode::CallAt);
+    assert_eq!(prg.recover(e.clone(), Opcode::CallAt, 0x100, 1), Err(e));
+
+    assert_eq!(prg.recover(Error::StackUnderflow, Opcode::AnimBusy, 0x100, 1),
+        Err(Error::StackUnderflow));
+    assert_eq!(prg.data_stack.as_slice(), &[Value::Int(1)]);
+}